@@ -0,0 +1,498 @@
+//! This module contains a client for modems that are controlled with AT commands, like ESP8266 Wi-Fi or SIM800
+//! cellular modules.
+//!
+//! A [command](AtClient::command) is sent with a `\r` and the response lines are collected until the modem sends a
+//! final result code. `OK` and `SEND OK` end the command successfully, `ERROR`, `+CME ERROR: <code>`,
+//! `+CMS ERROR: <code>` and the call results like `NO CARRIER` are returned as error-enum. The echo of the command is
+//! skipped, so echo does not have to be disabled with `ATE0`.
+//!
+//! Modems also send unsolicited result codes (URCs), like `+CMTI: "SM",3` for a new SMS or `WIFI DISCONNECT`. The
+//! lines that start with a [registered](AtClient::on_urc) prefix are passed to the handler, while a command is running
+//! and when the client is [polled](AtClient::poll). A line that starts with the name of the running command, like
+//! `+CREG: 0,1` for `AT+CREG?`, is always part of the response.
+//!
+//! Transfers like `AT+CIPSEND=5` or `AT+CMGS="+49..."` wait for the `>` prompt of the modem before the data is sent,
+//! see [send_data](AtClient::send_data).
+//!
+//! The client works with every [AtPort]. The port is implemented by the [UART](crate::uart::UART), which needs the
+//! time base started with [`start_time()`](crate::time::start_time). Other ports, like a scripted fake modem, can be
+//! used to test the client on the host.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::uart::UART;
+//! use rustuino::at::AtClient;
+//!
+//! fn new_sms(line: &str) {
+//!   rprintln!("SMS received: {}", line);
+//! }
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   start_time();
+//!   let uart = UART::new(1, PA9, PA10, 115200).unwrap();
+//!   let mut modem = AtClient::new(uart);
+//!   modem.on_urc("+CMTI:", new_sms).unwrap();
+//!
+//!   let response = modem.command("AT+CSQ", 1000).unwrap();
+//!   rprintln!("Signal quality: {:?}", response.find("+CSQ:"));
+//!
+//!   modem.command("AT+CMGF=1", 1000).unwrap();
+//!   modem.send_data("AT+CMGS=\"+491701234567\"", b"Hello!\x1A", 60000).unwrap();
+//!
+//!   loop {
+//!     modem.poll().unwrap();
+//!   }
+//! }
+//! ```
+
+#![allow(clippy::question_mark)]
+
+use crate::include::{AtError, ProgError, SerialError};
+use crate::uart::UART;
+use crate::time::{millis, is_expired};
+use heapless::{String, Vec};
+use rtt_target::rprintln;
+
+/// The maximum length of a line, longer lines are cut off.
+pub const LINE_LEN: usize = 128;
+/// The maximum number of lines in a response, further lines are dropped.
+pub const MAX_LINES: usize = 8;
+/// The maximum number of URC handlers.
+pub const MAX_HANDLERS: usize = 8;
+
+/// A line of a response.
+pub type Line = String<LINE_LEN>;
+/// A handler that is called with the line of an unsolicited result code.
+pub type UrcHandler = fn(&str);
+
+
+/// A serial connection to a modem.
+pub trait AtPort {
+  /// Sends the data.
+  fn send(&mut self, data: &[u8]) -> Result<(), SerialError>;
+
+  /// Returns the next received byte, or `None` if nothing was received. Does not wait.
+  fn receive(&mut self) -> Result<Option<u8>, SerialError>;
+
+  /// Returns the time in milliseconds, used for the timeouts.
+  fn millis(&self) -> usize;
+}
+
+impl AtPort for UART {
+  fn send(&mut self, data: &[u8]) -> Result<(), SerialError> {
+    return self.write_bytes(data);
+  }
+
+  fn receive(&mut self) -> Result<Option<u8>, SerialError> {
+    return match self.read_byte_timeout(0) {
+      Ok(byte) => Ok(Some(byte)),
+      Err(SerialError::Prog(ProgError::TimedOut)) => Ok(None),
+      Err(error) => Err(error)
+    };
+  }
+
+  fn millis(&self) -> usize {
+    return millis();
+  }
+}
+
+
+/// The lines that a modem sent in response to a command, without the echo and the final result code.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Response {
+  #[doc(hidden)]
+  lines: Vec<Line, MAX_LINES>
+}
+
+impl Response {
+  /// Returns all lines.
+  pub fn lines(&self) -> &[Line] {
+    return &self.lines;
+  }
+
+  /// Returns the rest of the first line that starts with the prefix, without leading spaces. For example
+  /// `find("+CSQ:")` returns `"21,0"` for the line `+CSQ: 21,0`.
+  pub fn find(&self, prefix: &str) -> Option<&str> {
+    return self.lines.iter().find_map(|line| line.strip_prefix(prefix)).map(|rest| rest.trim_start());
+  }
+}
+
+
+/// This struct represents a modem that is controlled with AT commands.
+pub struct AtClient<P: AtPort> {
+  #[doc(hidden)]
+  port: P,
+  #[doc(hidden)]
+  line: Line,
+  #[doc(hidden)]
+  complete: bool,
+  #[doc(hidden)]
+  handlers: Vec<(&'static str, UrcHandler), MAX_HANDLERS>
+}
+
+// The next input of the modem
+enum Input {
+  Line,
+  Prompt
+}
+
+impl<P: AtPort> AtClient<P> {
+  /// Creates a client without URC handlers.
+  pub fn new(port: P) -> Self {
+    return Self {port, line: String::new(), complete: false, handlers: Vec::new()};
+  }
+
+  /// Registers a handler that is called with every line that starts with the prefix. A handler that was registered
+  /// for the same prefix before is replaced. Returns an error-enum if there are already [MAX_HANDLERS] handlers.
+  pub fn on_urc(&mut self, prefix: &'static str, handler: UrcHandler) -> Result<(), AtError> {
+    if let Some(entry) = self.handlers.iter_mut().find(|entry| entry.0 == prefix) {
+      entry.1 = handler;
+      return Ok(());
+    }
+
+    if self.handlers.push((prefix, handler)).is_err() {
+      rprintln!("Too many URC handlers! | .on_urc()");
+      return Err(AtError::Prog(ProgError::OutOfMemory));
+    }
+
+    return Ok(());
+  }
+
+  /// Removes the handler of the prefix. Returns false if no handler was registered.
+  pub fn remove_urc(&mut self, prefix: &str) -> bool {
+    return match self.handlers.iter().position(|entry| entry.0 == prefix) {
+      Some(index) => {
+        self.handlers.swap_remove(index);
+        true
+      },
+      None => false
+    };
+  }
+
+  /// Sends a command like `AT+CSQ` and returns the response lines after `OK`. Returns an error-enum for an error
+  /// result, if the modem does not finish the response within the timeout in milliseconds or if problems with the
+  /// connection are detected.
+  pub fn command(&mut self, command: &str, timeout_ms: usize) -> Result<Response, AtError> {
+    let deadline = self.port.millis().wrapping_add(timeout_ms);
+
+    if let Err(error) = self.send_command(command) {return Err(error);}
+
+    return self.read_response(command, deadline);
+  }
+
+  /// Sends a command that asks for data, like `AT+CIPSEND=5`, waits for the `>` prompt and sends the data. Returns the
+  /// response lines after the final result, e.g. `SEND OK`. An `OK` before the prompt is skipped. SMS texts have to
+  /// end with Ctrl-Z (`0x1A`).
+  ///
+  /// Returns an error-enum like [command](AtClient::command), the timeout in milliseconds is used for the whole
+  /// transfer.
+  pub fn send_data(&mut self, command: &str, data: &[u8], timeout_ms: usize) -> Result<Response, AtError> {
+    let deadline = self.port.millis().wrapping_add(timeout_ms);
+
+    if let Err(error) = self.send_command(command) {return Err(error);}
+
+    loop {
+      match self.next_input(deadline, true) {
+        Ok(Input::Prompt) => break,
+        Ok(Input::Line) => {},
+        Err(error) => return Err(error)
+      };
+
+      match final_result(&self.line) {
+        Some(Ok(())) => {},
+        Some(Err(error)) => return Err(error),
+        None => {self.dispatch(command);}
+      };
+    }
+
+    if let Err(error) = self.port.send(data) {return Err(AtError::Serial(error));}
+
+    return self.read_response(command, deadline);
+  }
+
+  /// Processes the received lines and passes URCs to their handlers. Other lines are dropped. Returns an error-enum
+  /// if problems with the connection are detected.
+  pub fn poll(&mut self) -> Result<(), AtError> {
+    loop {
+      let byte = match self.port.receive() {
+        Ok(Some(value)) => value,
+        Ok(None) => return Ok(()),
+        Err(error) => return Err(AtError::Serial(error))
+      };
+
+      if let Some(Input::Line) = self.feed(byte, false) {self.dispatch("");}
+    }
+  }
+
+  /// Returns the port.
+  pub fn port(&mut self) -> &mut P {
+    return &mut self.port;
+  }
+
+  /// Returns the port.
+  pub fn release(self) -> P {
+    return self.port;
+  }
+
+  fn send_command(&mut self, command: &str) -> Result<(), AtError> {
+    // Drop the rest of a line that was received before
+    self.line.clear();
+    self.complete = false;
+
+    if let Err(error) = self.port.send(command.as_bytes()) {return Err(AtError::Serial(error));}
+    if let Err(error) = self.port.send(b"\r") {return Err(AtError::Serial(error));}
+
+    return Ok(());
+  }
+
+  fn read_response(&mut self, command: &str, deadline: usize) -> Result<Response, AtError> {
+    let mut response = Response::default();
+
+    loop {
+      if let Err(error) = self.next_input(deadline, false) {return Err(error);}
+
+      if let Some(result) = final_result(&self.line) {
+        return result.map(|_| response);
+      }
+
+      if self.line.as_str() == command {continue;}
+
+      if !self.dispatch(command) {
+        // Further lines are dropped, the final result code is still needed
+        response.lines.push(self.line.clone()).ok();
+      }
+    }
+  }
+
+  // Waits for the next line or the prompt
+  fn next_input(&mut self, deadline: usize, prompt: bool) -> Result<Input, AtError> {
+    loop {
+      let byte = match self.port.receive() {
+        Ok(Some(value)) => value,
+        Ok(None) => {
+          if is_expired(self.port.millis(), deadline) {
+            rprintln!("The modem did not respond in time! | AtClient");
+            return Err(AtError::Prog(ProgError::TimedOut));
+          }
+          continue;
+        },
+        Err(error) => return Err(AtError::Serial(error))
+      };
+
+      if let Some(input) = self.feed(byte, prompt) {return Ok(input);}
+    }
+  }
+
+  // Collects the received bytes into lines, empty lines are skipped
+  fn feed(&mut self, byte: u8, prompt: bool) -> Option<Input> {
+    if self.complete {
+      self.line.clear();
+      self.complete = false;
+    }
+
+    match byte {
+      b'\n' if !self.line.is_empty() => {
+        self.complete = true;
+        return Some(Input::Line);
+      },
+      b'\r' | b'\n' => {},
+      // Leading spaces, e.g. after the prompt "> "
+      b' ' if self.line.is_empty() => {},
+      b'>' if prompt && self.line.is_empty() => return Some(Input::Prompt),
+      _ => {self.line.push(byte as char).ok();}
+    };
+
+    return None;
+  }
+
+  // Passes the line to its URC handler, returns false if it is no URC
+  fn dispatch(&self, command: &str) -> bool {
+    let line = self.line.as_str();
+    if is_response(command, line) {return false;}
+
+    let handler = match self.handlers.iter().find(|entry| line.starts_with(entry.0)) {
+      Some(entry) => entry.1,
+      None => return false
+    };
+
+    handler(line);
+    return true;
+  }
+}
+
+
+// Private Functions ==============================================================================
+fn final_result(line: &str) -> Option<Result<(), AtError>> {
+  if let Some(code) = line.strip_prefix("+CME ERROR:") {
+    return Some(Err(code.trim().parse().map_or(AtError::Error, AtError::CmeError)));
+  }
+  if let Some(code) = line.strip_prefix("+CMS ERROR:") {
+    return Some(Err(code.trim().parse().map_or(AtError::Error, AtError::CmsError)));
+  }
+
+  return match line {
+    "OK" | "SEND OK" => Some(Ok(())),
+    "ERROR" | "SEND FAIL" | "NO CARRIER" | "BUSY" | "NO ANSWER" | "NO DIALTONE" => Some(Err(AtError::Error)),
+    _ => None
+  };
+}
+
+// A line like "+CREG: 0,1" belongs to the command "AT+CREG?"
+fn is_response(command: &str, line: &str) -> bool {
+  let name = command.get(2..).unwrap_or("");
+  let name = name.split(['=', '?']).next().unwrap_or("");
+
+  if name.is_empty() {return false;}
+
+  return line.strip_prefix(name).is_some_and(|rest| rest.starts_with(':'));
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use core::cell::{Cell, RefCell};
+  use std::collections::VecDeque;
+
+  // Answers with a script of received bytes, every call of millis() takes a millisecond
+  struct FakeModem {
+    script: VecDeque<u8>,
+    sent: std::vec::Vec<u8>,
+    time: Cell<usize>
+  }
+
+  impl FakeModem {
+    fn new(script: &[u8]) -> Self {
+      return Self {script: script.iter().copied().collect(), sent: std::vec::Vec::new(), time: Cell::new(0)};
+    }
+  }
+
+  impl AtPort for FakeModem {
+    fn send(&mut self, data: &[u8]) -> Result<(), SerialError> {
+      self.sent.extend_from_slice(data);
+      return Ok(());
+    }
+
+    fn receive(&mut self) -> Result<Option<u8>, SerialError> {
+      return Ok(self.script.pop_front());
+    }
+
+    fn millis(&self) -> usize {
+      self.time.set(self.time.get().wrapping_add(1));
+      return self.time.get();
+    }
+  }
+
+  std::thread_local! {
+    static URCS: RefCell<std::vec::Vec<std::string::String>> = const {RefCell::new(std::vec::Vec::new())};
+  }
+
+  fn record(line: &str) {
+    URCS.with(|urcs| urcs.borrow_mut().push(line.into()));
+  }
+
+  fn urcs() -> std::vec::Vec<std::string::String> {
+    return URCS.with(|urcs| urcs.take());
+  }
+
+  #[test]
+  fn echo_is_skipped() {
+    let mut modem = AtClient::new(FakeModem::new(b"AT+CSQ\r\r\n+CSQ: 21,0\r\n\r\nOK\r\n"));
+
+    let response = modem.command("AT+CSQ", 100).unwrap();
+    assert_eq!(response.lines(), ["+CSQ: 21,0"]);
+    assert_eq!(response.find("+CSQ:"), Some("21,0"));
+    assert_eq!(response.find("+CREG:"), None);
+    assert_eq!(modem.release().sent, b"AT+CSQ\r");
+  }
+
+  #[test]
+  fn final_result_codes() {
+    let mut modem = AtClient::new(FakeModem::new(b"\r\nOK\r\n\r\nERROR\r\n\r\n+CME ERROR: 10\r\n\r\n+CMS ERROR: 500\r\n\r\nNO CARRIER\r\n+CME ERROR: SIM not inserted\r\n"));
+
+    assert!(modem.command("ATE0", 100).unwrap().lines().is_empty());
+    assert_eq!(modem.command("AT+FOO", 100), Err(AtError::Error));
+    assert_eq!(modem.command("AT+CPIN?", 100), Err(AtError::CmeError(10)));
+    assert_eq!(modem.command("AT+CMGR=1", 100), Err(AtError::CmsError(500)));
+    assert_eq!(modem.command("ATD+491701234567;", 100), Err(AtError::Error));
+    // Verbose error texts have no code
+    assert_eq!(modem.command("AT+CPIN?", 100), Err(AtError::Error));
+  }
+
+  #[test]
+  fn urcs_are_dispatched() {
+    let script = b"AT+CREG?\r\r\n+CMTI: \"SM\",3\r\n+CREG: 0,1\r\nRING\r\n\r\nOK\r\n\r\n+CMTI: \"SM\",4\r\n+CLIP: 1\r\n";
+    let mut modem = AtClient::new(FakeModem::new(script));
+    modem.on_urc("+CMTI:", record).unwrap();
+    modem.on_urc("RING", record).unwrap();
+    // A handler for the prefix of the command does not get its response
+    modem.on_urc("+CREG:", record).unwrap();
+
+    let response = modem.command("AT+CREG?", 100).unwrap();
+    assert_eq!(response.lines(), ["+CREG: 0,1"]);
+    assert_eq!(urcs(), ["+CMTI: \"SM\",3", "RING"]);
+
+    // Lines without a handler are dropped while polling
+    assert!(modem.remove_urc("RING"));
+    assert!(!modem.remove_urc("RING"));
+    modem.poll().unwrap();
+    assert_eq!(urcs(), ["+CMTI: \"SM\",4"]);
+  }
+
+  #[test]
+  fn handlers_are_limited() {
+    let mut modem = AtClient::new(FakeModem::new(b""));
+    let prefixes = ["A", "B", "C", "D", "E", "F", "G", "H", "I"];
+
+    for prefix in &prefixes[..MAX_HANDLERS] {modem.on_urc(prefix, record).unwrap();}
+    // Replacing a handler does not need space
+    modem.on_urc("A", record).unwrap();
+    assert_eq!(modem.on_urc(prefixes[MAX_HANDLERS], record), Err(AtError::Prog(ProgError::OutOfMemory)));
+  }
+
+  #[test]
+  fn data_is_sent_after_prompt() {
+    let script = b"AT+CIPSEND=5\r\r\nOK\r\n> \r\nRecv 5 bytes\r\n\r\nSEND OK\r\n";
+    let mut modem = AtClient::new(FakeModem::new(script));
+
+    let response = modem.send_data("AT+CIPSEND=5", b"hello", 100).unwrap();
+    assert_eq!(response.lines(), ["Recv 5 bytes"]);
+    assert_eq!(modem.release().sent, b"AT+CIPSEND=5\rhello");
+
+    // An error before the prompt ends the transfer
+    let mut modem = AtClient::new(FakeModem::new(b"\r\n+CMS ERROR: 304\r\n"));
+    assert_eq!(modem.send_data("AT+CMGS=\"+49\"", b"Hi\x1A", 100), Err(AtError::CmsError(304)));
+    assert_eq!(modem.release().sent, b"AT+CMGS=\"+49\"\r");
+
+    let mut modem = AtClient::new(FakeModem::new(b"\r\n> \r\nSEND FAIL\r\n"));
+    assert_eq!(modem.send_data("AT+CIPSEND=2", b"hi", 100), Err(AtError::Error));
+  }
+
+  #[test]
+  fn missing_results_time_out() {
+    let mut modem = AtClient::new(FakeModem::new(b""));
+    assert_eq!(modem.command("AT", 10), Err(AtError::Prog(ProgError::TimedOut)));
+
+    // A response without the final result code
+    let mut modem = AtClient::new(FakeModem::new(b"AT+CSQ\r\r\n+CSQ: 21,0\r\n"));
+    assert_eq!(modem.command("AT+CSQ", 10), Err(AtError::Prog(ProgError::TimedOut)));
+    assert!(modem.port().time.get() >= 10);
+
+    // The deadline lies behind the wrap of millis()
+    let mut modem = AtClient::new(FakeModem::new(b""));
+    modem.port().time.set(usize::MAX - 5);
+    assert_eq!(modem.command("AT", 10), Err(AtError::Prog(ProgError::TimedOut)));
+    assert!(modem.port().time.get() >= 4 && modem.port().time.get() < 10);
+
+    // No prompt
+    let mut modem = AtClient::new(FakeModem::new(b"\r\nOK\r\n"));
+    assert_eq!(modem.send_data("AT+CIPSEND=5", b"hello", 10), Err(AtError::Prog(ProgError::TimedOut)));
+    assert_eq!(modem.release().sent, b"AT+CIPSEND=5\r");
+  }
+}
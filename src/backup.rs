@@ -0,0 +1,187 @@
+//! This module contains everything that is used to keep data through resets and standby.
+//!
+//! There are two kinds of backup memory:
+//!
+//! | Memory           | Size          | Kept during reset and standby | Kept on VBAT | Erased by tamper |
+//! | ---------------- | ------------- | ----------------------------- | ------------ | ---------------- |
+//! | Backup registers | 20 x 32 bits  | Yes                           | Yes          | Yes              |
+//! | Backup SRAM      | 4 KB          | Yes                           | Yes          | No               |
+//!
+//! The backup SRAM only keeps its content in standby and on VBAT while the backup regulator is running, which
+//! [`BackupSram::new()`](crate::backup::BackupSram::new) turns on. Its first bytes hold a header with a checksum, so
+//! after a power loss the content can be detected as invalid.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::backup::{self, BackupSram};
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   // Count the boots in the first backup register
+//!   let boots = backup::read_register(0).unwrap();
+//!   backup::write_register(0, boots + 1).unwrap();
+//!
+//!   // Store a crash marker in the backup SRAM
+//!   let mut sram = BackupSram::new().unwrap();
+//!   if !sram.is_valid() {sram.clear();}
+//!   sram.data_mut()[0] = 0xAA;
+//!   sram.commit();
+//!
+//!   loop {}
+//! }
+//! ```
+
+use crate::framing::crc32;
+use crate::include::ProgError;
+use crate::rtc::enable_backup_access;
+use core::sync::atomic::{AtomicBool, Ordering};
+use rtt_target::rprintln;
+
+/// The number of backup registers.
+pub const BACKUP_REGISTERS: u8 = 20;
+/// The number of bytes in the backup SRAM that are available for data.
+pub const BACKUP_SRAM_CAPACITY: usize = SRAM_SIZE - HEADER_SIZE;
+
+const BKP0R: usize = 0x4000_2850;
+const SRAM_BASE: usize = 0x4002_4000;
+const SRAM_SIZE: usize = 4096;
+// Magic number and CRC-32 of the data
+const HEADER_SIZE: usize = 8;
+const MAGIC: u32 = 0x5255_5354;
+
+static SRAM_TAKEN: AtomicBool = AtomicBool::new(false);
+
+
+// Backup Registers ===============================================================================
+/// Reads one of the 20 backup registers. Returns an error-enum if the index is out of range.
+pub fn read_register(index: u8) -> Result<u32, ProgError> {
+  if index >= BACKUP_REGISTERS {
+    rprintln!("There are only {} backup registers! | backup::read_register()", BACKUP_REGISTERS);
+    return Err(ProgError::InvalidConfiguration);
+  }
+
+  enable_backup_access();
+
+  return Ok(unsafe {core::ptr::read_volatile((BKP0R + 4 * index as usize) as *const u32)});
+}
+
+/// Writes one of the 20 backup registers. Returns an error-enum if the index is out of range.
+pub fn write_register(index: u8, value: u32) -> Result<(), ProgError> {
+  if index >= BACKUP_REGISTERS {
+    rprintln!("There are only {} backup registers! | backup::write_register()", BACKUP_REGISTERS);
+    return Err(ProgError::InvalidConfiguration);
+  }
+
+  enable_backup_access();
+  unsafe {core::ptr::write_volatile((BKP0R + 4 * index as usize) as *mut u32, value);}
+
+  return Ok(());
+}
+
+
+// Backup SRAM ====================================================================================
+/// This struct represents the 4 KB backup SRAM.
+pub struct BackupSram {
+  #[doc(hidden)]
+  _private: ()
+}
+
+impl BackupSram {
+  /// Enables the backup SRAM and the backup regulator that keeps it powered in standby and on VBAT.
+  ///
+  /// Returns an error-enum if the backup SRAM is already in use or the regulator does not start.
+  pub fn new() -> Result<Self, ProgError> {
+    let peripheral_ptr;
+    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+    let rcc = &peripheral_ptr.RCC;
+    let pwr = &peripheral_ptr.PWR;
+
+    if SRAM_TAKEN.swap(true, Ordering::SeqCst) {
+      rprintln!("The backup SRAM is already in use! | BackupSram::new()");
+      return Err(ProgError::AlreadyConfigured);
+    }
+
+    enable_backup_access();
+    // BKPSRAMEN
+    rcc.ahb1enr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 18))});
+
+    // BRE, wait for BRR
+    pwr.csr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 9))});
+    let mut ready = false;
+    for _ in 0..1_000_000 {
+      if pwr.csr.read().bits() & (1 << 3) != 0 {
+        ready = true;
+        break;
+      }
+    }
+
+    if !ready {
+      rprintln!("The backup regulator did not start! | BackupSram::new()");
+      SRAM_TAKEN.store(false, Ordering::SeqCst);
+      return Err(ProgError::TimedOut);
+    }
+
+    return Ok(Self {_private: ()});
+  }
+
+  /// Checks if the header is intact and the checksum matches the data, i.e. the content survived since the last
+  /// [commit](crate::backup::BackupSram::commit).
+  pub fn is_valid(&self) -> bool {
+    let (magic, crc) = self.header();
+    return magic == MAGIC && crc == crc32(self.data());
+  }
+
+  /// Returns the data area of the backup SRAM.
+  pub fn data(&self) -> &[u8] {
+    return unsafe {core::slice::from_raw_parts((SRAM_BASE + HEADER_SIZE) as *const u8, BACKUP_SRAM_CAPACITY)};
+  }
+
+  /// Returns the data area of the backup SRAM for writing. Call [commit](crate::backup::BackupSram::commit)
+  /// afterwards to update the checksum.
+  pub fn data_mut(&mut self) -> &mut [u8] {
+    return unsafe {core::slice::from_raw_parts_mut((SRAM_BASE + HEADER_SIZE) as *mut u8, BACKUP_SRAM_CAPACITY)};
+  }
+
+  /// Updates the header with the checksum of the current data.
+  pub fn commit(&mut self) {
+    let crc = crc32(self.data());
+
+    unsafe {
+      core::ptr::write_volatile(SRAM_BASE as *mut u32, MAGIC);
+      core::ptr::write_volatile((SRAM_BASE + 4) as *mut u32, crc);
+    }
+  }
+
+  /// Sets all data to zero and commits it.
+  pub fn clear(&mut self) {
+    self.data_mut().fill(0);
+    self.commit();
+  }
+
+  /// Turns off the backup regulator and frees the backup SRAM. The content is lost in standby and on VBAT afterwards.
+  pub fn end(self) {
+    let peripheral_ptr;
+    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+    peripheral_ptr.PWR.csr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 9))});
+    drop(self);
+  }
+
+  fn header(&self) -> (u32, u32) {
+    unsafe {
+      return (core::ptr::read_volatile(SRAM_BASE as *const u32), core::ptr::read_volatile((SRAM_BASE + 4) as *const u32));
+    }
+  }
+}
+
+impl Drop for BackupSram {
+  fn drop(&mut self) {
+    SRAM_TAKEN.store(false, Ordering::SeqCst);
+  }
+}
+
@@ -0,0 +1,164 @@
+//! This module contains the shared access to the DMA controllers.
+//!
+//! The peripherals of this crate use these functions to move data without the CPU, e.g. the
+//! [DMA transfers of the UART](crate::uart::UART::write_dma). Every stream can only be used by one peripheral at a
+//! time, so a stream is claimed before it is configured and released afterwards.
+//!
+//! | DMA | Peripherals                       |
+//! | --- | --------------------------------- |
+//! | 1   | USART2, USART3, UART4, UART5      |
+//! | 2   | USART1, USART6                    |
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+const DMA1_BASE: usize = 0x4002_6000;
+const DMA2_BASE: usize = 0x4002_6400;
+
+// SxCR
+const EN: u32 = 1 << 0;
+const DIR_M2P: u32 = 1 << 6;
+const CIRC: u32 = 1 << 8;
+const MINC: u32 = 1 << 10;
+// Transfer complete, half transfer, transfer error, direct mode error and FIFO error
+const FLAGS: u32 = 0x3D;
+const TCIF: u32 = 1 << 5;
+
+// One bit per stream, DMA2 starts at bit 8
+static CLAIMED: AtomicU16 = AtomicU16::new(0);
+
+
+/// Represents the direction of a DMA transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaDirection {
+  PeripheralToMemory, MemoryToPeripheral
+}
+
+/// This struct represents a claimed stream of one of the DMA controllers.
+///
+/// The stream is released when the struct is dropped.
+pub struct DmaStream {
+  #[doc(hidden)]
+  dma: u8,
+  #[doc(hidden)]
+  stream: u8,
+  #[doc(hidden)]
+  channel: u8
+}
+
+impl DmaStream {
+  /// Claims a stream with the channel that selects the peripheral request. Returns `None` if the stream is invalid or
+  /// already used.
+  pub fn claim(dma: u8, stream: u8, channel: u8) -> Option<Self> {
+    let peripheral_ptr;
+    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+    let rcc = &peripheral_ptr.RCC;
+
+    if !(1..=2).contains(&dma) || stream > 7 || channel > 7 {return None;}
+
+    let bit = 1 << ((dma - 1) * 8 + stream);
+    if CLAIMED.fetch_or(bit, Ordering::SeqCst) & bit != 0 {return None;}
+
+    // DMA1EN and DMA2EN
+    rcc.ahb1enr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << (20 + dma)))});
+
+    return Some(Self {dma, stream, channel});
+  }
+
+  /// Configures a byte transfer between a peripheral register and memory and starts it. In circular mode the stream
+  /// starts over at the beginning of the memory when it reaches the end.
+  ///
+  /// # Safety
+  ///
+  /// The memory has to stay valid and must not be accessed in a way that conflicts with the transfer until the stream
+  /// is stopped.
+  pub unsafe fn start(&self, direction: DmaDirection, peripheral: usize, memory: *const u8, len: u16, circular: bool) {
+    self.stop();
+    self.clear_flags();
+
+    self.write(0x08, peripheral as u32);
+    self.write(0x0C, memory as u32);
+    self.write(0x04, len as u32);
+    // Direct mode
+    self.write(0x14, 0);
+
+    let mut cr = ((self.channel as u32) << 25) | MINC;
+    if direction == DmaDirection::MemoryToPeripheral {cr |= DIR_M2P;}
+    if circular {cr |= CIRC;}
+
+    self.write(0x00, cr);
+    self.write(0x00, cr | EN);
+  }
+
+  /// Stops the transfer and waits until the stream is disabled.
+  pub fn stop(&self) {
+    unsafe {
+      self.write(0x00, self.read(0x00) & !EN);
+      while self.read(0x00) & EN != 0 {}
+    }
+  }
+
+  /// Checks if the stream is transferring.
+  pub fn is_running(&self) -> bool {
+    return unsafe {self.read(0x00)} & EN != 0;
+  }
+
+  /// Checks if the stream has transferred all data since the last start.
+  pub fn is_complete(&self) -> bool {
+    return self.flags() & TCIF != 0;
+  }
+
+  /// Returns the number of bytes that are left until the end of the memory.
+  pub fn remaining(&self) -> u16 {
+    return stream_remaining(self.dma, self.stream);
+  }
+
+  /// Returns the event flags of the stream shifted to the position of stream 0.
+  #[doc(hidden)]
+  pub fn flags(&self) -> u32 {
+    let isr = if self.stream < 4 {0x00} else {0x04};
+    return (unsafe {core::ptr::read_volatile((self.base() + isr) as *const u32)} >> self.flag_offset()) & FLAGS;
+  }
+
+  #[doc(hidden)]
+  pub fn clear_flags(&self) {
+    let ifcr = if self.stream < 4 {0x08} else {0x0C};
+    unsafe {core::ptr::write_volatile((self.base() + ifcr) as *mut u32, FLAGS << self.flag_offset());}
+  }
+
+  fn base(&self) -> usize {
+    return if self.dma == 1 {DMA1_BASE} else {DMA2_BASE};
+  }
+
+  fn flag_offset(&self) -> u32 {
+    return match self.stream % 4 {
+      0 => 0,
+      1 => 6,
+      2 => 16,
+      _ => 22
+    };
+  }
+
+  // Offset from SxCR
+  unsafe fn read(&self, offset: usize) -> u32 {
+    return core::ptr::read_volatile((self.base() + 0x10 + 0x18 * self.stream as usize + offset) as *const u32);
+  }
+
+  unsafe fn write(&self, offset: usize, value: u32) {
+    core::ptr::write_volatile((self.base() + 0x10 + 0x18 * self.stream as usize + offset) as *mut u32, value);
+  }
+}
+
+impl Drop for DmaStream {
+  fn drop(&mut self) {
+    self.stop();
+    CLAIMED.fetch_and(!(1 << ((self.dma - 1) * 8 + self.stream)), Ordering::SeqCst);
+  }
+}
+
+
+// Private Functions ==============================================================================
+#[doc(hidden)]
+pub fn stream_remaining(dma: u8, stream: u8) -> u16 {
+  let base = if dma == 1 {DMA1_BASE} else {DMA2_BASE};
+  return unsafe {core::ptr::read_volatile((base + 0x14 + 0x18 * stream as usize) as *const u32)} as u16;
+}
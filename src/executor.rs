@@ -0,0 +1,402 @@
+//! This module contains a small executor to run async functions and the futures to wait for time to pass.
+//!
+//! Other peripherals provide async versions of their blocking functions, like
+//! [`wait_for_edge()`](crate::gpio::Pin::wait_for_edge), [`read_async()`](crate::uart::UART::read_async) and
+//! [`write_read_async()`](crate::i2c::I2C::write_read_async). These futures register their wakers in the interrupts of
+//! the peripheral, so the executor only polls a task again if something happened. In between the microcontroller sleeps.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::executor::{Executor, Timer};
+//! use core::pin::pin;
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   // The timer futures need the millisecond time base
+//!   start_time();
+//!
+//!   let led = pinmode_output(PA5).unwrap();
+//!   let button = pinmode_input(PC13).unwrap();
+//!
+//!   let blink = pin!(async {
+//!     loop {
+//!       digital_write(&led, !digital_state(&led));
+//!       Timer::after(500).await;
+//!     }
+//!   });
+//!
+//!   let press = pin!(async {
+//!     loop {
+//!       button.wait_for_falling_edge().await;
+//!       rprintln!("Button pressed!");
+//!     }
+//!   });
+//!
+//!   let mut executor = Executor::<2>::new();
+//!   executor.spawn(blink).unwrap();
+//!   executor.spawn(press).unwrap();
+//!   executor.run();
+//!
+//!   loop {}
+//! }
+//! ```
+
+use crate::include::ProgError;
+use crate::time::{millis, is_expired};
+use heapless::Vec;
+use cortex_m::interrupt::{Mutex, free};
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+// One bit per task, the highest bit is reserved for block_on
+static READY: AtomicU32 = AtomicU32::new(0);
+const BLOCK_ON_BIT: u32 = 1 << 31;
+
+type TimerWakers = Vec<(usize, Waker), 8>;
+
+static TIMER_WAKERS: Mutex<RefCell<TimerWakers>> = Mutex::new(RefCell::new(Vec::new()));
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(waker_clone, waker_wake, waker_wake, waker_drop);
+
+
+/// Runs up to `N` tasks concurrently on the current core. `N` can be at most 31.
+pub struct Executor<'a, const N: usize> {
+  #[doc(hidden)]
+  tasks: Vec<Option<Pin<&'a mut dyn Future<Output = ()>>>, N>
+}
+
+impl<'a, const N: usize> Executor<'a, N> {
+  /// Creates an empty executor. Specify the maximum number of tasks with the turbofish operator.
+  pub fn new() -> Self {
+    assert!(N < 32, "An executor can hold at most 31 tasks! | Executor::new()");
+    READY.fetch_and(!task_mask(N), Ordering::SeqCst);
+
+    return Self {tasks: Vec::new()};
+  }
+
+  /// Adds a pinned future to the executor. Returns an error-enum if the executor is full.
+  pub fn spawn(&mut self, task: Pin<&'a mut dyn Future<Output = ()>>) -> Result<(), ProgError> {
+    let index = self.tasks.len();
+
+    if self.tasks.push(Some(task)).is_err() {return Err(ProgError::OutOfMemory);}
+    READY.fetch_or(1 << index, Ordering::SeqCst);
+
+    return Ok(());
+  }
+
+  /// Polls every task that was woken since the last call once. Returns true as long as there are unfinished tasks.
+  ///
+  /// This is the part of [`run()`](crate::executor::Executor::run) that does not depend on the hardware, so it can
+  /// also be driven with mock wakers.
+  pub fn poll_ready(&mut self) -> bool {
+    let ready = READY.fetch_and(!task_mask(self.tasks.len()), Ordering::SeqCst);
+
+    for (index, slot) in self.tasks.iter_mut().enumerate() {
+      if ready & (1 << index) == 0 {continue;}
+
+      if let Some(task) = slot {
+        let waker = unsafe {Waker::from_raw(RawWaker::new((1usize << index) as *const (), &VTABLE))};
+        let mut cx = Context::from_waker(&waker);
+        if task.as_mut().poll(&mut cx).is_ready() {*slot = None;}
+      }
+    }
+
+    return self.tasks.iter().any(|slot| slot.is_some());
+  }
+
+  /// Runs all tasks until they are finished. The microcontroller sleeps while no task is ready.
+  pub fn run(&mut self) {
+    while self.poll_ready() {
+      if READY.load(Ordering::SeqCst) & task_mask(self.tasks.len()) == 0 {sleep();}
+    }
+  }
+}
+
+impl<'a, const N: usize> Default for Executor<'a, N> {
+  fn default() -> Self {
+    return Self::new();
+  }
+}
+
+/// Runs a single future to completion and returns its output. The microcontroller sleeps while the future is pending.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+  let mut future = core::pin::pin!(future);
+  let waker = unsafe {Waker::from_raw(RawWaker::new(BLOCK_ON_BIT as usize as *const (), &VTABLE))};
+  let mut cx = Context::from_waker(&waker);
+
+  loop {
+    READY.fetch_and(!BLOCK_ON_BIT, Ordering::SeqCst);
+    if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {return output;}
+    if READY.load(Ordering::SeqCst) & BLOCK_ON_BIT == 0 {sleep();}
+  }
+}
+
+
+/// Storage for the waker of a future that waits for an interrupt.
+///
+/// The future registers its waker before it returns `Poll::Pending` and the interrupt calls
+/// [`wake()`](crate::executor::WakerSlot::wake).
+pub struct WakerSlot {
+  #[doc(hidden)]
+  waker: Mutex<RefCell<Option<Waker>>>
+}
+
+impl WakerSlot {
+  /// Creates an empty slot. Can be used to initialize statics.
+  pub const fn new() -> Self {
+    return Self {waker: Mutex::new(RefCell::new(None))};
+  }
+
+  /// Stores the waker, replacing the previous one.
+  pub fn register(&self, waker: &Waker) {
+    free(|cs| {
+      let mut slot = self.waker.borrow(cs).borrow_mut();
+      match slot.as_ref() {
+        Some(stored) if stored.will_wake(waker) => {},
+        _ => *slot = Some(waker.clone())
+      };
+    });
+  }
+
+  /// Wakes the stored waker, if there is one, and empties the slot.
+  pub fn wake(&self) {
+    if let Some(waker) = free(|cs| self.waker.borrow(cs).borrow_mut().take()) {waker.wake();}
+  }
+}
+
+impl Default for WakerSlot {
+  fn default() -> Self {
+    return Self::new();
+  }
+}
+
+
+/// A future that completes after a number of milliseconds. Needs the time base started with
+/// [`start_time()`](crate::time::start_time).
+///
+/// The deadlines are compared so that they still work when [`millis()`](crate::time::millis) wraps around, as long as
+/// a timer is not set further than half the range of `usize` into the future.
+pub struct Timer {
+  #[doc(hidden)]
+  deadline: usize
+}
+
+impl Timer {
+  /// Creates a timer that expires `ms` milliseconds from now.
+  pub fn after(ms: usize) -> Self {
+    return Self {deadline: millis().wrapping_add(ms)};
+  }
+
+  /// Creates a timer that expires when [`millis()`](crate::time::millis) reaches `deadline`.
+  pub fn at(deadline: usize) -> Self {
+    return Self {deadline};
+  }
+}
+
+impl Future for Timer {
+  type Output = ();
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+    if is_expired(millis(), self.deadline) {return Poll::Ready(());}
+
+    let stored = free(|cs| insert_timer(&mut TIMER_WAKERS.borrow(cs).borrow_mut(), self.deadline, cx.waker()));
+
+    // No free slot, fall back to polling
+    if !stored {cx.waker().wake_by_ref();}
+
+    return Poll::Pending;
+  }
+}
+
+
+// Private Functions ==============================================================================
+#[doc(hidden)]
+pub fn wake_timers(now: usize) {
+  free(|cs| expire_timers(&mut TIMER_WAKERS.borrow(cs).borrow_mut(), now));
+}
+
+// Futures of one task share its waker, so only the earliest deadline is stored. The task polls the other timers
+// again when it is woken and they register their deadlines then.
+fn insert_timer(wakers: &mut TimerWakers, deadline: usize, waker: &Waker) -> bool {
+  if let Some(entry) = wakers.iter_mut().find(|entry| entry.1.will_wake(waker)) {
+    if is_expired(entry.0, deadline) {entry.0 = deadline;}
+    return true;
+  }
+
+  return wakers.push((deadline, waker.clone())).is_ok();
+}
+
+fn expire_timers(wakers: &mut TimerWakers, now: usize) {
+  let mut index = 0;
+  while index < wakers.len() {
+    if is_expired(now, wakers[index].0) {wakers.swap_remove(index).1.wake();}
+    else {index += 1;}
+  }
+}
+
+fn task_mask(n: usize) -> u32 {
+  if n >= 32 {return !0;}
+  return (1 << n) - 1;
+}
+
+fn sleep() {
+  #[cfg(target_arch = "arm")]
+  cortex_m::asm::wfe();
+}
+
+unsafe fn waker_clone(data: *const ()) -> RawWaker {
+  return RawWaker::new(data, &VTABLE);
+}
+
+unsafe fn waker_wake(data: *const ()) {
+  READY.fetch_or(data as usize as u32, Ordering::SeqCst);
+  #[cfg(target_arch = "arm")]
+  cortex_m::asm::sev();
+}
+
+unsafe fn waker_drop(_data: *const ()) {}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use core::cell::Cell;
+  use std::sync::Arc;
+  use std::sync::atomic::AtomicUsize;
+  use std::task::Wake;
+
+  struct MockWaker(AtomicUsize);
+
+  impl Wake for MockWaker {
+    fn wake(self: Arc<Self>) {
+      self.0.fetch_add(1, Ordering::SeqCst);
+    }
+  }
+
+  fn mock_waker() -> (Arc<MockWaker>, Waker) {
+    let mock = Arc::new(MockWaker(AtomicUsize::new(0)));
+    return (mock.clone(), Waker::from(mock));
+  }
+
+  // Pending until it was polled `polls` times, stores the waker of the last poll
+  struct Countdown<'a> {
+    polls: &'a Cell<u32>,
+    waker: &'a RefCell<Option<Waker>>,
+    finish_after: u32
+  }
+
+  impl Future for Countdown<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+      self.polls.set(self.polls.get() + 1);
+      if self.polls.get() >= self.finish_after {return Poll::Ready(());}
+
+      *self.waker.borrow_mut() = Some(cx.waker().clone());
+      return Poll::Pending;
+    }
+  }
+
+  #[test]
+  fn executor_polls_only_woken_tasks() {
+    let (polls_a, polls_b) = (Cell::new(0), Cell::new(0));
+    let (waker_a, waker_b) = (RefCell::new(None), RefCell::new(None));
+    let mut task_a = core::pin::pin!(Countdown {polls: &polls_a, waker: &waker_a, finish_after: 3});
+    let mut task_b = core::pin::pin!(Countdown {polls: &polls_b, waker: &waker_b, finish_after: 2});
+
+    let mut task_c = core::pin::pin!(async {});
+    let mut executor = Executor::<2>::new();
+    executor.spawn(task_a.as_mut()).unwrap();
+    executor.spawn(task_b.as_mut()).unwrap();
+    assert_eq!(executor.spawn(task_c.as_mut()), Err(ProgError::OutOfMemory));
+
+    // Spawned tasks are polled once
+    assert!(executor.poll_ready());
+    assert_eq!((polls_a.get(), polls_b.get()), (1, 1));
+
+    // Nothing was woken
+    assert!(executor.poll_ready());
+    assert_eq!((polls_a.get(), polls_b.get()), (1, 1));
+
+    waker_a.borrow_mut().take().unwrap().wake();
+    assert!(executor.poll_ready());
+    assert_eq!((polls_a.get(), polls_b.get()), (2, 1));
+
+    // A waker that is woken twice polls the task only once
+    let waker = waker_b.borrow_mut().take().unwrap();
+    waker.wake_by_ref();
+    waker.wake();
+    assert!(executor.poll_ready());
+    assert_eq!((polls_a.get(), polls_b.get()), (2, 2));
+
+    waker_a.borrow_mut().take().unwrap().wake();
+    assert!(!executor.poll_ready());
+    assert_eq!((polls_a.get(), polls_b.get()), (3, 2));
+  }
+
+  #[test]
+  fn timers_of_one_task_keep_earliest_deadline() {
+    let mut wakers = TimerWakers::new();
+    let (mock, waker) = mock_waker();
+
+    assert!(insert_timer(&mut wakers, 100, &waker));
+    assert!(insert_timer(&mut wakers, 50, &waker));
+    assert!(insert_timer(&mut wakers, 200, &waker.clone()));
+    assert_eq!(wakers.len(), 1);
+
+    expire_timers(&mut wakers, 49);
+    assert_eq!(mock.0.load(Ordering::SeqCst), 0);
+    expire_timers(&mut wakers, 50);
+    assert_eq!(mock.0.load(Ordering::SeqCst), 1);
+    assert!(wakers.is_empty());
+  }
+
+  #[test]
+  fn timers_wake_only_expired_tasks() {
+    let mut wakers = TimerWakers::new();
+    let (early, early_waker) = mock_waker();
+    let (late, late_waker) = mock_waker();
+
+    insert_timer(&mut wakers, 10, &early_waker);
+    insert_timer(&mut wakers, 20, &late_waker);
+
+    expire_timers(&mut wakers, 15);
+    assert_eq!((early.0.load(Ordering::SeqCst), late.0.load(Ordering::SeqCst)), (1, 0));
+    expire_timers(&mut wakers, 25);
+    assert_eq!((early.0.load(Ordering::SeqCst), late.0.load(Ordering::SeqCst)), (1, 1));
+  }
+
+  #[test]
+  fn timers_reject_when_full() {
+    let mut wakers = TimerWakers::new();
+    let mocks: std::vec::Vec<_> = (0..9).map(|_| mock_waker()).collect();
+
+    for (_, waker) in mocks.iter().take(8) {assert!(insert_timer(&mut wakers, 10, waker));}
+    assert!(!insert_timer(&mut wakers, 10, &mocks[8].1));
+  }
+
+  #[test]
+  fn deadlines_survive_millis_wrap() {
+    let deadline = (usize::MAX - 5).wrapping_add(10);
+
+    assert!(!is_expired(usize::MAX - 5, deadline));
+    assert!(!is_expired(usize::MAX, deadline));
+    assert!(is_expired(4, deadline));
+    assert!(is_expired(100, deadline));
+
+    let mut wakers = TimerWakers::new();
+    let (mock, waker) = mock_waker();
+    insert_timer(&mut wakers, usize::MAX - 1, &waker);
+    insert_timer(&mut wakers, deadline, &waker);
+    expire_timers(&mut wakers, usize::MAX - 1);
+    assert_eq!(mock.0.load(Ordering::SeqCst), 1);
+  }
+}
@@ -0,0 +1,822 @@
+//! This module contains everything that is used to update the firmware over a UART with XMODEM or YMODEM.
+//!
+//! The application [receives](receive_firmware) a new image with XMODEM-CRC, XMODEM-1K or YMODEM (a single file) and
+//! writes it into the staging sector. After the transfer the length and CRC-32 of the image are written into a header
+//! in front of it, followed by a marker. After the next reset a small bootloader [installs](install_update) the marked
+//! image, i.e. verifies it with the header, copies it into the application sector and clears the marker, and then
+//! [jumps](jump_to_application) to the application. If the power fails during the copy, the marker is still set and
+//! the copy is repeated.
+//!
+//! The flash of the STM32F446 is used like this:
+//!
+//! | Sectors | Address     | Size   | Content                                           |
+//! | ------- | ----------- | ------ | ------------------------------------------------- |
+//! | 0 - 4   | 0x0800_0000 | 128 KB | Bootloader                                        |
+//! | 5       | 0x0802_0000 | 128 KB | Application, has to be linked for this address    |
+//! | 6       | 0x0804_0000 | 128 KB | Staging, header and received image                |
+//! | 7       | 0x0806_0000 | 128 KB | Free                                              |
+//!
+//! The protocol is implemented by the [Receiver] state machine, which does not use the hardware and can be tested on
+//! the host.
+//!
+//! # Examples
+//!
+//! The bootloader is a separate program, linked for the start of the flash:
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::firmware::boot;
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   // Only returns if there is no valid application
+//!   let error = boot().unwrap_err();
+//!   rprintln!("Cannot start the application: {:?}", error);
+//!
+//!   loop {}
+//! }
+//! ```
+//!
+//! The application receives the update, e.g. with `sb firmware.bin` or the YMODEM transfer of a terminal program:
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::uart::UART;
+//! use rustuino::firmware::receive_firmware;
+//! use cortex_m::peripheral::SCB;
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   start_time();
+//!   let uart = UART::new(2, PA2, PA3, 115200).unwrap();
+//!
+//!   // Wait up to 60s for the sender
+//!   match receive_firmware(&uart, 60000) {
+//!     Ok(length) => {
+//!       rprintln!("Received {} bytes, restarting...", length);
+//!       SCB::sys_reset();
+//!     },
+//!     Err(error) => rprintln!("Update failed: {:?}", error)
+//!   };
+//!
+//!   loop {}
+//! }
+//! ```
+
+#![allow(clippy::question_mark)]
+
+use crate::include::{FirmwareError, ProgError, SerialError};
+use crate::uart::UART;
+use crate::time::millis;
+use crate::framing::crc32;
+use stm32f4::stm32f446::flash::RegisterBlock;
+use core::convert::Infallible;
+use rtt_target::rprintln;
+
+/// The address of the application.
+pub const APP_ADDRESS: usize = 0x0802_0000;
+/// The address of the staging sector.
+pub const STAGING_ADDRESS: usize = 0x0804_0000;
+/// The size of the header in front of the staged image.
+pub const HEADER_SIZE: usize = 16;
+/// The maximum length of an image.
+pub const MAX_IMAGE: usize = SECTOR_SIZE - HEADER_SIZE;
+
+/// Start of a block with 128 bytes.
+pub const SOH: u8 = 0x01;
+/// Start of a block with 1024 bytes.
+pub const STX: u8 = 0x02;
+/// End of the file.
+pub const EOT: u8 = 0x04;
+/// Block received.
+pub const ACK: u8 = 0x06;
+/// Block damaged, send again.
+pub const NAK: u8 = 0x15;
+/// Cancel the transfer, sent twice.
+pub const CAN: u8 = 0x18;
+/// Start a transfer with CRC-16.
+pub const CRC_REQUEST: u8 = b'C';
+
+const APP_SECTOR: u8 = 5;
+const STAGING_SECTOR: u8 = 6;
+const SECTOR_SIZE: usize = 128 * 1024;
+
+// Header words: length, CRC-32, reserved, marker
+const SWAP_MARKER: u32 = 0x5055_5746;
+const MARKER_OFFSET: usize = 12;
+
+// Consecutive errors before the transfer is cancelled
+const MAX_ERRORS: u8 = 10;
+// Time to wait for the next byte of a block
+const BYTE_TIMEOUT: usize = 1000;
+
+const SRAM_START: u32 = 0x2000_0000;
+const SRAM_END: u32 = 0x2002_0000;
+
+
+/// The header in front of the staged image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+  pub length: u32,
+  /// CRC-32 of the image
+  pub crc: u32
+}
+
+/// Represents the result of a received byte or a timeout. The [reply](Event::reply) has to be sent to the sender.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<'a> {
+  /// Nothing to do but sending the reply, e.g. a NAK for a damaged block
+  Reply(&'static [u8]),
+  /// The YMODEM header block with the length of the file, if it was sent
+  Header(Option<u32>),
+  /// The data of the next block, which has to be stored before the reply is sent. The padding of the last block is
+  /// removed if the length is known from a YMODEM header.
+  Data(&'a [u8]),
+  /// The file was received completely
+  Complete,
+  /// The sender cancelled the transfer
+  Cancelled,
+  /// The transfer failed, the reply cancels it
+  Failed
+}
+
+impl Event<'_> {
+  /// Returns the bytes that have to be sent to the sender.
+  pub fn reply(&self) -> &'static [u8] {
+    return match self {
+      Event::Reply(reply) => reply,
+      Event::Header(_) => &[ACK, CRC_REQUEST],
+      Event::Data(_) | Event::Complete => &[ACK],
+      Event::Cancelled => &[],
+      Event::Failed => &[CAN, CAN]
+    };
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+  Start,
+  Transfer,
+  // YMODEM, the first EOT was answered with a NAK
+  EndOfFile,
+  // YMODEM, waiting for the empty header that ends the batch
+  EndOfBatch,
+  Finished
+}
+
+/// The receiving side of XMODEM-CRC, XMODEM-1K and YMODEM, which is fed with the received bytes.
+///
+/// The protocol is detected with the first block, YMODEM starts with the header block 0. Only the first file of a
+/// YMODEM batch is received, a second one fails the transfer.
+pub struct Receiver {
+  #[doc(hidden)]
+  buffer: [u8; 1029],
+  #[doc(hidden)]
+  len: usize,
+  #[doc(hidden)]
+  state: State,
+  #[doc(hidden)]
+  ymodem: bool,
+  #[doc(hidden)]
+  block: u8,
+  #[doc(hidden)]
+  length: Option<u32>,
+  #[doc(hidden)]
+  received: u32,
+  #[doc(hidden)]
+  errors: u8,
+  #[doc(hidden)]
+  cancels: u8
+}
+
+impl Receiver {
+  /// Creates a receiver that waits for the first block. The sender is started by sending [CRC_REQUEST].
+  pub fn new() -> Self {
+    return Self {
+      buffer: [0; 1029],
+      len: 0,
+      state: State::Start,
+      ymodem: false,
+      block: 1,
+      length: None,
+      received: 0,
+      errors: 0,
+      cancels: 0
+    };
+  }
+
+  /// Processes the next received byte. Returns an event at the end of a block or for a control character.
+  pub fn push(&mut self, byte: u8) -> Option<Event<'_>> {
+    if self.state == State::Finished {return None;}
+
+    if byte == CAN && self.len == 0 {
+      self.cancels += 1;
+      if self.cancels < 2 {return None;}
+
+      self.state = State::Finished;
+      return Some(Event::Cancelled);
+    }
+    self.cancels = 0;
+
+    if self.len == 0 {
+      match byte {
+        SOH | STX => {},
+        EOT => return self.end_of_file(),
+        // Noise between the blocks
+        _ => return None
+      };
+    }
+
+    self.buffer[self.len] = byte;
+    self.len += 1;
+
+    let size = if self.buffer[0] == SOH {128} else {1024};
+    if self.len < size + 5 {return None;}
+
+    self.len = 0;
+    return Some(self.block_received(size));
+  }
+
+  /// Has to be called if nothing was received for about a second. Returns the event that asks the sender for the
+  /// next or the damaged block.
+  pub fn timeout(&mut self) -> Event<'static> {
+    self.len = 0;
+
+    return match self.state {
+      State::Start => Event::Reply(&[CRC_REQUEST]),
+      State::Finished => Event::Reply(&[]),
+      State::EndOfBatch => match self.error() {
+        Event::Reply(_) => Event::Reply(&[CRC_REQUEST]),
+        event => event
+      },
+      _ => self.error()
+    };
+  }
+
+  /// Returns true if the first block was received.
+  pub fn is_started(&self) -> bool {
+    return self.state != State::Start;
+  }
+
+  /// Returns true if the protocol is YMODEM.
+  pub fn is_ymodem(&self) -> bool {
+    return self.ymodem;
+  }
+
+  /// Returns the number of data bytes that were received.
+  pub fn received(&self) -> u32 {
+    return self.received;
+  }
+
+  fn block_received(&mut self, size: usize) -> Event<'_> {
+    let number = self.buffer[1];
+    if number != !self.buffer[2] {return self.error();}
+
+    let crc = u16::from_be_bytes([self.buffer[size + 3], self.buffer[size + 4]]);
+    if crc16(&self.buffer[3..size + 3]) != crc {return self.error();}
+
+    match self.state {
+      State::Start if number == 0 => {
+        self.ymodem = true;
+        self.errors = 0;
+
+        let (name, length) = parse_header(&self.buffer[3..size + 3]);
+        if name.is_empty() {
+          // Empty batch
+          self.state = State::Finished;
+          return Event::Complete;
+        }
+
+        self.state = State::Transfer;
+        self.length = length;
+        return Event::Header(length);
+      },
+      State::Start if number == 1 => self.state = State::Transfer,
+      State::Transfer if number == self.block => {},
+      // Our reply got lost, the sender repeats the block
+      State::Transfer if number == self.block.wrapping_sub(1) => {
+        self.errors = 0;
+        if self.ymodem && self.received == 0 {return Event::Reply(&[ACK, CRC_REQUEST]);}
+        return Event::Reply(&[ACK]);
+      },
+      State::EndOfBatch if number == 0 => {
+        self.state = State::Finished;
+        if parse_header(&self.buffer[3..size + 3]).0.is_empty() {return Event::Complete;}
+
+        rprintln!("Only one file can be received! | Receiver");
+        return Event::Failed;
+      },
+      State::EndOfFile => return self.error(),
+      _ => {
+        rprintln!("Block out of sequence! | Receiver");
+        self.state = State::Finished;
+        return Event::Failed;
+      }
+    };
+
+    self.block = self.block.wrapping_add(1);
+    self.errors = 0;
+
+    let mut len = size;
+    if let Some(length) = self.length {
+      len = len.min(length.saturating_sub(self.received) as usize);
+    }
+    self.received += len as u32;
+
+    return Event::Data(&self.buffer[3..len + 3]);
+  }
+
+  fn end_of_file(&mut self) -> Option<Event<'static>> {
+    return match self.state {
+      State::Transfer if self.ymodem => {
+        // YMODEM senders expect a NAK for the first EOT
+        self.state = State::EndOfFile;
+        Some(Event::Reply(&[NAK]))
+      },
+      State::Transfer => {
+        self.state = State::Finished;
+        Some(Event::Complete)
+      },
+      State::EndOfFile | State::EndOfBatch => {
+        self.state = State::EndOfBatch;
+        self.block = 0;
+        Some(Event::Reply(&[ACK, CRC_REQUEST]))
+      },
+      _ => None
+    };
+  }
+
+  fn error(&mut self) -> Event<'static> {
+    self.errors += 1;
+
+    if self.errors >= MAX_ERRORS {
+      rprintln!("Too many errors! | Receiver");
+      self.state = State::Finished;
+      return Event::Failed;
+    }
+
+    return Event::Reply(&[NAK]);
+  }
+}
+
+impl Default for Receiver {
+  fn default() -> Self {
+    return Self::new();
+  }
+}
+
+
+// Update Functions ===============================================================================
+/// Receives an image with XMODEM or YMODEM, writes it into the staging sector and marks it for the bootloader. The
+/// new firmware is installed after the next reset, e.g. with `cortex_m::peripheral::SCB::sys_reset()`. Returns the
+/// length of the image.
+///
+/// The transfer has to start within the timeout in milliseconds. Needs the time base started with
+/// [`start_time()`](crate::time::start_time). Returns an error-enum if the transfer fails or is cancelled, the image is
+/// too large or the flash cannot be written. A staged image that was not installed yet is lost.
+pub fn receive_firmware(uart: &UART, timeout_ms: usize) -> Result<u32, FirmwareError> {
+  // Erasing takes a few seconds, so it is done before the sender is started
+  if let Err(error) = erase_sector(STAGING_SECTOR) {return Err(error);}
+
+  let mut receiver = Receiver::new();
+  let mut length: usize = 0;
+  let start = millis();
+
+  if let Err(error) = uart.write(CRC_REQUEST) {return Err(FirmwareError::Serial(error));}
+
+  loop {
+    let event = match uart.read_byte_timeout(BYTE_TIMEOUT) {
+      Ok(byte) => match receiver.push(byte) {
+        Some(event) => event,
+        None => continue
+      },
+      Err(SerialError::Prog(ProgError::TimedOut)) => {
+        if !receiver.is_started() && millis().wrapping_sub(start) >= timeout_ms {
+          rprintln!("The transfer did not start in time! | receive_firmware()");
+          return Err(FirmwareError::Prog(ProgError::TimedOut));
+        }
+        receiver.timeout()
+      },
+      // A damaged byte, the block is repeated after its CRC fails or a timeout
+      Err(_) => continue
+    };
+    let reply = event.reply();
+    let complete = event == Event::Complete;
+
+    let result = match event {
+      Event::Header(Some(value)) if value as usize > MAX_IMAGE => Err(FirmwareError::TooLarge),
+      Event::Data(data) if length + data.len() > MAX_IMAGE => Err(FirmwareError::TooLarge),
+      Event::Data(data) => {
+        let result = program(STAGING_ADDRESS + HEADER_SIZE + length, data);
+        length += data.len();
+        result
+      },
+      Event::Cancelled => return Err(FirmwareError::Cancelled),
+      Event::Failed => Err(FirmwareError::Protocol),
+      _ => Ok(())
+    };
+
+    if let Err(error) = result {
+      rprintln!("The transfer is cancelled: {:?} | receive_firmware()", error);
+      uart.write_bytes(&[CAN, CAN]).ok();
+      return Err(error);
+    }
+
+    if let Err(error) = uart.write_bytes(reply) {return Err(FirmwareError::Serial(error));}
+
+    if complete {break;}
+  }
+
+  if length == 0 {
+    rprintln!("The image is empty! | receive_firmware()");
+    return Err(FirmwareError::NoImage);
+  }
+
+  let crc = crc32(staged_image(length));
+
+  let mut header = [0xFF; HEADER_SIZE];
+  header[0..4].copy_from_slice(&(length as u32).to_le_bytes());
+  header[4..8].copy_from_slice(&crc.to_le_bytes());
+  if let Err(error) = program(STAGING_ADDRESS, &header[..MARKER_OFFSET]) {return Err(error);}
+
+  // The marker is written last, so the bootloader never sees an incomplete header
+  if let Err(error) = program(STAGING_ADDRESS + MARKER_OFFSET, &SWAP_MARKER.to_le_bytes()) {return Err(error);}
+
+  return Ok(length as u32);
+}
+
+/// Returns the header of the staged image if it is marked for installation.
+pub fn pending_update() -> Option<ImageHeader> {
+  if read_word(STAGING_ADDRESS + MARKER_OFFSET) != SWAP_MARKER {return None;}
+
+  return Some(ImageHeader {length: read_word(STAGING_ADDRESS), crc: read_word(STAGING_ADDRESS + 4)});
+}
+
+/// Verifies the staged image with its header, copies it into the application sector and clears the marker. Returns
+/// the length of the image.
+///
+/// Returns an error-enum if no image is marked, the image does not match its header or the flash cannot be written.
+/// The marker is kept if the copy fails, so it is tried again after the next reset.
+pub fn install_update() -> Result<u32, FirmwareError> {
+  let header = match pending_update() {
+    Some(value) => value,
+    None => return Err(FirmwareError::NoImage)
+  };
+
+  if header.length == 0 || header.length as usize > MAX_IMAGE {
+    rprintln!("Invalid image length! | install_update()");
+    return Err(FirmwareError::Verify);
+  }
+
+  let image = staged_image(header.length as usize);
+  if crc32(image) != header.crc || !is_vector_table(image) {
+    rprintln!("The staged image is damaged! | install_update()");
+    return Err(FirmwareError::Verify);
+  }
+
+  if let Err(error) = erase_sector(APP_SECTOR) {return Err(error);}
+  if let Err(error) = program(APP_ADDRESS, image) {return Err(error);}
+
+  let app = unsafe {core::slice::from_raw_parts(APP_ADDRESS as *const u8, image.len())};
+  if crc32(app) != header.crc {
+    rprintln!("The installed image is damaged! | install_update()");
+    return Err(FirmwareError::Verify);
+  }
+
+  // Bits can be cleared without erasing the sector
+  if let Err(error) = program(STAGING_ADDRESS + MARKER_OFFSET, &[0; 4]) {return Err(error);}
+
+  return Ok(header.length);
+}
+
+/// Returns true if the application sector starts with a valid vector table.
+pub fn application_valid() -> bool {
+  return is_vector_table(unsafe {core::slice::from_raw_parts(APP_ADDRESS as *const u8, 8)});
+}
+
+/// Starts the application with its stack pointer and reset handler.
+///
+/// # Safety
+///
+/// Has to be called before any peripheral or interrupt is configured, because the application expects the state after
+/// a reset. The application sector has to contain a [valid](application_valid) vector table.
+pub unsafe fn jump_to_application() -> ! {
+  let peripheral_ptr = cortex_m::Peripherals::steal();
+  peripheral_ptr.SCB.vtor.write(APP_ADDRESS as u32);
+
+  cortex_m::asm::bootload(APP_ADDRESS as *const u32);
+}
+
+/// The main function of a bootloader. Installs a pending update and starts the application.
+///
+/// Has to be called at the start of the bootloader, before any peripheral is configured. Only returns an error-enum if
+/// there is no valid application. An update that cannot be installed is reported over RTT and the old application is
+/// started.
+pub fn boot() -> Result<Infallible, FirmwareError> {
+  if pending_update().is_some() {
+    if let Err(error) = install_update() {
+      rprintln!("The update cannot be installed: {:?} | boot()", error);
+    }
+  }
+
+  if !application_valid() {
+    rprintln!("There is no valid application! | boot()");
+    return Err(FirmwareError::NoImage);
+  }
+
+  unsafe {jump_to_application();}
+}
+
+/// Calculates the CRC-16 of XMODEM blocks (polynomial 0x1021, start value 0).
+pub fn crc16(data: &[u8]) -> u16 {
+  let mut crc: u16 = 0;
+
+  for byte in data {
+    crc ^= (*byte as u16) << 8;
+    for _ in 0..8 {
+      if crc & 0x8000 != 0 {crc = (crc << 1) ^ 0x1021;}
+      else {crc <<= 1;}
+    }
+  }
+
+  return crc;
+}
+
+
+// Private Functions ==============================================================================
+// YMODEM header: file name, NUL, decimal length followed by optional fields
+fn parse_header(data: &[u8]) -> (&[u8], Option<u32>) {
+  let name_end = data.iter().position(|i| *i == 0).unwrap_or(data.len());
+  let rest = data.get(name_end + 1..).unwrap_or(&[]);
+
+  let digits = rest.iter().take_while(|i| i.is_ascii_digit()).count();
+  let length = core::str::from_utf8(&rest[..digits]).ok().and_then(|i| i.parse().ok());
+
+  return (&data[..name_end], length);
+}
+
+// The first words are the initial stack pointer and the reset handler in thumb mode
+fn is_vector_table(image: &[u8]) -> bool {
+  if image.len() < 8 {return false;}
+
+  let stack = u32::from_le_bytes([image[0], image[1], image[2], image[3]]);
+  let reset = u32::from_le_bytes([image[4], image[5], image[6], image[7]]);
+
+  return stack > SRAM_START && stack <= SRAM_END && reset & 1 == 1 &&
+    (reset as usize) >= APP_ADDRESS && (reset as usize) < APP_ADDRESS + SECTOR_SIZE;
+}
+
+fn staged_image(length: usize) -> &'static [u8] {
+  return unsafe {core::slice::from_raw_parts((STAGING_ADDRESS + HEADER_SIZE) as *const u8, length)};
+}
+
+fn read_word(address: usize) -> u32 {
+  return unsafe {core::ptr::read_volatile(address as *const u32)};
+}
+
+fn erase_sector(sector: u8) -> Result<(), FirmwareError> {
+  return flash_operation(|flash| {
+    flash.cr.write(|w| unsafe {w.ser().sector_erase().psize().psize32().snb().bits(sector)});
+    flash.cr.modify(|_, w| w.strt().start());
+  });
+}
+
+// Byte by byte, so the data does not have to be aligned
+fn program(address: usize, data: &[u8]) -> Result<(), FirmwareError> {
+  return flash_operation(|flash| {
+    flash.cr.write(|w| w.pg().program().psize().psize8());
+
+    for (index, byte) in data.iter().enumerate() {
+      unsafe {core::ptr::write_volatile((address + index) as *mut u8, *byte);}
+      while flash.sr.read().bsy().bit_is_set() {}
+    }
+  });
+}
+
+fn flash_operation<F: FnOnce(&RegisterBlock)>(operation: F) -> Result<(), FirmwareError> {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let flash = &peripheral_ptr.FLASH;
+
+  while flash.sr.read().bsy().bit_is_set() {}
+
+  if flash.cr.read().lock().is_locked() {
+    flash.keyr.write(|w| w.key().bits(0x4567_0123));
+    flash.keyr.write(|w| w.key().bits(0xCDEF_89AB));
+  }
+  // The error flags are cleared by writing 1
+  flash.sr.write(|w| w.operr().set_bit().wrperr().set_bit().pgaerr().set_bit().pgperr().set_bit().pgserr().set_bit());
+
+  operation(flash);
+
+  while flash.sr.read().bsy().bit_is_set() {}
+  let sr = flash.sr.read();
+  let failed = sr.operr().bit_is_set() || sr.wrperr().bit_is_set() || sr.pgaerr().bit_is_set() ||
+    sr.pgperr().bit_is_set() || sr.pgserr().bit_is_set();
+
+  flash.cr.write(|w| w.lock().locked());
+
+  if failed {
+    rprintln!("Flash error {:#x}! | flash_operation()", sr.bits());
+    return Err(FirmwareError::Flash);
+  }
+
+  return Ok(());
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A block with SOH for 128 bytes or STX for 1024 bytes, the data is padded with the fill byte
+  fn block(number: u8, size: usize, data: &[u8], fill: u8) -> std::vec::Vec<u8> {
+    let mut payload = data.to_vec();
+    payload.resize(size, fill);
+
+    let mut block = std::vec![if size == 128 {SOH} else {STX}, number, !number];
+    block.extend_from_slice(&payload);
+    block.extend_from_slice(&crc16(&payload).to_be_bytes());
+    return block;
+  }
+
+  fn header(content: &[u8]) -> std::vec::Vec<u8> {
+    return block(0, 128, content, 0);
+  }
+
+  // Returns the events of the bytes, the data is copied out of the receiver
+  fn feed(receiver: &mut Receiver, bytes: &[u8]) -> std::vec::Vec<Event<'static>> {
+    let mut events = std::vec::Vec::new();
+
+    for byte in bytes {
+      let event = match receiver.push(*byte) {
+        Some(Event::Data(data)) => Event::Data(data.to_vec().leak()),
+        Some(Event::Reply(reply)) => Event::Reply(reply),
+        Some(Event::Header(length)) => Event::Header(length),
+        Some(Event::Complete) => Event::Complete,
+        Some(Event::Cancelled) => Event::Cancelled,
+        Some(Event::Failed) => Event::Failed,
+        None => continue
+      };
+      events.push(event);
+    }
+
+    return events;
+  }
+
+  fn counting(len: usize) -> std::vec::Vec<u8> {
+    return (0..len).map(|i| i as u8).collect();
+  }
+
+  #[test]
+  fn crc_check_value() {
+    assert_eq!(crc16(b"123456789"), 0x31C3);
+    assert_eq!(crc16(&[]), 0);
+  }
+
+  #[test]
+  fn headers_are_parsed() {
+    assert_eq!(parse_header(b"fw.bin\x001300 14567022421 100644\x00\x00"), (&b"fw.bin"[..], Some(1300)));
+    assert_eq!(parse_header(b"fw.bin\x00\x00\x00"), (&b"fw.bin"[..], None));
+    assert_eq!(parse_header(b"fw.bin"), (&b"fw.bin"[..], None));
+    assert_eq!(parse_header(&[0; 128]), (&b""[..], None));
+    // Too long for the length
+    assert_eq!(parse_header(b"a\x0099999999999\x00"), (&b"a"[..], None));
+  }
+
+  #[test]
+  fn xmodem_blocks() {
+    let mut receiver = Receiver::new();
+    assert_eq!(receiver.timeout(), Event::Reply(&[CRC_REQUEST]));
+    assert!(!receiver.is_started());
+
+    let first = counting(128);
+    let second = counting(1024);
+    // Noise before the first block is ignored
+    let mut bytes = std::vec![0xFF, b'C'];
+    bytes.extend(block(1, 128, &first, 0x1A));
+    bytes.extend(block(2, 1024, &second, 0x1A));
+    bytes.push(EOT);
+
+    let events = feed(&mut receiver, &bytes);
+    assert_eq!(events, [Event::Data(&first), Event::Data(&second), Event::Complete]);
+    assert_eq!(events.iter().map(|i| i.reply()).collect::<std::vec::Vec<_>>(), [&[ACK][..], &[ACK], &[ACK]]);
+    assert!(receiver.is_started());
+    assert!(!receiver.is_ymodem());
+    assert_eq!(receiver.received(), 1152);
+
+    // Bytes after the end are ignored
+    assert!(feed(&mut receiver, &block(3, 128, &[], 0x1A)).is_empty());
+  }
+
+  #[test]
+  fn repeated_block_is_acknowledged() {
+    let mut receiver = Receiver::new();
+
+    let mut bytes = block(1, 128, b"one", 0x1A);
+    // Our ACK got lost
+    bytes.extend(block(1, 128, b"one", 0x1A));
+    bytes.extend(block(2, 128, b"two", 0x1A));
+
+    let events = feed(&mut receiver, &bytes);
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[1], Event::Reply(&[ACK]));
+    assert!(matches!(events[2], Event::Data(data) if data.starts_with(b"two")));
+    assert_eq!(receiver.received(), 256);
+
+    // A block that was skipped fails the transfer
+    assert_eq!(feed(&mut receiver, &block(4, 128, b"four", 0x1A)), [Event::Failed]);
+    assert_eq!(Event::Failed.reply(), [CAN, CAN]);
+  }
+
+  #[test]
+  fn damaged_blocks_are_rejected() {
+    let mut receiver = Receiver::new();
+
+    let mut damaged = block(1, 128, b"data", 0x1A);
+    damaged[10] ^= 0x01;
+    let mut number = block(1, 128, b"data", 0x1A);
+    number[2] = 0x00;
+
+    assert_eq!(feed(&mut receiver, &damaged), [Event::Reply(&[NAK])]);
+    assert_eq!(feed(&mut receiver, &number), [Event::Reply(&[NAK])]);
+
+    // A good block resets the errors
+    assert_eq!(feed(&mut receiver, &block(1, 128, b"data", 0x1A)).len(), 1);
+    assert_eq!(receiver.timeout(), Event::Reply(&[NAK]));
+
+    for _ in 0..MAX_ERRORS - 2 {
+      assert_eq!(feed(&mut receiver, &damaged), [Event::Reply(&[NAK])]);
+    }
+    assert_eq!(feed(&mut receiver, &damaged), [Event::Failed]);
+    assert!(feed(&mut receiver, &block(2, 128, b"data", 0x1A)).is_empty());
+  }
+
+  #[test]
+  fn ymodem_file() {
+    let mut receiver = Receiver::new();
+    let image = counting(1300);
+
+    let mut bytes = header(b"fw.bin\x001300 14567022421 100644");
+    // Our reply to the header got lost
+    bytes.extend(header(b"fw.bin\x001300 14567022421 100644"));
+    bytes.extend(block(1, 1024, &image[..1024], 0x1A));
+    bytes.extend(block(2, 1024, &image[1024..], 0x1A));
+    bytes.extend([EOT, EOT]);
+    bytes.extend(header(&[]));
+
+    let events = feed(&mut receiver, &bytes);
+    assert_eq!(events, [
+      Event::Header(Some(1300)),
+      Event::Reply(&[ACK, CRC_REQUEST]),
+      Event::Data(&image[..1024]),
+      // The padding is removed with the length of the header
+      Event::Data(&image[1024..]),
+      Event::Reply(&[NAK]),
+      Event::Reply(&[ACK, CRC_REQUEST]),
+      Event::Complete
+    ]);
+    assert_eq!(Event::Header(None).reply(), [ACK, CRC_REQUEST]);
+    assert!(receiver.is_ymodem());
+    assert_eq!(receiver.received(), 1300);
+  }
+
+  #[test]
+  fn ymodem_end_of_batch() {
+    // An empty header right away is an empty batch
+    let mut receiver = Receiver::new();
+    assert_eq!(feed(&mut receiver, &header(&[])), [Event::Complete]);
+    assert_eq!(receiver.received(), 0);
+
+    // A second file is not received
+    let mut receiver = Receiver::new();
+    let mut bytes = header(b"a.bin\x0010");
+    bytes.extend(block(1, 128, b"0123456789", 0x1A));
+    bytes.extend([EOT, EOT]);
+    assert_eq!(feed(&mut receiver, &bytes).len(), 4);
+
+    // The header that ends the batch is requested again after a timeout
+    assert_eq!(receiver.timeout(), Event::Reply(&[CRC_REQUEST]));
+    assert_eq!(feed(&mut receiver, &header(b"b.bin\x0010")), [Event::Failed]);
+  }
+
+  #[test]
+  fn double_can_cancels() {
+    let mut receiver = Receiver::new();
+
+    // A single CAN is ignored, CAN inside a block is data
+    assert!(feed(&mut receiver, &[CAN]).is_empty());
+    assert_eq!(feed(&mut receiver, &block(1, 128, &[CAN, CAN], 0x1A)).len(), 1);
+
+    assert_eq!(feed(&mut receiver, &[CAN, CAN]), [Event::Cancelled]);
+    assert_eq!(Event::Cancelled.reply(), [0; 0]);
+    assert!(feed(&mut receiver, &block(2, 128, &[], 0x1A)).is_empty());
+    assert_eq!(receiver.timeout(), Event::Reply(&[]));
+  }
+}
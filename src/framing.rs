@@ -0,0 +1,647 @@
+//! This module contains everything that is used to send binary packets over a serial connection.
+//!
+//! A byte stream has no packet boundaries, so every packet is encoded in a way that a delimiter byte never appears
+//! inside it. After a lost byte the receiver loses only the current packet and continues with the next delimiter.
+//!
+//! | Encoding | Delimiter        | Overhead                            |
+//! | -------- | ---------------- | ----------------------------------- |
+//! | COBS     | 0x00 after frame | 1 byte per 254 bytes, plus 2 bytes  |
+//! | SLIP     | 0xC0 around      | Up to 1 byte per byte, plus 2 bytes |
+//!
+//! A CRC trailer can be appended to every packet, it is sent with the low byte first:
+//!
+//! | Trailer | Algorithm                              | Check value of "123456789" |
+//! | ------- | -------------------------------------- | -------------------------- |
+//! | Crc16   | CRC-16/CCITT-FALSE (0x1021, init FFFF) | 0x29B1                     |
+//! | Crc32   | CRC-32 as in Ethernet and zlib         | 0xCBF43926                 |
+//!
+//! The [PacketPort] sends and receives packets over a [UART](crate::uart::UART). The [Decoder] can also be fed byte by
+//! byte, e.g. from an RX interrupt. The encoders and decoders do not use the hardware, so they can be tested on the
+//! host.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::uart::UART;
+//! use rustuino::framing::{PacketPort, Encoding, Trailer};
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   start_time();
+//!   let uart = UART::new(2, PA2, PA3, 115200).unwrap();
+//!   let mut port = PacketPort::<64>::new(uart, Encoding::Cobs, Trailer::Crc16).unwrap();
+//!
+//!   loop {
+//!     // Send every packet back with the bytes in reverse order
+//!     if let Some(Ok(packet)) = port.receive() {
+//!       let mut answer: Vec<u8, 64> = Vec::from_slice(packet).unwrap();
+//!       answer.reverse();
+//!       port.send(&answer).unwrap();
+//!     }
+//!   }
+//! }
+//! ```
+
+#![allow(clippy::question_mark)]
+
+use crate::include::{FramingError, ProgError, SerialError};
+use crate::uart::UART;
+use crate::time::is_time_started;
+use heapless::Vec;
+use rtt_target::rprintln;
+
+// SLIP special bytes
+const END: u8 = 0xC0;
+const ESC: u8 = 0xDB;
+const ESC_END: u8 = 0xDC;
+const ESC_ESC: u8 = 0xDD;
+
+// Longest COBS block: code byte and 254 data bytes
+const COBS_BLOCK: usize = 255;
+
+
+/// Represents the encoding that separates the packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+  /// Consistent Overhead Byte Stuffing, every packet ends with 0x00
+  Cobs,
+  /// Serial Line Internet Protocol (RFC 1055), every packet starts and ends with 0xC0
+  Slip
+}
+
+/// Represents the checksum that is appended to every packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trailer {
+  None, Crc16, Crc32
+}
+
+impl Trailer {
+  /// Returns the number of bytes of the trailer.
+  pub fn len(&self) -> usize {
+    return match self {
+      Trailer::None => 0,
+      Trailer::Crc16 => 2,
+      Trailer::Crc32 => 4
+    };
+  }
+
+  /// Checks if the trailer has no bytes.
+  pub fn is_empty(&self) -> bool {
+    return self.len() == 0;
+  }
+
+  /// Returns the trailer of the data.
+  pub fn calculate(&self, data: &[u8]) -> Vec<u8, 4> {
+    return match self {
+      Trailer::None => Vec::new(),
+      Trailer::Crc16 => Vec::from_slice(&crc16(data).to_le_bytes()).unwrap(),
+      Trailer::Crc32 => Vec::from_slice(&crc32(data).to_le_bytes()).unwrap()
+    };
+  }
+}
+
+
+/// A decoder that is fed with the received bytes one by one and returns the packets with checked trailer.
+///
+/// Specify the maximum packet length including the trailer with the turbofish operator. Longer packets are dropped
+/// and reported at their end. Empty packets are ignored, so additional delimiters between packets do no harm.
+pub struct Decoder<const N: usize> {
+  #[doc(hidden)]
+  encoding: Encoding,
+  #[doc(hidden)]
+  trailer: Trailer,
+  #[doc(hidden)]
+  buffer: Vec<u8, N>,
+  #[doc(hidden)]
+  error: Option<FramingError>,
+  #[doc(hidden)]
+  complete: bool,
+  // COBS: code byte of the current block, the data bytes that are left and a zero that belongs in front of the next
+  // block. SLIP: escape byte received.
+  #[doc(hidden)]
+  code: u8,
+  #[doc(hidden)]
+  remaining: u8,
+  #[doc(hidden)]
+  pending_zero: bool,
+  #[doc(hidden)]
+  escaped: bool
+}
+
+impl<const N: usize> Decoder<N> {
+  /// Creates a decoder for the encoding and the trailer.
+  pub fn new(encoding: Encoding, trailer: Trailer) -> Self {
+    return Self {
+      encoding,
+      trailer,
+      buffer: Vec::new(),
+      error: None,
+      complete: false,
+      code: 0,
+      remaining: 0,
+      pending_zero: false,
+      escaped: false
+    };
+  }
+
+  /// Processes the next received byte. Returns the packet without trailer if the byte completed one, or an error-enum
+  /// if the completed packet was too long, damaged or its trailer does not match.
+  ///
+  /// The packet stays valid until the next byte is pushed.
+  pub fn push(&mut self, byte: u8) -> Option<Result<&[u8], FramingError>> {
+    return match self.feed(byte)? {
+      Ok(len) => Some(Ok(&self.buffer[..len])),
+      Err(error) => Some(Err(error))
+    };
+  }
+
+  /// Drops the packet that is received at the moment.
+  pub fn reset(&mut self) {
+    self.buffer.clear();
+    self.error = None;
+    self.complete = false;
+    self.remaining = 0;
+    self.pending_zero = false;
+    self.escaped = false;
+  }
+
+  #[doc(hidden)]
+  pub fn feed(&mut self, byte: u8) -> Option<Result<usize, FramingError>> {
+    if self.complete {self.reset();}
+
+    match self.encoding {
+      Encoding::Cobs => {
+        if byte == 0 {
+          if self.remaining != 0 && self.error.is_none() {self.error = Some(FramingError::Encoding);}
+          return self.finish();
+        }
+
+        if self.remaining == 0 {
+          // Code byte, the number of data bytes in the block plus one
+          if self.pending_zero {self.store(0);}
+          self.code = byte;
+          self.remaining = byte - 1;
+        }
+        else {
+          self.store(byte);
+          self.remaining -= 1;
+        }
+
+        // Blocks of 254 data bytes are not followed by a zero
+        if self.remaining == 0 {self.pending_zero = self.code != 0xFF;}
+      },
+      Encoding::Slip => {
+        if byte == END {return self.finish();}
+
+        if self.escaped {
+          self.escaped = false;
+
+          match byte {
+            ESC_END => self.store(END),
+            ESC_ESC => self.store(ESC),
+            _ => {if self.error.is_none() {self.error = Some(FramingError::Encoding);}}
+          };
+        }
+        else if byte == ESC {self.escaped = true;}
+        else {self.store(byte);}
+      }
+    };
+
+    return None;
+  }
+
+  fn store(&mut self, byte: u8) {
+    if self.buffer.push(byte).is_err() && self.error.is_none() {self.error = Some(FramingError::TooLong);}
+  }
+
+  fn finish(&mut self) -> Option<Result<usize, FramingError>> {
+    if let Some(error) = self.error.take() {
+      self.reset();
+      return Some(Err(error));
+    }
+
+    if self.buffer.is_empty() {
+      self.reset();
+      return None;
+    }
+
+    self.complete = true;
+
+    return match check_trailer(self.trailer, &self.buffer) {
+      Ok(len) => Some(Ok(len)),
+      Err(error) => Some(Err(error))
+    };
+  }
+}
+
+
+/// This struct represents a connection that sends and receives packets over a UART.
+///
+/// Specify the maximum packet length including the trailer with the turbofish operator.
+pub struct PacketPort<const N: usize> {
+  #[doc(hidden)]
+  uart: UART,
+  #[doc(hidden)]
+  decoder: Decoder<N>
+}
+
+impl<const N: usize> PacketPort<N> {
+  /// Uses the UART for packets with the encoding and the trailer. Needs the time base started with
+  /// [`start_time()`](crate::time::start_time), otherwise an error-enum is returned.
+  pub fn new(uart: UART, encoding: Encoding, trailer: Trailer) -> Result<Self, ProgError> {
+    if !is_time_started() {
+      rprintln!("The time base is not started! | PacketPort::new()");
+      return Err(ProgError::NotConfigured);
+    }
+
+    return Ok(Self {uart, decoder: Decoder::new(encoding, trailer)});
+  }
+
+  /// Encodes and sends a packet with its trailer. Returns an error-enum if the packet with the trailer is longer than
+  /// the maximum packet length or problems with the connection are detected.
+  pub fn send(&self, data: &[u8]) -> Result<(), FramingError> {
+    let trailer = self.decoder.trailer.calculate(data);
+
+    if data.len() + trailer.len() > N {
+      rprintln!("The packet is too long! | .send()");
+      return Err(FramingError::TooLong);
+    }
+
+    return encode_with(self.decoder.encoding, data.iter().chain(trailer.iter()).copied(), |bytes| {
+      return self.uart.write_bytes(bytes).map_err(FramingError::Serial);
+    });
+  }
+
+  /// Processes the bytes that were received since the last call. Returns a packet as soon as one is complete, an
+  /// error-enum if a damaged packet or a connection problem was detected, or `None` if no packet is complete yet.
+  ///
+  /// The packet stays valid until the next call.
+  pub fn receive(&mut self) -> Option<Result<&[u8], FramingError>> {
+    let result = loop {
+      let byte = match self.uart.read_byte_timeout(0) {
+        Ok(value) => value,
+        Err(SerialError::Prog(ProgError::TimedOut)) => return None,
+        Err(error) => {
+          self.decoder.reset();
+          return Some(Err(FramingError::Serial(error)));
+        }
+      };
+
+      if let Some(result) = self.decoder.feed(byte) {break result;}
+    };
+
+    return match result {
+      Ok(len) => Some(Ok(&self.decoder.buffer[..len])),
+      Err(error) => Some(Err(error))
+    };
+  }
+
+  /// Waits until a packet is complete, at most the timeout in milliseconds between two bytes. Returns an error-enum
+  /// like [receive](crate::framing::PacketPort::receive) or if the timeout expired.
+  pub fn receive_timeout(&mut self, timeout_ms: usize) -> Result<&[u8], FramingError> {
+    let result = loop {
+      let byte = match self.uart.read_byte_timeout(timeout_ms) {
+        Ok(value) => value,
+        Err(error) => {
+          self.decoder.reset();
+          return Err(FramingError::Serial(error));
+        }
+      };
+
+      if let Some(result) = self.decoder.feed(byte) {break result;}
+    };
+
+    return match result {
+      Ok(len) => Ok(&self.decoder.buffer[..len]),
+      Err(error) => Err(error)
+    };
+  }
+
+  /// Returns the UART.
+  pub fn release(self) -> UART {
+    return self.uart;
+  }
+}
+
+
+// Encoding Functions =============================================================================
+/// Calculates the CRC-16/CCITT-FALSE of the data.
+pub fn crc16(data: &[u8]) -> u16 {
+  let mut crc: u16 = 0xFFFF;
+
+  for byte in data {
+    crc ^= (*byte as u16) << 8;
+    for _ in 0..8 {
+      if crc & 0x8000 != 0 {crc = (crc << 1) ^ 0x1021;}
+      else {crc <<= 1;}
+    }
+  }
+
+  return crc;
+}
+
+/// Calculates the CRC-32 of the data, like Ethernet and zlib.
+pub fn crc32(data: &[u8]) -> u32 {
+  let mut crc: u32 = 0xFFFF_FFFF;
+
+  for byte in data {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      if crc & 1 != 0 {crc = (crc >> 1) ^ 0xEDB8_8320;}
+      else {crc >>= 1;}
+    }
+  }
+
+  return !crc;
+}
+
+/// Returns the maximum length of an encoded packet with `len` bytes including the delimiters.
+pub fn max_encoded_len(encoding: Encoding, len: usize) -> usize {
+  return match encoding {
+    Encoding::Cobs => len + len / 254 + 2,
+    Encoding::Slip => 2 * len + 2
+  };
+}
+
+/// Encodes a packet with its trailer and the delimiters into the buffer. Returns the length of the encoded packet or
+/// an error-enum if the buffer is too small.
+pub fn encode(encoding: Encoding, trailer: Trailer, data: &[u8], buffer: &mut [u8]) -> Result<usize, FramingError> {
+  let trailer = trailer.calculate(data);
+  let mut len = 0;
+
+  let result = encode_with(encoding, data.iter().chain(trailer.iter()).copied(), |bytes| {
+    if len + bytes.len() > buffer.len() {return Err(FramingError::TooLong);}
+
+    buffer[len..len + bytes.len()].copy_from_slice(bytes);
+    len += bytes.len();
+    return Ok(());
+  });
+
+  return result.map(|_| len);
+}
+
+/// Decodes a complete packet, the delimiters are optional. Checks and removes the trailer and returns the length of
+/// the packet in the buffer. Returns an error-enum if the buffer is too small, the packet is damaged or the trailer
+/// does not match.
+pub fn decode(encoding: Encoding, trailer: Trailer, data: &[u8], buffer: &mut [u8]) -> Result<usize, FramingError> {
+  let result = match encoding {
+    Encoding::Cobs => cobs_decode(data, buffer),
+    Encoding::Slip => slip_decode(data, buffer)
+  };
+
+  return match result {
+    Ok(len) => check_trailer(trailer, &buffer[..len]),
+    Err(error) => Err(error)
+  };
+}
+
+
+// Private Functions ==============================================================================
+// Passes the encoded packet in pieces to the sink
+fn encode_with<I, F>(encoding: Encoding, bytes: I, mut sink: F) -> Result<(), FramingError>
+where I: Iterator<Item = u8>, F: FnMut(&[u8]) -> Result<(), FramingError> {
+  match encoding {
+    Encoding::Cobs => {
+      let mut block = [0; COBS_BLOCK];
+      let mut len = 1;
+      let mut full = false;
+
+      for byte in bytes {
+        full = false;
+        if byte != 0 {
+          block[len] = byte;
+          len += 1;
+          if len < COBS_BLOCK {continue;}
+          full = true;
+        }
+
+        // The code byte is the distance to the next zero
+        block[0] = len as u8;
+        if let Err(error) = sink(&block[..len]) {return Err(error);}
+        len = 1;
+      }
+
+      // A block of 254 data bytes at the end is not followed by an empty block
+      if full {return sink(&[0]);}
+
+      block[0] = len as u8;
+      block[len] = 0;
+      return sink(&block[..len + 1]);
+    },
+    Encoding::Slip => {
+      if let Err(error) = sink(&[END]) {return Err(error);}
+
+      for byte in bytes {
+        let result = match byte {
+          END => sink(&[ESC, ESC_END]),
+          ESC => sink(&[ESC, ESC_ESC]),
+          _ => sink(&[byte])
+        };
+        if let Err(error) = result {return Err(error);}
+      }
+
+      return sink(&[END]);
+    }
+  };
+}
+
+fn cobs_decode(data: &[u8], buffer: &mut [u8]) -> Result<usize, FramingError> {
+  let mut index = 0;
+  let mut len = 0;
+
+  while index < data.len() && data[index] != 0 {
+    let code = data[index] as usize;
+    let block = match data.get(index + 1..index + code) {
+      Some(block) if !block.contains(&0) => block,
+      _ => return Err(FramingError::Encoding)
+    };
+
+    if len + block.len() > buffer.len() {return Err(FramingError::TooLong);}
+    buffer[len..len + block.len()].copy_from_slice(block);
+    len += block.len();
+    index += code;
+
+    // Blocks of 254 data bytes and the last block are not followed by a zero
+    if code != 0xFF && index < data.len() && data[index] != 0 {
+      if len == buffer.len() {return Err(FramingError::TooLong);}
+      buffer[len] = 0;
+      len += 1;
+    }
+  }
+
+  return Ok(len);
+}
+
+fn slip_decode(data: &[u8], buffer: &mut [u8]) -> Result<usize, FramingError> {
+  let mut escaped = false;
+  let mut len = 0;
+
+  for byte in data {
+    let value = match (escaped, *byte) {
+      (false, END) => continue,
+      (false, ESC) => {
+        escaped = true;
+        continue;
+      },
+      (false, value) => value,
+      (true, ESC_END) => END,
+      (true, ESC_ESC) => ESC,
+      (true, _) => return Err(FramingError::Encoding)
+    };
+    escaped = false;
+
+    if len == buffer.len() {return Err(FramingError::TooLong);}
+    buffer[len] = value;
+    len += 1;
+  }
+
+  if escaped {return Err(FramingError::Encoding);}
+
+  return Ok(len);
+}
+
+// Returns the length without trailer
+fn check_trailer(trailer: Trailer, packet: &[u8]) -> Result<usize, FramingError> {
+  if packet.len() < trailer.len() {return Err(FramingError::Crc);}
+
+  let len = packet.len() - trailer.len();
+  if *trailer.calculate(&packet[..len]) != packet[len..] {return Err(FramingError::Crc);}
+
+  return Ok(len);
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn encoded(encoding: Encoding, trailer: Trailer, data: &[u8]) -> std::vec::Vec<u8> {
+    let mut buffer = [0; 1024];
+    let len = encode(encoding, trailer, data, &mut buffer).unwrap();
+    assert!(len <= max_encoded_len(encoding, data.len() + trailer.len()));
+    return buffer[..len].to_vec();
+  }
+
+  // Decodes with decode() and with a Decoder that is fed byte by byte
+  fn round_trip(encoding: Encoding, trailer: Trailer, data: &[u8]) {
+    let packet = encoded(encoding, trailer, data);
+
+    let mut buffer = [0; 1024];
+    let len = decode(encoding, trailer, &packet, &mut buffer).unwrap();
+    assert_eq!(&buffer[..len], data);
+
+    let mut decoder = Decoder::<1024>::new(encoding, trailer);
+    let (last, bytes) = packet.split_last().unwrap();
+    for byte in bytes {assert!(decoder.push(*byte).is_none());}
+    assert_eq!(decoder.push(*last), Some(Ok(data)));
+  }
+
+  fn counting(len: usize) -> std::vec::Vec<u8> {
+    return (0..len).map(|i| (i % 255 + 1) as u8).collect();
+  }
+
+  #[test]
+  fn crc_check_values() {
+    assert_eq!(crc16(b"123456789"), 0x29B1);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+  }
+
+  #[test]
+  fn cobs_known_vectors() {
+    assert_eq!(encoded(Encoding::Cobs, Trailer::None, &[0x00]), [0x01, 0x01, 0x00]);
+    assert_eq!(encoded(Encoding::Cobs, Trailer::None, &[0x00, 0x00]), [0x01, 0x01, 0x01, 0x00]);
+    assert_eq!(encoded(Encoding::Cobs, Trailer::None, &[0x00, 0x11, 0x00]), [0x01, 0x02, 0x11, 0x01, 0x00]);
+    assert_eq!(encoded(Encoding::Cobs, Trailer::None, &[0x11, 0x22, 0x00, 0x33]), [0x03, 0x11, 0x22, 0x02, 0x33, 0x00]);
+    assert_eq!(encoded(Encoding::Cobs, Trailer::None, &[0x11, 0x00, 0x00, 0x00]), [0x02, 0x11, 0x01, 0x01, 0x01, 0x00]);
+  }
+
+  #[test]
+  fn cobs_block_boundaries() {
+    // 254 bytes fill one block
+    let data = counting(254);
+    let packet = encoded(Encoding::Cobs, Trailer::None, &data);
+    assert_eq!(packet.len(), 256);
+    assert_eq!((packet[0], packet[255]), (0xFF, 0x00));
+
+    // The 255th byte starts a new block
+    let data = counting(255);
+    let packet = encoded(Encoding::Cobs, Trailer::None, &data);
+    assert_eq!(&packet[255..], [0x02, 0xFF, 0x00]);
+
+    // A zero in front of a full block
+    let mut data = std::vec![0x00];
+    data.extend(counting(254));
+    let packet = encoded(Encoding::Cobs, Trailer::None, &data);
+    assert_eq!((packet[0], packet[1], packet.len()), (0x01, 0xFF, 257));
+
+    for len in [1, 253, 254, 255, 256, 508, 509, 600] {
+      round_trip(Encoding::Cobs, Trailer::None, &counting(len));
+      round_trip(Encoding::Cobs, Trailer::Crc32, &counting(len));
+    }
+  }
+
+  #[test]
+  fn cobs_zero_runs() {
+    round_trip(Encoding::Cobs, Trailer::None, &[0; 10]);
+    round_trip(Encoding::Cobs, Trailer::Crc16, &[0, 1, 0, 0, 2, 0]);
+
+    let mut data = counting(300);
+    data[253] = 0;
+    data[254] = 0;
+    round_trip(Encoding::Cobs, Trailer::Crc16, &data);
+  }
+
+  #[test]
+  fn slip_escaping() {
+    let packet = encoded(Encoding::Slip, Trailer::None, &[0x01, END, 0x02, ESC, 0x03]);
+    assert_eq!(packet, [END, 0x01, ESC, ESC_END, 0x02, ESC, ESC_ESC, 0x03, END]);
+
+    round_trip(Encoding::Slip, Trailer::None, &[END, ESC, END, ESC]);
+    round_trip(Encoding::Slip, Trailer::Crc32, &counting(300));
+
+    let mut buffer = [0; 16];
+    assert_eq!(decode(Encoding::Slip, Trailer::None, &[END, ESC, 0x01, END], &mut buffer), Err(FramingError::Encoding));
+    assert_eq!(decode(Encoding::Slip, Trailer::None, &[END, 0x01, ESC], &mut buffer), Err(FramingError::Encoding));
+
+    let mut decoder = Decoder::<16>::new(Encoding::Slip, Trailer::None);
+    for byte in [END, 0x01, ESC, 0x01] {assert!(decoder.push(byte).is_none());}
+    assert_eq!(decoder.push(END), Some(Err(FramingError::Encoding)));
+  }
+
+  #[test]
+  fn bad_trailer() {
+    for encoding in [Encoding::Cobs, Encoding::Slip] {
+      let mut packet = encoded(encoding, Trailer::Crc16, b"payload");
+      // Change a data byte that needs no escaping
+      packet[2] ^= 0x01;
+
+      let mut buffer = [0; 32];
+      assert_eq!(decode(encoding, Trailer::Crc16, &packet, &mut buffer), Err(FramingError::Crc));
+
+      let mut decoder = Decoder::<32>::new(encoding, Trailer::Crc16);
+      let result = packet.iter().find_map(|byte| decoder.feed(*byte));
+      assert_eq!(result, Some(Err(FramingError::Crc)));
+    }
+  }
+
+  #[test]
+  fn decoder_limits() {
+    let mut buffer = [0; 4];
+    let packet = encoded(Encoding::Cobs, Trailer::None, b"too long");
+    assert_eq!(decode(Encoding::Cobs, Trailer::None, &packet, &mut buffer), Err(FramingError::TooLong));
+
+    let mut decoder = Decoder::<4>::new(Encoding::Cobs, Trailer::None);
+    let result = packet.iter().find_map(|byte| decoder.feed(*byte));
+    assert_eq!(result, Some(Err(FramingError::TooLong)));
+
+    // Empty packets between delimiters are ignored, the next packet is still received
+    let mut decoder = Decoder::<16>::new(Encoding::Slip, Trailer::None);
+    for byte in [END, END] {assert!(decoder.push(byte).is_none());}
+    for byte in [0x41, 0x42] {assert!(decoder.push(byte).is_none());}
+    assert_eq!(decoder.push(END), Some(Ok(&b"AB"[..])));
+  }
+}
@@ -26,11 +26,21 @@
 //! }
 //! ```
 
+#![allow(clippy::question_mark, clippy::needless_late_init)]
+
 use crate::analog::enable_channel;
 use crate::time::setup_pwm;
 use crate::include::{ProgError, PIN_CONF};
+use crate::executor::WakerSlot;
+use stm32f4::stm32f446::{NVIC, Interrupt, interrupt, gpioh::RegisterBlock};
+use core::future::Future;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::{Context, Poll};
 use rtt_target::rprintln;
 
+static EXTI_WAKERS: [WakerSlot; 16] = [const {WakerSlot::new()}; 16];
+static EXTI_FIRED: AtomicU32 = AtomicU32::new(0);
+
 /// Represents a configured pin. Is returned from pinmode-functions.
 pub struct Pin<T> {
   #[doc(hidden)]
@@ -46,6 +56,7 @@ pub struct Input;
 #[doc(hidden)]
 pub struct Output;
 #[doc(hidden)]
+#[allow(dead_code)]
 pub struct AlternateFunction(u32);
 #[doc(hidden)]
 pub struct Analog {
@@ -81,6 +92,70 @@ pub enum GpioBias {
   None, Pullup, Pulldown
 }
 
+/// Represents the signal edges that can trigger an external interrupt (EXTI) on a pin.
+#[derive(Clone, Copy)]
+pub enum GpioEdge {
+  Rising, Falling, Both
+}
+
+/// A future that completes when the selected edge is detected on an input pin.
+///
+/// Is returned from [`wait_for_edge()`](crate::gpio::Pin::wait_for_edge) and its variants.
+pub struct EdgeFuture<'a> {
+  #[doc(hidden)]
+  pin: &'a Pin<Input>,
+  #[doc(hidden)]
+  edge: GpioEdge,
+  #[doc(hidden)]
+  armed: bool
+}
+
+impl Pin<Input> {
+  /// Waits asynchronously for a rising or falling edge on the pin.
+  ///
+  /// Uses the EXTI line of the pin number, so only one pin per number (e.g. PA0 or PB0) can be awaited at a time.
+  pub fn wait_for_edge(&self) -> EdgeFuture<'_> {
+    return EdgeFuture {pin: self, edge: GpioEdge::Both, armed: false};
+  }
+
+  /// Waits asynchronously for a rising edge on the pin.
+  pub fn wait_for_rising_edge(&self) -> EdgeFuture<'_> {
+    return EdgeFuture {pin: self, edge: GpioEdge::Rising, armed: false};
+  }
+
+  /// Waits asynchronously for a falling edge on the pin.
+  pub fn wait_for_falling_edge(&self) -> EdgeFuture<'_> {
+    return EdgeFuture {pin: self, edge: GpioEdge::Falling, armed: false};
+  }
+}
+
+impl<'a> Future for EdgeFuture<'a> {
+  type Output = ();
+
+  fn poll(mut self: core::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+    let line = self.pin.number;
+
+    if !self.armed {
+      EXTI_FIRED.fetch_and(!(1 << line), Ordering::SeqCst);
+      EXTI_WAKERS[line as usize].register(cx.waker());
+      setup_exti((self.pin.block, line), self.edge);
+      self.armed = true;
+      return Poll::Pending;
+    }
+
+    if EXTI_FIRED.fetch_and(!(1 << line), Ordering::SeqCst) & (1 << line) != 0 {return Poll::Ready(());}
+
+    EXTI_WAKERS[line as usize].register(cx.waker());
+    return Poll::Pending;
+  }
+}
+
+impl<'a> Drop for EdgeFuture<'a> {
+  fn drop(&mut self) {
+    if self.armed {disable_exti(self.pin.number);}
+  }
+}
+
 
 // Public Functions ===============================================================================
 /// Configures a pin to be a digital input.
@@ -96,7 +171,7 @@ pub fn pinmode_input(pin: (char, u8)) -> Result<Pin<Input>, ProgError> {
   if let Err(error) = check_pin(pin) {return Err(error);}
 
   unsafe {
-    if !PIN_CONF.contains(&pin) {PIN_CONF.push(pin).unwrap();}
+    if !(*core::ptr::addr_of_mut!(PIN_CONF)).contains(&pin) {(*core::ptr::addr_of_mut!(PIN_CONF)).push(pin).unwrap();}
     else {
       rprintln!("P{}{} is already configured! | pin_mode()", pin.0.to_uppercase(), pin.1);
       return Err(ProgError::AlreadyConfigured);
@@ -204,7 +279,7 @@ pub fn pinmode_output(pin: (char, u8)) -> Result<Pin<Output>, ProgError> {
   if let Err(error) = check_pin(pin) {return Err(error);}
 
   unsafe {
-    if !PIN_CONF.contains(&pin) {PIN_CONF.push(pin).unwrap();}
+    if !(*core::ptr::addr_of_mut!(PIN_CONF)).contains(&pin) {(*core::ptr::addr_of_mut!(PIN_CONF)).push(pin).unwrap();}
     else {
       rprintln!("P{}{} is already configured! | pin_mode()", pin.0.to_uppercase(), pin.1);
       return Err(ProgError::AlreadyConfigured);
@@ -316,7 +391,7 @@ pub fn pinmode_alternate_function(pin: (char, u8), af: u32) -> Result<Pin<Altern
   }
 
   unsafe {
-    if !PIN_CONF.contains(&pin) {PIN_CONF.push(pin).unwrap();}
+    if !(*core::ptr::addr_of_mut!(PIN_CONF)).contains(&pin) {(*core::ptr::addr_of_mut!(PIN_CONF)).push(pin).unwrap();}
     else {
       rprintln!("P{}{} is already configured! | pin_mode()", pin.0.to_uppercase(), pin.1);
       return Err(ProgError::AlreadyConfigured);
@@ -451,7 +526,7 @@ pub fn pinmode_analog(pin: (char, u8)) -> Result<Pin<Analog>, ProgError> {
   if let Err(error) = check_pin(pin) {return Err(error);}
 
   unsafe {
-    if !PIN_CONF.contains(&pin) {PIN_CONF.push(pin).unwrap();}
+    if !(*core::ptr::addr_of_mut!(PIN_CONF)).contains(&pin) {(*core::ptr::addr_of_mut!(PIN_CONF)).push(pin).unwrap();}
     else {
       rprintln!("P{}{} is already configured! | pin_mode()", pin.0.to_uppercase(), pin.1);
       return Err(ProgError::AlreadyConfigured);
@@ -610,7 +685,7 @@ pub fn pinmode_pwm(pin: (char, u8)) -> Result<Pin<PWM>, ProgError> {
   if let Err(error) = check_pin(pin) {return Err(error);}
 
   unsafe {
-    if !PIN_CONF.contains(&pin) {PIN_CONF.push(pin).unwrap();}
+    if !(*core::ptr::addr_of_mut!(PIN_CONF)).contains(&pin) {(*core::ptr::addr_of_mut!(PIN_CONF)).push(pin).unwrap();}
     else {
       rprintln!("P{}{} is already configured! | pin_mode()", pin.0.to_uppercase(), pin.1);
       return Err(ProgError::AlreadyConfigured);
@@ -688,7 +763,7 @@ pub unsafe fn pinmode_pwm_force(pin: (char, u8)) -> Result<Pin<PWM>, ProgError>
 
   if let Err(error) = check_pin(pin) {return Err(error);}
 
-  if !PIN_CONF.contains(&pin) {PIN_CONF.push(pin).unwrap();}
+  if !(*core::ptr::addr_of_mut!(PIN_CONF)).contains(&pin) {(*core::ptr::addr_of_mut!(PIN_CONF)).push(pin).unwrap();}
   else {
     rprintln!("P{}{} is already configured! | pin_mode()", pin.0.to_uppercase(), pin.1);
     return Err(ProgError::AlreadyConfigured);
@@ -1017,8 +1092,175 @@ fn check_pin(pin: (char, u8)) -> Result<(), ProgError> {
   else {return Ok(());}
 }
 
+// GPIOA and GPIOB only differ from the other ports in their reset values
+#[doc(hidden)]
+pub fn get_gpio(block: char) -> &'static RegisterBlock {
+  unsafe {
+    match block {
+      'a' => &*(stm32f4::stm32f446::GPIOA::ptr() as *const RegisterBlock),
+      'b' => &*(stm32f4::stm32f446::GPIOB::ptr() as *const RegisterBlock),
+      'c' => &*stm32f4::stm32f446::GPIOC::ptr(),
+      'd' => &*stm32f4::stm32f446::GPIOD::ptr(),
+      'h' => &*stm32f4::stm32f446::GPIOH::ptr(),
+      _   => unreachable!()
+    }
+  }
+}
+
+// The MODER bits: 0 input, 1 output, 2 alternate function, 3 analog
+#[doc(hidden)]
+pub fn read_mode(pin: (char, u8)) -> u32 {
+  return (get_gpio(pin.0).moder.read().bits() >> (2 * pin.1)) & 0x3;
+}
+
+// Changes the mode of a configured pin without releasing it, e.g. to drive the pins of a peripheral by hand
+#[doc(hidden)]
+pub fn write_mode(pin: (char, u8), mode: u32) {
+  get_gpio(pin.0).moder.modify(|r, w| unsafe {w.bits(r.bits() & !(0x3 << (2 * pin.1)) | (mode << (2 * pin.1)))});
+}
+
+#[doc(hidden)]
+pub fn read_pin(pin: (char, u8)) -> bool {
+  return get_gpio(pin.0).idr.read().bits() & (1 << pin.1) != 0;
+}
+
+#[doc(hidden)]
+pub fn write_pin(pin: (char, u8), value: bool) {
+  let bit = if value {1 << pin.1} else {1 << (pin.1 + 16)};
+  get_gpio(pin.0).bsrr.write(|w| unsafe {w.bits(bit)});
+}
+
+#[doc(hidden)]
+pub fn setup_exti(pin: (char, u8), edge: GpioEdge) {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rcc = &peripheral_ptr.RCC;
+  let syscfg = &peripheral_ptr.SYSCFG;
+  let exti = &peripheral_ptr.EXTI;
+
+  let line = pin.1;
+  let port: u32 = match pin.0 {
+    'a' => 0,
+    'b' => 1,
+    'c' => 2,
+    'd' => 3,
+    'h' => 7,
+    _   => unreachable!()
+  };
+  let shift = 4 * (line % 4);
+
+  rcc.apb2enr.modify(|_, w| w.syscfgen().enabled());
+  match line / 4 {
+    0 => syscfg.exticr1.modify(|r, w| unsafe {w.bits(r.bits() & !(0xF << shift) | (port << shift))}),
+    1 => syscfg.exticr2.modify(|r, w| unsafe {w.bits(r.bits() & !(0xF << shift) | (port << shift))}),
+    2 => syscfg.exticr3.modify(|r, w| unsafe {w.bits(r.bits() & !(0xF << shift) | (port << shift))}),
+    3 => syscfg.exticr4.modify(|r, w| unsafe {w.bits(r.bits() & !(0xF << shift) | (port << shift))}),
+    _ => unreachable!()
+  };
+
+  match edge {
+    GpioEdge::Rising => {
+      exti.rtsr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << line))});
+      exti.ftsr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << line))});
+    },
+    GpioEdge::Falling => {
+      exti.rtsr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << line))});
+      exti.ftsr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << line))});
+    },
+    GpioEdge::Both => {
+      exti.rtsr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << line))});
+      exti.ftsr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << line))});
+    }
+  };
+
+  exti.pr.write(|w| unsafe {w.bits(1 << line)});
+  exti.imr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << line))});
+
+  let irq = match line {
+    0 => Interrupt::EXTI0,
+    1 => Interrupt::EXTI1,
+    2 => Interrupt::EXTI2,
+    3 => Interrupt::EXTI3,
+    4 => Interrupt::EXTI4,
+    5..=9 => Interrupt::EXTI9_5,
+    _ => Interrupt::EXTI15_10
+  };
+  unsafe {NVIC::unmask(irq);}
+}
+
+#[doc(hidden)]
+pub fn disable_exti(line: u8) {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let exti = &peripheral_ptr.EXTI;
+
+  exti.imr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << line))});
+  exti.pr.write(|w| unsafe {w.bits(1 << line)});
+}
+
+fn exti_interrupt(first: u8, last: u8) {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let exti = &peripheral_ptr.EXTI;
+
+  let pending = exti.pr.read().bits();
+
+  for line in first..=last {
+    if pending & (1 << line) != 0 {
+      exti.pr.write(|w| unsafe {w.bits(1 << line)});
+      exti.imr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << line))});
+      EXTI_FIRED.fetch_or(1 << line, Ordering::SeqCst);
+      EXTI_WAKERS[line as usize].wake();
+    }
+  }
+}
+
 impl<T> Drop for Pin<T> {
   fn drop(&mut self) {
-    unsafe {PIN_CONF.swap_remove(PIN_CONF.iter().position(|&i| i == (self.block, self.number)).unwrap());}
+    unsafe {(*core::ptr::addr_of_mut!(PIN_CONF)).swap_remove((*core::ptr::addr_of_mut!(PIN_CONF)).iter().position(|&i| i == (self.block, self.number)).unwrap());}
   }
 }
+
+
+// Interrupts =====================================================================================
+#[allow(non_snake_case)]
+#[interrupt]
+fn EXTI0() {
+  exti_interrupt(0, 0);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn EXTI1() {
+  exti_interrupt(1, 1);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn EXTI2() {
+  exti_interrupt(2, 2);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn EXTI3() {
+  exti_interrupt(3, 3);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn EXTI4() {
+  exti_interrupt(4, 4);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn EXTI9_5() {
+  exti_interrupt(5, 9);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn EXTI15_10() {
+  exti_interrupt(10, 15);
+}
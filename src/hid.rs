@@ -0,0 +1,439 @@
+//! This module contains USB HID devices: a keyboard, a mouse and devices with own report descriptors.
+//!
+//! The devices use the [USB bus](crate::usb::usb_bus) on the pins PA11 and PA12, so only one HID device or
+//! [serial port](crate::usb::UsbSerial) can be created. The [keyboard](UsbHid::keyboard) and [mouse](UsbHid::mouse)
+//! use the report formats of the boot protocol, so they also work in the BIOS of a PC:
+//!
+//! | Device   | Input report                                                  | Output report |
+//! | -------- | ------------------------------------------------------------- | ------------- |
+//! | Keyboard | Modifiers, reserved, 6 key codes                              | LEDs          |
+//! | Mouse    | Buttons, X, Y, wheel (signed)                                 | -             |
+//!
+//! A [custom](UsbHid::custom) device takes the report descriptor of the application. Its reports are sent with
+//! [send_report](UsbHid::send_report) or by an [input callback](UsbHid::on_input), the output reports of the host are
+//! passed to the [output callback](UsbHid::on_output). The device has to be [polled](UsbHid::poll) at least every
+//! 10ms while no other function of it is called.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::hid::UsbHid;
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   let mut keyboard = UsbHid::keyboard().unwrap();
+//!   let button = pinmode_input(PC13).unwrap();
+//!
+//!   loop {
+//!     keyboard.poll();
+//!
+//!     if !digital_read(&button) {
+//!       keyboard.type_str("Hello World!\n").unwrap();
+//!     }
+//!   }
+//! }
+//! ```
+
+#![allow(clippy::question_mark)]
+
+use crate::include::ProgError;
+use crate::usb::{usb_bus, claim_device, UsbBus, UsbOtgFs, DEFAULT_VID, DEFAULT_PID};
+use usb_device::prelude::*;
+use usbd_hid::hid_class::{HIDClass, HidClassSettings, HidSubClass, HidProtocol, ProtocolModeConfig, HidCountryCode};
+use rtt_target::rprintln;
+
+/// The maximum length of a report, the packet size of a full speed interrupt endpoint.
+pub const MAX_REPORT: usize = 64;
+
+/// Boot keyboard report descriptor from the HID specification, 8 byte input reports and 1 byte LED output reports.
+pub const KEYBOARD_DESCRIPTOR: [u8; 63] = [
+  0x05, 0x01, // Usage Page (Generic Desktop)
+  0x09, 0x06, // Usage (Keyboard)
+  0xA1, 0x01, // Collection (Application)
+  0x05, 0x07, //   Usage Page (Key Codes)
+  0x19, 0xE0, //   Usage Minimum (Left Control)
+  0x29, 0xE7, //   Usage Maximum (Right GUI)
+  0x15, 0x00, //   Logical Minimum (0)
+  0x25, 0x01, //   Logical Maximum (1)
+  0x75, 0x01, //   Report Size (1)
+  0x95, 0x08, //   Report Count (8)
+  0x81, 0x02, //   Input (Data, Variable, Absolute), modifiers
+  0x95, 0x01, //   Report Count (1)
+  0x75, 0x08, //   Report Size (8)
+  0x81, 0x01, //   Input (Constant), reserved
+  0x95, 0x05, //   Report Count (5)
+  0x75, 0x01, //   Report Size (1)
+  0x05, 0x08, //   Usage Page (LEDs)
+  0x19, 0x01, //   Usage Minimum (Num Lock)
+  0x29, 0x05, //   Usage Maximum (Kana)
+  0x91, 0x02, //   Output (Data, Variable, Absolute), LEDs
+  0x95, 0x01, //   Report Count (1)
+  0x75, 0x03, //   Report Size (3)
+  0x91, 0x01, //   Output (Constant), padding
+  0x95, 0x06, //   Report Count (6)
+  0x75, 0x08, //   Report Size (8)
+  0x15, 0x00, //   Logical Minimum (0)
+  0x25, 0x65, //   Logical Maximum (101)
+  0x05, 0x07, //   Usage Page (Key Codes)
+  0x19, 0x00, //   Usage Minimum (0)
+  0x29, 0x65, //   Usage Maximum (101)
+  0x81, 0x00, //   Input (Data, Array), keys
+  0xC0        // End Collection
+];
+
+/// Boot mouse report descriptor from the HID specification with a wheel, 4 byte input reports.
+pub const MOUSE_DESCRIPTOR: [u8; 52] = [
+  0x05, 0x01, // Usage Page (Generic Desktop)
+  0x09, 0x02, // Usage (Mouse)
+  0xA1, 0x01, // Collection (Application)
+  0x09, 0x01, //   Usage (Pointer)
+  0xA1, 0x00, //   Collection (Physical)
+  0x05, 0x09, //     Usage Page (Buttons)
+  0x19, 0x01, //     Usage Minimum (1)
+  0x29, 0x03, //     Usage Maximum (3)
+  0x15, 0x00, //     Logical Minimum (0)
+  0x25, 0x01, //     Logical Maximum (1)
+  0x95, 0x03, //     Report Count (3)
+  0x75, 0x01, //     Report Size (1)
+  0x81, 0x02, //     Input (Data, Variable, Absolute), buttons
+  0x95, 0x01, //     Report Count (1)
+  0x75, 0x05, //     Report Size (5)
+  0x81, 0x01, //     Input (Constant), padding
+  0x05, 0x01, //     Usage Page (Generic Desktop)
+  0x09, 0x30, //     Usage (X)
+  0x09, 0x31, //     Usage (Y)
+  0x09, 0x38, //     Usage (Wheel)
+  0x15, 0x81, //     Logical Minimum (-127)
+  0x25, 0x7F, //     Logical Maximum (127)
+  0x75, 0x08, //     Report Size (8)
+  0x95, 0x03, //     Report Count (3)
+  0x81, 0x06, //     Input (Data, Variable, Relative), X, Y, wheel
+  0xC0,       //   End Collection
+  0xC0        // End Collection
+];
+
+// Modifier bits of a keyboard report
+pub const LEFT_CTRL: u8 = 0x01;
+pub const LEFT_SHIFT: u8 = 0x02;
+pub const LEFT_ALT: u8 = 0x04;
+pub const LEFT_GUI: u8 = 0x08;
+pub const RIGHT_CTRL: u8 = 0x10;
+pub const RIGHT_SHIFT: u8 = 0x20;
+pub const RIGHT_ALT: u8 = 0x40;
+pub const RIGHT_GUI: u8 = 0x80;
+
+// Button bits of a mouse report
+pub const BUTTON_LEFT: u8 = 0x01;
+pub const BUTTON_RIGHT: u8 = 0x02;
+pub const BUTTON_MIDDLE: u8 = 0x04;
+
+/// Fills the next input report and returns its length, or `None` if there is nothing to send.
+pub type InputCallback = fn(&mut [u8; MAX_REPORT]) -> Option<usize>;
+/// Is called with every output report of the host.
+pub type OutputCallback = fn(&[u8]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HidKind {
+  Keyboard, Mouse, Custom
+}
+
+
+/// This struct represents a USB HID device.
+pub struct UsbHid {
+  #[doc(hidden)]
+  device: UsbDevice<'static, UsbBus<UsbOtgFs>>,
+  #[doc(hidden)]
+  hid: HIDClass<'static, UsbBus<UsbOtgFs>>,
+  #[doc(hidden)]
+  kind: HidKind,
+  #[doc(hidden)]
+  leds: u8,
+  #[doc(hidden)]
+  on_input: Option<InputCallback>,
+  #[doc(hidden)]
+  on_output: Option<OutputCallback>,
+  #[doc(hidden)]
+  pending: [u8; MAX_REPORT],
+  #[doc(hidden)]
+  pending_len: usize
+}
+
+impl UsbHid {
+  /// Creates a boot keyboard. Returns an error-enum if there is already a USB device or the pins are used for another
+  /// function.
+  pub fn keyboard() -> Result<Self, ProgError> {
+    return Self::create(&KEYBOARD_DESCRIPTOR, 10, HidKind::Keyboard, "Keyboard");
+  }
+
+  /// Creates a boot mouse. Returns an error-enum like [keyboard](UsbHid::keyboard).
+  pub fn mouse() -> Result<Self, ProgError> {
+    return Self::create(&MOUSE_DESCRIPTOR, 10, HidKind::Mouse, "Mouse");
+  }
+
+  /// Creates a device with the report descriptor, whose input reports are requested by the host every `poll_ms`
+  /// milliseconds (1 to 255). Returns an error-enum like [keyboard](UsbHid::keyboard).
+  pub fn custom(report_descriptor: &'static [u8], poll_ms: u8) -> Result<Self, ProgError> {
+    if poll_ms == 0 {
+      rprintln!("The poll interval has to be at least 1ms! | UsbHid::custom()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    return Self::create(report_descriptor, poll_ms, HidKind::Custom, "HID device");
+  }
+
+  /// Serves the requests of the host, calls the output callback with received reports and sends the report of the
+  /// input callback when the last one was taken. Has to be called at least every 10ms while no other function is
+  /// called. Returns true if something might have been received.
+  pub fn poll(&mut self) -> bool {
+    let active = self.device.poll(&mut [&mut self.hid]);
+
+    let mut report = [0; MAX_REPORT];
+    if let Ok(len) = self.hid.pull_raw_output(&mut report) {
+      if self.kind == HidKind::Keyboard && len > 0 {self.leds = report[0];}
+      if let Some(callback) = self.on_output {callback(&report[..len]);}
+    }
+
+    if !self.is_configured() {return active;}
+
+    if self.pending_len == 0 {
+      if let Some(callback) = self.on_input {
+        self.pending_len = callback(&mut self.pending).unwrap_or(0).min(MAX_REPORT);
+      }
+    }
+    if self.pending_len > 0 && self.hid.push_raw_input(&self.pending[..self.pending_len]).is_ok() {
+      self.pending_len = 0;
+    }
+
+    return active;
+  }
+
+  /// Returns true if the device is configured by the host.
+  pub fn is_configured(&self) -> bool {
+    return self.device.state() == UsbDeviceState::Configured;
+  }
+
+  /// Sets the function that is called by [poll](UsbHid::poll) to get the next input report, or removes it.
+  pub fn on_input(&mut self, callback: Option<InputCallback>) {
+    self.on_input = callback;
+  }
+
+  /// Sets the function that is called by [poll](UsbHid::poll) with every output report of the host, or removes it.
+  pub fn on_output(&mut self, callback: Option<OutputCallback>) {
+    self.on_output = callback;
+  }
+
+  /// Sends an input report and waits until the host took the previous one. Returns an error-enum if the device is not
+  /// configured by the host or the report is longer than the endpoint.
+  pub fn send_report(&mut self, report: &[u8]) -> Result<(), ProgError> {
+    loop {
+      self.poll();
+
+      if !self.is_configured() {
+        rprintln!("The device is not configured by the host! | .send_report()");
+        return Err(ProgError::NotConfigured);
+      }
+
+      match self.hid.push_raw_input(report) {
+        Ok(_) => return Ok(()),
+        Err(UsbError::WouldBlock) => {},
+        Err(_) => {
+          rprintln!("The report is too long! | .send_report()");
+          return Err(ProgError::InvalidConfiguration);
+        }
+      };
+    }
+  }
+
+  /// Sends a keyboard report with the modifier bits and up to 6 pressed keys, using the key codes of the HID usage
+  /// tables. The keys stay pressed until [release_keys](UsbHid::release_keys) is called. Returns an error-enum if the
+  /// device is no keyboard, there are more than 6 keys or the device is not configured by the host.
+  pub fn press(&mut self, modifiers: u8, keys: &[u8]) -> Result<(), ProgError> {
+    if let Err(error) = self.check_kind(HidKind::Keyboard) {return Err(error);}
+    if keys.len() > 6 {
+      rprintln!("A report has space for 6 keys! | .press()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    let mut report = [0; 8];
+    report[0] = modifiers;
+    report[2..2 + keys.len()].copy_from_slice(keys);
+
+    return self.send_report(&report);
+  }
+
+  /// Releases all keys. Returns an error-enum like [press](UsbHid::press).
+  pub fn release_keys(&mut self) -> Result<(), ProgError> {
+    return self.press(0, &[]);
+  }
+
+  /// Types the text with a US keyboard layout, every character is pressed and released. Supports the printable ASCII
+  /// characters, `\n`, `\t` and `\x08` (backspace). Returns an error-enum if the text contains other characters, before
+  /// anything is typed, or like [press](UsbHid::press).
+  pub fn type_str(&mut self, text: &str) -> Result<(), ProgError> {
+    if text.chars().any(|c| ascii_to_key(c).is_none()) {
+      rprintln!("The text contains characters that cannot be typed! | .type_str()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    for c in text.chars() {
+      let (modifiers, key) = ascii_to_key(c).unwrap();
+
+      if let Err(error) = self.press(modifiers, &[key]) {return Err(error);}
+      if let Err(error) = self.release_keys() {return Err(error);}
+    }
+
+    return Ok(());
+  }
+
+  /// Returns the LED bits that the host set on the keyboard: num lock (bit 0), caps lock (bit 1), scroll lock (bit 2),
+  /// compose (bit 3) and kana (bit 4).
+  pub fn leds(&self) -> u8 {
+    return self.leds;
+  }
+
+  /// Sends a mouse report with the pressed buttons and the movement since the last report. Returns an error-enum if
+  /// the device is no mouse or it is not configured by the host.
+  pub fn move_mouse(&mut self, buttons: u8, x: i8, y: i8, wheel: i8) -> Result<(), ProgError> {
+    if let Err(error) = self.check_kind(HidKind::Mouse) {return Err(error);}
+
+    return self.send_report(&[buttons, x as u8, y as u8, wheel as u8]);
+  }
+
+  /// Returns the device to use other functions of the usb-device crate.
+  pub fn device(&mut self) -> &mut UsbDevice<'static, UsbBus<UsbOtgFs>> {
+    return &mut self.device;
+  }
+
+  fn create(descriptor: &'static [u8], poll_ms: u8, kind: HidKind, product: &'static str) -> Result<Self, ProgError> {
+    let bus = match usb_bus() {
+      Ok(value) => value,
+      Err(error) => return Err(error)
+    };
+    if let Err(error) = claim_device() {return Err(error);}
+
+    let settings = HidClassSettings {
+      subclass: if kind == HidKind::Custom {HidSubClass::NoSubClass} else {HidSubClass::Boot},
+      protocol: match kind {
+        HidKind::Keyboard => HidProtocol::Keyboard,
+        HidKind::Mouse => HidProtocol::Mouse,
+        HidKind::Custom => HidProtocol::Generic
+      },
+      config: ProtocolModeConfig::ForceReport,
+      locale: HidCountryCode::NotSupported
+    };
+    let hid = HIDClass::new_with_settings(bus, descriptor, poll_ms, settings);
+
+    let strings = StringDescriptors::default().manufacturer("Rustuino").product(product).serial_number("0001");
+    let device = match UsbDeviceBuilder::new(bus, UsbVidPid(DEFAULT_VID, DEFAULT_PID)).strings(&[strings]) {
+      Ok(builder) => builder.build(),
+      Err(_) => return Err(ProgError::Internal)
+    };
+
+    return Ok(Self {
+      device,
+      hid,
+      kind,
+      leds: 0,
+      on_input: None,
+      on_output: None,
+      pending: [0; MAX_REPORT],
+      pending_len: 0
+    });
+  }
+
+  fn check_kind(&self, kind: HidKind) -> Result<(), ProgError> {
+    if self.kind != kind {
+      rprintln!("The device is no {:?}! | UsbHid", kind);
+      return Err(ProgError::PermissionDenied);
+    }
+
+    return Ok(());
+  }
+}
+
+
+// Private Functions ==============================================================================
+// Modifiers and key code of a character on a US keyboard
+fn ascii_to_key(c: char) -> Option<(u8, u8)> {
+  const SHIFTED_DIGITS: &[u8] = b"!@#$%^&*()";
+  const SYMBOLS: &[u8] = b"-=[]\\";
+  const SHIFTED_SYMBOLS: &[u8] = b"_+{}|";
+  const PUNCTUATION: &[u8] = b";'`,./";
+  const SHIFTED_PUNCTUATION: &[u8] = b":\"~<>?";
+
+  if !c.is_ascii() {return None;}
+  let byte = c as u8;
+  let find = |table: &[u8]| table.iter().position(|i| *i == byte).map(|i| i as u8);
+
+  return match byte {
+    b'a'..=b'z' => Some((0, 0x04 + byte - b'a')),
+    b'A'..=b'Z' => Some((LEFT_SHIFT, 0x04 + byte - b'A')),
+    b'1'..=b'9' => Some((0, 0x1E + byte - b'1')),
+    b'0' => Some((0, 0x27)),
+    b'\n' => Some((0, 0x28)),
+    0x08 => Some((0, 0x2A)),
+    b'\t' => Some((0, 0x2B)),
+    b' ' => Some((0, 0x2C)),
+    _ => {
+      if let Some(index) = find(SHIFTED_DIGITS) {Some((LEFT_SHIFT, 0x1E + index))}
+      else if let Some(index) = find(SYMBOLS) {Some((0, 0x2D + index))}
+      else if let Some(index) = find(SHIFTED_SYMBOLS) {Some((LEFT_SHIFT, 0x2D + index))}
+      // 0x32 is the non-US hash key
+      else if let Some(index) = find(PUNCTUATION) {Some((0, 0x33 + index))}
+      else {find(SHIFTED_PUNCTUATION).map(|index| (LEFT_SHIFT, 0x33 + index))}
+    }
+  };
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn letters_and_digits() {
+    assert_eq!(ascii_to_key('a'), Some((0, 0x04)));
+    assert_eq!(ascii_to_key('z'), Some((0, 0x1D)));
+    assert_eq!(ascii_to_key('Q'), Some((LEFT_SHIFT, 0x14)));
+    assert_eq!(ascii_to_key('1'), Some((0, 0x1E)));
+    assert_eq!(ascii_to_key('0'), Some((0, 0x27)));
+    // The shifted digits share the key codes of 1 to 0
+    assert_eq!(ascii_to_key('!'), Some((LEFT_SHIFT, 0x1E)));
+    assert_eq!(ascii_to_key('^'), Some((LEFT_SHIFT, 0x23)));
+    assert_eq!(ascii_to_key(')'), Some((LEFT_SHIFT, 0x27)));
+  }
+
+  #[test]
+  fn symbols_and_punctuation() {
+    assert_eq!(ascii_to_key('-'), Some((0, 0x2D)));
+    assert_eq!(ascii_to_key('\\'), Some((0, 0x31)));
+    assert_eq!(ascii_to_key('_'), Some((LEFT_SHIFT, 0x2D)));
+    assert_eq!(ascii_to_key('|'), Some((LEFT_SHIFT, 0x31)));
+    // 0x32 is skipped
+    assert_eq!(ascii_to_key(';'), Some((0, 0x33)));
+    assert_eq!(ascii_to_key('`'), Some((0, 0x35)));
+    assert_eq!(ascii_to_key('/'), Some((0, 0x38)));
+    assert_eq!(ascii_to_key('"'), Some((LEFT_SHIFT, 0x34)));
+    assert_eq!(ascii_to_key('?'), Some((LEFT_SHIFT, 0x38)));
+  }
+
+  #[test]
+  fn control_characters() {
+    assert_eq!(ascii_to_key('\n'), Some((0, 0x28)));
+    assert_eq!(ascii_to_key('\x08'), Some((0, 0x2A)));
+    assert_eq!(ascii_to_key('\t'), Some((0, 0x2B)));
+    assert_eq!(ascii_to_key(' '), Some((0, 0x2C)));
+  }
+
+  #[test]
+  fn unknown_characters() {
+    assert_eq!(ascii_to_key('\u{e9}'), None);
+    assert_eq!(ascii_to_key('\u{20ac}'), None);
+    assert_eq!(ascii_to_key('\r'), None);
+    assert_eq!(ascii_to_key('\x7F'), None);
+  }
+}
@@ -2,6 +2,14 @@
 //! 
 //! For information on whitch pins have UART capabilities, check [`I2C_MAP`](crate::include::I2C_MAP)
 //! 
+//! The blocking transfers give up if the slave does not respond within the [timeout](crate::i2c::I2C::set_timeout),
+//! e.g. because it holds SDA low after a reset in the middle of a transfer. The bus is then freed with
+//! [recover_bus](crate::i2c::I2C::recover_bus).
+//! 
+//! Registers of sensors and other slaves are accessed with [read_register](crate::i2c::I2C::read_register),
+//! [write_register](crate::i2c::I2C::write_register) and their variants, which use a repeated start and the buffers
+//! of the caller instead of the internal tx and rx buffers.
+//! 
 //! # Examples
 //! 
 //! ```no_run
@@ -27,13 +35,31 @@
 //! }
 //! ```
 
+#![allow(clippy::question_mark)]
+
 use crate::include::{I2cError, ProgError, I2C_MAP, PIN_CONF};
-use crate::gpio::{pinmode_alternate_function, open_drain, set_bias, GpioBias::Pullup, Pin, AlternateFunction};
+use crate::gpio::{pinmode_alternate_function, open_drain, set_bias, read_pin, write_pin, write_mode, GpioBias::Pullup, Pin, AlternateFunction};
+use crate::executor::WakerSlot;
+use crate::time::{millis, is_expired, is_time_started};
+use stm32f4::stm32f446::{NVIC, Interrupt, interrupt, i2c1::RegisterBlock};
 use heapless::Vec;
+use core::future::Future;
+use core::task::{Context, Poll};
 use rtt_target::rprintln;
 
 const BUS_FREQ: u32 = 16000000;
 const I2C_FREQ: u32 = 100000;
+const CYCLES_PER_US: u32 = BUS_FREQ / 1000000;
+const DEFAULT_TIMEOUT: usize = 25;
+
+// SR1 event flags
+const SB: u32 = 1 << 0;
+const ADDR: u32 = 1 << 1;
+const BTF: u32 = 1 << 2;
+const RXNE: u32 = 1 << 6;
+const TXE: u32 = 1 << 7;
+
+static I2C_WAKERS: [WakerSlot; 3] = [const {WakerSlot::new()}; 3];
 
 /// This struct represents a configured I2C peripheral.
 pub struct I2C<const N: usize> {
@@ -50,7 +76,9 @@ pub struct I2C<const N: usize> {
   #[doc(hidden)]
   tx_addr: u8,
   #[doc(hidden)]
-  transmitting: bool
+  transmitting: bool,
+  #[doc(hidden)]
+  timeout: usize
 }
 
 impl<const N: usize> I2C<N> {
@@ -70,13 +98,13 @@ impl<const N: usize> I2C<N> {
     }
 
     unsafe {
-      if PIN_CONF.contains(&scl_pin) || PIN_CONF.contains(&sda_pin) {
+      if (*core::ptr::addr_of_mut!(PIN_CONF)).contains(&scl_pin) || (*core::ptr::addr_of_mut!(PIN_CONF)).contains(&sda_pin) {
         rprintln!("These pins are already configured for another function! | I2C::new()");
         return Err(ProgError::InvalidConfiguration);
       }
       else {
-        PIN_CONF.push(scl_pin).expect("Could not store pin number! | I2C::new()");
-        PIN_CONF.push(sda_pin).expect("Could not store pin number! | I2C::new()");
+        (*core::ptr::addr_of_mut!(PIN_CONF)).push(scl_pin).expect("Could not store pin number! | I2C::new()");
+        (*core::ptr::addr_of_mut!(PIN_CONF)).push(sda_pin).expect("Could not store pin number! | I2C::new()");
       }
     }
 
@@ -162,7 +190,8 @@ impl<const N: usize> I2C<N> {
       tx_buffer: Vec::new(),
       rx_buffer: Vec::new(),
       tx_addr: 0,
-      transmitting: false
+      transmitting: false,
+      timeout: DEFAULT_TIMEOUT
     });
   }
 
@@ -223,69 +252,17 @@ impl<const N: usize> I2C<N> {
 
   /// Burst tranfers the bytes in the tx buffer to the slave. Specify if a stop bit should be send. Normally this
   /// should be true. It can be false if you want to communicate with multiple slaves simultaniously.
-  /// Returns an error-enum if problems with the connection are detected.
+  /// Returns an error-enum if problems with the connection are detected or the slave does not respond within the
+  /// [timeout](crate::i2c::I2C::set_timeout).
   pub fn end_transmission(&mut self, stop: bool) -> Result<(), I2cError> {
-    let peripheral_ptr;
-    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
-    let _sr: u32;
-  
-    match self.core {
-      1 => {
-        let i2c1 = &peripheral_ptr.I2C1;
-        i2c1.cr1.write(|w| w.start().set_bit());
-        while i2c1.sr1.read().sb().is_no_start() {}
-        i2c1.dr.write(|w| w.dr().bits(self.tx_addr));
-        while i2c1.sr1.read().addr().is_not_match() {
-          if let Err(error) = scan_i2c_error(i2c1.sr1.read().bits() as u16) {return Err(error);}
-        }
-        _sr = i2c1.sr2.read().bits();
-        for byte in self.tx_buffer.iter() {
-          i2c1.dr.write(|w| w.dr().bits(*byte));
-          while i2c1.sr1.read().tx_e().is_not_empty() {
-            if let Err(error) = scan_i2c_error(i2c1.sr1.read().bits() as u16) {return Err(error);}
-          }
-        }
-        if stop {i2c1.cr1.write(|w| w.stop().set_bit());}
-        else {i2c1.cr1.write(|w| w.start().set_bit());}
-      },
-      2 => {
-        let i2c2 = &peripheral_ptr.I2C2;
-        i2c2.cr1.write(|w| w.start().set_bit());
-        while i2c2.sr1.read().sb().is_no_start() {}
-        i2c2.dr.write(|w| w.dr().bits(self.tx_addr));
-        while i2c2.sr1.read().addr().is_not_match() {
-          if let Err(error) = scan_i2c_error(i2c2.sr1.read().bits() as u16) {return Err(error);}
-        }
-        _sr= i2c2.sr2.read().bits();
-        for byte in self.tx_buffer.iter() {
-          i2c2.dr.write(|w| w.dr().bits(*byte));
-          while i2c2.sr1.read().tx_e().is_not_empty() {
-            if let Err(error) = scan_i2c_error(i2c2.sr1.read().bits() as u16) {return Err(error);}
-          }
-        }
-        if stop {i2c2.cr1.write(|w| w.stop().set_bit());}
-        else {i2c2.cr1.write(|w| w.start().set_bit());}
-      },
-      3 => {
-        let i2c3 = &peripheral_ptr.I2C3;
-        i2c3.cr1.write(|w| w.start().set_bit());
-        while i2c3.sr1.read().sb().is_no_start() {}
-        i2c3.dr.write(|w| w.dr().bits(self.tx_addr));
-        while i2c3.sr1.read().addr().is_not_match() {
-          if let Err(error) = scan_i2c_error(i2c3.sr1.read().bits() as u16) {return Err(error);}
-        }
-        _sr = i2c3.sr2.read().bits();
-        for byte in self.tx_buffer.iter() {
-          i2c3.dr.write(|w| w.dr().bits(*byte));
-          while i2c3.sr1.read().tx_e().is_not_empty() {
-            if let Err(error) = scan_i2c_error(i2c3.sr1.read().bits() as u16) {return Err(error);}
-          }
-        }
-        if stop {i2c3.cr1.write(|w| w.stop().set_bit());}
-        else {i2c3.cr1.write(|w| w.start().set_bit());}
-      },
-      _ => panic!("I2C{} is not a valid core! | .send_bytes(...)", self.core)
-    };
+    let i2c = get_i2c(self.core);
+
+    if let Err(error) = write_blocking(i2c, self.tx_addr >> 1, &self.tx_buffer, self.timeout) {
+      return Err(self.handle_error(error));
+    }
+
+    if stop {i2c.cr1.modify(|_, w| w.stop().set_bit());}
+    else {i2c.cr1.modify(|_, w| w.start().set_bit());}
 
     return Ok(());
   }
@@ -293,205 +270,21 @@ impl<const N: usize> I2C<N> {
   /// Request a number of bytes from a slave with the specified address. Recieved bytes are stored in the rx buffer.
   /// Specify if a stop bit should be send. Normally this should be true. It can be false if you want to communicate
   /// with multiple slaves simultaniously.
-  /// Returns an error-enum if the buffer cannot hold the number of bytes or a problem with the connection is detected.
+  /// Returns an error-enum if the buffer cannot hold the number of bytes, a problem with the connection is detected
+  /// or the slave does not respond within the [timeout](crate::i2c::I2C::set_timeout).
   pub fn request_bytes(&mut self, addr: u8, nbytes: u8, stop: bool) -> Result<usize, I2cError> {
-    let peripheral_ptr;
-    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
-    let _sr: u32;
-
     if nbytes == 0 || nbytes as usize > N {
       rprintln!("Cannot store number of bytes! ({}) | .request_bytes()", nbytes);
       return Err(I2cError::Prog(ProgError::InvalidConfiguration));
     }
 
     self.rx_buffer.clear();
-  
-    match self.core {
-      1 => {
-        let i2c1 = &peripheral_ptr.I2C1;
-        i2c1.cr1.write(|w| w.start().set_bit());
-        while i2c1.sr1.read().sb().is_no_start() {}
-        i2c1.dr.write(|w| w.dr().bits((addr << 1) + 1));
-        if nbytes == 1 {i2c1.cr1.modify(|_, w| w.ack().clear_bit());}
-        while i2c1.sr1.read().addr().is_not_match() {
-          if let Err(error) = scan_i2c_error(i2c1.sr1.read().bits() as u16) {return Err(error);}
-        }
-
-        if nbytes == 1 {
-          _sr = i2c1.sr2.read().bits();
-          if stop {i2c1.cr1.modify(|_, w| w.stop().set_bit());}
-          else {i2c1.cr1.modify(|_, w| w.start().set_bit());}
-          while i2c1.sr1.read().rx_ne().is_empty() {
-            if let Err(error) = scan_i2c_error(i2c1.sr1.read().bits() as u16) {return Err(error);}
-          }
-        }
-        else if nbytes == 2 {
-          i2c1.cr1.modify(|_, w| {
-            w.ack().clear_bit();
-            w.pos().set_bit()
-          });
-          _sr = i2c1.sr2.read().bits();
-          while i2c1.sr1.read().btf().is_not_finished() {
-            if let Err(error) = scan_i2c_error(i2c1.sr1.read().bits() as u16) {return Err(error);}
-          }
-          if stop {i2c1.cr1.modify(|_, w| w.stop().set_bit());}
-          else {i2c1.cr1.modify(|_, w| w.start().set_bit());}
-          while i2c1.sr1.read().rx_ne().is_empty() {}
-          self.rx_buffer.push(i2c1.dr.read().dr().bits()).unwrap();
-          while i2c1.sr1.read().rx_ne().is_empty() {}
-        }
-        else {
-          _sr = i2c1.sr2.read().bits();
-          if nbytes > 3 {
-            for _ in 0..(nbytes - 3) {
-              while i2c1.sr1.read().btf().is_not_finished() {
-                if let Err(error) = scan_i2c_error(i2c1.sr1.read().bits() as u16) {return Err(error);}
-              }
-              self.rx_buffer.push(i2c1.dr.read().dr().bits()).unwrap();
-            }
-          }
-          while i2c1.sr1.read().btf().is_not_finished() {
-            if let Err(error) = scan_i2c_error(i2c1.sr1.read().bits() as u16) {return Err(error);}
-          }
-          i2c1.cr1.modify(|_, w| w.ack().clear_bit());
-          self.rx_buffer.push(i2c1.dr.read().dr().bits()).unwrap();
-          while i2c1.sr1.read().btf().is_not_finished() {
-            if let Err(error) = scan_i2c_error(i2c1.sr1.read().bits() as u16) {return Err(error);}
-          }
-          if stop {i2c1.cr1.modify(|_, w| w.stop().set_bit());}
-          else {i2c1.cr1.modify(|_, w| w.start().set_bit());}
-          while i2c1.sr1.read().rx_ne().is_empty() {}
-          self.rx_buffer.push(i2c1.dr.read().dr().bits()).unwrap();
-          while i2c1.sr1.read().rx_ne().is_empty() {}
-        }
-
-        self.rx_buffer.push(i2c1.dr.read().dr().bits()).unwrap();
-        i2c1.cr1.modify(|_, w| w.ack().set_bit());
-      },
-      2 => {
-        let i2c2 = &peripheral_ptr.I2C2;
-        i2c2.cr1.write(|w| w.start().set_bit());
-        while i2c2.sr1.read().sb().is_no_start() {}
-        i2c2.dr.write(|w| w.dr().bits((addr << 1) + 1));
-        if nbytes == 1 {i2c2.cr1.modify(|_, w| w.ack().clear_bit());}
-        while i2c2.sr1.read().addr().is_not_match() {
-          if let Err(error) = scan_i2c_error(i2c2.sr1.read().bits() as u16) {return Err(error);}
-        }
-
-        if nbytes == 1 {
-          _sr = i2c2.sr2.read().bits();
-          if stop {i2c2.cr1.modify(|_, w| w.stop().set_bit());}
-          else {i2c2.cr1.modify(|_, w| w.start().set_bit());}
-          while i2c2.sr1.read().rx_ne().is_empty() {
-            if let Err(error) = scan_i2c_error(i2c2.sr1.read().bits() as u16) {return Err(error);}
-          }
-        }
-        else if nbytes == 2 {
-          i2c2.cr1.modify(|_, w| {
-            w.ack().clear_bit();
-            w.pos().set_bit()
-          });
-          _sr = i2c2.sr2.read().bits();
-          while i2c2.sr1.read().btf().is_not_finished() {
-            if let Err(error) = scan_i2c_error(i2c2.sr1.read().bits() as u16) {return Err(error);}
-          }
-          if stop {i2c2.cr1.modify(|_, w| w.stop().set_bit());}
-          else {i2c2.cr1.modify(|_, w| w.start().set_bit());}
-          while i2c2.sr1.read().rx_ne().is_empty() {}
-          self.rx_buffer.push(i2c2.dr.read().dr().bits()).unwrap();
-          while i2c2.sr1.read().rx_ne().is_empty() {}
-        }
-        else {
-          _sr = i2c2.sr2.read().bits();
-          if nbytes > 3 {
-            for _ in 0..(nbytes - 3) {
-              while i2c2.sr1.read().btf().is_not_finished() {
-                if let Err(error) = scan_i2c_error(i2c2.sr1.read().bits() as u16) {return Err(error);}
-              }
-              self.rx_buffer.push(i2c2.dr.read().dr().bits()).unwrap();
-            }
-          }
-          while i2c2.sr1.read().btf().is_not_finished() {
-            if let Err(error) = scan_i2c_error(i2c2.sr1.read().bits() as u16) {return Err(error);}
-          }
-          i2c2.cr1.modify(|_, w| w.ack().clear_bit());
-          self.rx_buffer.push(i2c2.dr.read().dr().bits()).unwrap();
-          while i2c2.sr1.read().btf().is_not_finished() {
-            if let Err(error) = scan_i2c_error(i2c2.sr1.read().bits() as u16) {return Err(error);}
-          }
-          if stop {i2c2.cr1.modify(|_, w| w.stop().set_bit());}
-          else {i2c2.cr1.modify(|_, w| w.start().set_bit());}
-          while i2c2.sr1.read().rx_ne().is_empty() {}
-          self.rx_buffer.push(i2c2.dr.read().dr().bits()).unwrap();
-          while i2c2.sr1.read().rx_ne().is_empty() {}
-        }
+    self.rx_buffer.resize(nbytes as usize, 0).unwrap();
 
-        self.rx_buffer.push(i2c2.dr.read().dr().bits()).unwrap();
-        i2c2.cr1.modify(|_, w| w.ack().set_bit());
-      },
-      3 => {
-        let i2c3 = &peripheral_ptr.I2C3;
-        i2c3.cr1.write(|w| w.start().set_bit());
-        while i2c3.sr1.read().sb().is_no_start() {}
-        i2c3.dr.write(|w| w.dr().bits((addr << 1) + 1));
-        if nbytes == 1 {i2c3.cr1.modify(|_, w| w.ack().clear_bit());}
-        while i2c3.sr1.read().addr().is_not_match() {
-          if let Err(error) = scan_i2c_error(i2c3.sr1.read().bits() as u16) {return Err(error);}
-        }
-
-        if nbytes == 1 {
-          _sr = i2c3.sr2.read().bits();
-          if stop {i2c3.cr1.modify(|_, w| w.stop().set_bit());}
-          else {i2c3.cr1.modify(|_, w| w.start().set_bit());}
-          while i2c3.sr1.read().rx_ne().is_empty() {
-            if let Err(error) = scan_i2c_error(i2c3.sr1.read().bits() as u16) {return Err(error);}
-          }
-        }
-        else if nbytes == 2 {
-          i2c3.cr1.modify(|_, w| {
-            w.ack().clear_bit();
-            w.pos().set_bit()
-          });
-          _sr = i2c3.sr2.read().bits();
-          while i2c3.sr1.read().btf().is_not_finished() {
-            if let Err(error) = scan_i2c_error(i2c3.sr1.read().bits() as u16) {return Err(error);}
-          }
-          if stop {i2c3.cr1.modify(|_, w| w.stop().set_bit());}
-          else {i2c3.cr1.modify(|_, w| w.start().set_bit());}
-          while i2c3.sr1.read().rx_ne().is_empty() {}
-          self.rx_buffer.push(i2c3.dr.read().dr().bits()).unwrap();
-          while i2c3.sr1.read().rx_ne().is_empty() {}
-        }
-        else {
-          _sr = i2c3.sr2.read().bits();
-          if nbytes > 3 {
-            for _ in 0..(nbytes - 3) {
-              while i2c3.sr1.read().btf().is_not_finished() {
-                if let Err(error) = scan_i2c_error(i2c3.sr1.read().bits() as u16) {return Err(error);}
-              }
-              self.rx_buffer.push(i2c3.dr.read().dr().bits()).unwrap();
-            }
-          }
-          while i2c3.sr1.read().btf().is_not_finished() {
-            if let Err(error) = scan_i2c_error(i2c3.sr1.read().bits() as u16) {return Err(error);}
-          }
-          i2c3.cr1.modify(|_, w| w.ack().clear_bit());
-          self.rx_buffer.push(i2c3.dr.read().dr().bits()).unwrap();
-          while i2c3.sr1.read().btf().is_not_finished() {
-            if let Err(error) = scan_i2c_error(i2c3.sr1.read().bits() as u16) {return Err(error);}
-          }
-          if stop {i2c3.cr1.modify(|_, w| w.stop().set_bit());}
-          else {i2c3.cr1.modify(|_, w| w.start().set_bit());}
-          while i2c3.sr1.read().rx_ne().is_empty() {}
-          self.rx_buffer.push(i2c3.dr.read().dr().bits()).unwrap();
-          while i2c3.sr1.read().rx_ne().is_empty() {}
-        }
-
-        self.rx_buffer.push(i2c3.dr.read().dr().bits()).unwrap();
-        i2c3.cr1.modify(|_, w| w.ack().set_bit());
-      },
-      _ => panic!("I2C{} is not a valid core! | .recieve_bytes(...)", self.core)
-    };
+    if let Err(error) = read_blocking(get_i2c(self.core), addr, &mut self.rx_buffer, stop, self.timeout) {
+      self.rx_buffer.clear();
+      return Err(self.handle_error(error));
+    }
 
     return Ok(self.rx_buffer.len());
   }
@@ -506,6 +299,85 @@ impl<const N: usize> I2C<N> {
     return self.rx_buffer.pop();
   }
 
+  /// Writes the bytes to the slave, then reads until the buffer is full after a repeated start. Either of the slices
+  /// can be empty to only read or write. Does not use the internal tx and rx buffers, the bytes are stored in the
+  /// order they are received.
+  /// Returns an error-enum if problems with the connection are detected or the slave does not respond within the
+  /// [timeout](crate::i2c::I2C::set_timeout).
+  pub fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), I2cError> {
+    let i2c = get_i2c(self.core);
+
+    if !bytes.is_empty() {
+      if let Err(error) = write_blocking(i2c, addr, bytes, self.timeout) {return Err(self.handle_error(error));}
+    }
+
+    if buffer.is_empty() {
+      i2c.cr1.modify(|_, w| w.stop().set_bit());
+      return Ok(());
+    }
+
+    if let Err(error) = read_blocking(i2c, addr, buffer, true, self.timeout) {return Err(self.handle_error(error));}
+
+    return Ok(());
+  }
+
+  /// Reads a register of the slave. Returns an error-enum like [write_read](crate::i2c::I2C::write_read).
+  pub fn read_register(&mut self, addr: u8, reg: u8) -> Result<u8, I2cError> {
+    let mut buffer = [0; 1];
+
+    if let Err(error) = self.write_read(addr, &[reg], &mut buffer) {return Err(error);}
+
+    return Ok(buffer[0]);
+  }
+
+  /// Writes a value to a register of the slave. Returns an error-enum like [write_read](crate::i2c::I2C::write_read).
+  pub fn write_register(&mut self, addr: u8, reg: u8, value: u8) -> Result<(), I2cError> {
+    return self.write_read(addr, &[reg, value], &mut []);
+  }
+
+  /// Reads consecutive registers of the slave, starting with the specified register, until the buffer is full. This
+  /// relies on the slave incrementing the register address. Returns an error-enum like
+  /// [write_read](crate::i2c::I2C::write_read).
+  pub fn read_registers(&mut self, addr: u8, reg: u8, buffer: &mut [u8]) -> Result<(), I2cError> {
+    return self.write_read(addr, &[reg], buffer);
+  }
+
+  /// Reads a 16-bit value from two registers of the slave, the high byte first. Returns an error-enum like
+  /// [write_read](crate::i2c::I2C::write_read).
+  pub fn read_register_u16_be(&mut self, addr: u8, reg: u8) -> Result<u16, I2cError> {
+    let mut buffer = [0; 2];
+
+    if let Err(error) = self.write_read(addr, &[reg], &mut buffer) {return Err(error);}
+
+    return Ok(u16::from_be_bytes(buffer));
+  }
+
+  /// Reads a 16-bit value from two registers of the slave, the low byte first. Returns an error-enum like
+  /// [write_read](crate::i2c::I2C::write_read).
+  pub fn read_register_u16_le(&mut self, addr: u8, reg: u8) -> Result<u16, I2cError> {
+    let mut buffer = [0; 2];
+
+    if let Err(error) = self.write_read(addr, &[reg], &mut buffer) {return Err(error);}
+
+    return Ok(u16::from_le_bytes(buffer));
+  }
+
+  /// Writes a 16-bit value to two registers of the slave, the high byte first. Returns an error-enum like
+  /// [write_read](crate::i2c::I2C::write_read).
+  pub fn write_register_u16_be(&mut self, addr: u8, reg: u8, value: u16) -> Result<(), I2cError> {
+    let [high, low] = value.to_be_bytes();
+
+    return self.write_read(addr, &[reg, high, low], &mut []);
+  }
+
+  /// Writes a 16-bit value to two registers of the slave, the low byte first. Returns an error-enum like
+  /// [write_read](crate::i2c::I2C::write_read).
+  pub fn write_register_u16_le(&mut self, addr: u8, reg: u8, value: u16) -> Result<(), I2cError> {
+    let [low, high] = value.to_le_bytes();
+
+    return self.write_read(addr, &[reg, low, high], &mut []);
+  }
+
   /// Set the clock frequency to a specific value in Hz. Default is 100kHz. Values lower than 10kHz or higher than
   /// 400kHz are not recomendet.
   pub fn set_clock(&self, clk: u32) -> Result<(), I2cError> {
@@ -546,10 +418,378 @@ impl<const N: usize> I2C<N> {
 
     return Ok(());
   }
+
+  /// Sets the timeout in milliseconds for every event of the blocking transfers, e.g. the address or a byte being
+  /// acknowledged. The default is 25ms, the timeout of SMBus. A slave that stretches the clock for longer is treated
+  /// as stuck: the transfer is aborted and the [bus is recovered](crate::i2c::I2C::recover_bus).
+  ///
+  /// Without the time base started by [`start_time()`](crate::time::start_time) the timeout is counted with busy
+  /// waiting and only approximate.
+  pub fn set_timeout(&mut self, timeout_ms: usize) {
+    self.timeout = timeout_ms;
+  }
+
+  /// Frees a bus that is blocked by a slave holding SDA low, e.g. after a reset of the master in the middle of a
+  /// transfer. The pins are switched to GPIO, up to nine clock pulses are sent until the slave releases SDA and a
+  /// stop condition is generated. Afterwards the peripheral is reset and configured again with the same clock.
+  ///
+  /// This is done automatically when a blocking transfer times out. Returns an error-enum if SDA is still held low.
+  pub fn recover_bus(&mut self) -> Result<(), I2cError> {
+    let i2c = get_i2c(self.core);
+    let scl = (self._scl_pin.block, self._scl_pin.number);
+    let sda = (self._sda_pin.block, self._sda_pin.number);
+
+    i2c.cr1.modify(|_, w| w.pe().disabled());
+
+    // Both pins are already configured as open-drain, so high releases the line
+    write_pin(scl, true);
+    write_pin(sda, true);
+    write_mode(scl, 1);
+    write_mode(sda, 1);
+    cortex_m::asm::delay(5 * CYCLES_PER_US);
+
+    let mut released = read_pin(sda);
+    for _ in 0..9 {
+      if released {break;}
+      write_pin(scl, false);
+      cortex_m::asm::delay(5 * CYCLES_PER_US);
+      if !release_clock(scl, self.timeout) {break;}
+      cortex_m::asm::delay(5 * CYCLES_PER_US);
+      released = read_pin(sda);
+    }
+
+    // Stop condition: SDA rises while SCL is high
+    write_pin(scl, false);
+    cortex_m::asm::delay(5 * CYCLES_PER_US);
+    write_pin(sda, false);
+    cortex_m::asm::delay(5 * CYCLES_PER_US);
+    release_clock(scl, self.timeout);
+    cortex_m::asm::delay(5 * CYCLES_PER_US);
+    write_pin(sda, true);
+    cortex_m::asm::delay(5 * CYCLES_PER_US);
+    released = read_pin(sda);
+
+    // Back to the alternate function
+    write_mode(scl, 2);
+    write_mode(sda, 2);
+
+    // SWRST clears all registers, the configuration is restored afterwards
+    let cr2 = i2c.cr2.read().bits() & !(7 << 8);
+    let ccr = i2c.ccr.read().bits();
+    let trise = i2c.trise.read().bits();
+    let oar1 = i2c.oar1.read().bits();
+    i2c.cr1.modify(|_, w| w.swrst().set_bit());
+    i2c.cr1.modify(|_, w| w.swrst().clear_bit());
+    i2c.cr2.write(|w| unsafe {w.bits(cr2)});
+    i2c.ccr.write(|w| unsafe {w.bits(ccr)});
+    i2c.trise.write(|w| unsafe {w.bits(trise)});
+    i2c.oar1.write(|w| unsafe {w.bits(oar1)});
+    i2c.cr1.modify(|_, w| {
+      w.ack().set_bit();
+      w.pe().enabled()
+    });
+
+    if !released {
+      rprintln!("SDA is still held low by a slave! | .recover_bus()");
+      return Err(I2cError::Bus);
+    }
+
+    return Ok(());
+  }
+
+  /// Asynchronously writes the bytes to the slave, then reads until the buffer is full after a repeated start. Either
+  /// of the slices can be empty to only read or write. Does not use the internal tx and rx buffers.
+  ///
+  /// The future sleeps until the event interrupts of the peripheral wake it, so other tasks of the
+  /// [executor](crate::executor) can run in the meantime.
+  /// Returns an error-enum if problems with the connection are detected.
+  pub async fn write_read_async(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), I2cError> {
+    let (ev, er) = get_interrupts(self.core);
+    unsafe {
+      NVIC::unmask(ev);
+      NVIC::unmask(er);
+    }
+
+    let result = transfer_async(self.core, addr, bytes, buffer).await;
+    if result.is_err() {abort_transfer(self.core);}
+
+    return result;
+  }
+
+  // Aborts a blocking transfer, a stuck bus is recovered
+  fn handle_error(&mut self, error: I2cError) -> I2cError {
+    abort_transfer(self.core);
+    if error == I2cError::Prog(ProgError::TimedOut) {let _ = self.recover_bus();}
+
+    return error;
+  }
 }
 
 
 // Private Functions ==============================================================================
+async fn transfer_async(core: u8, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), I2cError> {
+  let i2c = get_i2c(core);
+
+  if !bytes.is_empty() {
+    i2c.cr1.modify(|_, w| w.start().set_bit());
+    I2cFlag {core, flag: SB}.await?;
+    i2c.dr.write(|w| w.dr().bits(addr << 1));
+    I2cFlag {core, flag: ADDR}.await?;
+    let _ = i2c.sr2.read().bits();
+
+    for byte in bytes {
+      I2cFlag {core, flag: TXE}.await?;
+      i2c.dr.write(|w| w.dr().bits(*byte));
+    }
+    I2cFlag {core, flag: BTF}.await?;
+  }
+
+  if buffer.is_empty() {
+    i2c.cr1.modify(|_, w| w.stop().set_bit());
+    return Ok(());
+  }
+
+  i2c.cr1.modify(|_, w| {
+    w.ack().set_bit();
+    w.start().set_bit()
+  });
+  I2cFlag {core, flag: SB}.await?;
+  i2c.dr.write(|w| w.dr().bits((addr << 1) + 1));
+  I2cFlag {core, flag: ADDR}.await?;
+
+  let n = buffer.len();
+  if n == 1 {
+    i2c.cr1.modify(|_, w| w.ack().clear_bit());
+    let _ = i2c.sr2.read().bits();
+    i2c.cr1.modify(|_, w| w.stop().set_bit());
+    I2cFlag {core, flag: RXNE}.await?;
+    buffer[0] = i2c.dr.read().dr().bits();
+  }
+  else if n == 2 {
+    i2c.cr1.modify(|_, w| {
+      w.ack().clear_bit();
+      w.pos().set_bit()
+    });
+    let _ = i2c.sr2.read().bits();
+    I2cFlag {core, flag: BTF}.await?;
+    i2c.cr1.modify(|_, w| w.stop().set_bit());
+    buffer[0] = i2c.dr.read().dr().bits();
+    buffer[1] = i2c.dr.read().dr().bits();
+  }
+  else {
+    let _ = i2c.sr2.read().bits();
+    for byte in buffer.iter_mut().take(n - 3) {
+      I2cFlag {core, flag: RXNE}.await?;
+      *byte = i2c.dr.read().dr().bits();
+    }
+    // Byte N-2 in DR, N-1 in the shift register: NACK the last byte
+    I2cFlag {core, flag: BTF}.await?;
+    i2c.cr1.modify(|_, w| w.ack().clear_bit());
+    buffer[n - 3] = i2c.dr.read().dr().bits();
+    I2cFlag {core, flag: BTF}.await?;
+    i2c.cr1.modify(|_, w| w.stop().set_bit());
+    buffer[n - 2] = i2c.dr.read().dr().bits();
+    I2cFlag {core, flag: RXNE}.await?;
+    buffer[n - 1] = i2c.dr.read().dr().bits();
+  }
+
+  i2c.cr1.modify(|_, w| {
+    w.pos().clear_bit();
+    w.ack().set_bit()
+  });
+
+  return Ok(());
+}
+
+fn write_blocking(i2c: &RegisterBlock, addr: u8, bytes: &[u8], timeout_ms: usize) -> Result<(), I2cError> {
+  i2c.cr1.modify(|_, w| w.start().set_bit());
+  if let Err(error) = wait_flag(i2c, SB, timeout_ms) {return Err(error);}
+  i2c.dr.write(|w| w.dr().bits(addr << 1));
+  if let Err(error) = wait_flag(i2c, ADDR, timeout_ms) {return Err(error);}
+  let _ = i2c.sr2.read().bits();
+
+  for byte in bytes {
+    if let Err(error) = wait_flag(i2c, TXE, timeout_ms) {return Err(error);}
+    i2c.dr.write(|w| w.dr().bits(*byte));
+  }
+
+  // BTF is never set for an address probe without data
+  if bytes.is_empty() {return Ok(());}
+  return wait_flag(i2c, BTF, timeout_ms);
+}
+
+// Receives the buffer and ends with a stop bit or a repeated start
+fn read_blocking(i2c: &RegisterBlock, addr: u8, buffer: &mut [u8], stop: bool, timeout_ms: usize) -> Result<(), I2cError> {
+  let end = |i2c: &RegisterBlock| {
+    if stop {i2c.cr1.modify(|_, w| w.stop().set_bit());}
+    else {i2c.cr1.modify(|_, w| w.start().set_bit());}
+  };
+
+  i2c.cr1.modify(|_, w| {
+    w.ack().set_bit();
+    w.start().set_bit()
+  });
+  if let Err(error) = wait_flag(i2c, SB, timeout_ms) {return Err(error);}
+  i2c.dr.write(|w| w.dr().bits((addr << 1) + 1));
+  if let Err(error) = wait_flag(i2c, ADDR, timeout_ms) {return Err(error);}
+
+  let n = buffer.len();
+  if n == 1 {
+    i2c.cr1.modify(|_, w| w.ack().clear_bit());
+    let _ = i2c.sr2.read().bits();
+    end(i2c);
+    if let Err(error) = wait_flag(i2c, RXNE, timeout_ms) {return Err(error);}
+    buffer[0] = i2c.dr.read().dr().bits();
+  }
+  else if n == 2 {
+    i2c.cr1.modify(|_, w| {
+      w.ack().clear_bit();
+      w.pos().set_bit()
+    });
+    let _ = i2c.sr2.read().bits();
+    if let Err(error) = wait_flag(i2c, BTF, timeout_ms) {return Err(error);}
+    end(i2c);
+    buffer[0] = i2c.dr.read().dr().bits();
+    buffer[1] = i2c.dr.read().dr().bits();
+  }
+  else {
+    let _ = i2c.sr2.read().bits();
+    for byte in buffer.iter_mut().take(n - 3) {
+      if let Err(error) = wait_flag(i2c, RXNE, timeout_ms) {return Err(error);}
+      *byte = i2c.dr.read().dr().bits();
+    }
+    // Byte N-2 in DR, N-1 in the shift register: NACK the last byte
+    if let Err(error) = wait_flag(i2c, BTF, timeout_ms) {return Err(error);}
+    i2c.cr1.modify(|_, w| w.ack().clear_bit());
+    buffer[n - 3] = i2c.dr.read().dr().bits();
+    if let Err(error) = wait_flag(i2c, BTF, timeout_ms) {return Err(error);}
+    end(i2c);
+    buffer[n - 2] = i2c.dr.read().dr().bits();
+    if let Err(error) = wait_flag(i2c, RXNE, timeout_ms) {return Err(error);}
+    buffer[n - 1] = i2c.dr.read().dr().bits();
+  }
+
+  i2c.cr1.modify(|_, w| {
+    w.pos().clear_bit();
+    w.ack().set_bit()
+  });
+
+  return Ok(());
+}
+
+// Busy waits for an event flag in SR1
+fn wait_flag(i2c: &RegisterBlock, flag: u32, timeout_ms: usize) -> Result<(), I2cError> {
+  let mut deadline = Deadline::new(timeout_ms);
+
+  loop {
+    let sr1 = i2c.sr1.read().bits();
+
+    if let Err(error) = scan_i2c_error(sr1 as u16) {return Err(error);}
+    if sr1 & flag != 0 {return Ok(());}
+    if deadline.expired() {
+      rprintln!("The slave did not respond in time! | I2C");
+      return Err(I2cError::Prog(ProgError::TimedOut));
+    }
+  }
+}
+
+// Uses millis or counts down microseconds of busy waiting if the time base is not started
+struct Deadline {
+  timed: bool,
+  end: usize
+}
+
+impl Deadline {
+  fn new(timeout_ms: usize) -> Self {
+    if is_time_started() {return Self {timed: true, end: millis().wrapping_add(timeout_ms)};}
+    else {return Self {timed: false, end: timeout_ms.saturating_mul(1000)};}
+  }
+
+  fn expired(&mut self) -> bool {
+    if self.timed {return is_expired(millis(), self.end);}
+    if self.end == 0 {return true;}
+
+    cortex_m::asm::delay(CYCLES_PER_US);
+    self.end -= 1;
+    return false;
+  }
+}
+
+// Releases SCL and waits while a slave stretches the clock, returns false if it is held low
+fn release_clock(scl: (char, u8), timeout_ms: usize) -> bool {
+  let mut deadline = Deadline::new(timeout_ms);
+
+  write_pin(scl, true);
+  while !read_pin(scl) {
+    if deadline.expired() {return false;}
+  }
+
+  return true;
+}
+
+fn abort_transfer(core: u8) {
+  let i2c = get_i2c(core);
+
+  i2c.cr2.modify(|r, w| unsafe {w.bits(r.bits() & !(7 << 8))});
+  i2c.sr1.modify(|r, w| unsafe {w.bits(r.bits() & !(0xF << 8))});
+  i2c.cr1.modify(|_, w| {
+    w.pos().clear_bit();
+    w.ack().set_bit();
+    w.stop().set_bit()
+  });
+}
+
+// Waits for an event flag in SR1, woken by the event and error interrupts.
+struct I2cFlag {
+  core: u8,
+  flag: u32
+}
+
+impl Future for I2cFlag {
+  type Output = Result<(), I2cError>;
+
+  fn poll(self: core::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let i2c = get_i2c(self.core);
+    let sr1 = i2c.sr1.read().bits();
+
+    if let Err(error) = scan_i2c_error(sr1 as u16) {return Poll::Ready(Err(error));}
+    if sr1 & self.flag != 0 {return Poll::Ready(Ok(()));}
+
+    I2C_WAKERS[self.core as usize - 1].register(cx.waker());
+    // ITERREN, ITEVTEN and ITBUFEN, disabled again by the interrupt
+    i2c.cr2.modify(|r, w| unsafe {w.bits(r.bits() | (7 << 8))});
+
+    return Poll::Pending;
+  }
+}
+
+fn get_i2c(core: u8) -> &'static RegisterBlock {
+  unsafe {
+    match core {
+      1 => &*stm32f4::stm32f446::I2C1::ptr(),
+      2 => &*stm32f4::stm32f446::I2C2::ptr(),
+      3 => &*stm32f4::stm32f446::I2C3::ptr(),
+      _ => unreachable!()
+    }
+  }
+}
+
+fn get_interrupts(core: u8) -> (Interrupt, Interrupt) {
+  return match core {
+    1 => (Interrupt::I2C1_EV, Interrupt::I2C1_ER),
+    2 => (Interrupt::I2C2_EV, Interrupt::I2C2_ER),
+    3 => (Interrupt::I2C3_EV, Interrupt::I2C3_ER),
+    _ => unreachable!()
+  };
+}
+
+fn i2c_interrupt(core: u8) {
+  let i2c = get_i2c(core);
+
+  i2c.cr2.modify(|r, w| unsafe {w.bits(r.bits() & !(7 << 8))});
+  I2C_WAKERS[core as usize - 1].wake();
+}
+
 fn calc_i2c_freq(freq: u32) -> (u32, u32) {
   // (I2C_T / 2) / BUS_T ->  BUS_FREQ / (I2C_FREQ * 2)
   let ccr_t = BUS_FREQ / (2 * freq);
@@ -569,3 +809,41 @@ fn scan_i2c_error(sr: u16) -> Result<(), I2cError> {
   else if status & 0b0000000100000000 > 0 {return Err(I2cError::Bus);}
   else {return Ok(());}
 }
+
+
+// Interrupts =====================================================================================
+#[allow(non_snake_case)]
+#[interrupt]
+fn I2C1_EV() {
+  i2c_interrupt(1);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn I2C1_ER() {
+  i2c_interrupt(1);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn I2C2_EV() {
+  i2c_interrupt(2);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn I2C2_ER() {
+  i2c_interrupt(2);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn I2C3_EV() {
+  i2c_interrupt(3);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn I2C3_ER() {
+  i2c_interrupt(3);
+}
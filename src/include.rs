@@ -172,6 +172,28 @@ pub const UART_MAP: UARTMap = UARTMap {
   cores:   [1,   1,  1,   1,  3,   3,   3,   3,   3,   3,   4,  4,   4,   4,   5,   6]
 };
 
+#[doc(hidden)]
+pub struct UARTFlowMap {
+  pub rts_pins: [(char, u8); 3],
+  pub cts_pins: [(char, u8); 3],
+  pub cores: [u8; 3]
+}
+
+/// Pinmap for the hardware flow control of the UART peripheral.
+/// 
+/// In [`UART::enable_flow_control()`](crate::uart::UART::enable_flow_control) choose the RTS and CTS pins of the core. UART4 and UART5 have no flow control lines. The lines of USART6 are only available on port G, which this package does not have.
+/// 
+/// | UART Core | RTS Pin | CTS Pin |
+/// | --------- | ------- | ------- |
+/// | 1         | PA12    | PA11    |
+/// | 2         | PA1     | PA0     |
+/// | 3         | PB14    | PB13    |
+pub const UART_FLOW_MAP: UARTFlowMap = UARTFlowMap {
+  rts_pins: [A12, A1, B14],
+  cts_pins: [A11, A0, B13],
+  cores:    [1,   2,  3]
+};
+
 #[doc(hidden)]
 pub struct I2CMap {
   pub scl_pins: [(char, u8); 9],
@@ -288,6 +310,132 @@ pub enum SerialError {
   Prog(ProgError)
 }
 
+/// A LIN specific error.
+///
+/// This error type contains errors specific to the LIN bus. Also it has a "Serial" kind to pass through errors of the
+/// underlying serial connection and a "Prog" kind for implementation specific errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LinError {
+  /// The checksum of a received frame does not match its data
+  Checksum,
+  /// The parity bits of a received protected identifier are wrong
+  IdParity,
+  /// No or not enough bytes arrived in time, e.g. because no slave answered a header
+  NoResponse,
+  /// A sent byte was not read back from the bus, e.g. due to a collision with another node
+  BitError,
+  /// The serial connection detected an error
+  Serial(SerialError),
+  /// Implementation specific error (shared across all peripheral specific error kinds)
+  Prog(ProgError)
+}
+
+/// A Modbus specific error.
+///
+/// This error type contains errors specific to the Modbus protocol. Also it has a "Serial" kind to pass through
+/// errors of the underlying serial connection and a "Prog" kind for implementation specific errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ModbusError {
+  /// The slave answered with an exception code, see [Exception](crate::modbus::Exception)
+  Exception(u8),
+  /// The CRC of a received frame does not match its content
+  Crc,
+  /// The slave did not answer in time
+  NoResponse,
+  /// A received frame is too short, too long or does not match the request
+  InvalidFrame,
+  /// The serial connection detected an error
+  Serial(SerialError),
+  /// Implementation specific error (shared across all peripheral specific error kinds)
+  Prog(ProgError)
+}
+
+/// A packet framing specific error.
+///
+/// This error type contains errors of the [framing](crate::framing) module. Also it has a "Serial" kind to pass
+/// through errors of the underlying serial connection and a "Prog" kind for implementation specific errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FramingError {
+  /// The packet does not fit into the buffer
+  TooLong,
+  /// The CRC trailer of a received packet does not match its data
+  Crc,
+  /// A received frame is not valid COBS or SLIP, e.g. because bytes were lost
+  Encoding,
+  /// The serial connection detected an error
+  Serial(SerialError),
+  /// Implementation specific error (shared across all peripheral specific error kinds)
+  Prog(ProgError)
+}
+
+/// A NMEA specific error.
+///
+/// This error type contains errors of the [nmea](crate::nmea) module. Also it has a "Serial" kind to pass through
+/// errors of the underlying serial connection and a "Prog" kind for implementation specific errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NmeaError {
+  /// The checksum of a sentence is missing or does not match its content
+  Checksum,
+  /// A sentence or one of its fields is malformed
+  Format,
+  /// The sentence type is not decoded by the parser
+  Unsupported,
+  /// The sentence is longer than the buffer
+  TooLong,
+  /// The serial connection detected an error
+  Serial(SerialError),
+  /// Implementation specific error (shared across all peripheral specific error kinds)
+  Prog(ProgError)
+}
+
+/// An AT command specific error.
+///
+/// This error type contains the error results of the [at](crate::at) module. Also it has a "Serial" kind to pass
+/// through errors of the underlying serial connection and a "Prog" kind for implementation specific errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AtError {
+  /// The modem answered with `ERROR` or another final result code that reports a failure
+  Error,
+  /// The modem answered with `+CME ERROR: <code>` (equipment error)
+  CmeError(u16),
+  /// The modem answered with `+CMS ERROR: <code>` (message service error)
+  CmsError(u16),
+  /// The serial connection detected an error
+  Serial(SerialError),
+  /// Implementation specific error (shared across all peripheral specific error kinds)
+  Prog(ProgError)
+}
+
+/// A firmware update specific error.
+///
+/// This error type contains errors of the [firmware](crate::firmware) module. Also it has a "Serial" kind to pass
+/// through errors of the underlying serial connection and a "Prog" kind for implementation specific errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FirmwareError {
+  /// The sender cancelled the transfer
+  Cancelled,
+  /// Too many damaged blocks or blocks out of sequence
+  Protocol,
+  /// The image does not fit into the staging sector
+  TooLarge,
+  /// Erasing or programming the flash failed
+  Flash,
+  /// The length or CRC of an image does not match its header, or it has no valid vector table
+  Verify,
+  /// There is no image
+  NoImage,
+  /// The serial connection detected an error
+  Serial(SerialError),
+  /// Implementation specific error (shared across all peripheral specific error kinds)
+  Prog(ProgError)
+}
+
 /// An I2C specific error.
 ///
 /// This error type contains errors specific to I2C peripherals. Also it has an "Prog" kind to pass
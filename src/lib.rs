@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![allow(clippy::needless_return)]
 #![deny(warnings)]
 
@@ -21,13 +21,32 @@ pub mod analog;
 pub mod time;
 pub mod uart;
 pub mod i2c;
+pub mod executor;
+pub mod rtc;
+pub mod backup;
+pub mod watchdog;
+pub mod power;
+pub mod dma;
+pub mod stream;
+pub mod lin;
+pub mod modbus;
+pub mod framing;
+pub mod shell;
+pub mod nmea;
+pub mod at;
+pub mod firmware;
+pub mod usb;
+pub mod hid;
 // pub mod spi;
 
 
 // Panic handler ==================================================================================
+#[cfg(not(test))]
 use core::panic::PanicInfo;
+#[cfg(not(test))]
 use core::sync::atomic::{compiler_fence, Ordering};
 
+#[cfg(not(test))]
 #[inline(never)]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
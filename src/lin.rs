@@ -0,0 +1,554 @@
+//! This module contains everything that is used for the LIN bus.
+//!
+//! LIN is a single-wire bus for cars, where a master sends headers (break, sync byte and protected identifier) and
+//! the master itself or a slave answers with up to 8 data bytes and a checksum. It runs on a
+//! [UART](crate::uart::UART) with a LIN transceiver. Since the bus is a single wire, every sent byte is also
+//! received and compared with the sent one.
+//!
+//! | Identifier | Checksum                                            |
+//! | ---------- | --------------------------------------------------- |
+//! | 0 - 59     | Classic (LIN 1.x) or enhanced (LIN 2.x) per frame   |
+//! | 60, 61     | Always classic (diagnostic frames)                  |
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::uart::UART;
+//! use rustuino::lin::{LinMaster, ScheduleEntry, Checksum};
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   start_time();
+//!   let uart = UART::new(1, PA9, PA10, 19200).unwrap();
+//!   let mut master = LinMaster::new(uart).unwrap();
+//!
+//!   // Send the lights every 20ms and read a switch panel 10ms later
+//!   let mut schedule = [
+//!     ScheduleEntry::publish(0x10, &[0x01, 0xFF], Checksum::Enhanced, 10).unwrap(),
+//!     ScheduleEntry::subscribe(0x21, 4, Checksum::Enhanced, 10).unwrap()
+//!   ];
+//!
+//!   loop {
+//!     if let Some((id, Ok(()))) = master.poll_schedule(&mut schedule) {
+//!       if id == 0x21 {rprintln!("Switches: {:?}", schedule[1].data());}
+//!     }
+//!   }
+//! }
+//! ```
+
+#![allow(clippy::question_mark)]
+
+use crate::include::{LinError, ProgError, SerialError};
+use crate::uart::UART;
+use crate::time::{millis, is_expired};
+use heapless::Vec;
+use rtt_target::rprintln;
+
+/// The byte after the break that lets the slaves synchronize to the baudrate.
+pub const SYNC_BYTE: u8 = 0x55;
+
+// Bytes from the bus that belong to the break, e.g. 0x00 with a framing error
+const MAX_BREAK_BYTES: u8 = 3;
+
+
+/// Represents the checksum model of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+  /// Only over the data (LIN 1.x)
+  Classic,
+  /// Over the protected identifier and the data (LIN 2.x)
+  Enhanced
+}
+
+/// Represents who sends the response of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+  /// This node sends the data after the header
+  Publish,
+  /// Another node sends the data after the header
+  Subscribe
+}
+
+/// A frame of a [LinSlave] or a slot of a master schedule table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleEntry {
+  #[doc(hidden)]
+  id: u8,
+  #[doc(hidden)]
+  direction: Direction,
+  #[doc(hidden)]
+  checksum: Checksum,
+  #[doc(hidden)]
+  data: Vec<u8, 8>,
+  #[doc(hidden)]
+  len: u8,
+  #[doc(hidden)]
+  slot_ms: u16
+}
+
+impl ScheduleEntry {
+  /// A frame where this node sends the data. `slot_ms` is the time until the next slot of the schedule table and is
+  /// ignored by slaves.
+  ///
+  /// Returns an error-enum if the identifier is larger than 63 or there are more than 8 data bytes.
+  pub fn publish(id: u8, data: &[u8], checksum: Checksum, slot_ms: u16) -> Result<Self, ProgError> {
+    if id > 63 || data.len() > 8 {
+      rprintln!("Invalid LIN frame! | ScheduleEntry::publish()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    return Ok(Self {
+      id,
+      direction: Direction::Publish,
+      checksum,
+      data: Vec::from_slice(data).unwrap(),
+      len: data.len() as u8,
+      slot_ms
+    });
+  }
+
+  /// A frame where another node sends `len` data bytes. `slot_ms` is the time until the next slot of the schedule
+  /// table and is ignored by slaves.
+  ///
+  /// Returns an error-enum if the identifier is larger than 63 or the length is larger than 8.
+  pub fn subscribe(id: u8, len: u8, checksum: Checksum, slot_ms: u16) -> Result<Self, ProgError> {
+    if id > 63 || len > 8 {
+      rprintln!("Invalid LIN frame! | ScheduleEntry::subscribe()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    return Ok(Self {
+      id,
+      direction: Direction::Subscribe,
+      checksum,
+      data: Vec::new(),
+      len,
+      slot_ms
+    });
+  }
+
+  /// Returns the identifier of the frame.
+  pub fn id(&self) -> u8 {
+    return self.id;
+  }
+
+  /// Returns the data that is sent, or the data that was received last.
+  pub fn data(&self) -> &[u8] {
+    return &self.data;
+  }
+
+  /// Replaces the data that is sent. Returns an error-enum if the length does not match the frame.
+  pub fn set_data(&mut self, data: &[u8]) -> Result<(), ProgError> {
+    if data.len() != self.len as usize {
+      rprintln!("The data does not match the frame length! | .set_data()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    self.data = Vec::from_slice(data).unwrap();
+    return Ok(());
+  }
+}
+
+
+/// This struct represents a LIN master on a UART.
+pub struct LinMaster {
+  #[doc(hidden)]
+  uart: UART,
+  #[doc(hidden)]
+  slot: usize,
+  #[doc(hidden)]
+  next_slot: usize
+}
+
+impl LinMaster {
+  /// Enables the LIN mode on the UART. Needs the time base started with [`start_time()`](crate::time::start_time).
+  /// Returns an error-enum if the UART is not configured with 8N1.
+  pub fn new(mut uart: UART) -> Result<Self, ProgError> {
+    if let Err(error) = uart.enable_lin() {return Err(error);}
+
+    return Ok(Self {uart, slot: 0, next_slot: 0});
+  }
+
+  /// Sends a header and the data with the checksum. Returns an error-enum if the frame is invalid or the bus does not
+  /// read back the sent bytes.
+  pub fn send_frame(&mut self, id: u8, data: &[u8], checksum: Checksum) -> Result<(), LinError> {
+    let frame = match build_frame(id, data, checksum) {
+      Some(frame) => frame,
+      None => {
+        rprintln!("Invalid LIN frame! | .send_frame()");
+        return Err(LinError::Prog(ProgError::InvalidConfiguration));
+      }
+    };
+
+    return self.send_header_and(&frame);
+  }
+
+  /// Sends a header and receives `len` data bytes from a slave. Returns the data or an error-enum if no slave answered
+  /// in time or the checksum is wrong.
+  pub fn request_frame(&mut self, id: u8, len: u8, checksum: Checksum) -> Result<Vec<u8, 8>, LinError> {
+    if id > 63 || len > 8 {
+      rprintln!("Invalid LIN frame! | .request_frame()");
+      return Err(LinError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    let pid = protected_id(id);
+    if let Err(error) = self.send_header_and(&[SYNC_BYTE, pid]) {return Err(error);}
+
+    let mut response = [0; 9];
+    let timeout = response_timeout(self.uart.config().baud, len);
+    if let Err(error) = read_exact(&self.uart, &mut response[..len as usize + 1], timeout) {return Err(error);}
+
+    return match check_response(pid, &response[..len as usize + 1], checksum) {
+      Ok(data) => Ok(Vec::from_slice(data).unwrap()),
+      Err(error) => Err(error)
+    };
+  }
+
+  /// Runs the next slot of a schedule table if its time has come. Returns the identifier of the frame and the result
+  /// of the transfer, or `None` if it is not time for the next slot yet. Received data is stored in the entry.
+  pub fn poll_schedule(&mut self, schedule: &mut [ScheduleEntry]) -> Option<(u8, Result<(), LinError>)> {
+    let now = millis();
+    if schedule.is_empty() || !is_expired(now, self.next_slot) {return None;}
+
+    if self.slot >= schedule.len() {self.slot = 0;}
+    let entry = &mut schedule[self.slot];
+
+    // The slots follow each other without the frame time adding up. After a pause, like before the first call, the
+    // schedule starts again from now.
+    let start = if now.wrapping_sub(self.next_slot) > entry.slot_ms as usize {now} else {self.next_slot};
+
+    let result = match entry.direction {
+      Direction::Publish => {
+        let data = entry.data.clone();
+        self.send_frame(entry.id, &data, entry.checksum)
+      },
+      Direction::Subscribe => match self.request_frame(entry.id, entry.len, entry.checksum) {
+        Ok(data) => {
+          entry.data = data;
+          Ok(())
+        },
+        Err(error) => Err(error)
+      }
+    };
+
+    self.next_slot = start.wrapping_add(entry.slot_ms as usize);
+    self.slot += 1;
+
+    return Some((entry.id, result));
+  }
+
+  /// Disables the LIN mode and returns the UART.
+  pub fn release(mut self) -> UART {
+    self.uart.disable_lin();
+    return self.uart;
+  }
+
+  fn send_header_and(&mut self, bytes: &[u8]) -> Result<(), LinError> {
+    if let Err(error) = self.uart.send_break() {return Err(LinError::Serial(error));}
+
+    for byte in bytes {
+      if let Err(error) = self.uart.write(*byte) {return Err(LinError::Serial(error));}
+    }
+
+    // The bus echoes the break and every byte
+    let timeout = response_timeout(self.uart.config().baud, bytes.len() as u8);
+    return read_echo(&self.uart, bytes, timeout);
+  }
+}
+
+
+/// This struct represents a LIN slave on a UART that answers the headers of its frames.
+pub struct LinSlave<const N: usize> {
+  #[doc(hidden)]
+  uart: UART,
+  #[doc(hidden)]
+  frames: Vec<ScheduleEntry, N>
+}
+
+impl<const N: usize> LinSlave<N> {
+  /// Enables the LIN mode on the UART. Specify the maximum number of frames with the turbofish operator.
+  /// Needs the time base started with [`start_time()`](crate::time::start_time). Returns an error-enum if the UART
+  /// is not configured with 8N1.
+  pub fn new(mut uart: UART) -> Result<Self, ProgError> {
+    if let Err(error) = uart.enable_lin() {return Err(error);}
+
+    return Ok(Self {uart, frames: Vec::new()});
+  }
+
+  /// Adds a frame the slave answers or listens to. A frame with the same identifier is replaced.
+  /// Returns an error-enum if there is no space for the frame.
+  pub fn add_frame(&mut self, frame: ScheduleEntry) -> Result<(), ProgError> {
+    if let Some(existing) = self.frames.iter_mut().find(|i| i.id == frame.id) {
+      *existing = frame;
+      return Ok(());
+    }
+
+    if self.frames.push(frame).is_err() {
+      rprintln!("There is no space for more LIN frames! | .add_frame()");
+      return Err(ProgError::OutOfMemory);
+    }
+
+    return Ok(());
+  }
+
+  /// Replaces the data of a published frame. Returns an error-enum if the frame was not added or the length does not
+  /// match.
+  pub fn set_response(&mut self, id: u8, data: &[u8]) -> Result<(), ProgError> {
+    return match self.frames.iter_mut().find(|i| i.id == id && i.direction == Direction::Publish) {
+      Some(frame) => frame.set_data(data),
+      None => Err(ProgError::InvalidConfiguration)
+    };
+  }
+
+  /// Handles a header if a break was received. Answers published frames and returns received frames.
+  ///
+  /// Returns `None` if no break was received or the header belongs to an unknown frame. Call this often, the master
+  /// expects an answer within a few bit times.
+  pub fn poll(&mut self) -> Option<Result<ScheduleEntry, LinError>> {
+    if !self.uart.break_detected() {return None;}
+
+    let baud = self.uart.config().baud;
+    let mut header = [0; 2];
+    if let Err(error) = read_after_break(&self.uart, &mut header, response_timeout(baud, 1)) {return Some(Err(error));}
+    if header[0] != SYNC_BYTE {return Some(Err(LinError::NoResponse));}
+
+    let id = match parse_pid(header[1]) {
+      Some(id) => id,
+      None => return Some(Err(LinError::IdParity))
+    };
+
+    let frame = self.frames.iter_mut().find(|i| i.id == id)?;
+
+    let result = match frame.direction {
+      Direction::Publish => {
+        let bytes = build_frame(id, &frame.data, frame.checksum).unwrap();
+
+        for byte in &bytes[2..] {
+          if let Err(error) = self.uart.write(*byte) {return Some(Err(LinError::Serial(error)));}
+        }
+        read_exact_echo(&self.uart, &bytes[2..], response_timeout(baud, frame.len))
+      },
+      Direction::Subscribe => {
+        let mut response = [0; 9];
+        let len = frame.len as usize + 1;
+
+        match read_exact(&self.uart, &mut response[..len], response_timeout(baud, frame.len)) {
+          Ok(()) => match check_response(header[1], &response[..len], frame.checksum) {
+            Ok(data) => {
+              frame.data = Vec::from_slice(data).unwrap();
+              Ok(())
+            },
+            Err(error) => Err(error)
+          },
+          Err(error) => Err(error)
+        }
+      }
+    };
+
+    return Some(result.map(|_| frame.clone()));
+  }
+
+  /// Disables the LIN mode and returns the UART.
+  pub fn release(mut self) -> UART {
+    self.uart.disable_lin();
+    return self.uart;
+  }
+}
+
+
+// Frame Functions ================================================================================
+/// Adds the two parity bits to a 6 bit identifier.
+pub fn protected_id(id: u8) -> u8 {
+  let bit = |n: u8| (id >> n) & 1;
+
+  let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+  let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+
+  return (id & 0x3F) | (p0 << 6) | (p1 << 7);
+}
+
+/// Checks the parity bits of a protected identifier and returns the identifier.
+pub fn parse_pid(pid: u8) -> Option<u8> {
+  let id = pid & 0x3F;
+  if protected_id(id) == pid {return Some(id);}
+  else {return None;}
+}
+
+/// Calculates the checksum of a frame. The diagnostic frames 60 and 61 always use the classic checksum.
+pub fn checksum(pid: u8, data: &[u8], model: Checksum) -> u8 {
+  let id = pid & 0x3F;
+  let mut sum: u16 = if model == Checksum::Enhanced && id != 0x3C && id != 0x3D {pid as u16} else {0};
+
+  // Sum with carry
+  for byte in data {
+    sum += *byte as u16;
+    if sum > 0xFF {sum -= 0xFF;}
+  }
+
+  return !(sum as u8);
+}
+
+/// Builds the bytes of a frame after the break: sync byte, protected identifier, data and checksum.
+/// Returns `None` if the identifier is larger than 63 or there are more than 8 data bytes.
+pub fn build_frame(id: u8, data: &[u8], model: Checksum) -> Option<Vec<u8, 11>> {
+  if id > 63 || data.len() > 8 {return None;}
+
+  let pid = protected_id(id);
+  let mut frame: Vec<u8, 11> = Vec::new();
+
+  frame.push(SYNC_BYTE).unwrap();
+  frame.push(pid).unwrap();
+  frame.extend_from_slice(data).unwrap();
+  frame.push(checksum(pid, data, model)).unwrap();
+
+  return Some(frame);
+}
+
+/// Checks the checksum of a response (data and checksum byte) and returns the data.
+pub fn check_response(pid: u8, response: &[u8], model: Checksum) -> Result<&[u8], LinError> {
+  let (data, received) = match response.split_last() {
+    Some((received, data)) => (data, *received),
+    None => return Err(LinError::NoResponse)
+  };
+
+  if checksum(pid, data, model) != received {return Err(LinError::Checksum);}
+
+  return Ok(data);
+}
+
+/// Returns the maximum time in milliseconds a response with `len` data bytes may take, which is 1.4 times the
+/// nominal frame time.
+pub fn response_timeout(baud: u32, len: u8) -> usize {
+  let bits = 14 * (34 + 10 * (len as u32 + 1)) / 10;
+  return (bits * 1000).div_ceil(baud.max(1)) as usize + 1;
+}
+
+
+// Private Functions ==============================================================================
+fn read_exact(uart: &UART, buffer: &mut [u8], timeout_ms: usize) -> Result<(), LinError> {
+  let deadline = millis().wrapping_add(timeout_ms);
+
+  for byte in buffer.iter_mut() {
+    *byte = match uart.read_byte_timeout(remaining_ms(deadline)) {
+      Ok(value) => value,
+      Err(SerialError::Prog(ProgError::TimedOut)) => return Err(LinError::NoResponse),
+      Err(error) => return Err(LinError::Serial(error))
+    };
+  }
+
+  return Ok(());
+}
+
+// Skips the bytes that the break caused
+fn read_after_break(uart: &UART, buffer: &mut [u8], timeout_ms: usize) -> Result<(), LinError> {
+  let deadline = millis().wrapping_add(timeout_ms);
+  let mut skipped = 0;
+
+  loop {
+    match uart.read_byte_timeout(remaining_ms(deadline)) {
+      Ok(SYNC_BYTE) => break,
+      Ok(0) | Err(SerialError::FrameFormat) if skipped < MAX_BREAK_BYTES => skipped += 1,
+      Ok(_) => return Err(LinError::BitError),
+      Err(SerialError::Prog(ProgError::TimedOut)) => return Err(LinError::NoResponse),
+      Err(error) => return Err(LinError::Serial(error))
+    };
+  }
+
+  buffer[0] = SYNC_BYTE;
+  return read_exact(uart, &mut buffer[1..], timeout_ms);
+}
+
+fn read_echo(uart: &UART, sent: &[u8], timeout_ms: usize) -> Result<(), LinError> {
+  let mut echo = [0; 11];
+  let echo = &mut echo[..sent.len()];
+
+  if let Err(error) = read_after_break(uart, echo, timeout_ms) {return Err(error);}
+  if echo != sent {return Err(LinError::BitError);}
+
+  return Ok(());
+}
+
+fn read_exact_echo(uart: &UART, sent: &[u8], timeout_ms: usize) -> Result<(), LinError> {
+  let mut echo = [0; 9];
+  let echo = &mut echo[..sent.len()];
+
+  if let Err(error) = read_exact(uart, echo, timeout_ms) {return Err(error);}
+  if echo != sent {return Err(LinError::BitError);}
+
+  return Ok(());
+}
+
+// Time until the deadline, 0 if it has passed
+fn remaining_ms(deadline: usize) -> usize {
+  let now = millis();
+  if is_expired(now, deadline) {return 0;}
+  return deadline.wrapping_sub(now);
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn schedule_entries() {
+    let mut entry = ScheduleEntry::publish(0x10, &[1, 2], Checksum::Enhanced, 10).unwrap();
+    assert_eq!((entry.id(), entry.data()), (0x10, &[1, 2][..]));
+    assert_eq!(entry.set_data(&[3, 4]), Ok(()));
+    assert_eq!(entry.data(), [3, 4]);
+    // The length of a frame is fixed
+    assert_eq!(entry.set_data(&[5]), Err(ProgError::InvalidConfiguration));
+    assert_eq!(entry.data(), [3, 4]);
+
+    assert!(ScheduleEntry::publish(63, &[0; 8], Checksum::Classic, 10).is_ok());
+    assert_eq!(ScheduleEntry::publish(64, &[], Checksum::Classic, 10), Err(ProgError::InvalidConfiguration));
+    assert_eq!(ScheduleEntry::publish(1, &[0; 9], Checksum::Classic, 10), Err(ProgError::InvalidConfiguration));
+
+    assert!(ScheduleEntry::subscribe(63, 8, Checksum::Classic, 10).is_ok());
+    assert_eq!(ScheduleEntry::subscribe(64, 1, Checksum::Classic, 10), Err(ProgError::InvalidConfiguration));
+    assert_eq!(ScheduleEntry::subscribe(1, 9, Checksum::Classic, 10), Err(ProgError::InvalidConfiguration));
+  }
+
+  #[test]
+  fn protected_identifiers() {
+    assert_eq!(protected_id(0x00), 0x80);
+    assert_eq!(protected_id(0x01), 0xC1);
+    assert_eq!(protected_id(0x02), 0x42);
+    assert_eq!(protected_id(0x03), 0x03);
+    assert_eq!(protected_id(0x3C), 0x3C);
+    assert_eq!(protected_id(0x3D), 0x7D);
+    assert_eq!(protected_id(0x3F), 0xBF);
+
+    for id in 0..64 {assert_eq!(parse_pid(protected_id(id)), Some(id));}
+    // Flipped parity bits
+    assert_eq!(parse_pid(0x00), None);
+    assert_eq!(parse_pid(0x81), None);
+  }
+
+  #[test]
+  fn classic_and_enhanced_checksums() {
+    // Example of the LIN 2.x specification
+    assert_eq!(checksum(0x4A, &[0x55, 0x93, 0xE5], Checksum::Enhanced), 0xE6);
+    assert_eq!(checksum(0x4A, &[0x55, 0x93, 0xE5], Checksum::Classic), 0x31);
+    // Diagnostic frames always use the classic checksum
+    assert_eq!(checksum(0x3C, &[0x55, 0x93, 0xE5], Checksum::Enhanced), 0x31);
+    assert_eq!(checksum(0x80, &[], Checksum::Classic), 0xFF);
+  }
+
+  #[test]
+  fn frames() {
+    let frame = build_frame(0x0A, &[0x55, 0x93, 0xE5], Checksum::Enhanced).unwrap();
+    assert_eq!(frame.as_slice(), &[SYNC_BYTE, 0xCA, 0x55, 0x93, 0xE5, 0x66]);
+
+    assert_eq!(build_frame(64, &[], Checksum::Classic), None);
+    assert_eq!(build_frame(1, &[0; 9], Checksum::Classic), None);
+
+    assert_eq!(check_response(0x4A, &[0x55, 0x93, 0xE5, 0xE6], Checksum::Enhanced), Ok(&[0x55, 0x93, 0xE5][..]));
+    assert_eq!(check_response(0x4A, &[0x55, 0x93, 0xE5, 0xE7], Checksum::Enhanced), Err(LinError::Checksum));
+    assert_eq!(check_response(0x4A, &[], Checksum::Enhanced), Err(LinError::NoResponse));
+  }
+}
@@ -0,0 +1,925 @@
+//! This module contains everything that is used for Modbus RTU.
+//!
+//! Modbus RTU is a master/slave protocol on a serial line, usually RS-485. The master sends a request to a slave
+//! address (1 - 247, 0 is a broadcast to all slaves) and the slave answers with data or an exception code. Frames end
+//! with a CRC-16 and are separated by a silence of 3.5 characters. The [ModbusSlave] serves the data of a
+//! [RegisterMap] and the [ModbusMaster] sends requests with a response timeout. For a transceiver with a driver enable
+//! pin, use [enable_rs485](crate::uart::UART::enable_rs485) on the UART.
+//!
+//! | Code | Function                      | Table                      |
+//! | ---- | ----------------------------- | -------------------------- |
+//! | 1    | Read coils                    | Coils (bits, read/write)   |
+//! | 2    | Read discrete inputs          | Discrete inputs (bits)     |
+//! | 3    | Read holding registers        | Holding registers (words)  |
+//! | 4    | Read input registers          | Input registers (words)    |
+//! | 5    | Write single coil             | Coils                      |
+//! | 6    | Write single register         | Holding registers          |
+//! | 15   | Write multiple coils          | Coils                      |
+//! | 16   | Write multiple registers      | Holding registers          |
+//! | 23   | Read/write multiple registers | Holding registers          |
+//!
+//! The frame functions do not use the hardware, so they can be tested on the host.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::uart::UART;
+//! use rustuino::modbus::{ModbusSlave, RegisterMap, Exception};
+//!
+//! struct Heater {
+//!   temperature: u16,
+//!   setpoint: u16
+//! }
+//!
+//! impl RegisterMap for Heater {
+//!   fn read_input_register(&mut self, address: u16) -> Result<u16, Exception> {
+//!     return match address {
+//!       0 => Ok(self.temperature),
+//!       _ => Err(Exception::IllegalDataAddress)
+//!     };
+//!   }
+//!
+//!   fn read_holding_register(&mut self, address: u16) -> Result<u16, Exception> {
+//!     return match address {
+//!       0 => Ok(self.setpoint),
+//!       _ => Err(Exception::IllegalDataAddress)
+//!     };
+//!   }
+//!
+//!   fn write_holding_register(&mut self, address: u16, value: u16) -> Result<(), Exception> {
+//!     return match address {
+//!       0 => {
+//!         self.setpoint = value;
+//!         Ok(())
+//!       },
+//!       _ => Err(Exception::IllegalDataAddress)
+//!     };
+//!   }
+//! }
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   start_time();
+//!   let uart = UART::new(2, PA2, PA3, 19200).unwrap();
+//!   let mut slave = ModbusSlave::new(uart, 17, Heater {temperature: 215, setpoint: 200}).unwrap();
+//!
+//!   loop {
+//!     slave.poll();
+//!   }
+//! }
+//! ```
+
+#![allow(clippy::question_mark)]
+
+use crate::include::{ModbusError, ProgError, SerialError};
+use crate::uart::UART;
+use crate::time::{millis, is_expired, is_time_started};
+use heapless::Vec;
+use rtt_target::rprintln;
+
+pub const READ_COILS: u8 = 0x01;
+pub const READ_DISCRETE_INPUTS: u8 = 0x02;
+pub const READ_HOLDING_REGISTERS: u8 = 0x03;
+pub const READ_INPUT_REGISTERS: u8 = 0x04;
+pub const WRITE_SINGLE_COIL: u8 = 0x05;
+pub const WRITE_SINGLE_REGISTER: u8 = 0x06;
+pub const WRITE_MULTIPLE_COILS: u8 = 0x0F;
+pub const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+pub const READ_WRITE_REGISTERS: u8 = 0x17;
+
+/// The slave address that all slaves execute without answering.
+pub const BROADCAST: u8 = 0;
+
+/// The maximum length of a frame including address and CRC.
+pub const MAX_FRAME: usize = 256;
+
+/// A frame with slave address, function code, data and CRC.
+pub type Frame = Vec<u8, MAX_FRAME>;
+
+const DEFAULT_TIMEOUT: usize = 1000;
+
+// Quantities that fit into one frame
+const MAX_READ_BITS: u16 = 2000;
+const MAX_READ_REGISTERS: u16 = 125;
+const MAX_WRITE_COILS: u16 = 1968;
+const MAX_WRITE_REGISTERS: u16 = 123;
+const MAX_READ_WRITE_REGISTERS: u16 = 121;
+
+
+/// Represents the exception codes a slave answers with, if it cannot execute a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+  /// The function code is not supported
+  IllegalFunction = 1,
+  /// The address or a part of the address range does not exist
+  IllegalDataAddress = 2,
+  /// A value or quantity in the request is not allowed
+  IllegalDataValue = 3,
+  /// The slave failed while executing the request
+  ServerDeviceFailure = 4,
+  /// The request was accepted, but takes a long time
+  Acknowledge = 5,
+  /// The slave is busy with a long request
+  ServerDeviceBusy = 6
+}
+
+/// The data of a [ModbusSlave].
+///
+/// Every function handles one address and returns [Exception::IllegalDataAddress] for addresses that do not exist.
+/// Functions that are not implemented answer with [Exception::IllegalFunction].
+pub trait RegisterMap {
+  /// Returns the state of a coil.
+  fn read_coil(&mut self, _address: u16) -> Result<bool, Exception> {
+    return Err(Exception::IllegalFunction);
+  }
+
+  /// Returns the state of a discrete input.
+  fn read_discrete_input(&mut self, _address: u16) -> Result<bool, Exception> {
+    return Err(Exception::IllegalFunction);
+  }
+
+  /// Returns the value of a holding register.
+  fn read_holding_register(&mut self, _address: u16) -> Result<u16, Exception> {
+    return Err(Exception::IllegalFunction);
+  }
+
+  /// Returns the value of an input register.
+  fn read_input_register(&mut self, _address: u16) -> Result<u16, Exception> {
+    return Err(Exception::IllegalFunction);
+  }
+
+  /// Sets the state of a coil.
+  fn write_coil(&mut self, _address: u16, _value: bool) -> Result<(), Exception> {
+    return Err(Exception::IllegalFunction);
+  }
+
+  /// Sets the value of a holding register.
+  fn write_holding_register(&mut self, _address: u16, _value: u16) -> Result<(), Exception> {
+    return Err(Exception::IllegalFunction);
+  }
+}
+
+
+/// This struct represents a Modbus master on a UART.
+pub struct ModbusMaster {
+  #[doc(hidden)]
+  uart: UART,
+  #[doc(hidden)]
+  timeout: usize,
+  #[doc(hidden)]
+  idle_at: usize
+}
+
+impl ModbusMaster {
+  /// Sends requests over the UART with a response timeout of 1000ms. Needs the time base started with
+  /// [`start_time()`](crate::time::start_time), otherwise an error-enum is returned.
+  pub fn new(uart: UART) -> Result<Self, ProgError> {
+    if !is_time_started() {
+      rprintln!("The time base is not started! | ModbusMaster::new()");
+      return Err(ProgError::NotConfigured);
+    }
+
+    return Ok(Self {uart, timeout: DEFAULT_TIMEOUT, idle_at: 0});
+  }
+
+  /// Sets the time in milliseconds the master waits for the first byte of a response.
+  pub fn set_timeout(&mut self, timeout_ms: usize) {
+    self.timeout = timeout_ms;
+  }
+
+  /// Reads as many coils as the buffer holds, starting at the address. Returns an error-enum if the request is
+  /// invalid, the slave did not answer in time or answered with an exception.
+  pub fn read_coils(&mut self, slave: u8, address: u16, values: &mut [bool]) -> Result<(), ModbusError> {
+    return self.read_bits(slave, READ_COILS, address, values);
+  }
+
+  /// Reads as many discrete inputs as the buffer holds, starting at the address. Returns an error-enum like
+  /// [read_coils](crate::modbus::ModbusMaster::read_coils).
+  pub fn read_discrete_inputs(&mut self, slave: u8, address: u16, values: &mut [bool]) -> Result<(), ModbusError> {
+    return self.read_bits(slave, READ_DISCRETE_INPUTS, address, values);
+  }
+
+  /// Reads as many holding registers as the buffer holds, starting at the address. Returns an error-enum like
+  /// [read_coils](crate::modbus::ModbusMaster::read_coils).
+  pub fn read_holding_registers(&mut self, slave: u8, address: u16, values: &mut [u16]) -> Result<(), ModbusError> {
+    return self.read_words(slave, READ_HOLDING_REGISTERS, address, values);
+  }
+
+  /// Reads as many input registers as the buffer holds, starting at the address. Returns an error-enum like
+  /// [read_coils](crate::modbus::ModbusMaster::read_coils).
+  pub fn read_input_registers(&mut self, slave: u8, address: u16, values: &mut [u16]) -> Result<(), ModbusError> {
+    return self.read_words(slave, READ_INPUT_REGISTERS, address, values);
+  }
+
+  /// Sets a coil. Broadcasts to slave 0 return without a response. Returns an error-enum if the slave did not answer
+  /// in time or answered with an exception.
+  pub fn write_single_coil(&mut self, slave: u8, address: u16, value: bool) -> Result<(), ModbusError> {
+    return self.write(write_coil_request(slave, address, value));
+  }
+
+  /// Sets a holding register. Returns an error-enum like
+  /// [write_single_coil](crate::modbus::ModbusMaster::write_single_coil).
+  pub fn write_single_register(&mut self, slave: u8, address: u16, value: u16) -> Result<(), ModbusError> {
+    return self.write(write_register_request(slave, address, value));
+  }
+
+  /// Sets consecutive coils, starting at the address. Returns an error-enum like
+  /// [write_single_coil](crate::modbus::ModbusMaster::write_single_coil) or if there are too many values.
+  pub fn write_multiple_coils(&mut self, slave: u8, address: u16, values: &[bool]) -> Result<(), ModbusError> {
+    return self.write(write_coils_request(slave, address, values));
+  }
+
+  /// Sets consecutive holding registers, starting at the address. Returns an error-enum like
+  /// [write_single_coil](crate::modbus::ModbusMaster::write_single_coil) or if there are too many values.
+  pub fn write_multiple_registers(&mut self, slave: u8, address: u16, values: &[u16]) -> Result<(), ModbusError> {
+    return self.write(write_registers_request(slave, address, values));
+  }
+
+  /// Writes holding registers and then reads as many holding registers as the buffer holds, in one request.
+  /// Returns an error-enum like [read_coils](crate::modbus::ModbusMaster::read_coils).
+  pub fn read_write_registers(&mut self, slave: u8, read_address: u16, values: &mut [u16], write_address: u16, data: &[u16]) -> Result<(), ModbusError> {
+    let count = u16::try_from(values.len()).unwrap_or(0);
+    let response = match self.read(read_write_request(slave, read_address, count, write_address, data)) {
+      Ok(value) => value,
+      Err(error) => return Err(error)
+    };
+
+    return match parse_response(slave, READ_WRITE_REGISTERS, &response) {
+      Ok(data) => unpack_registers(data, values),
+      Err(error) => Err(error)
+    };
+  }
+
+  /// Returns the UART.
+  pub fn release(self) -> UART {
+    return self.uart;
+  }
+
+  fn read_bits(&mut self, slave: u8, function: u8, address: u16, values: &mut [bool]) -> Result<(), ModbusError> {
+    let count = u16::try_from(values.len()).unwrap_or(0);
+    let response = match self.read(read_request(slave, function, address, count)) {
+      Ok(value) => value,
+      Err(error) => return Err(error)
+    };
+
+    return match parse_response(slave, function, &response) {
+      Ok(data) => unpack_bits(data, values),
+      Err(error) => Err(error)
+    };
+  }
+
+  fn read_words(&mut self, slave: u8, function: u8, address: u16, values: &mut [u16]) -> Result<(), ModbusError> {
+    let count = u16::try_from(values.len()).unwrap_or(0);
+    let response = match self.read(read_request(slave, function, address, count)) {
+      Ok(value) => value,
+      Err(error) => return Err(error)
+    };
+
+    return match parse_response(slave, function, &response) {
+      Ok(data) => unpack_registers(data, values),
+      Err(error) => Err(error)
+    };
+  }
+
+  fn read(&mut self, request: Option<Frame>) -> Result<Frame, ModbusError> {
+    return match request {
+      Some(request) => self.transact(&request),
+      None => {
+        rprintln!("Invalid Modbus request! | ModbusMaster");
+        Err(ModbusError::Prog(ProgError::InvalidConfiguration))
+      }
+    };
+  }
+
+  fn write(&mut self, request: Option<Frame>) -> Result<(), ModbusError> {
+    let request = match request {
+      Some(value) => value,
+      None => {
+        rprintln!("Invalid Modbus request! | ModbusMaster");
+        return Err(ModbusError::Prog(ProgError::InvalidConfiguration));
+      }
+    };
+
+    let response = match self.transact(&request) {
+      Ok(value) => value,
+      Err(error) => return Err(error)
+    };
+    if request[0] == BROADCAST {return Ok(());}
+
+    return match parse_response(request[0], request[1], &response) {
+      // The slave echoes the address and the value or quantity
+      Ok(data) if data == &request[2..6] => Ok(()),
+      Ok(_) => Err(ModbusError::InvalidFrame),
+      Err(error) => Err(error)
+    };
+  }
+
+  // Returns an empty frame for broadcasts
+  fn transact(&mut self, request: &[u8]) -> Result<Frame, ModbusError> {
+    let gap = silent_ms(self.uart.config().baud);
+
+    // The bus has to be silent between two frames
+    while !is_expired(millis(), self.idle_at) {}
+
+    // Drop the rest of responses that arrived after their timeout
+    while self.uart.read_byte_timeout(0).is_ok() {}
+
+    if let Err(error) = self.uart.write_bytes(request) {return Err(ModbusError::Serial(error));}
+
+    if request[0] == BROADCAST {
+      self.idle_at = millis().wrapping_add(gap);
+      return Ok(Frame::new());
+    }
+
+    let first = match self.uart.read_byte_timeout(self.timeout) {
+      Ok(value) => value,
+      Err(SerialError::Prog(ProgError::TimedOut)) => return Err(ModbusError::NoResponse),
+      Err(error) => return Err(ModbusError::Serial(error))
+    };
+
+    let response = read_frame(&self.uart, first, gap);
+    self.idle_at = millis().wrapping_add(gap);
+
+    return response;
+  }
+}
+
+
+/// This struct represents a Modbus slave on a UART that answers requests with the data of a [RegisterMap].
+pub struct ModbusSlave<M: RegisterMap> {
+  #[doc(hidden)]
+  uart: UART,
+  #[doc(hidden)]
+  address: u8,
+  #[doc(hidden)]
+  map: M
+}
+
+impl<M: RegisterMap> ModbusSlave<M> {
+  /// Answers requests to the address (1 - 247) and executes broadcasts. Needs the time base started with
+  /// [`start_time()`](crate::time::start_time). Returns an error-enum if the time base is not started or the address
+  /// is invalid.
+  pub fn new(uart: UART, address: u8, map: M) -> Result<Self, ProgError> {
+    if !is_time_started() {
+      rprintln!("The time base is not started! | ModbusSlave::new()");
+      return Err(ProgError::NotConfigured);
+    }
+
+    if !(1..=247).contains(&address) {
+      rprintln!("Modbus slave addresses go from 1 to 247! | ModbusSlave::new()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    return Ok(Self {uart, address, map});
+  }
+
+  /// Returns the register map.
+  pub fn map(&self) -> &M {
+    return &self.map;
+  }
+
+  /// Returns the register map, e.g. to update measured values.
+  pub fn map_mut(&mut self) -> &mut M {
+    return &mut self.map;
+  }
+
+  /// Receives and answers a request if one arrived. Returns the function code of an executed request, an error-enum
+  /// if a damaged frame was received, or `None` if nothing arrived or the request was for another slave.
+  ///
+  /// Call this often, the master only waits for its response timeout.
+  pub fn poll(&mut self) -> Option<Result<u8, ModbusError>> {
+    let first = self.uart.read_byte_timeout(0).ok()?;
+
+    let frame = match read_frame(&self.uart, first, silent_ms(self.uart.config().baud)) {
+      Ok(value) => value,
+      Err(error) => return Some(Err(error))
+    };
+
+    let (slave, pdu) = match check_frame(&frame) {
+      Ok(value) => value,
+      Err(error) => return Some(Err(error))
+    };
+    if slave != self.address && slave != BROADCAST {return None;}
+
+    if let Some(response) = handle_request(&mut self.map, self.address, &frame) {
+      if let Err(error) = self.uart.write_bytes(&response) {return Some(Err(ModbusError::Serial(error)));}
+    }
+
+    return Some(Ok(pdu[0]));
+  }
+
+  /// Returns the UART and the register map.
+  pub fn release(self) -> (UART, M) {
+    return (self.uart, self.map);
+  }
+}
+
+
+// Frame Functions ================================================================================
+/// Calculates the CRC-16 of a frame, which is sent with the low byte first.
+pub fn crc16(data: &[u8]) -> u16 {
+  let mut crc: u16 = 0xFFFF;
+
+  for byte in data {
+    crc ^= *byte as u16;
+    for _ in 0..8 {
+      if crc & 1 != 0 {crc = (crc >> 1) ^ 0xA001;}
+      else {crc >>= 1;}
+    }
+  }
+
+  return crc;
+}
+
+/// Returns the silence in microseconds that separates two frames. This is 3.5 characters of 11 bits, or 1750µs above
+/// 19200 baud as the specification recommends.
+pub fn frame_gap_us(baud: u32) -> u32 {
+  if baud > 19200 {return 1750;}
+  return 38_500_000u32.div_ceil(baud.max(1));
+}
+
+/// Adds the slave address and the CRC to a function code with its data. Returns `None` if the frame gets too long.
+pub fn build_frame(slave: u8, pdu: &[u8]) -> Option<Frame> {
+  if pdu.is_empty() || pdu.len() > MAX_FRAME - 3 {return None;}
+
+  let mut frame = Frame::new();
+  frame.push(slave).unwrap();
+  frame.extend_from_slice(pdu).unwrap();
+
+  return finish_frame(frame);
+}
+
+/// Checks the length and the CRC of a received frame. Returns the slave address and the function code with its data.
+pub fn check_frame(frame: &[u8]) -> Result<(u8, &[u8]), ModbusError> {
+  if frame.len() < 4 {return Err(ModbusError::InvalidFrame);}
+
+  let (content, crc) = frame.split_at(frame.len() - 2);
+  if crc16(content).to_le_bytes() != crc {return Err(ModbusError::Crc);}
+
+  return Ok((content[0], &content[1..]));
+}
+
+/// Builds a request that reads coils, discrete inputs, holding registers or input registers (function code 1 - 4).
+/// Returns `None` if the function code, the slave or the quantity is invalid.
+pub fn read_request(slave: u8, function: u8, address: u16, count: u16) -> Option<Frame> {
+  let max = match function {
+    READ_COILS | READ_DISCRETE_INPUTS => MAX_READ_BITS,
+    READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => MAX_READ_REGISTERS,
+    _ => return None
+  };
+  if slave == BROADCAST || !(1..=max).contains(&count) {return None;}
+
+  let mut frame = start_frame(slave, function, address);
+  frame.extend_from_slice(&count.to_be_bytes()).unwrap();
+
+  return finish_frame(frame);
+}
+
+/// Builds a request that sets a coil (function code 5).
+pub fn write_coil_request(slave: u8, address: u16, value: bool) -> Option<Frame> {
+  let mut frame = start_frame(slave, WRITE_SINGLE_COIL, address);
+  frame.extend_from_slice(if value {&[0xFF, 0x00]} else {&[0x00, 0x00]}).unwrap();
+
+  return finish_frame(frame);
+}
+
+/// Builds a request that sets a holding register (function code 6).
+pub fn write_register_request(slave: u8, address: u16, value: u16) -> Option<Frame> {
+  let mut frame = start_frame(slave, WRITE_SINGLE_REGISTER, address);
+  frame.extend_from_slice(&value.to_be_bytes()).unwrap();
+
+  return finish_frame(frame);
+}
+
+/// Builds a request that sets consecutive coils (function code 15). Returns `None` if there are no or too many values.
+pub fn write_coils_request(slave: u8, address: u16, values: &[bool]) -> Option<Frame> {
+  if values.is_empty() || values.len() > MAX_WRITE_COILS as usize {return None;}
+
+  let mut frame = start_frame(slave, WRITE_MULTIPLE_COILS, address);
+  frame.extend_from_slice(&(values.len() as u16).to_be_bytes()).unwrap();
+  frame.push(values.len().div_ceil(8) as u8).unwrap();
+  for chunk in values.chunks(8) {
+    frame.push(pack_bits(chunk)).unwrap();
+  }
+
+  return finish_frame(frame);
+}
+
+/// Builds a request that sets consecutive holding registers (function code 16). Returns `None` if there are no or
+/// too many values.
+pub fn write_registers_request(slave: u8, address: u16, values: &[u16]) -> Option<Frame> {
+  if values.is_empty() || values.len() > MAX_WRITE_REGISTERS as usize {return None;}
+
+  let mut frame = start_frame(slave, WRITE_MULTIPLE_REGISTERS, address);
+  frame.extend_from_slice(&(values.len() as u16).to_be_bytes()).unwrap();
+  push_registers(&mut frame, values);
+
+  return finish_frame(frame);
+}
+
+/// Builds a request that writes holding registers and reads `count` holding registers afterwards (function code
+/// 23). Returns `None` if the slave is the broadcast address or a quantity is invalid.
+pub fn read_write_request(slave: u8, read_address: u16, count: u16, write_address: u16, values: &[u16]) -> Option<Frame> {
+  if slave == BROADCAST || !(1..=MAX_READ_REGISTERS).contains(&count) {return None;}
+  if values.is_empty() || values.len() > MAX_READ_WRITE_REGISTERS as usize {return None;}
+
+  let mut frame = start_frame(slave, READ_WRITE_REGISTERS, read_address);
+  frame.extend_from_slice(&count.to_be_bytes()).unwrap();
+  frame.extend_from_slice(&write_address.to_be_bytes()).unwrap();
+  frame.extend_from_slice(&(values.len() as u16).to_be_bytes()).unwrap();
+  push_registers(&mut frame, values);
+
+  return finish_frame(frame);
+}
+
+/// Checks a response to a request with the function code and returns the data after the function code. An
+/// exception of the slave is returned as error-enum.
+pub fn parse_response(slave: u8, function: u8, frame: &[u8]) -> Result<&[u8], ModbusError> {
+  let (address, pdu) = match check_frame(frame) {
+    Ok(value) => value,
+    Err(error) => return Err(error)
+  };
+  if address != slave {return Err(ModbusError::InvalidFrame);}
+
+  if pdu[0] == function | 0x80 && pdu.len() == 2 {return Err(ModbusError::Exception(pdu[1]));}
+  if pdu[0] != function {return Err(ModbusError::InvalidFrame);}
+
+  return Ok(&pdu[1..]);
+}
+
+/// Fills the buffer with the bits of a read response (byte count and packed bits). Returns an error-enum if the
+/// response does not contain as many bits as the buffer holds.
+pub fn unpack_bits(data: &[u8], values: &mut [bool]) -> Result<(), ModbusError> {
+  let len = values.len().div_ceil(8);
+  if data.len() != len + 1 || data[0] as usize != len {return Err(ModbusError::InvalidFrame);}
+
+  for (index, value) in values.iter_mut().enumerate() {
+    *value = (data[1 + index / 8] >> (index % 8)) & 1 != 0;
+  }
+
+  return Ok(());
+}
+
+/// Fills the buffer with the registers of a read response (byte count and big-endian words). Returns an error-enum
+/// if the response does not contain as many registers as the buffer holds.
+pub fn unpack_registers(data: &[u8], values: &mut [u16]) -> Result<(), ModbusError> {
+  let len = values.len() * 2;
+  if data.len() != len + 1 || data[0] as usize != len {return Err(ModbusError::InvalidFrame);}
+
+  for (value, bytes) in values.iter_mut().zip(data[1..].chunks(2)) {
+    *value = u16::from_be_bytes([bytes[0], bytes[1]]);
+  }
+
+  return Ok(());
+}
+
+/// Executes a request with the register map and returns the response of a slave with the address. Exceptions are
+/// answered with an exception response.
+///
+/// Returns `None` if the frame is damaged, for another slave or a broadcast, which is executed without a response.
+pub fn handle_request<M: RegisterMap + ?Sized>(map: &mut M, address: u8, frame: &[u8]) -> Option<Frame> {
+  let (slave, pdu) = check_frame(frame).ok()?;
+  if slave != address && slave != BROADCAST {return None;}
+
+  let mut response = Frame::new();
+  response.push(address).unwrap();
+  response.push(pdu[0]).unwrap();
+
+  if let Err(exception) = execute(map, pdu, &mut response) {
+    response.truncate(1);
+    response.push(pdu[0] | 0x80).unwrap();
+    response.push(exception as u8).unwrap();
+  }
+
+  if slave == BROADCAST {return None;}
+  return finish_frame(response);
+}
+
+
+// Private Functions ==============================================================================
+// Silence after the last byte of a frame in milliseconds, the time base has a resolution of 1ms
+fn silent_ms(baud: u32) -> usize {
+  return frame_gap_us(baud).div_ceil(1000) as usize + 1;
+}
+
+// Reads until the bus is silent, also after an error, so the next frame starts at its first byte
+fn read_frame(uart: &UART, first: u8, gap_ms: usize) -> Result<Frame, ModbusError> {
+  let mut frame = Frame::new();
+  let mut result = Ok(());
+
+  frame.push(first).unwrap();
+
+  loop {
+    match uart.read_byte_timeout(gap_ms) {
+      Ok(byte) => {if frame.push(byte).is_err() && result.is_ok() {result = Err(ModbusError::InvalidFrame);}},
+      Err(SerialError::Prog(ProgError::TimedOut)) => break,
+      Err(error) => {if result.is_ok() {result = Err(ModbusError::Serial(error));}}
+    };
+  }
+
+  return result.map(|_| frame);
+}
+
+fn start_frame(slave: u8, function: u8, address: u16) -> Frame {
+  let mut frame = Frame::new();
+
+  frame.push(slave).unwrap();
+  frame.push(function).unwrap();
+  frame.extend_from_slice(&address.to_be_bytes()).unwrap();
+
+  return frame;
+}
+
+fn finish_frame(mut frame: Frame) -> Option<Frame> {
+  let crc = crc16(&frame);
+  frame.extend_from_slice(&crc.to_le_bytes()).ok()?;
+
+  return Some(frame);
+}
+
+// The first value is the lowest bit
+fn pack_bits(values: &[bool]) -> u8 {
+  return values.iter().enumerate().fold(0, |byte, (index, value)| byte | ((*value as u8) << index));
+}
+
+fn push_registers(frame: &mut Frame, values: &[u16]) {
+  frame.push((values.len() * 2) as u8).unwrap();
+  for value in values {
+    frame.extend_from_slice(&value.to_be_bytes()).unwrap();
+  }
+}
+
+// Quantity before address, like the specification checks them
+fn check_range(address: u16, count: u16, max: u16) -> Result<(), Exception> {
+  if !(1..=max).contains(&count) {return Err(Exception::IllegalDataValue);}
+  if address as u32 + count as u32 > 0x10000 {return Err(Exception::IllegalDataAddress);}
+
+  return Ok(());
+}
+
+// Appends the data of the response after the function code
+fn execute<M: RegisterMap + ?Sized>(map: &mut M, pdu: &[u8], response: &mut Frame) -> Result<(), Exception> {
+  let function = pdu[0];
+  let word = |index: usize| u16::from_be_bytes([pdu[index], pdu[index + 1]]);
+
+  match function {
+    READ_COILS | READ_DISCRETE_INPUTS => {
+      if pdu.len() != 5 {return Err(Exception::IllegalDataValue);}
+      let (address, count) = (word(1), word(3));
+      if let Err(exception) = check_range(address, count, MAX_READ_BITS) {return Err(exception);}
+
+      response.push(count.div_ceil(8) as u8).unwrap();
+
+      let mut byte = 0;
+      for index in 0..count {
+        let value = if function == READ_COILS {map.read_coil(address + index)}
+        else {map.read_discrete_input(address + index)};
+
+        match value {
+          Ok(bit) => byte |= (bit as u8) << (index % 8),
+          Err(exception) => return Err(exception)
+        };
+
+        if index % 8 == 7 || index == count - 1 {
+          response.push(byte).unwrap();
+          byte = 0;
+        }
+      }
+    },
+    READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+      if pdu.len() != 5 {return Err(Exception::IllegalDataValue);}
+      let (address, count) = (word(1), word(3));
+      if let Err(exception) = check_range(address, count, MAX_READ_REGISTERS) {return Err(exception);}
+
+      return read_registers(map, function == READ_HOLDING_REGISTERS, address, count, response);
+    },
+    WRITE_SINGLE_COIL => {
+      if pdu.len() != 5 {return Err(Exception::IllegalDataValue);}
+
+      let value = match word(3) {
+        0xFF00 => true,
+        0x0000 => false,
+        _ => return Err(Exception::IllegalDataValue)
+      };
+      if let Err(exception) = map.write_coil(word(1), value) {return Err(exception);}
+
+      response.extend_from_slice(&pdu[1..5]).unwrap();
+    },
+    WRITE_SINGLE_REGISTER => {
+      if pdu.len() != 5 {return Err(Exception::IllegalDataValue);}
+      if let Err(exception) = map.write_holding_register(word(1), word(3)) {return Err(exception);}
+
+      response.extend_from_slice(&pdu[1..5]).unwrap();
+    },
+    WRITE_MULTIPLE_COILS => {
+      if pdu.len() < 6 {return Err(Exception::IllegalDataValue);}
+      let (address, count, len) = (word(1), word(3), pdu[5] as usize);
+      if let Err(exception) = check_range(address, count, MAX_WRITE_COILS) {return Err(exception);}
+      if len != count.div_ceil(8) as usize || pdu.len() != 6 + len {return Err(Exception::IllegalDataValue);}
+
+      for index in 0..count {
+        let value = (pdu[6 + index as usize / 8] >> (index % 8)) & 1 != 0;
+        if let Err(exception) = map.write_coil(address + index, value) {return Err(exception);}
+      }
+
+      response.extend_from_slice(&pdu[1..5]).unwrap();
+    },
+    WRITE_MULTIPLE_REGISTERS => {
+      if pdu.len() < 6 {return Err(Exception::IllegalDataValue);}
+      let (address, count, len) = (word(1), word(3), pdu[5] as usize);
+      if let Err(exception) = check_range(address, count, MAX_WRITE_REGISTERS) {return Err(exception);}
+      if len != count as usize * 2 || pdu.len() != 6 + len {return Err(Exception::IllegalDataValue);}
+
+      for index in 0..count {
+        let value = word(6 + index as usize * 2);
+        if let Err(exception) = map.write_holding_register(address + index, value) {return Err(exception);}
+      }
+
+      response.extend_from_slice(&pdu[1..5]).unwrap();
+    },
+    READ_WRITE_REGISTERS => {
+      if pdu.len() < 10 {return Err(Exception::IllegalDataValue);}
+      let (read_address, read_count) = (word(1), word(3));
+      let (write_address, write_count, len) = (word(5), word(7), pdu[9] as usize);
+      if let Err(exception) = check_range(read_address, read_count, MAX_READ_REGISTERS) {return Err(exception);}
+      if let Err(exception) = check_range(write_address, write_count, MAX_READ_WRITE_REGISTERS) {return Err(exception);}
+      if len != write_count as usize * 2 || pdu.len() != 10 + len {return Err(Exception::IllegalDataValue);}
+
+      // The write is executed before the read
+      for index in 0..write_count {
+        let value = word(10 + index as usize * 2);
+        if let Err(exception) = map.write_holding_register(write_address + index, value) {return Err(exception);}
+      }
+
+      return read_registers(map, true, read_address, read_count, response);
+    },
+    _ => return Err(Exception::IllegalFunction)
+  };
+
+  return Ok(());
+}
+
+fn read_registers<M: RegisterMap + ?Sized>(map: &mut M, holding: bool, address: u16, count: u16, response: &mut Frame) -> Result<(), Exception> {
+  response.push((count * 2) as u8).unwrap();
+
+  for index in 0..count {
+    let value = if holding {map.read_holding_register(address + index)}
+    else {map.read_input_register(address + index)};
+
+    match value {
+      Ok(value) => response.extend_from_slice(&value.to_be_bytes()).unwrap(),
+      Err(exception) => return Err(exception)
+    };
+  }
+
+  return Ok(());
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Holding registers at 0x0000 - 0x000F, input registers mirror them
+  struct Registers {
+    values: [u16; 16]
+  }
+
+  impl RegisterMap for Registers {
+    fn read_holding_register(&mut self, address: u16) -> Result<u16, Exception> {
+      return self.values.get(address as usize).copied().ok_or(Exception::IllegalDataAddress);
+    }
+
+    fn read_input_register(&mut self, address: u16) -> Result<u16, Exception> {
+      return self.read_holding_register(address);
+    }
+
+    fn write_holding_register(&mut self, address: u16, value: u16) -> Result<(), Exception> {
+      match self.values.get_mut(address as usize) {
+        Some(register) => *register = value,
+        None => return Err(Exception::IllegalDataAddress)
+      };
+      return Ok(());
+    }
+  }
+
+  fn registers() -> Registers {
+    let mut values = [0; 16];
+    for (index, value) in values.iter_mut().enumerate() {*value = 0x1000 + index as u16;}
+    return Registers {values};
+  }
+
+  #[test]
+  fn crc_known_vector() {
+    assert_eq!(crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]), 0xCDC5);
+    assert_eq!(read_request(1, READ_HOLDING_REGISTERS, 0, 10).unwrap(), [0x01, 0x03, 0x00, 0x00, 0x00, 0x0A, 0xC5, 0xCD]);
+  }
+
+  #[test]
+  fn frames_are_checked() {
+    let frame = build_frame(0x11, &[0x03, 0x00, 0x6B, 0x00, 0x03]).unwrap();
+    assert_eq!(check_frame(&frame), Ok((0x11, &[0x03, 0x00, 0x6B, 0x00, 0x03][..])));
+
+    let mut damaged = frame.clone();
+    damaged[3] ^= 0x01;
+    assert_eq!(check_frame(&damaged), Err(ModbusError::Crc));
+    assert_eq!(check_frame(&frame[..3]), Err(ModbusError::InvalidFrame));
+
+    assert!(build_frame(1, &[]).is_none());
+    assert!(build_frame(1, &[0; MAX_FRAME - 2]).is_none());
+  }
+
+  #[test]
+  fn read_registers_round_trip() {
+    let mut map = registers();
+
+    for function in [READ_HOLDING_REGISTERS, READ_INPUT_REGISTERS] {
+      let request = read_request(7, function, 2, 3).unwrap();
+      let response = handle_request(&mut map, 7, &request).unwrap();
+      assert_eq!(&response[..9], [7, function, 6, 0x10, 0x02, 0x10, 0x03, 0x10, 0x04]);
+
+      let mut values = [0; 3];
+      unpack_registers(parse_response(7, function, &response).unwrap(), &mut values).unwrap();
+      assert_eq!(values, [0x1002, 0x1003, 0x1004]);
+
+      // The buffer has to match the number of registers in the response
+      let mut values = [0; 2];
+      assert_eq!(unpack_registers(parse_response(7, function, &response).unwrap(), &mut values), Err(ModbusError::InvalidFrame));
+    }
+  }
+
+  #[test]
+  fn write_register_round_trip() {
+    let mut map = registers();
+
+    let request = write_register_request(7, 5, 0xBEEF).unwrap();
+    let response = handle_request(&mut map, 7, &request).unwrap();
+    // The response echoes the request
+    assert_eq!(response, request);
+    assert_eq!(parse_response(7, WRITE_SINGLE_REGISTER, &response).unwrap(), [0x00, 0x05, 0xBE, 0xEF]);
+    assert_eq!(map.values[5], 0xBEEF);
+  }
+
+  #[test]
+  fn write_registers_round_trip() {
+    let mut map = registers();
+
+    let request = write_registers_request(7, 14, &[0xAAAA, 0x5555]).unwrap();
+    assert_eq!(&request[..11], [7, 0x10, 0x00, 0x0E, 0x00, 0x02, 4, 0xAA, 0xAA, 0x55, 0x55]);
+
+    let response = handle_request(&mut map, 7, &request).unwrap();
+    assert_eq!(parse_response(7, WRITE_MULTIPLE_REGISTERS, &response).unwrap(), [0x00, 0x0E, 0x00, 0x02]);
+    assert_eq!(map.values[14..], [0xAAAA, 0x5555]);
+
+    assert!(write_registers_request(7, 0, &[]).is_none());
+    assert!(write_registers_request(7, 0, &[0; MAX_WRITE_REGISTERS as usize + 1]).is_none());
+  }
+
+  #[test]
+  fn requests_for_others_are_ignored() {
+    let mut map = registers();
+
+    assert!(handle_request(&mut map, 8, &read_request(7, READ_HOLDING_REGISTERS, 0, 1).unwrap()).is_none());
+
+    // Broadcasts are executed without a response
+    assert!(handle_request(&mut map, 7, &write_register_request(BROADCAST, 0, 0x1234).unwrap()).is_none());
+    assert_eq!(map.values[0], 0x1234);
+    assert!(read_request(BROADCAST, READ_HOLDING_REGISTERS, 0, 1).is_none());
+  }
+
+  #[test]
+  fn exception_responses() {
+    let mut map = registers();
+
+    // Registers past the end of the map
+    let response = handle_request(&mut map, 7, &read_request(7, READ_HOLDING_REGISTERS, 15, 2).unwrap()).unwrap();
+    assert_eq!(&response[..3], [7, 0x83, Exception::IllegalDataAddress as u8]);
+    assert_eq!(response.len(), 5);
+    assert_eq!(parse_response(7, READ_HOLDING_REGISTERS, &response), Err(ModbusError::Exception(2)));
+
+    // Coils are not implemented by the map
+    let response = handle_request(&mut map, 7, &read_request(7, READ_COILS, 0, 1).unwrap()).unwrap();
+    assert_eq!(parse_response(7, READ_COILS, &response), Err(ModbusError::Exception(1)));
+
+    // An unknown function code
+    let response = handle_request(&mut map, 7, &build_frame(7, &[0x2B, 0x0E]).unwrap()).unwrap();
+    assert_eq!(parse_response(7, 0x2B, &response), Err(ModbusError::Exception(1)));
+
+    // A byte count that does not match the quantity
+    let response = handle_request(&mut map, 7, &build_frame(7, &[0x10, 0x00, 0x00, 0x00, 0x01, 4, 0, 0]).unwrap()).unwrap();
+    assert_eq!(parse_response(7, WRITE_MULTIPLE_REGISTERS, &response), Err(ModbusError::Exception(3)));
+
+    // A quantity of zero
+    let response = handle_request(&mut map, 7, &build_frame(7, &[0x03, 0x00, 0x00, 0x00, 0x00]).unwrap()).unwrap();
+    assert_eq!(parse_response(7, READ_HOLDING_REGISTERS, &response), Err(ModbusError::Exception(3)));
+
+    // A response of another slave or function
+    assert_eq!(parse_response(8, READ_HOLDING_REGISTERS, &response), Err(ModbusError::InvalidFrame));
+    let response = handle_request(&mut map, 7, &read_request(7, READ_INPUT_REGISTERS, 0, 1).unwrap()).unwrap();
+    assert_eq!(parse_response(7, READ_HOLDING_REGISTERS, &response), Err(ModbusError::InvalidFrame));
+  }
+}
@@ -0,0 +1,694 @@
+//! This module contains a parser for the NMEA 0183 sentences of GPS receivers.
+//!
+//! GPS modules send text sentences like `$GPGGA,...*47` over a serial connection. The [NmeaParser] is fed with the
+//! received characters one by one, validates the checksum and decodes these sentences of all talkers (GP, GN, GL, GA,
+//! ...):
+//!
+//! | Sentence | Content                                                      |
+//! | -------- | ------------------------------------------------------------ |
+//! | GGA      | UTC time, position, fix quality, satellites used, altitude   |
+//! | RMC      | UTC time and date, position, speed and course over ground    |
+//! | GSA      | 2D/3D fix, satellites used for the fix, dilution of precision |
+//! | GSV      | Satellites in view with elevation, azimuth and signal level  |
+//! | VTG      | Course and speed over ground                                 |
+//!
+//! Empty fields, e.g. the position before the first fix, are returned as `None`. The [Gps] reads the sentences from a
+//! [UART](crate::uart::UART) and sends PMTK and UBX configuration commands. The parser does not allocate and does not
+//! use the hardware, so it can be tested on the host with recorded logs.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::uart::UART;
+//! use rustuino::nmea::{Gps, Sentence};
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   start_time();
+//!   let uart = UART::new(1, PA9, PA10, 9600).unwrap();
+//!   let mut gps = Gps::new(uart).unwrap();
+//!
+//!   // Only send RMC and GGA, once per second (MediaTek receivers)
+//!   gps.send_command("PMTK314,0,1,0,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0").unwrap();
+//!
+//!   loop {
+//!     if let Some(Ok(Sentence::Gga(gga))) = gps.poll() {
+//!       if let (Some(latitude), Some(longitude)) = (gga.latitude, gga.longitude) {
+//!         rprintln!("{} satellites: {}, {}", gga.satellites, latitude, longitude);
+//!       }
+//!     }
+//!   }
+//! }
+//! ```
+
+use crate::include::{NmeaError, ProgError, SerialError};
+use crate::uart::UART;
+use crate::time::is_time_started;
+use heapless::{String, Vec};
+use core::fmt::Write;
+use rtt_target::rprintln;
+
+/// The maximum length of a sentence. The standard allows 82 characters, some receivers send a few more.
+pub const MAX_SENTENCE: usize = 96;
+/// The maximum payload length of a UBX message that can be sent.
+pub const MAX_UBX_PAYLOAD: usize = 256;
+
+// Most fields of a supported sentence, GSV with four satellites and signal identifier
+const MAX_FIELDS: usize = 24;
+
+
+/// Represents a UTC time of day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Time {
+  pub hour: u8,
+  pub minute: u8,
+  pub second: u8,
+  pub millisecond: u16
+}
+
+/// Represents a UTC date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Date {
+  pub day: u8,
+  pub month: u8,
+  pub year: u16
+}
+
+/// Represents the quality of a position fix in a GGA sentence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixQuality {
+  Invalid, Gps, Dgps, Pps, Rtk, FloatRtk, Estimated, Manual, Simulation
+}
+
+/// Represents the kind of fix in a GSA sentence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixType {
+  NoFix, Fix2D, Fix3D
+}
+
+/// Time, position and fix data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gga {
+  pub time: Option<Time>,
+  /// Degrees, negative in the south
+  pub latitude: Option<f64>,
+  /// Degrees, negative in the west
+  pub longitude: Option<f64>,
+  pub quality: FixQuality,
+  /// Satellites used for the fix
+  pub satellites: u8,
+  /// Horizontal dilution of precision
+  pub hdop: Option<f32>,
+  /// Meters above mean sea level
+  pub altitude: Option<f32>,
+  /// Meters between the ellipsoid and mean sea level
+  pub geoid_separation: Option<f32>
+}
+
+/// Recommended minimum data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rmc {
+  pub time: Option<Time>,
+  /// False if the receiver has no valid fix
+  pub valid: bool,
+  /// Degrees, negative in the south
+  pub latitude: Option<f64>,
+  /// Degrees, negative in the west
+  pub longitude: Option<f64>,
+  /// Speed over ground in knots
+  pub speed_knots: Option<f32>,
+  /// Course over ground in degrees from true north
+  pub course: Option<f32>,
+  pub date: Option<Date>
+}
+
+/// Fix type, satellites used and dilution of precision.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gsa {
+  /// False if the receiver is forced to 2D or 3D mode
+  pub automatic: bool,
+  pub fix: FixType,
+  /// PRNs of the satellites used for the fix
+  pub satellites: Vec<u8, 12>,
+  pub pdop: Option<f32>,
+  pub hdop: Option<f32>,
+  pub vdop: Option<f32>
+}
+
+/// A satellite of a GSV sentence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Satellite {
+  pub prn: u8,
+  /// Degrees above the horizon
+  pub elevation: Option<u8>,
+  /// Degrees from true north
+  pub azimuth: Option<u16>,
+  /// Signal to noise ratio in dBHz, `None` if the satellite is not tracked
+  pub snr: Option<u8>
+}
+
+/// Satellites in view. The satellites are spread over several messages with up to four satellites.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gsv {
+  pub messages: u8,
+  /// Number of this message, starting with 1
+  pub message: u8,
+  pub satellites_in_view: u8,
+  pub satellites: Vec<Satellite, 4>
+}
+
+/// Course and speed over ground.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vtg {
+  /// Degrees from true north
+  pub course_true: Option<f32>,
+  /// Degrees from magnetic north
+  pub course_magnetic: Option<f32>,
+  pub speed_knots: Option<f32>,
+  pub speed_kmh: Option<f32>
+}
+
+/// Represents a decoded sentence.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sentence {
+  Gga(Gga),
+  Rmc(Rmc),
+  Gsa(Gsa),
+  Gsv(Gsv),
+  Vtg(Vtg)
+}
+
+
+/// A parser that is fed with the received characters one by one.
+///
+/// Characters before the first `$` and between sentences are ignored.
+pub struct NmeaParser {
+  #[doc(hidden)]
+  buffer: String<MAX_SENTENCE>,
+  #[doc(hidden)]
+  in_sentence: bool
+}
+
+impl NmeaParser {
+  /// Creates a parser that waits for the start of a sentence.
+  pub fn new() -> Self {
+    return Self {buffer: String::new(), in_sentence: false};
+  }
+
+  /// Processes the next received character. Returns the decoded sentence at the end of the line, or an error-enum if
+  /// the sentence is damaged, too long or not supported.
+  pub fn push(&mut self, byte: u8) -> Option<Result<Sentence, NmeaError>> {
+    if byte == b'$' {
+      self.buffer.clear();
+      self.in_sentence = true;
+      return None;
+    }
+    if !self.in_sentence {return None;}
+
+    if byte == b'\r' || byte == b'\n' {
+      self.in_sentence = false;
+      return Some(parse(&self.buffer));
+    }
+    if !byte.is_ascii() {
+      self.in_sentence = false;
+      return Some(Err(NmeaError::Format));
+    }
+    if self.buffer.push(byte as char).is_err() {
+      self.in_sentence = false;
+      return Some(Err(NmeaError::TooLong));
+    }
+
+    return None;
+  }
+}
+
+impl Default for NmeaParser {
+  fn default() -> Self {
+    return Self::new();
+  }
+}
+
+
+/// This struct represents a GPS receiver on a UART.
+pub struct Gps {
+  #[doc(hidden)]
+  uart: UART,
+  #[doc(hidden)]
+  parser: NmeaParser
+}
+
+impl Gps {
+  /// Reads sentences from the UART. Needs the time base started with [`start_time()`](crate::time::start_time),
+  /// otherwise an error-enum is returned.
+  pub fn new(uart: UART) -> Result<Self, ProgError> {
+    if !is_time_started() {
+      rprintln!("The time base is not started! | Gps::new()");
+      return Err(ProgError::NotConfigured);
+    }
+
+    return Ok(Self {uart, parser: NmeaParser::new()});
+  }
+
+  /// Processes the characters that were received since the last call. Returns a sentence as soon as one is complete,
+  /// an error-enum like [NmeaParser::push] or for problems with the connection, or `None` if no sentence is complete
+  /// yet.
+  pub fn poll(&mut self) -> Option<Result<Sentence, NmeaError>> {
+    loop {
+      let byte = match self.uart.read_byte_timeout(0) {
+        Ok(value) => value,
+        Err(SerialError::Prog(ProgError::TimedOut)) => return None,
+        Err(error) => return Some(Err(NmeaError::Serial(error)))
+      };
+
+      if let Some(result) = self.parser.push(byte) {return Some(result);}
+    }
+  }
+
+  /// Sends a sentence with `$`, checksum and line ending, e.g. `PMTK220,1000` to set the update rate of MediaTek
+  /// receivers. Returns an error-enum if the sentence is too long or problems with the connection are detected.
+  pub fn send_command(&self, body: &str) -> Result<(), NmeaError> {
+    let sentence = match build_sentence(body) {
+      Some(value) => value,
+      None => {
+        rprintln!("The sentence is too long! | .send_command()");
+        return Err(NmeaError::TooLong);
+      }
+    };
+
+    return self.uart.write_bytes(sentence.as_bytes()).map_err(NmeaError::Serial);
+  }
+
+  /// Sends a binary UBX message to a u-blox receiver. Returns an error-enum if the payload is too long or problems with
+  /// the connection are detected.
+  pub fn send_ubx(&self, class: u8, id: u8, payload: &[u8]) -> Result<(), NmeaError> {
+    let message = match build_ubx(class, id, payload) {
+      Some(value) => value,
+      None => {
+        rprintln!("The UBX payload is too long! | .send_ubx()");
+        return Err(NmeaError::TooLong);
+      }
+    };
+
+    return self.uart.write_bytes(&message).map_err(NmeaError::Serial);
+  }
+
+  /// Returns the UART.
+  pub fn release(self) -> UART {
+    return self.uart;
+  }
+}
+
+
+// Sentence Functions =============================================================================
+/// Calculates the checksum of a sentence, the XOR of all characters between `$` and `*`.
+pub fn checksum(body: &str) -> u8 {
+  return body.bytes().fold(0, |sum, byte| sum ^ byte);
+}
+
+/// Adds `$`, the checksum and the line ending to a sentence. Returns `None` if the sentence gets too long.
+pub fn build_sentence(body: &str) -> Option<String<MAX_SENTENCE>> {
+  let mut sentence: String<MAX_SENTENCE> = String::new();
+
+  write!(sentence, "${}*{:02X}\r\n", body, checksum(body)).ok()?;
+
+  return Some(sentence);
+}
+
+/// Builds a UBX message with sync characters, class, id, length, payload and checksum. Returns `None` if the payload
+/// is longer than [MAX_UBX_PAYLOAD].
+pub fn build_ubx(class: u8, id: u8, payload: &[u8]) -> Option<Vec<u8, {MAX_UBX_PAYLOAD + 8}>> {
+  if payload.len() > MAX_UBX_PAYLOAD {return None;}
+
+  let mut message: Vec<u8, {MAX_UBX_PAYLOAD + 8}> = Vec::new();
+  message.extend_from_slice(&[0xB5, 0x62, class, id]).unwrap();
+  message.extend_from_slice(&(payload.len() as u16).to_le_bytes()).unwrap();
+  message.extend_from_slice(payload).unwrap();
+
+  // 8 bit Fletcher checksum over everything after the sync characters
+  let (mut a, mut b) = (0u8, 0u8);
+  for byte in &message[2..] {
+    a = a.wrapping_add(*byte);
+    b = b.wrapping_add(a);
+  }
+  message.extend_from_slice(&[a, b]).unwrap();
+
+  return Some(message);
+}
+
+/// Validates and decodes a sentence. The `$` at the start and the line ending are optional.
+pub fn parse(sentence: &str) -> Result<Sentence, NmeaError> {
+  let trimmed = sentence.trim_end();
+  let sentence = trimmed.strip_prefix('$').unwrap_or(trimmed);
+
+  let (body, received) = match sentence.rsplit_once('*') {
+    Some(value) => value,
+    None => return Err(NmeaError::Checksum)
+  };
+  if u8::from_str_radix(received, 16) != Ok(checksum(body)) {return Err(NmeaError::Checksum);}
+
+  let mut fields: Vec<&str, MAX_FIELDS> = Vec::new();
+  for field in body.split(',') {
+    if fields.push(field).is_err() {return Err(NmeaError::Format);}
+  }
+  let field = |index: usize| fields.get(index).copied().unwrap_or("");
+
+  // Two characters for the talker, three for the type
+  let kind = match fields[0].get(2..) {
+    Some(kind) if fields[0].len() == 5 => kind,
+    _ => return Err(NmeaError::Unsupported)
+  };
+
+  let sentence = match kind {
+    "GGA" => Sentence::Gga(Gga {
+      time: parse_time(field(1)),
+      latitude: parse_coordinate(field(2), field(3)),
+      longitude: parse_coordinate(field(4), field(5)),
+      quality: match field(6) {
+        "1" => FixQuality::Gps,
+        "2" => FixQuality::Dgps,
+        "3" => FixQuality::Pps,
+        "4" => FixQuality::Rtk,
+        "5" => FixQuality::FloatRtk,
+        "6" => FixQuality::Estimated,
+        "7" => FixQuality::Manual,
+        "8" => FixQuality::Simulation,
+        _ => FixQuality::Invalid
+      },
+      satellites: field(7).parse().unwrap_or(0),
+      hdop: field(8).parse().ok(),
+      altitude: field(9).parse().ok(),
+      geoid_separation: field(11).parse().ok()
+    }),
+    "RMC" => Sentence::Rmc(Rmc {
+      time: parse_time(field(1)),
+      valid: field(2) == "A",
+      latitude: parse_coordinate(field(3), field(4)),
+      longitude: parse_coordinate(field(5), field(6)),
+      speed_knots: field(7).parse().ok(),
+      course: field(8).parse().ok(),
+      date: parse_date(field(9))
+    }),
+    "GSA" => Sentence::Gsa(Gsa {
+      automatic: field(1) == "A",
+      fix: match field(2) {
+        "2" => FixType::Fix2D,
+        "3" => FixType::Fix3D,
+        _ => FixType::NoFix
+      },
+      satellites: (3..15).filter_map(|i| field(i).parse().ok()).collect(),
+      pdop: field(15).parse().ok(),
+      hdop: field(16).parse().ok(),
+      vdop: field(17).parse().ok()
+    }),
+    "GSV" => {
+      let mut satellites = Vec::new();
+
+      // Four fields per satellite, NMEA 4.10 adds a signal identifier at the end
+      for index in (4..fields.len().saturating_sub(3)).step_by(4) {
+        let prn = match field(index).parse() {
+          Ok(prn) => prn,
+          Err(_) => continue
+        };
+
+        satellites.push(Satellite {
+          prn,
+          elevation: field(index + 1).parse().ok(),
+          azimuth: field(index + 2).parse().ok(),
+          snr: field(index + 3).parse().ok()
+        }).ok();
+      }
+
+      let (messages, message, satellites_in_view) = match (field(1).parse(), field(2).parse(), field(3).parse()) {
+        (Ok(messages), Ok(message), Ok(in_view)) => (messages, message, in_view),
+        _ => return Err(NmeaError::Format)
+      };
+
+      Sentence::Gsv(Gsv {messages, message, satellites_in_view, satellites})
+    },
+    "VTG" => Sentence::Vtg(Vtg {
+      course_true: field(1).parse().ok(),
+      course_magnetic: field(3).parse().ok(),
+      speed_knots: field(5).parse().ok(),
+      speed_kmh: field(7).parse().ok()
+    }),
+    _ => return Err(NmeaError::Unsupported)
+  };
+
+  return Ok(sentence);
+}
+
+
+// Private Functions ==============================================================================
+// hhmmss with optional fraction of a second
+fn parse_time(text: &str) -> Option<Time> {
+  let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+  if whole.len() != 6 || !whole.is_ascii() || !fraction.bytes().all(|i| i.is_ascii_digit()) {return None;}
+
+  // Milliseconds from the first three digits of the fraction
+  let mut millisecond: u16 = 0;
+  for index in 0..3 {
+    let digit = fraction.as_bytes().get(index).map_or(0, |i| i - b'0');
+    millisecond = millisecond * 10 + digit as u16;
+  }
+
+  return Some(Time {
+    hour: whole[0..2].parse().ok()?,
+    minute: whole[2..4].parse().ok()?,
+    second: whole[4..6].parse().ok()?,
+    millisecond
+  });
+}
+
+// ddmmyy, the century is not sent
+fn parse_date(text: &str) -> Option<Date> {
+  if text.len() != 6 || !text.is_ascii() {return None;}
+
+  return Some(Date {
+    day: text[0..2].parse().ok()?,
+    month: text[2..4].parse().ok()?,
+    year: 2000 + text[4..6].parse::<u16>().ok()?
+  });
+}
+
+// (d)ddmm.mmmm and N/S or E/W
+fn parse_coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+  let value: f64 = value.parse().ok()?;
+
+  let degrees = (value / 100.0) as u32 as f64;
+  let coordinate = degrees + (value - degrees * 100.0) / 60.0;
+
+  return match hemisphere {
+    "N" | "E" => Some(coordinate),
+    "S" | "W" => Some(-coordinate),
+    _ => None
+  };
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A log of a receiver with a fix and one without
+  const LOG: &str = "\
+    $GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n\
+    $GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A\r\n\
+    $GPGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.3,2.1*39\r\n\
+    $GPGSV,2,1,08,01,40,083,46,02,17,308,41,12,07,344,39,14,22,228,45*75\r\n\
+    $GPVTG,054.7,T,034.4,M,005.5,N,010.2,K*48\r\n\
+    $GPGGA,,,,,,0,00,99.99,,,,,,*48\r\n\
+    $GPRMC,,V,,,,,,,,,,N*53\r\n\
+    $GPGSA,A,1,,,,,,,,,,,,,99.99,99.99,99.99*30\r\n\
+    $GPGSV,1,1,00*79\r\n\
+    $GNVTG,,T,,M,0.021,N,0.039,K,A*34\r\n";
+
+  fn parse_log(log: &str) -> std::vec::Vec<Result<Sentence, NmeaError>> {
+    let mut parser = NmeaParser::new();
+    return log.bytes().filter_map(|byte| parser.push(byte)).collect();
+  }
+
+  fn assert_close(value: Option<f64>, expected: f64) {
+    assert!((value.unwrap() - expected).abs() < 1e-6, "{:?} != {}", value, expected);
+  }
+
+  #[test]
+  fn log_with_fix() {
+    let sentences = parse_log(LOG);
+    assert_eq!(sentences.len(), 10);
+
+    let gga = match &sentences[0] {
+      Ok(Sentence::Gga(gga)) => gga,
+      other => panic!("{:?}", other)
+    };
+    assert_eq!(gga.time, Some(Time {hour: 12, minute: 35, second: 19, millisecond: 0}));
+    assert_close(gga.latitude, 48.0 + 7.038 / 60.0);
+    assert_close(gga.longitude, 11.0 + 31.0 / 60.0);
+    assert_eq!(gga.quality, FixQuality::Gps);
+    assert_eq!(gga.satellites, 8);
+    assert_eq!((gga.hdop, gga.altitude, gga.geoid_separation), (Some(0.9), Some(545.4), Some(46.9)));
+
+    let rmc = match &sentences[1] {
+      Ok(Sentence::Rmc(rmc)) => rmc,
+      other => panic!("{:?}", other)
+    };
+    assert!(rmc.valid);
+    assert_close(rmc.latitude, 48.0 + 7.038 / 60.0);
+    assert_eq!((rmc.speed_knots, rmc.course), (Some(22.4), Some(84.4)));
+    assert_eq!(rmc.date, Some(Date {day: 23, month: 3, year: 2094}));
+
+    assert_eq!(sentences[2], Ok(Sentence::Gsa(Gsa {
+      automatic: true,
+      fix: FixType::Fix3D,
+      satellites: Vec::from_slice(&[4, 5, 9, 12, 24]).unwrap(),
+      pdop: Some(2.5),
+      hdop: Some(1.3),
+      vdop: Some(2.1)
+    })));
+
+    let gsv = match &sentences[3] {
+      Ok(Sentence::Gsv(gsv)) => gsv,
+      other => panic!("{:?}", other)
+    };
+    assert_eq!((gsv.messages, gsv.message, gsv.satellites_in_view), (2, 1, 8));
+    assert_eq!(gsv.satellites.len(), 4);
+    assert_eq!(gsv.satellites[0], Satellite {prn: 1, elevation: Some(40), azimuth: Some(83), snr: Some(46)});
+    assert_eq!(gsv.satellites[3], Satellite {prn: 14, elevation: Some(22), azimuth: Some(228), snr: Some(45)});
+
+    assert_eq!(sentences[4], Ok(Sentence::Vtg(Vtg {
+      course_true: Some(54.7),
+      course_magnetic: Some(34.4),
+      speed_knots: Some(5.5),
+      speed_kmh: Some(10.2)
+    })));
+  }
+
+  #[test]
+  fn log_without_fix() {
+    let sentences = parse_log(LOG);
+
+    assert_eq!(sentences[5], Ok(Sentence::Gga(Gga {
+      time: None,
+      latitude: None,
+      longitude: None,
+      quality: FixQuality::Invalid,
+      satellites: 0,
+      hdop: Some(99.99),
+      altitude: None,
+      geoid_separation: None
+    })));
+
+    assert_eq!(sentences[6], Ok(Sentence::Rmc(Rmc {
+      time: None,
+      valid: false,
+      latitude: None,
+      longitude: None,
+      speed_knots: None,
+      course: None,
+      date: None
+    })));
+
+    match &sentences[7] {
+      Ok(Sentence::Gsa(gsa)) => {
+        assert_eq!(gsa.fix, FixType::NoFix);
+        assert!(gsa.satellites.is_empty());
+      },
+      other => panic!("{:?}", other)
+    };
+
+    assert_eq!(sentences[8], Ok(Sentence::Gsv(Gsv {messages: 1, message: 1, satellites_in_view: 0, satellites: Vec::new()})));
+
+    assert_eq!(sentences[9], Ok(Sentence::Vtg(Vtg {
+      course_true: None,
+      course_magnetic: None,
+      speed_knots: Some(0.021),
+      speed_kmh: Some(0.039)
+    })));
+  }
+
+  #[test]
+  fn gsv_signal_identifier() {
+    // NMEA 4.10 adds the signal identifier after the satellites
+    let gsv = match parse("$GPGSV,3,3,10,26,05,180,,29,62,052,44,1*65") {
+      Ok(Sentence::Gsv(gsv)) => gsv,
+      other => panic!("{:?}", other)
+    };
+    assert_eq!((gsv.messages, gsv.message, gsv.satellites_in_view), (3, 3, 10));
+    assert_eq!(gsv.satellites[..], [
+      Satellite {prn: 26, elevation: Some(5), azimuth: Some(180), snr: None},
+      Satellite {prn: 29, elevation: Some(62), azimuth: Some(52), snr: Some(44)}
+    ]);
+  }
+
+  #[test]
+  fn damaged_sentences() {
+    // A flipped character, a missing checksum and a checksum that is no hex number
+    let sentences = parse_log("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*46\r\n$GPVTG,054.7,T\r\n$GPVTG*ZZ\r\n");
+    assert_eq!(sentences, [Err(NmeaError::Checksum), Err(NmeaError::Checksum), Err(NmeaError::Checksum)]);
+
+    assert_eq!(parse("$GPZDA,201530.00,04,07,2002,00,00*60"), Err(NmeaError::Unsupported));
+    // The $ and the line ending are optional
+    let empty = Ok(Sentence::Gsv(Gsv {messages: 1, message: 1, satellites_in_view: 0, satellites: Vec::new()}));
+    assert_eq!(parse("GPGSV,1,1,00*79\r\n"), empty);
+    assert_eq!(parse("GPGSV,1,1,00*79"), empty);
+    assert_eq!(parse("$GPGSV,1,1,00*79\r\n"), empty);
+    assert_eq!(parse(&build_sentence("GPGSV,1,1").unwrap()), Err(NmeaError::Format));
+    assert_eq!(parse(&build_sentence(&[","; MAX_FIELDS].concat()).unwrap()), Err(NmeaError::Format));
+  }
+
+  #[test]
+  fn long_lines_are_dropped() {
+    let mut log = std::string::String::from("$GPGSV");
+    log.push_str(&",99".repeat(MAX_SENTENCE));
+    log.push_str("\r\n$GPGSV,1,1,00*79\r\n");
+
+    // The parser waits for the next $ after the error
+    assert_eq!(parse_log(&log), [
+      Err(NmeaError::TooLong),
+      Ok(Sentence::Gsv(Gsv {messages: 1, message: 1, satellites_in_view: 0, satellites: Vec::new()}))
+    ]);
+
+    assert_eq!(parse_log("noise$GP\u{e9}\r\n"), [Err(NmeaError::Format)]);
+  }
+
+  #[test]
+  fn non_ascii_fields() {
+    // Six bytes, the slices would split the character
+    let gga = match parse(&build_sentence("GPGGA,1\u{e9}345,,,,,0,00,,,,,,").unwrap()) {
+      Ok(Sentence::Gga(gga)) => gga,
+      other => panic!("{:?}", other)
+    };
+    assert_eq!(gga.time, None);
+
+    let rmc = match parse(&build_sentence("GPRMC,123519,A,,,,,,,1\u{e9}345,,,N").unwrap()) {
+      Ok(Sentence::Rmc(rmc)) => rmc,
+      other => panic!("{:?}", other)
+    };
+    assert_eq!(rmc.time, Some(Time {hour: 12, minute: 35, second: 19, millisecond: 0}));
+    assert_eq!(rmc.date, None);
+  }
+
+  #[test]
+  fn time_and_coordinates() {
+    assert_eq!(parse_time("235959.5"), Some(Time {hour: 23, minute: 59, second: 59, millisecond: 500}));
+    assert_eq!(parse_time("000000.1234"), Some(Time {hour: 0, minute: 0, second: 0, millisecond: 123}));
+    assert_eq!(parse_time("12345"), None);
+    assert_eq!(parse_time("123519.x"), None);
+
+    assert_close(parse_coordinate("4807.038", "S"), -(48.0 + 7.038 / 60.0));
+    assert_close(parse_coordinate("01131.000", "W"), -(11.0 + 31.0 / 60.0));
+    assert_eq!(parse_coordinate("4807.038", ""), None);
+  }
+
+  #[test]
+  fn built_messages() {
+    assert_eq!(build_sentence("PUBX,00").unwrap(), "$PUBX,00*33\r\n");
+    // UBX-CFG-RATE with 200ms
+    assert_eq!(build_ubx(0x06, 0x08, &[0xC8, 0x00, 0x01, 0x00, 0x01, 0x00]).unwrap(),
+      [0xB5, 0x62, 0x06, 0x08, 0x06, 0x00, 0xC8, 0x00, 0x01, 0x00, 0x01, 0x00, 0xDE, 0x6A]);
+    assert!(build_ubx(0x06, 0x08, &[0; MAX_UBX_PAYLOAD + 1]).is_none());
+  }
+}
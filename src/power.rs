@@ -0,0 +1,242 @@
+//! This module contains everything that is used for the low-power modes.
+//!
+//! | Mode    | Wakeup sources                                  | Wakeup time | After wakeup                  |
+//! | ------- | ----------------------------------------------- | ----------- | ----------------------------- |
+//! | Sleep   | Any interrupt                                   | Immediate   | Continues                     |
+//! | Stop    | EXTI pins, UART (start bit), RTC alarm/wakeup   | ~15µs       | Continues, clocks restored    |
+//! | Standby | WKUP pins, RTC alarm/wakeup, NRST, IWDG         | ~300µs      | Reset, only backup data kept  |
+//!
+//! In stop mode the timers are not clocked, so [`millis()`](crate::time::millis) would fall behind. If the
+//! [RTC](crate::rtc) is running, the time spent in stop mode is measured with it and added to the time base.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::power::{self, RegulatorMode};
+//! use rustuino::rtc::{self, RtcClock};
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   start_time();
+//!   rtc::start(RtcClock::Lse).unwrap();
+//!   let button = pinmode_input(PC13).unwrap();
+//!
+//!   loop {
+//!     // Log something, then sleep until the button is pressed or 10 seconds have passed
+//!     power::wake_on_pin(&button, GpioEdge::Falling);
+//!     power::wake_after(10000).unwrap();
+//!     power::stop(RegulatorMode::LowPower);
+//!   }
+//! }
+//! ```
+
+use crate::include::ProgError;
+use crate::gpio::{setup_exti, GpioEdge, Pin, Input};
+use crate::uart::UART;
+use crate::rtc;
+use crate::time::add_millis;
+
+// PWR_CR
+const LPDS: u32 = 1 << 0;
+const PDDS: u32 = 1 << 1;
+const CWUF: u32 = 1 << 2;
+const CSBF: u32 = 1 << 3;
+const FPDS: u32 = 1 << 9;
+// PWR_CSR
+const SBF: u32 = 1 << 1;
+
+
+/// Represents the state of the voltage regulator in stop mode.
+///
+/// | Mode     | Consumption | Wakeup time |
+/// | -------- | ----------- | ----------- |
+/// | Main     | Higher      | Shorter     |
+/// | LowPower | Lower       | Longer      |
+#[derive(Clone, Copy)]
+pub enum RegulatorMode {
+  Main, LowPower
+}
+
+/// Represents the pins that can wake the microcontroller from standby.
+#[derive(Clone, Copy)]
+pub enum WakeupPin {
+  /// WKUP1 on PA0
+  PA0,
+  /// WKUP2 on PC13
+  PC13
+}
+
+
+// Public Functions ===============================================================================
+/// Stops the CPU until the next interrupt. All peripherals keep running.
+pub fn sleep() {
+  let mut core_ptr;
+  unsafe {core_ptr = cortex_m::Peripherals::steal();}
+
+  core_ptr.SCB.clear_sleepdeep();
+  cortex_m::asm::wfi();
+}
+
+/// Stops all clocks until an EXTI line wakes the microcontroller. SRAM and registers are kept.
+///
+/// Configure the wakeup sources before with [wake_on_pin](crate::power::wake_on_pin),
+/// [wake_on_uart](crate::power::wake_on_uart), [wake_after](crate::power::wake_after) or an RTC alarm. After wakeup the
+/// clock configuration is restored, so UART baudrates and the time base stay correct.
+pub fn stop(regulator: RegulatorMode) {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let mut core_ptr;
+  unsafe {core_ptr = cortex_m::Peripherals::steal();}
+  let rcc = &peripheral_ptr.RCC;
+  let pwr = &peripheral_ptr.PWR;
+
+  rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+
+  let saved_cr = rcc.cr.read().bits();
+  let saved_cfgr = rcc.cfgr.read().bits();
+  let before = rtc::ms_of_day();
+
+  let mode = match regulator {
+    RegulatorMode::Main => 0,
+    RegulatorMode::LowPower => LPDS | FPDS
+  };
+  pwr.cr.modify(|r, w| unsafe {w.bits(r.bits() & !(PDDS | LPDS | FPDS) | mode | CWUF)});
+
+  core_ptr.SCB.set_sleepdeep();
+  cortex_m::asm::dsb();
+  cortex_m::asm::wfi();
+  core_ptr.SCB.clear_sleepdeep();
+
+  restore_clocks(saved_cr, saved_cfgr);
+
+  if let (Some(before), Some(after)) = (before, rtc::ms_of_day()) {
+    // Wraps around at midnight
+    let elapsed = (after + 86_400_000 - before) % 86_400_000;
+    add_millis(elapsed as usize);
+  }
+}
+
+/// Turns off everything except the backup domain until a wakeup pin, the RTC, NRST or the independent watchdog wakes
+/// the microcontroller. The wakeup is a reset, so this function does not return.
+///
+/// Only the backup registers, the backup SRAM and the RTC keep their content.
+pub fn standby() -> ! {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let mut core_ptr;
+  unsafe {core_ptr = cortex_m::Peripherals::steal();}
+  let rcc = &peripheral_ptr.RCC;
+  let pwr = &peripheral_ptr.PWR;
+
+  rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+
+  // Pending wakeup flags would end the standby immediately
+  if rtc::is_running() {
+    rtc::enable_backup_access();
+    rtc::clear_wakeup_flags();
+  }
+  pwr.cr.modify(|r, w| unsafe {w.bits(r.bits() | PDDS | CWUF)});
+
+  core_ptr.SCB.set_sleepdeep();
+  cortex_m::asm::dsb();
+
+  loop {cortex_m::asm::wfi();}
+}
+
+/// Checks if the microcontroller was woken from standby and clears the flag.
+pub fn woke_from_standby() -> bool {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rcc = &peripheral_ptr.RCC;
+  let pwr = &peripheral_ptr.PWR;
+
+  rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+
+  let standby = pwr.csr.read().bits() & SBF != 0;
+  pwr.cr.modify(|r, w| unsafe {w.bits(r.bits() | CSBF)});
+
+  return standby;
+}
+
+
+// Wakeup Sources =================================================================================
+/// Wakes the microcontroller from sleep or stop mode when the selected edge is detected on the pin.
+///
+/// The EXTI line is disabled again after it triggered, so call this before every low-power mode.
+pub fn wake_on_pin(pin: &Pin<Input>, edge: GpioEdge) {
+  setup_exti((pin.block, pin.number), edge);
+}
+
+/// Wakes the microcontroller from stop mode when a start bit is received by the UART.
+///
+/// The RX pin triggers an EXTI line on the falling edge of the start bit. The clocks are only running again after the
+/// wakeup, so the first byte is usually lost. Let the sender transmit a wakeup byte first. In sleep mode the UART
+/// interrupts wake the microcontroller without this function.
+pub fn wake_on_uart(uart: &UART) {
+  setup_exti(uart.rx_pin(), GpioEdge::Falling);
+}
+
+/// Wakes the microcontroller from stop or standby mode after the specified time with the RTC wakeup timer.
+///
+/// The [RTC](crate::rtc::start) has to be started. The timer keeps running periodically until
+/// [rtc::stop_wakeup](crate::rtc::stop_wakeup) is called. Returns an error-enum if the RTC is not started or the
+/// period is out of range.
+pub fn wake_after(ms: u32) -> Result<(), ProgError> {
+  return rtc::start_wakeup(ms, None);
+}
+
+/// Enables a wakeup pin that wakes the microcontroller from standby on a rising edge.
+pub fn enable_wakeup_pin(pin: WakeupPin) {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rcc = &peripheral_ptr.RCC;
+  let pwr = &peripheral_ptr.PWR;
+
+  rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+
+  match pin {
+    WakeupPin::PA0 => pwr.csr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 8))}),
+    WakeupPin::PC13 => pwr.csr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 7))})
+  };
+}
+
+/// Disables a wakeup pin, so it can be used as a normal GPIO again.
+pub fn disable_wakeup_pin(pin: WakeupPin) {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let pwr = &peripheral_ptr.PWR;
+
+  match pin {
+    WakeupPin::PA0 => pwr.csr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 8))}),
+    WakeupPin::PC13 => pwr.csr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 7))})
+  };
+}
+
+
+// Private Functions ==============================================================================
+fn restore_clocks(saved_cr: u32, saved_cfgr: u32) {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rcc = &peripheral_ptr.RCC;
+
+  // The microcontroller always wakes up with the HSI as system clock, HSE and PLL are off
+  if saved_cr & (1 << 16) != 0 {
+    rcc.cr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 16))});
+    while rcc.cr.read().bits() & (1 << 17) == 0 {}
+  }
+
+  if saved_cr & (1 << 24) != 0 {
+    rcc.cr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 24))});
+    while rcc.cr.read().bits() & (1 << 25) == 0 {}
+  }
+
+  let sw = saved_cfgr & 0x3;
+  if sw != 0 {
+    rcc.cfgr.modify(|r, w| unsafe {w.bits(r.bits() & !0x3 | sw)});
+    while (rcc.cfgr.read().bits() >> 2) & 0x3 != sw {}
+  }
+}
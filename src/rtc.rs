@@ -0,0 +1,904 @@
+//! This module contains everything that is used for the real-time clock (RTC).
+//!
+//! The RTC keeps running through resets (and with a backup battery on VBAT even without power), so unlike
+//! [`millis()`](crate::time::millis) the calendar does not start over on every boot. It can be clocked by the external
+//! 32.768kHz crystal (LSE) or the less accurate internal oscillator (LSI).
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::rtc::{self, DateTime, RtcClock, RtcAlarm, AlarmTime};
+//!
+//! fn on_alarm() {
+//!   rprintln!("Good morning!");
+//! }
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   // Start the RTC with the external crystal, keeps the calendar if it is already running
+//!   rtc::start(RtcClock::Lse).unwrap();
+//!
+//!   // Set the date and time once
+//!   rtc::set_datetime(&DateTime::new(2022, 3, 14, 15, 9, 26).unwrap()).unwrap();
+//!
+//!   // Call a function every day at 07:30:00
+//!   let alarm = AlarmTime {day: None, hour: Some(7), minute: Some(30), second: Some(0)};
+//!   rtc::set_alarm(RtcAlarm::A, alarm, on_alarm).unwrap();
+//!
+//!   loop {
+//!     let now = rtc::now().unwrap();
+//!     rprintln!("{:02}:{:02}:{:02}", now.hour, now.minute, now.second);
+//!     delay(1000);
+//!   }
+//! }
+//! ```
+
+use crate::include::ProgError;
+use stm32f4::stm32f446::{NVIC, Interrupt, interrupt};
+use cortex_m::interrupt::{Mutex, free};
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use rtt_target::rprintln;
+
+type Callback = Option<fn()>;
+type TamperCallback = Option<fn(DateTime)>;
+
+static ALARM_CALLBACKS: Mutex<RefCell<[Callback; 2]>> = Mutex::new(RefCell::new([None; 2]));
+static WAKEUP_CALLBACK: Mutex<RefCell<Callback>> = Mutex::new(RefCell::new(None));
+static TAMPER_CALLBACK: Mutex<RefCell<TamperCallback>> = Mutex::new(RefCell::new(None));
+static WAKEUP_PERIOD: AtomicU32 = AtomicU32::new(0);
+
+// RTC_ISR flags
+const ALRAWF: u32 = 1 << 0;
+const ALRBWF: u32 = 1 << 1;
+const WUTWF: u32 = 1 << 2;
+const RSF: u32 = 1 << 5;
+const INITF: u32 = 1 << 6;
+const INIT: u32 = 1 << 7;
+const ALRAF: u32 = 1 << 8;
+const ALRBF: u32 = 1 << 9;
+const WUTF: u32 = 1 << 10;
+const TSF: u32 = 1 << 11;
+const TSOVF: u32 = 1 << 12;
+const TAMP1F: u32 = 1 << 13;
+const RECALPF: u32 = 1 << 16;
+
+// EXTI lines connected to the RTC
+const EXTI_ALARM: u32 = 17;
+const EXTI_TAMPER: u32 = 21;
+const EXTI_WAKEUP: u32 = 22;
+
+// A cold LSE crystal needs up to two seconds to start
+const OSCILLATOR_TIMEOUT_MS: u32 = 5000;
+const CYCLES_PER_MS: u32 = 16000;
+
+
+/// Represents the clock sources for the RTC.
+///
+/// | Source | Frequency | Accuracy                   |
+/// | ------ | --------- | -------------------------- |
+/// | Lse    | 32.768kHz | Crystal, a few ppm         |
+/// | Lsi    | ~32kHz    | RC oscillator, up to ±5%   |
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RtcClock {
+  Lse, Lsi
+}
+
+/// Represents the two alarms of the RTC.
+#[derive(Clone, Copy)]
+pub enum RtcAlarm {
+  A, B
+}
+
+/// A calendar date and time in 24h format. Valid for the years 2000 to 2099.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+  /// Year from 2000 to 2099
+  pub year: u16,
+  /// Month from 1 (January) to 12 (December)
+  pub month: u8,
+  /// Day of the month from 1 to 31
+  pub day: u8,
+  /// Day of the week from 1 (Monday) to 7 (Sunday)
+  pub weekday: u8,
+  /// Hour from 0 to 23
+  pub hour: u8,
+  /// Minute from 0 to 59
+  pub minute: u8,
+  /// Second from 0 to 59
+  pub second: u8
+}
+
+/// The time at which an alarm triggers. Fields that are `None` are ignored in the comparison, e.g. only setting the
+/// second to `Some(0)` triggers the alarm every minute.
+#[derive(Clone, Copy)]
+pub struct AlarmTime {
+  /// Day of the month from 1 to 31
+  pub day: Option<u8>,
+  /// Hour from 0 to 23
+  pub hour: Option<u8>,
+  /// Minute from 0 to 59
+  pub minute: Option<u8>,
+  /// Second from 0 to 59
+  pub second: Option<u8>
+}
+
+impl DateTime {
+  /// Creates a date-time and calculates the weekday. Returns an error-enum if the date or time does not exist.
+  pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Result<Self, ProgError> {
+    let datetime = Self {
+      year,
+      month,
+      day,
+      weekday: if (2000..=2099).contains(&year) && (1..=12).contains(&month) {weekday(year, month, day)} else {0},
+      hour,
+      minute,
+      second
+    };
+
+    if !datetime.is_valid() {return Err(ProgError::InvalidConfiguration);}
+
+    return Ok(datetime);
+  }
+
+  /// Checks if the date and time are within the range the RTC can represent.
+  pub fn is_valid(&self) -> bool {
+    return (2000..=2099).contains(&self.year) && (1..=12).contains(&self.month) &&
+      self.day >= 1 && self.day <= days_in_month(self.year, self.month) && (1..=7).contains(&self.weekday) &&
+      self.hour < 24 && self.minute < 60 && self.second < 60;
+  }
+
+  /// Encodes the date-time into the BCD layout of the RTC time (TR) and date (DR) registers.
+  pub fn to_registers(&self) -> (u32, u32) {
+    let tr = (to_bcd(self.hour) as u32) << 16 | (to_bcd(self.minute) as u32) << 8 | to_bcd(self.second) as u32;
+    let dr = (to_bcd((self.year - 2000) as u8) as u32) << 16 | (self.weekday as u32) << 13 |
+      (to_bcd(self.month) as u32) << 8 | to_bcd(self.day) as u32;
+
+    return (tr, dr);
+  }
+
+  /// Decodes the RTC time (TR) and date (DR) registers.
+  pub fn from_registers(tr: u32, dr: u32) -> Self {
+    return Self {
+      year: 2000 + from_bcd(((dr >> 16) & 0xFF) as u8) as u16,
+      month: from_bcd(((dr >> 8) & 0x1F) as u8),
+      day: from_bcd((dr & 0x3F) as u8),
+      weekday: ((dr >> 13) & 0x7) as u8,
+      hour: from_bcd(((tr >> 16) & 0x3F) as u8),
+      minute: from_bcd(((tr >> 8) & 0x7F) as u8),
+      second: from_bcd((tr & 0x7F) as u8)
+    };
+  }
+}
+
+impl AlarmTime {
+  /// Encodes the alarm into the layout of the alarm registers (ALRMAR, ALRMBR). Fields that are `None` set the mask
+  /// bit of their part.
+  pub fn to_register(&self) -> Result<u32, ProgError> {
+    let mut value = 0;
+
+    match self.day {
+      Some(day) if (1..=31).contains(&day) => value |= (to_bcd(day) as u32) << 24,
+      Some(_) => return Err(ProgError::InvalidConfiguration),
+      None => value |= 1 << 31
+    };
+    match self.hour {
+      Some(hour) if hour < 24 => value |= (to_bcd(hour) as u32) << 16,
+      Some(_) => return Err(ProgError::InvalidConfiguration),
+      None => value |= 1 << 23
+    };
+    match self.minute {
+      Some(minute) if minute < 60 => value |= (to_bcd(minute) as u32) << 8,
+      Some(_) => return Err(ProgError::InvalidConfiguration),
+      None => value |= 1 << 15
+    };
+    match self.second {
+      Some(second) if second < 60 => value |= to_bcd(second) as u32,
+      Some(_) => return Err(ProgError::InvalidConfiguration),
+      None => value |= 1 << 7
+    };
+
+    return Ok(value);
+  }
+}
+
+
+// Public Functions ===============================================================================
+/// Starts the RTC with the selected clock source.
+///
+/// If the RTC is already running from this source (e.g. after a reset) the calendar is kept. If it runs from another
+/// source, the backup domain has to be reset, which also clears the calendar and the backup registers.
+/// Returns an error-enum if the oscillator does not start within five seconds.
+pub fn start(clock: RtcClock) -> Result<(), ProgError> {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rcc = &peripheral_ptr.RCC;
+
+  enable_backup_access();
+
+  let rtcsel: u32 = match clock {
+    RtcClock::Lse => {
+      rcc.bdcr.modify(|r, w| unsafe {w.bits(r.bits() | 1)});
+      if !wait_for_oscillator(|| rcc.bdcr.read().bits() & 0x2 != 0) {
+        rprintln!("The LSE oscillator did not start! | rtc::start()");
+        return Err(ProgError::TimedOut);
+      }
+      1
+    },
+    RtcClock::Lsi => {
+      rcc.csr.modify(|r, w| unsafe {w.bits(r.bits() | 1)});
+      if !wait_for_oscillator(|| rcc.csr.read().bits() & 0x2 != 0) {
+        rprintln!("The LSI oscillator did not start! | rtc::start()");
+        return Err(ProgError::TimedOut);
+      }
+      2
+    }
+  };
+
+  let bdcr = rcc.bdcr.read().bits();
+  let running = bdcr & (1 << 15) != 0;
+  let current = (bdcr >> 8) & 0x3;
+
+  if running && current == rtcsel {return Ok(());}
+
+  if current != 0 && current != rtcsel {
+    // The clock source can only be changed with a backup domain reset, that also turns off the LSE
+    rcc.bdcr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 16))});
+    rcc.bdcr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 16))});
+    if clock == RtcClock::Lse {
+      rcc.bdcr.modify(|r, w| unsafe {w.bits(r.bits() | 1)});
+      if !wait_for_oscillator(|| rcc.bdcr.read().bits() & 0x2 != 0) {
+        rprintln!("The LSE oscillator did not start! | rtc::start()");
+        return Err(ProgError::TimedOut);
+      }
+    }
+  }
+
+  rcc.bdcr.modify(|r, w| unsafe {w.bits(r.bits() & !(0x3 << 8) | (rtcsel << 8) | (1 << 15))});
+
+  // ck_spre = RTCCLK / ((PREDIV_A + 1) * (PREDIV_S + 1)) = 1Hz
+  let prediv_s = match clock {
+    RtcClock::Lse => 255,
+    RtcClock::Lsi => 249
+  };
+
+  let rtc = &peripheral_ptr.RTC;
+  unlock();
+  if let Err(error) = enter_init() {
+    lock();
+    return Err(error);
+  }
+  rtc.prer.write(|w| unsafe {w.bits(prediv_s)});
+  rtc.prer.write(|w| unsafe {w.bits((127 << 16) | prediv_s)});
+  // 24 hour format
+  rtc.cr.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 6))});
+  exit_init();
+  lock();
+
+  return Ok(());
+}
+
+/// Sets the calendar of the RTC. Returns an error-enum if the RTC is not started or the date-time is invalid.
+pub fn set_datetime(datetime: &DateTime) -> Result<(), ProgError> {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rtc = &peripheral_ptr.RTC;
+
+  if !is_running() {
+    rprintln!("The RTC is not started! | rtc::set_datetime()");
+    return Err(ProgError::NotConfigured);
+  }
+
+  if !datetime.is_valid() {
+    rprintln!("The date-time is not valid! | rtc::set_datetime()");
+    return Err(ProgError::InvalidConfiguration);
+  }
+
+  let (tr, dr) = datetime.to_registers();
+
+  unlock();
+  if let Err(error) = enter_init() {
+    lock();
+    return Err(error);
+  }
+  rtc.tr.write(|w| unsafe {w.bits(tr)});
+  rtc.dr.write(|w| unsafe {w.bits(dr)});
+  exit_init();
+  lock();
+
+  // Shadow registers are only valid again after the next synchronization
+  clear_flag(RSF);
+
+  return Ok(());
+}
+
+/// Reads the current date and time. Returns an error-enum if the RTC is not started.
+pub fn now() -> Result<DateTime, ProgError> {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rtc = &peripheral_ptr.RTC;
+
+  if !is_running() {
+    rprintln!("The RTC is not started! | rtc::now()");
+    return Err(ProgError::NotConfigured);
+  }
+
+  if !wait_for(|| rtc.isr.read().bits() & RSF != 0) {return Err(ProgError::TimedOut);}
+
+  // Reading TR locks DR until it is read, so both belong to the same second
+  let tr = rtc.tr.read().bits();
+  let dr = rtc.dr.read().bits();
+
+  return Ok(DateTime::from_registers(tr, dr));
+}
+
+/// Configures one of the two alarms. The callback is called from the interrupt every time the alarm matches.
+/// Returns an error-enum if the RTC is not started or the alarm time is invalid.
+pub fn set_alarm(alarm: RtcAlarm, time: AlarmTime, callback: fn()) -> Result<(), ProgError> {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rtc = &peripheral_ptr.RTC;
+
+  if !is_running() {
+    rprintln!("The RTC is not started! | rtc::set_alarm()");
+    return Err(ProgError::NotConfigured);
+  }
+
+  let value = match time.to_register() {
+    Ok(value) => value,
+    Err(error) => {
+      rprintln!("The alarm time is not valid! | rtc::set_alarm()");
+      return Err(error);
+    }
+  };
+
+  free(|cs| ALARM_CALLBACKS.borrow(cs).borrow_mut()[alarm as usize] = Some(callback));
+
+  unlock();
+  match alarm {
+    RtcAlarm::A => {
+      rtc.cr.modify(|r, w| unsafe {w.bits(r.bits() & !((1 << 8) | (1 << 12)))});
+      if !wait_for(|| rtc.isr.read().bits() & ALRAWF != 0) {
+        lock();
+        return Err(ProgError::TimedOut);
+      }
+      rtc.alrmar.write(|w| unsafe {w.bits(value)});
+      rtc.alrmassr.write(|w| unsafe {w.bits(0)});
+      clear_flag(ALRAF);
+      rtc.cr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 8) | (1 << 12))});
+    },
+    RtcAlarm::B => {
+      rtc.cr.modify(|r, w| unsafe {w.bits(r.bits() & !((1 << 9) | (1 << 13)))});
+      if !wait_for(|| rtc.isr.read().bits() & ALRBWF != 0) {
+        lock();
+        return Err(ProgError::TimedOut);
+      }
+      rtc.alrmbr.write(|w| unsafe {w.bits(value)});
+      rtc.alrmbssr.write(|w| unsafe {w.bits(0)});
+      clear_flag(ALRBF);
+      rtc.cr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 9) | (1 << 13))});
+    }
+  };
+  lock();
+
+  enable_exti_line(EXTI_ALARM);
+  unsafe {NVIC::unmask(Interrupt::RTC_ALARM);}
+
+  return Ok(());
+}
+
+/// Disables one of the two alarms and removes its callback.
+pub fn disable_alarm(alarm: RtcAlarm) {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rtc = &peripheral_ptr.RTC;
+
+  unlock();
+  match alarm {
+    RtcAlarm::A => rtc.cr.modify(|r, w| unsafe {w.bits(r.bits() & !((1 << 8) | (1 << 12)))}),
+    RtcAlarm::B => rtc.cr.modify(|r, w| unsafe {w.bits(r.bits() & !((1 << 9) | (1 << 13)))})
+  };
+  lock();
+
+  free(|cs| ALARM_CALLBACKS.borrow(cs).borrow_mut()[alarm as usize] = None);
+}
+
+/// Starts the periodic wakeup timer. The callback is called from the interrupt after every period.
+///
+/// Periods up to about 32 seconds have a resolution of 0.5ms, longer periods are rounded to whole seconds. The maximum
+/// period is 131072 seconds. Returns an error-enum if the RTC is not started or the period is out of range.
+pub fn start_wakeup(period_ms: u32, callback: Option<fn()>) -> Result<(), ProgError> {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rtc = &peripheral_ptr.RTC;
+
+  if !is_running() {
+    rprintln!("The RTC is not started! | rtc::start_wakeup()");
+    return Err(ProgError::NotConfigured);
+  }
+
+  let (wucksel, wutr, actual) = match calc_wakeup(period_ms, rtc_clock_freq()) {
+    Ok(values) => values,
+    Err(error) => {
+      rprintln!("A wakeup period of {}ms is not possible! | rtc::start_wakeup()", period_ms);
+      return Err(error);
+    }
+  };
+
+  free(|cs| WAKEUP_CALLBACK.borrow(cs).replace(callback));
+
+  unlock();
+  rtc.cr.modify(|r, w| unsafe {w.bits(r.bits() & !((1 << 10) | (1 << 14)))});
+  if !wait_for(|| rtc.isr.read().bits() & WUTWF != 0) {
+    lock();
+    return Err(ProgError::TimedOut);
+  }
+  rtc.wutr.write(|w| unsafe {w.bits(wutr as u32)});
+  rtc.cr.modify(|r, w| unsafe {w.bits(r.bits() & !0x7 | wucksel as u32)});
+  clear_flag(WUTF);
+  rtc.cr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 10) | (1 << 14))});
+  lock();
+
+  WAKEUP_PERIOD.store(actual, Ordering::SeqCst);
+  enable_exti_line(EXTI_WAKEUP);
+  unsafe {NVIC::unmask(Interrupt::RTC_WKUP);}
+
+  return Ok(());
+}
+
+/// Stops the periodic wakeup timer and removes its callback.
+pub fn stop_wakeup() {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rtc = &peripheral_ptr.RTC;
+
+  unlock();
+  rtc.cr.modify(|r, w| unsafe {w.bits(r.bits() & !((1 << 10) | (1 << 14)))});
+  lock();
+
+  WAKEUP_PERIOD.store(0, Ordering::SeqCst);
+  free(|cs| WAKEUP_CALLBACK.borrow(cs).replace(None));
+}
+
+/// Corrects the drift of the RTC clock with the smooth digital calibration.
+///
+/// Takes the measured deviation in ppm as an argument, positive if the RTC runs too fast. The correction has a
+/// resolution of about 0.95ppm and a range of -487ppm to +488ppm. Returns an error-enum if the value is out of range.
+pub fn calibrate(ppm: f32) -> Result<(), ProgError> {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rtc = &peripheral_ptr.RTC;
+
+  let (calp, calm) = match calc_calibration(ppm) {
+    Ok(values) => values,
+    Err(error) => {
+      rprintln!("A correction of {}ppm is not possible! | rtc::calibrate()", ppm);
+      return Err(error);
+    }
+  };
+
+  if !wait_for(|| rtc.isr.read().bits() & RECALPF == 0) {return Err(ProgError::TimedOut);}
+
+  unlock();
+  rtc.calr.write(|w| unsafe {w.bits(((calp as u32) << 15) | calm as u32)});
+  lock();
+
+  return Ok(());
+}
+
+/// Saves a timestamp of the calendar when the tamper pin PC13 detects the selected edge.
+///
+/// The callback is called from the interrupt with the saved date-time. Without a callback the timestamp can be read
+/// with [timestamp](crate::rtc::timestamp).
+/// Keep in mind that a tamper event also erases the backup registers.
+pub fn enable_tamper_timestamp(falling_edge: bool, callback: Option<fn(DateTime)>) -> Result<(), ProgError> {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rtc = &peripheral_ptr.RTC;
+
+  if !is_running() {
+    rprintln!("The RTC is not started! | rtc::enable_tamper_timestamp()");
+    return Err(ProgError::NotConfigured);
+  }
+
+  free(|cs| TAMPER_CALLBACK.borrow(cs).replace(callback));
+
+  clear_flag(TSF | TSOVF | TAMP1F);
+  // TAMP1E, TAMP1TRG, TAMPIE (only with callback) and TAMPTS
+  let mut tafcr = 1 | (1 << 7);
+  if falling_edge {tafcr |= 1 << 1;}
+  if callback.is_some() {tafcr |= 1 << 2;}
+  rtc.tafcr.modify(|r, w| unsafe {w.bits(r.bits() & !0x87 | tafcr)});
+
+  if callback.is_some() {
+    enable_exti_line(EXTI_TAMPER);
+    unsafe {NVIC::unmask(Interrupt::TAMP_STAMP);}
+  }
+
+  return Ok(());
+}
+
+/// Returns the date-time saved by the last tamper event and clears it. Returns `None` if no event occured.
+///
+/// The timestamp registers do not contain the year, so the current year of the calendar is used.
+pub fn timestamp() -> Option<DateTime> {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rtc = &peripheral_ptr.RTC;
+
+  if rtc.isr.read().bits() & TSF == 0 {return None;}
+
+  let tr = rtc.tstr.read().bits();
+  let dr = rtc.tsdr.read().bits() | (rtc.dr.read().bits() & (0xFF << 16));
+  clear_flag(TSF | TSOVF | TAMP1F);
+
+  return Some(DateTime::from_registers(tr, dr));
+}
+
+
+// Date Functions =================================================================================
+/// Converts a binary value from 0 to 99 into BCD.
+pub fn to_bcd(value: u8) -> u8 {
+  return ((value / 10) << 4) | (value % 10);
+}
+
+/// Converts a BCD value into binary.
+pub fn from_bcd(value: u8) -> u8 {
+  return (value >> 4) * 10 + (value & 0xF);
+}
+
+/// Checks if a year is a leap year.
+#[allow(clippy::manual_is_multiple_of)]
+pub fn is_leap_year(year: u16) -> bool {
+  return (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+}
+
+/// Returns the number of days of a month from 1 to 12. Returns 0 for invalid months.
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+  return match month {
+    1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+    4 | 6 | 9 | 11 => 30,
+    2 => if is_leap_year(year) {29} else {28},
+    _ => 0
+  };
+}
+
+/// Calculates the weekday of a date from 1 (Monday) to 7 (Sunday).
+pub fn weekday(year: u16, month: u8, day: u8) -> u8 {
+  // Sakamoto's method
+  const OFFSETS: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+  let y = if month < 3 {year - 1} else {year};
+  let sunday_based = (y + y / 4 - y / 100 + y / 400 + OFFSETS[month as usize - 1] + day as u16) % 7;
+
+  return if sunday_based == 0 {7} else {sunday_based as u8};
+}
+
+
+// Private Functions ==============================================================================
+#[doc(hidden)]
+pub fn enable_backup_access() {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rcc = &peripheral_ptr.RCC;
+  let pwr = &peripheral_ptr.PWR;
+
+  rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+  // DBP: the backup domain is write protected after reset
+  pwr.cr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 8))});
+  while pwr.cr.read().bits() & (1 << 8) == 0 {}
+}
+
+#[doc(hidden)]
+pub fn is_running() -> bool {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+  return peripheral_ptr.RCC.bdcr.read().bits() & (1 << 15) != 0;
+}
+
+#[doc(hidden)]
+pub fn ms_of_day() -> Option<u32> {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rtc = &peripheral_ptr.RTC;
+
+  if !is_running() {return None;}
+
+  // After a low power mode the shadow registers have to be synchronized again
+  clear_flag(RSF);
+  if !wait_for(|| rtc.isr.read().bits() & RSF != 0) {return None;}
+
+  // Reading SSR locks TR and DR until DR is read
+  let ssr = rtc.ssr.read().bits() & 0xFFFF;
+  let tr = rtc.tr.read().bits();
+  let _ = rtc.dr.read().bits();
+  let prediv_s = rtc.prer.read().bits() & 0x7FFF;
+
+  let time = DateTime::from_registers(tr, 0);
+  let seconds = time.hour as u32 * 3600 + time.minute as u32 * 60 + time.second as u32;
+  let fraction = (prediv_s.saturating_sub(ssr)) * 1000 / (prediv_s + 1);
+
+  return Some(seconds * 1000 + fraction);
+}
+
+#[doc(hidden)]
+pub fn clear_wakeup_flags() {
+  clear_flag(ALRAF | ALRBF | WUTF | TSF | TAMP1F);
+}
+
+#[doc(hidden)]
+pub fn wakeup_period() -> u32 {
+  return WAKEUP_PERIOD.load(Ordering::SeqCst);
+}
+
+#[doc(hidden)]
+pub fn calc_wakeup(period_ms: u32, rtc_freq: u32) -> Result<(u8, u16, u32), ProgError> {
+  if period_ms == 0 {return Err(ProgError::InvalidConfiguration);}
+
+  // RTCCLK / 16
+  let ticks = (period_ms as u64 * rtc_freq as u64 / 16 + 500) / 1000;
+  if ticks <= 0x10000 {
+    let ticks = ticks.max(1);
+    return Ok((0, (ticks - 1) as u16, (ticks * 16000 / rtc_freq as u64) as u32));
+  }
+
+  // ck_spre (1Hz), with 2^16 added to the counter above 65536 seconds
+  let seconds = (period_ms + 500) / 1000;
+  if seconds <= 0x10000 {return Ok((4, (seconds - 1) as u16, seconds * 1000));}
+  if seconds <= 0x20000 {return Ok((6, (seconds - 0x10001) as u16, seconds * 1000));}
+
+  return Err(ProgError::InvalidConfiguration);
+}
+
+#[doc(hidden)]
+pub fn calc_calibration(ppm: f32) -> Result<(bool, u16), ProgError> {
+  // One CALM step masks one of 2^20 RTCCLK pulses
+  let steps = libm::roundf(ppm / 0.953_674_3) as i32;
+
+  if (0..=511).contains(&steps) {return Ok((false, steps as u16));}
+  // CALP inserts 512 pulses
+  if (-512..0).contains(&steps) {return Ok((true, (512 + steps) as u16));}
+
+  return Err(ProgError::InvalidConfiguration);
+}
+
+fn rtc_clock_freq() -> u32 {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+  return match (peripheral_ptr.RCC.bdcr.read().bits() >> 8) & 0x3 {
+    2 => 32000,
+    _ => 32768
+  };
+}
+
+fn unlock() {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rtc = &peripheral_ptr.RTC;
+
+  rtc.wpr.write(|w| unsafe {w.bits(0xCA)});
+  rtc.wpr.write(|w| unsafe {w.bits(0x53)});
+}
+
+fn lock() {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+  peripheral_ptr.RTC.wpr.write(|w| unsafe {w.bits(0xFF)});
+}
+
+fn enter_init() -> Result<(), ProgError> {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rtc = &peripheral_ptr.RTC;
+
+  rtc.isr.modify(|r, w| unsafe {w.bits(r.bits() | INIT)});
+  if !wait_for(|| rtc.isr.read().bits() & INITF != 0) {
+    rprintln!("The RTC did not enter the initialization mode! | rtc");
+    return Err(ProgError::TimedOut);
+  }
+
+  return Ok(());
+}
+
+fn exit_init() {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+  peripheral_ptr.RTC.isr.modify(|r, w| unsafe {w.bits(r.bits() & !INIT)});
+}
+
+fn clear_flag(flags: u32) {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+  // The flags are cleared by writing 0, writing 1 leaves them untouched. INIT has to keep its value.
+  peripheral_ptr.RTC.isr.write(|w| unsafe {w.bits(!(flags | INIT) | (peripheral_ptr.RTC.isr.read().bits() & INIT))});
+}
+
+fn enable_exti_line(line: u32) {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let exti = &peripheral_ptr.EXTI;
+
+  exti.rtsr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << line))});
+  exti.imr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << line))});
+}
+
+fn clear_exti_line(line: u32) {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+  peripheral_ptr.EXTI.pr.write(|w| unsafe {w.bits(1 << line)});
+}
+
+fn wait_for<F: Fn() -> bool>(condition: F) -> bool {
+  // The register flags are set within a few RTCCLK cycles, which is far below this limit at 16MHz
+  for _ in 0..1_000_000 {
+    if condition() {return true;}
+  }
+
+  return false;
+}
+
+// The time base may not be started yet, so the timeout is counted with busy waiting
+fn wait_for_oscillator<F: Fn() -> bool>(condition: F) -> bool {
+  for _ in 0..OSCILLATOR_TIMEOUT_MS {
+    if condition() {return true;}
+    cortex_m::asm::delay(CYCLES_PER_MS);
+  }
+
+  return condition();
+}
+
+
+// Interrupts =====================================================================================
+#[allow(non_snake_case)]
+#[interrupt]
+fn RTC_ALARM() {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+  let isr = peripheral_ptr.RTC.isr.read().bits();
+  clear_exti_line(EXTI_ALARM);
+
+  let callbacks = free(|cs| *ALARM_CALLBACKS.borrow(cs).borrow());
+
+  if isr & ALRAF != 0 {
+    clear_flag(ALRAF);
+    if let Some(callback) = callbacks[0] {callback();}
+  }
+  if isr & ALRBF != 0 {
+    clear_flag(ALRBF);
+    if let Some(callback) = callbacks[1] {callback();}
+  }
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn RTC_WKUP() {
+  clear_flag(WUTF);
+  clear_exti_line(EXTI_WAKEUP);
+
+  if let Some(callback) = free(|cs| *WAKEUP_CALLBACK.borrow(cs).borrow()) {callback();}
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn TAMP_STAMP() {
+  clear_exti_line(EXTI_TAMPER);
+
+  if let Some(datetime) = timestamp() {
+    if let Some(callback) = free(|cs| *TAMPER_CALLBACK.borrow(cs).borrow()) {callback(datetime);}
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bcd_round_trip() {
+    assert_eq!(to_bcd(0), 0x00);
+    assert_eq!(to_bcd(9), 0x09);
+    assert_eq!(to_bcd(10), 0x10);
+    assert_eq!(to_bcd(59), 0x59);
+    assert_eq!(to_bcd(99), 0x99);
+    assert_eq!(from_bcd(0x47), 47);
+
+    for value in 0..100 {assert_eq!(from_bcd(to_bcd(value)), value);}
+  }
+
+  #[test]
+  fn leap_years_and_month_lengths() {
+    assert!(is_leap_year(2000));
+    assert!(is_leap_year(2024));
+    assert!(!is_leap_year(2023));
+    assert!(!is_leap_year(2100));
+
+    assert_eq!(days_in_month(2024, 2), 29);
+    assert_eq!(days_in_month(2023, 2), 28);
+    assert_eq!(days_in_month(2023, 4), 30);
+    assert_eq!(days_in_month(2023, 12), 31);
+    assert_eq!(days_in_month(2023, 13), 0);
+  }
+
+  #[test]
+  fn weekdays() {
+    // Saturday, Monday, Thursday, Sunday
+    assert_eq!(weekday(2000, 1, 1), 6);
+    assert_eq!(weekday(2024, 1, 1), 1);
+    assert_eq!(weekday(2024, 2, 29), 4);
+    assert_eq!(weekday(2099, 12, 27), 7);
+  }
+
+  #[test]
+  fn datetime_validation() {
+    assert_eq!(DateTime::new(2024, 2, 29, 23, 59, 59).unwrap().weekday, 4);
+    assert_eq!(DateTime::new(2023, 2, 29, 0, 0, 0), Err(ProgError::InvalidConfiguration));
+    assert_eq!(DateTime::new(1999, 12, 31, 0, 0, 0), Err(ProgError::InvalidConfiguration));
+    assert_eq!(DateTime::new(2024, 13, 1, 0, 0, 0), Err(ProgError::InvalidConfiguration));
+    assert_eq!(DateTime::new(2024, 1, 1, 24, 0, 0), Err(ProgError::InvalidConfiguration));
+  }
+
+  #[test]
+  fn datetime_registers() {
+    let datetime = DateTime::new(2024, 10, 19, 13, 45, 7).unwrap();
+    let (tr, dr) = datetime.to_registers();
+
+    assert_eq!(tr, 0x0013_4507);
+    // Year 24, Saturday, October 19th
+    assert_eq!(dr, 0x0024_0000 | (6 << 13) | 0x1019);
+    assert_eq!(DateTime::from_registers(tr, dr), datetime);
+  }
+
+  #[test]
+  fn alarm_registers() {
+    let every_minute = AlarmTime {day: None, hour: None, minute: None, second: Some(30)};
+    assert_eq!(every_minute.to_register(), Ok((1 << 31) | (1 << 23) | (1 << 15) | 0x30));
+
+    let daily = AlarmTime {day: None, hour: Some(7), minute: Some(15), second: Some(0)};
+    assert_eq!(daily.to_register(), Ok((1 << 31) | 0x0007_1500));
+
+    let invalid = AlarmTime {day: Some(32), hour: None, minute: None, second: None};
+    assert_eq!(invalid.to_register(), Err(ProgError::InvalidConfiguration));
+  }
+
+  #[test]
+  fn wakeup_ranges() {
+    assert_eq!(calc_wakeup(0, 32768), Err(ProgError::InvalidConfiguration));
+    assert_eq!(calc_wakeup(1000, 32768), Ok((0, 2047, 1000)));
+    assert_eq!(calc_wakeup(1000, 32000), Ok((0, 1999, 1000)));
+
+    // 2^16 ticks of RTCCLK / 16 are the limit of the 16-bit counter
+    assert_eq!(calc_wakeup(32000, 32768), Ok((0, 0xFFFF, 32000)));
+    assert_eq!(calc_wakeup(32001, 32768), Ok((4, 31, 32000)));
+
+    // ck_spre up to 2^16 seconds, then WUCKSEL 11x adds 2^16 to the counter
+    assert_eq!(calc_wakeup(65536000, 32768), Ok((4, 0xFFFF, 65536000)));
+    assert_eq!(calc_wakeup(65537000, 32768), Ok((6, 0, 65537000)));
+    assert_eq!(calc_wakeup(131072000, 32768), Ok((6, 0xFFFF, 131072000)));
+    assert_eq!(calc_wakeup(131073000, 32768), Err(ProgError::InvalidConfiguration));
+  }
+
+  #[test]
+  fn calibration_limits() {
+    assert_eq!(calc_calibration(0.0), Ok((false, 0)));
+    assert_eq!(calc_calibration(10.0), Ok((false, 10)));
+    // Slowing down only masks pulses with CALM
+    assert_eq!(calc_calibration(487.3), Ok((false, 511)));
+    assert_eq!(calc_calibration(488.5), Err(ProgError::InvalidConfiguration));
+
+    // Speeding up sets CALP and masks less than 512 pulses
+    assert_eq!(calc_calibration(-1.0), Ok((true, 511)));
+    assert_eq!(calc_calibration(-10.0), Ok((true, 502)));
+    assert_eq!(calc_calibration(-488.28), Ok((true, 0)));
+    assert_eq!(calc_calibration(-489.5), Err(ProgError::InvalidConfiguration));
+  }
+}
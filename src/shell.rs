@@ -0,0 +1,678 @@
+//! This module contains an interactive command shell for a serial terminal.
+//!
+//! The shell reads lines with a [LineEditor], splits them into arguments and runs the matching command of a static
+//! table. Every command gets a context, e.g. a struct with the peripherals it controls. The built-in commands
+//! configure pins and read or set them, so the hardware can be tested without writing code:
+//!
+//! | Command                               | Function                                      |
+//! | ------------------------------------- | --------------------------------------------- |
+//! | help [command]                        | Lists the commands or shows one of them       |
+//! | pinmode <pin> <in\|out\|analog\|off>  | Configures a pin or releases it               |
+//! | read <pin>                            | Reads an input pin or the state of an output  |
+//! | write <pin> <0\|1>                    | Sets an output pin                            |
+//! | analog <pin>                          | Reads an analog pin                           |
+//! | pins                                  | Lists all configured pins with their mode     |
+//!
+//! Pins are written like `PA5` or `a5`. The line editor supports backspace, Ctrl-C and the arrow keys for the history.
+//! Arguments with spaces can be put in double quotes. The line editor and the command processing do not use the
+//! hardware, so they can be tested on the host with any [fmt::Write](core::fmt::Write) as output.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::uart::UART;
+//! use rustuino::shell::{Shell, Command};
+//! use core::fmt::Write;
+//!
+//! struct Board {
+//!   led: Pin<Output>
+//! }
+//!
+//! fn led(board: &mut Board, args: &[&str], out: &mut dyn Write) -> Result<(), &'static str> {
+//!   match args {
+//!     ["on"] => digital_write(&board.led, true),
+//!     ["off"] => digital_write(&board.led, false),
+//!     _ => return Err("Use on or off")
+//!   };
+//!
+//!   writeln!(out, "LED is {}", args[0]).ok();
+//!   return Ok(());
+//! }
+//!
+//! static COMMANDS: [Command<Board>; 1] = [
+//!   Command {name: "led", args: "<on|off>", help: "Switches the LED", run: led}
+//! ];
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   start_time();
+//!   let mut uart = UART::new(2, PA2, PA3, 115200).unwrap();
+//!   let mut shell = Shell::new(&COMMANDS, Board {led: pinmode_output(PA5).unwrap()});
+//!
+//!   shell.prompt(&mut uart);
+//!   loop {
+//!     shell.poll(&mut uart).ok();
+//!   }
+//! }
+//! ```
+
+use crate::include::{ProgError, SerialError, PIN_CONF};
+use crate::gpio::{pinmode_input, pinmode_output, pinmode_analog, digital_read, digital_write, digital_state, read_mode, Pin, Input, Output, Analog};
+use crate::analog::analog_read;
+use crate::uart::UART;
+use heapless::{String, Vec};
+use core::fmt::Write;
+
+/// The maximum length of a line.
+pub const LINE_LEN: usize = 80;
+/// The number of lines in the history.
+pub const HISTORY_LEN: usize = 4;
+/// The maximum number of arguments including the command name.
+pub const MAX_ARGS: usize = 8;
+
+// Pins the built-in commands can hold at the same time
+const MAX_PINS: usize = 8;
+const ERASE: &str = "\x08 \x08";
+
+
+/// A command of the shell.
+pub struct Command<C> {
+  /// The first word of the line
+  pub name: &'static str,
+  /// The arguments shown by the help, e.g. `<pin> <0|1>`
+  pub args: &'static str,
+  /// One line that describes the command
+  pub help: &'static str,
+  /// Runs the command with the context and the arguments after the name. The returned error is printed by the shell.
+  pub run: fn(&mut C, &[&str], &mut dyn Write) -> Result<(), &'static str>
+}
+
+
+/// A line editor that is fed with the received bytes one by one and echoes them.
+///
+/// Specify the maximum line length and the number of lines in the history with the turbofish operator. Characters
+/// after the maximum length are ignored.
+pub struct LineEditor<const N: usize, const H: usize> {
+  #[doc(hidden)]
+  line: String<N>,
+  #[doc(hidden)]
+  history: Vec<String<N>, H>,
+  // Position in the history while browsing, the length of the history for a new line
+  #[doc(hidden)]
+  browse: usize,
+  // 1 after ESC, 2 after ESC [
+  #[doc(hidden)]
+  escape: u8,
+  #[doc(hidden)]
+  last_cr: bool,
+  #[doc(hidden)]
+  complete: bool
+}
+
+impl<const N: usize, const H: usize> LineEditor<N, H> {
+  /// Creates an editor with an empty line and history.
+  pub fn new() -> Self {
+    return Self {
+      line: String::new(),
+      history: Vec::new(),
+      browse: 0,
+      escape: 0,
+      last_cr: false,
+      complete: false
+    };
+  }
+
+  /// Processes the next received byte and writes the echo to the output. Returns the line if the byte was `\r` or
+  /// `\n`, or an empty line after Ctrl-C.
+  ///
+  /// The line stays valid until the next byte is fed.
+  pub fn feed<W: Write + ?Sized>(&mut self, byte: u8, out: &mut W) -> Option<&str> {
+    if self.complete {
+      self.line.clear();
+      self.complete = false;
+    }
+
+    let last_cr = self.last_cr;
+    self.last_cr = false;
+
+    // Arrow keys send ESC [ A and ESC [ B
+    if self.escape == 1 {
+      self.escape = if byte == b'[' {2} else {0};
+      return None;
+    }
+    if self.escape == 2 {
+      self.escape = 0;
+
+      match byte {
+        b'A' => self.browse_history(true, out),
+        b'B' => self.browse_history(false, out),
+        _ => {}
+      };
+      return None;
+    }
+
+    match byte {
+      b'\r' | b'\n' => {
+        // Terminals send \r, \n or both
+        if byte == b'\n' && last_cr {return None;}
+        self.last_cr = byte == b'\r';
+
+        out.write_str("\r\n").ok();
+        self.add_history();
+        self.complete = true;
+
+        return Some(&self.line);
+      },
+      // Backspace and delete
+      0x08 | 0x7F => self.erase(out),
+      // Ctrl-C
+      0x03 => {
+        out.write_str("^C\r\n").ok();
+        self.line.clear();
+        self.browse = self.history.len();
+        self.complete = true;
+
+        return Some(&self.line);
+      },
+      0x1B => self.escape = 1,
+      0x20..=0x7E => self.insert(byte as char, out),
+      _ => {}
+    };
+
+    return None;
+  }
+
+  /// Returns the line that is edited at the moment.
+  pub fn line(&self) -> &str {
+    return &self.line;
+  }
+
+  /// Returns the history, the latest line is the last.
+  pub fn history(&self) -> &[String<N>] {
+    return &self.history;
+  }
+
+  fn insert<W: Write + ?Sized>(&mut self, character: char, out: &mut W) {
+    if self.line.push(character).is_ok() {out.write_char(character).ok();}
+  }
+
+  fn erase<W: Write + ?Sized>(&mut self, out: &mut W) {
+    if self.line.pop().is_some() {out.write_str(ERASE).ok();}
+  }
+
+  fn add_history(&mut self) {
+    if H > 0 && !self.line.trim().is_empty() && self.history.last() != Some(&self.line) {
+      if self.history.is_full() {self.history.remove(0);}
+      self.history.push(self.line.clone()).ok();
+    }
+
+    self.browse = self.history.len();
+  }
+
+  fn browse_history<W: Write + ?Sized>(&mut self, up: bool, out: &mut W) {
+    if up {
+      if self.browse == 0 {return;}
+      self.browse -= 1;
+    }
+    else {
+      if self.browse >= self.history.len() {return;}
+      self.browse += 1;
+    }
+
+    for _ in 0..self.line.len() {out.write_str(ERASE).ok();}
+
+    // Below the latest line is a new empty line
+    self.line = match self.history.get(self.browse) {
+      Some(line) => line.clone(),
+      None => String::new()
+    };
+    out.write_str(&self.line).ok();
+  }
+}
+
+impl<const N: usize, const H: usize> Default for LineEditor<N, H> {
+  fn default() -> Self {
+    return Self::new();
+  }
+}
+
+
+/// This struct represents a command shell with a static table of commands and their context.
+pub struct Shell<C: 'static> {
+  #[doc(hidden)]
+  editor: LineEditor<LINE_LEN, HISTORY_LEN>,
+  #[doc(hidden)]
+  commands: &'static [Command<C>],
+  #[doc(hidden)]
+  context: C,
+  #[doc(hidden)]
+  pins: ShellPins,
+  #[doc(hidden)]
+  prompt: &'static str
+}
+
+impl<C: 'static> Shell<C> {
+  /// Creates a shell with the commands, which are searched before the built-in commands, and their context.
+  pub fn new(commands: &'static [Command<C>], context: C) -> Self {
+    return Self {
+      editor: LineEditor::new(),
+      commands,
+      context,
+      pins: ShellPins {pins: Vec::new()},
+      prompt: "> "
+    };
+  }
+
+  /// Replaces the prompt, the default is `> `.
+  pub fn set_prompt(&mut self, prompt: &'static str) {
+    self.prompt = prompt;
+  }
+
+  /// Returns the context of the commands.
+  pub fn context(&self) -> &C {
+    return &self.context;
+  }
+
+  /// Returns the context of the commands.
+  pub fn context_mut(&mut self) -> &mut C {
+    return &mut self.context;
+  }
+
+  /// Writes the prompt, e.g. once after startup.
+  pub fn prompt(&self, out: &mut dyn Write) {
+    out.write_str(self.prompt).ok();
+  }
+
+  /// Processes all bytes that were received since the last call and runs the completed lines. Needs the time base
+  /// started with [`start_time()`](crate::time::start_time). Returns an error-enum if problems with the connection are
+  /// detected.
+  pub fn poll(&mut self, uart: &mut UART) -> Result<(), SerialError> {
+    loop {
+      match uart.read_byte_timeout(0) {
+        Ok(byte) => self.process(byte, uart),
+        Err(SerialError::Prog(ProgError::TimedOut)) => return Ok(()),
+        Err(error) => return Err(error)
+      };
+    }
+  }
+
+  /// Processes the next received byte. Runs the line and writes a new prompt if the line is complete.
+  pub fn process(&mut self, byte: u8, out: &mut dyn Write) {
+    let mut line: String<LINE_LEN> = String::new();
+
+    match self.editor.feed(byte, out) {
+      Some(text) => line.push_str(text).unwrap(),
+      None => return
+    };
+
+    self.execute(&line, out);
+    out.write_str(self.prompt).ok();
+  }
+
+  /// Runs a line and writes the output of the command.
+  pub fn execute(&mut self, line: &str, out: &mut dyn Write) {
+    let args = match tokenize::<MAX_ARGS>(line) {
+      Some(args) => args,
+      None => {
+        writeln!(out, "Error: Too many arguments or missing quote\r").ok();
+        return;
+      }
+    };
+
+    let name = match args.first() {
+      Some(name) => *name,
+      None => return
+    };
+
+    let result = if name == "help" {self.help(args.get(1).copied(), out)}
+    else if let Some(command) = self.commands.iter().find(|i| i.name == name) {(command.run)(&mut self.context, &args[1..], out)}
+    else if let Some(command) = BUILTINS.iter().find(|i| i.name == name) {(command.run)(&mut self.pins, &args[1..], out)}
+    else {Err("Unknown command, try help")};
+
+    if let Err(message) = result {writeln!(out, "Error: {}\r", message).ok();}
+  }
+
+  fn help(&self, name: Option<&str>, out: &mut dyn Write) -> Result<(), &'static str> {
+    let user = self.commands.iter().map(|i| (i.name, i.args, i.help));
+    let builtin = BUILTINS.iter().map(|i| (i.name, i.args, i.help));
+    let help = [("help", "[command]", "Lists the commands or shows one of them")];
+    let all = user.chain(builtin).chain(help);
+
+    match name {
+      Some(name) => match all.clone().find(|i| i.0 == name) {
+        Some((name, args, help)) => {writeln!(out, "{} {}\r\n  {}\r", name, args, help).ok();},
+        None => return Err("Unknown command")
+      },
+      None => {
+        for (name, args, help) in all {
+          let width = 34usize.saturating_sub(name.len() + 1);
+          writeln!(out, "{} {:<width$} {}\r", name, args, help, width = width).ok();
+        }
+      }
+    };
+
+    return Ok(());
+  }
+}
+
+
+// Parser Functions ===============================================================================
+/// Splits a line at whitespace into at most `M` arguments. Text in double quotes is one argument without the quotes.
+/// Returns `None` if there are more arguments or a quote is not closed.
+pub fn tokenize<const M: usize>(line: &str) -> Option<Vec<&str, M>> {
+  let mut args: Vec<&str, M> = Vec::new();
+  let mut rest = line.trim_start();
+
+  while !rest.is_empty() {
+    let (arg, next) = if let Some(quoted) = rest.strip_prefix('"') {
+      let end = quoted.find('"')?;
+      (&quoted[..end], &quoted[end + 1..])
+    }
+    else {
+      let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+      (&rest[..end], &rest[end..])
+    };
+
+    args.push(arg).ok()?;
+    rest = next.trim_start();
+  }
+
+  return Some(args);
+}
+
+/// Parses a pin like `PA5`, `pa5` or `A5`. Returns `None` if the text is no pin, whether the pin exists is checked
+/// when it is configured.
+pub fn parse_pin(text: &str) -> Option<(char, u8)> {
+  let text = text.strip_prefix(['P', 'p']).unwrap_or(text);
+
+  let mut chars = text.chars();
+  let block = chars.next()?.to_ascii_lowercase();
+  if !('a'..='h').contains(&block) {return None;}
+
+  let number: u8 = chars.as_str().parse().ok()?;
+  if number > 15 {return None;}
+
+  return Some((block, number));
+}
+
+
+// Built-in Commands ==============================================================================
+// Pins configured by the built-in commands
+struct ShellPins {
+  pins: Vec<ShellPin, MAX_PINS>
+}
+
+enum ShellPin {
+  Input(Pin<Input>),
+  Output(Pin<Output>),
+  Analog(Pin<Analog>)
+}
+
+impl ShellPin {
+  fn id(&self) -> (char, u8) {
+    return match self {
+      ShellPin::Input(pin) => (pin.block, pin.number),
+      ShellPin::Output(pin) => (pin.block, pin.number),
+      ShellPin::Analog(pin) => (pin.block, pin.number)
+    };
+  }
+}
+
+impl ShellPins {
+  fn find(&self, args: &[&str]) -> Result<&ShellPin, &'static str> {
+    let pin = match args.first().and_then(|i| parse_pin(i)) {
+      Some(pin) => pin,
+      None => return Err("Missing or invalid pin")
+    };
+
+    return match self.pins.iter().find(|i| i.id() == pin) {
+      Some(pin) => Ok(pin),
+      None => Err("The pin is not configured with pinmode")
+    };
+  }
+}
+
+static BUILTINS: [Command<ShellPins>; 5] = [
+  Command {name: "pinmode", args: "<pin> <in|out|analog|off>", help: "Configures a pin or releases it", run: pinmode},
+  Command {name: "read", args: "<pin>", help: "Reads an input pin or the state of an output", run: read},
+  Command {name: "write", args: "<pin> <0|1>", help: "Sets an output pin", run: write},
+  Command {name: "analog", args: "<pin>", help: "Reads an analog pin", run: analog},
+  Command {name: "pins", args: "", help: "Lists all configured pins with their mode", run: pins}
+];
+
+fn pinmode(shell: &mut ShellPins, args: &[&str], out: &mut dyn Write) -> Result<(), &'static str> {
+  let (pin, mode) = match args {
+    [pin, mode] => match parse_pin(pin) {
+      Some(pin) => (pin, *mode),
+      None => return Err("Invalid pin")
+    },
+    _ => return Err("Use pinmode <pin> <in|out|analog|off>")
+  };
+
+  // The pin is kept if the mode is invalid
+  if !["in", "out", "analog", "off"].contains(&mode) {return Err("The mode has to be in, out, analog or off");}
+
+  // Dropping the pin releases it
+  if let Some(index) = shell.pins.iter().position(|i| i.id() == pin) {shell.pins.swap_remove(index);}
+  if mode == "off" {return Ok(());}
+
+  if shell.pins.is_full() {return Err("Too many pins, release one with off");}
+
+  let result = match mode {
+    "in" => pinmode_input(pin).map(ShellPin::Input),
+    "out" => pinmode_output(pin).map(ShellPin::Output),
+    _ => pinmode_analog(pin).map(ShellPin::Analog)
+  };
+
+  return match result {
+    Ok(configured) => {
+      shell.pins.push(configured).ok();
+      writeln!(out, "P{}{} configured\r", pin.0.to_ascii_uppercase(), pin.1).ok();
+      Ok(())
+    },
+    Err(ProgError::AlreadyConfigured) => Err("The pin is used by the program"),
+    Err(_) => Err("The pin is not available in this mode")
+  };
+}
+
+fn read(shell: &mut ShellPins, args: &[&str], out: &mut dyn Write) -> Result<(), &'static str> {
+  let value = match shell.find(args) {
+    Ok(ShellPin::Input(pin)) => digital_read(pin),
+    Ok(ShellPin::Output(pin)) => digital_state(pin),
+    Ok(ShellPin::Analog(_)) => return Err("Use analog for analog pins"),
+    Err(error) => return Err(error)
+  };
+
+  writeln!(out, "{}\r", value as u8).ok();
+  return Ok(());
+}
+
+fn write(shell: &mut ShellPins, args: &[&str], out: &mut dyn Write) -> Result<(), &'static str> {
+  let value = match args.get(1) {
+    Some(&"0") => false,
+    Some(&"1") => true,
+    _ => return Err("Use write <pin> <0|1>")
+  };
+
+  return match shell.find(args) {
+    Ok(ShellPin::Output(pin)) => {
+      digital_write(pin, value);
+      writeln!(out, "{}\r", value as u8).ok();
+      Ok(())
+    },
+    Ok(_) => Err("The pin is no output"),
+    Err(error) => Err(error)
+  };
+}
+
+fn analog(shell: &mut ShellPins, args: &[&str], out: &mut dyn Write) -> Result<(), &'static str> {
+  return match shell.find(args) {
+    Ok(ShellPin::Analog(pin)) => {
+      writeln!(out, "{}\r", analog_read(pin)).ok();
+      Ok(())
+    },
+    Ok(_) => Err("The pin is no analog pin"),
+    Err(error) => Err(error)
+  };
+}
+
+fn pins(shell: &mut ShellPins, _args: &[&str], out: &mut dyn Write) -> Result<(), &'static str> {
+  for pin in unsafe {(*core::ptr::addr_of!(PIN_CONF)).iter()} {
+    let owner = if shell.pins.iter().any(|i| i.id() == *pin) {"shell"} else {"program"};
+    writeln!(out, "P{}{:<3} {:<10} {}\r", pin.0.to_ascii_uppercase(), pin.1, pin_mode(*pin), owner).ok();
+  }
+
+  return Ok(());
+}
+
+fn pin_mode(pin: (char, u8)) -> &'static str {
+  return match read_mode(pin) {
+    0 => "input",
+    1 => "output",
+    2 => "alternate",
+    _ => "analog"
+  };
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Feeds the bytes and returns the completed lines and the echo
+  fn feed<const N: usize, const H: usize>(editor: &mut LineEditor<N, H>, bytes: &[u8]) -> (std::vec::Vec<std::string::String>, std::string::String) {
+    let mut lines = std::vec::Vec::new();
+    let mut echo = std::string::String::new();
+
+    for byte in bytes {
+      if let Some(line) = editor.feed(*byte, &mut echo) {lines.push(line.into());}
+    }
+
+    return (lines, echo);
+  }
+
+  fn say(context: &mut u32, args: &[&str], out: &mut dyn Write) -> Result<(), &'static str> {
+    *context += 1;
+    writeln!(out, "{}\r", args.join(",")).ok();
+    return Ok(());
+  }
+
+  static COMMANDS: [Command<u32>; 1] = [Command {name: "say", args: "<text>...", help: "Prints the arguments", run: say}];
+
+  #[test]
+  fn lines_are_echoed() {
+    let mut editor = LineEditor::<16, 4>::new();
+
+    let (lines, echo) = feed(&mut editor, b"ls -l\r\nab\n");
+    assert_eq!(lines, ["ls -l", "ab"]);
+    // \n after \r does not complete an empty line
+    assert_eq!(echo, "ls -l\r\nab\r\n");
+
+    // Control characters are ignored, characters after the maximum length too
+    let (lines, echo) = feed(&mut editor, b"\x01abcdefghijklmnopq\r");
+    assert_eq!(lines, ["abcdefghijklmnop"]);
+    assert_eq!(echo, "abcdefghijklmnop\r\n");
+  }
+
+  #[test]
+  fn backspace_erases() {
+    let mut editor = LineEditor::<16, 4>::new();
+
+    let (lines, echo) = feed(&mut editor, b"ab\x08c\x7F\x7F\x08d\r");
+    assert_eq!(lines, ["d"]);
+    assert_eq!(echo, "ab\x08 \x08c\x08 \x08\x08 \x08d\r\n");
+  }
+
+  #[test]
+  fn ctrl_c_clears_line() {
+    let mut editor = LineEditor::<16, 4>::new();
+
+    let (lines, echo) = feed(&mut editor, b"reboot\x03");
+    assert_eq!(lines, [""]);
+    assert_eq!(echo, "reboot^C\r\n");
+    assert_eq!(editor.line(), "");
+    assert!(editor.history().is_empty());
+  }
+
+  #[test]
+  fn history_is_browsed() {
+    let mut editor = LineEditor::<16, 2>::new();
+
+    // Empty and repeated lines are not added, the oldest line is dropped
+    feed(&mut editor, b"one\rtwo\r\r  \rtwo\rthree\r");
+    assert_eq!(editor.history(), ["two", "three"]);
+
+    let (_, echo) = feed(&mut editor, b"x\x1b[A");
+    assert_eq!(editor.line(), "three");
+    assert_eq!(echo, "x\x08 \x08three");
+
+    feed(&mut editor, b"\x1b[A\x1b[A");
+    assert_eq!(editor.line(), "two");
+
+    // Below the latest line is the new empty line
+    feed(&mut editor, b"\x1b[B\x1b[B\x1b[B");
+    assert_eq!(editor.line(), "");
+
+    let (lines, _) = feed(&mut editor, b"\x1b[A!\r");
+    assert_eq!(lines, ["three!"]);
+    assert_eq!(editor.history(), ["three", "three!"]);
+  }
+
+  #[test]
+  fn escape_sequences_are_skipped() {
+    let mut editor = LineEditor::<16, 4>::new();
+
+    // Right arrow, ESC with another byte and up without history
+    let (lines, echo) = feed(&mut editor, b"a\x1b[Cb\x1bxc\x1b[Ad\r");
+    assert_eq!(lines, ["abcd"]);
+    assert_eq!(echo, "abcd\r\n");
+  }
+
+  #[test]
+  fn lines_are_tokenized() {
+    assert_eq!(tokenize::<8>("  write  PA5 1 ").unwrap(), ["write", "PA5", "1"]);
+    assert_eq!(tokenize::<8>("say \"hello world\" \"\" x").unwrap(), ["say", "hello world", "", "x"]);
+    assert!(tokenize::<8>("").unwrap().is_empty());
+    assert!(tokenize::<8>("say \"open").is_none());
+    assert!(tokenize::<2>("a b c").is_none());
+  }
+
+  #[test]
+  fn pins_are_parsed() {
+    assert_eq!(parse_pin("PA5"), Some(('a', 5)));
+    assert_eq!(parse_pin("pc13"), Some(('c', 13)));
+    assert_eq!(parse_pin("H15"), Some(('h', 15)));
+    assert_eq!(parse_pin("PA16"), None);
+    assert_eq!(parse_pin("PI0"), None);
+    assert_eq!(parse_pin("P"), None);
+    assert_eq!(parse_pin("PA"), None);
+    assert_eq!(parse_pin("PA-1"), None);
+  }
+
+  #[test]
+  fn commands_are_executed() {
+    let mut shell = Shell::new(&COMMANDS, 0);
+    let mut out = std::string::String::new();
+
+    for byte in b"say a \"b c\"\r" {shell.process(*byte, &mut out);}
+    assert_eq!(out, "say a \"b c\"\r\na,b c\r\n> ");
+    assert_eq!(*shell.context(), 1);
+
+    let mut out = std::string::String::new();
+    shell.execute("nope", &mut out);
+    assert_eq!(out, "Error: Unknown command, try help\r\n");
+
+    let mut out = std::string::String::new();
+    shell.execute("help say", &mut out);
+    assert_eq!(out, "say <text>...\r\n  Prints the arguments\r\n");
+
+    // An invalid mode is rejected before a pin is touched
+    let mut out = std::string::String::new();
+    shell.execute("pinmode PA5 foo", &mut out);
+    assert_eq!(out, "Error: The mode has to be in, out, analog or off\r\n");
+  }
+}
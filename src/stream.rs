@@ -0,0 +1,309 @@
+//! This module contains the [`Stream`] trait with parsers for text based protocols, like the `Stream` class in Arduino.
+//!
+//! The trait only needs a way to read and peek single bytes with a timeout, all parsers are provided. It is
+//! implemented by the [UART](crate::uart::UART) and by [BufferStream], which reads from memory and can be used to test
+//! parsers on the host.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::uart::UART;
+//! use rustuino::stream::Stream;
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   start_time();
+//!   let mut uart = UART::new(2, PA2, PA3, 115200).unwrap();
+//!
+//!   loop {
+//!     // Wait for a command like "SET 12,-5"
+//!     if uart.find(b"SET") {
+//!       let x = uart.parse_int();
+//!       let y = uart.parse_int();
+//!       rprintln!("x: {:?}, y: {:?}", x, y);
+//!     }
+//!   }
+//! }
+//! ```
+
+use heapless::String;
+
+/// A source of bytes with parsers for numbers and text.
+///
+/// Every read waits at most the [timeout](crate::stream::Stream::timeout) for the next byte, so the parsers return
+/// when the other side stops sending.
+pub trait Stream {
+  /// Returns the next byte, or `None` if nothing arrives within the timeout.
+  fn timed_read(&mut self) -> Option<u8>;
+
+  /// Returns the next byte without consuming it, or `None` if nothing arrives within the timeout.
+  fn timed_peek(&mut self) -> Option<u8>;
+
+  /// Returns the timeout in milliseconds.
+  fn timeout(&self) -> usize;
+
+  /// Sets the timeout in milliseconds.
+  fn set_timeout(&mut self, timeout_ms: usize);
+
+  /// Reads until the target is found. Returns false if the timeout expired before.
+  fn find(&mut self, target: &[u8]) -> bool {
+    return self.find_until(target, &[]);
+  }
+
+  /// Reads until the target or the terminator is found. Returns true if the target was found and false if the
+  /// terminator was found or the timeout expired. An empty terminator is never found.
+  fn find_until(&mut self, target: &[u8], terminator: &[u8]) -> bool {
+    if target.is_empty() {return true;}
+
+    let mut target_matched = 0;
+    let mut terminator_matched = 0;
+
+    while let Some(byte) = self.timed_read() {
+      target_matched = match_next(target, target_matched, byte);
+      if target_matched == target.len() {return true;}
+
+      if !terminator.is_empty() {
+        terminator_matched = match_next(terminator, terminator_matched, byte);
+        if terminator_matched == terminator.len() {return false;}
+      }
+    }
+
+    return false;
+  }
+
+  /// Skips everything up to the first digit or minus sign and returns the integer that starts there. The first
+  /// character after the number is not consumed. Returns `None` if no number arrived within the timeout.
+  fn parse_int(&mut self) -> Option<i32> {
+    let mut next = skip_to_number(self, false)?;
+
+    let mut negative = false;
+    let mut value: i32 = 0;
+
+    loop {
+      if next == b'-' {negative = true;}
+      else {value = value.wrapping_mul(10).wrapping_add((next - b'0') as i32);}
+
+      self.timed_read();
+      next = match self.timed_peek() {
+        Some(byte) if byte.is_ascii_digit() => byte,
+        _ => break
+      };
+    }
+
+    return Some(if negative {value.wrapping_neg()} else {value});
+  }
+
+  /// Skips everything up to the first digit, minus sign or decimal point and returns the decimal number that starts
+  /// there. The first character after the number is not consumed. Returns `None` if no number arrived within the
+  /// timeout.
+  fn parse_float(&mut self) -> Option<f32> {
+    let mut next = skip_to_number(self, true)?;
+
+    let mut negative = false;
+    let mut is_fraction = false;
+    let mut value: f32 = 0.0;
+    let mut divisor: f32 = 1.0;
+
+    loop {
+      if next == b'-' {negative = true;}
+      else if next == b'.' {is_fraction = true;}
+      else {
+        value = value * 10.0 + (next - b'0') as f32;
+        if is_fraction {divisor *= 10.0;}
+      }
+
+      self.timed_read();
+      next = match self.timed_peek() {
+        Some(byte) if byte.is_ascii_digit() || (byte == b'.' && !is_fraction) => byte,
+        _ => break
+      };
+    }
+
+    if negative {value = -value;}
+    return Some(value / divisor);
+  }
+
+  /// Reads into the buffer until the terminator is found, the buffer is full or the timeout expires. The terminator
+  /// is consumed but not stored. Returns the number of bytes in the buffer.
+  fn read_bytes_until(&mut self, terminator: u8, buffer: &mut [u8]) -> usize {
+    let mut len = 0;
+
+    while len < buffer.len() {
+      match self.timed_read() {
+        Some(byte) if byte == terminator => break,
+        Some(byte) => buffer[len] = byte,
+        None => break
+      };
+      len += 1;
+    }
+
+    return len;
+  }
+
+  /// Reads text until the terminator is found, the string is full or the timeout expires. The terminator is consumed
+  /// but not stored. Every byte is stored as one character, like in Arduino.
+  fn read_string_until<const N: usize>(&mut self, terminator: u8) -> String<N> {
+    let mut text: String<N> = String::new();
+
+    while text.len() < N {
+      match self.timed_read() {
+        Some(byte) if byte == terminator => break,
+        Some(byte) => {if text.push(byte as char).is_err() {break;}},
+        None => break
+      };
+    }
+
+    return text;
+  }
+}
+
+
+/// A [Stream] that reads from a slice in memory. Reads after the end return `None` immediately.
+///
+/// # Examples
+///
+/// ```
+/// use rustuino::stream::{Stream, BufferStream};
+///
+/// let mut stream = BufferStream::new(b"temp=21.5;hum=40");
+/// assert!(stream.find(b"temp="));
+/// assert_eq!(stream.parse_float(), Some(21.5));
+/// assert_eq!(stream.parse_int(), Some(40));
+/// ```
+pub struct BufferStream<'a> {
+  #[doc(hidden)]
+  data: &'a [u8],
+  #[doc(hidden)]
+  position: usize,
+  #[doc(hidden)]
+  timeout: usize
+}
+
+impl<'a> BufferStream<'a> {
+  /// Creates a stream that reads the data from the beginning.
+  pub fn new(data: &'a [u8]) -> Self {
+    return Self {data, position: 0, timeout: 1000};
+  }
+
+  /// Returns the data that was not read yet.
+  pub fn remaining(&self) -> &'a [u8] {
+    return &self.data[self.position..];
+  }
+}
+
+impl<'a> Stream for BufferStream<'a> {
+  fn timed_read(&mut self) -> Option<u8> {
+    let byte = self.timed_peek();
+    if byte.is_some() {self.position += 1;}
+    return byte;
+  }
+
+  fn timed_peek(&mut self) -> Option<u8> {
+    return self.data.get(self.position).copied();
+  }
+
+  fn timeout(&self) -> usize {
+    return self.timeout;
+  }
+
+  fn set_timeout(&mut self, timeout_ms: usize) {
+    self.timeout = timeout_ms;
+  }
+}
+
+
+// Private Functions ==============================================================================
+// Returns the first character of a number without consuming it
+fn skip_to_number<S: Stream + ?Sized>(stream: &mut S, allow_point: bool) -> Option<u8> {
+  loop {
+    let byte = stream.timed_peek()?;
+
+    if byte.is_ascii_digit() || byte == b'-' || (allow_point && byte == b'.') {return Some(byte);}
+    stream.timed_read();
+  }
+}
+
+// Returns the number of matched bytes of the pattern after the next byte
+fn match_next(pattern: &[u8], mut matched: usize, byte: u8) -> usize {
+  loop {
+    if pattern[matched] == byte {return matched + 1;}
+    if matched == 0 {return 0;}
+
+    // Fall back to the longest prefix that is also a suffix of the matched part
+    matched = (1..matched).rev().find(|&len| pattern[..len] == pattern[matched - len..matched]).unwrap_or(0);
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse_int_skips_leading_junk() {
+    let mut stream = BufferStream::new(b"SET x=12,y=-5;");
+
+    assert_eq!(stream.parse_int(), Some(12));
+    // The character after the number is not consumed
+    assert_eq!(stream.remaining(), b",y=-5;");
+    assert_eq!(stream.parse_int(), Some(-5));
+    assert_eq!(stream.remaining(), b";");
+  }
+
+  #[test]
+  fn parse_int_times_out_without_digits() {
+    let mut stream = BufferStream::new(b"no digits here");
+
+    assert_eq!(stream.parse_int(), None);
+    assert!(stream.remaining().is_empty());
+  }
+
+  #[test]
+  fn parse_float_reads_fractions() {
+    let mut stream = BufferStream::new(b"t=-3.25 h=.5 v=1.2.3");
+
+    assert_eq!(stream.parse_float(), Some(-3.25));
+    assert_eq!(stream.parse_float(), Some(0.5));
+    // A second decimal point ends the number
+    assert_eq!(stream.parse_float(), Some(1.2));
+    assert_eq!(stream.parse_float(), Some(0.3));
+    assert_eq!(stream.parse_float(), None);
+  }
+
+  #[test]
+  fn find_handles_partial_matches() {
+    let mut stream = BufferStream::new(b"aaab rest");
+
+    assert!(stream.find(b"aab"));
+    assert_eq!(stream.remaining(), b" rest");
+    assert!(!stream.find(b"missing"));
+    assert!(stream.find(b""));
+  }
+
+  #[test]
+  fn find_until_stops_at_terminator() {
+    let mut stream = BufferStream::new(b"key=1\nOK\n");
+
+    assert!(!stream.find_until(b"OK", b"\n"));
+    assert_eq!(stream.remaining(), b"OK\n");
+    assert!(stream.find_until(b"OK", b"\n"));
+  }
+
+  #[test]
+  fn read_until_terminator() {
+    let mut stream = BufferStream::new(b"hello,world");
+    let mut buffer = [0; 8];
+
+    assert_eq!(stream.read_bytes_until(b',', &mut buffer), 5);
+    assert_eq!(&buffer[..5], b"hello");
+
+    let text: String<3> = stream.read_string_until(b',');
+    assert_eq!(text.as_str(), "wor");
+    let text: String<8> = stream.read_string_until(b',');
+    assert_eq!(text.as_str(), "ld");
+  }
+}
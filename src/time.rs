@@ -24,8 +24,11 @@
 //! }
 //! ```
 
+#![allow(clippy::question_mark, clippy::needless_late_init)]
+
 use crate::include::{GpioError, ProgError, PWM_MAP};
 use crate::gpio::{Pin, PWM};
+use crate::executor::wake_timers;
 use stm32f4::stm32f446::{NVIC, Interrupt, interrupt};
 use cortex_m::interrupt::{Mutex, free};
 use core::cell::RefCell;
@@ -325,9 +328,41 @@ pub fn millis() -> usize {
 }
 
 
+/// Returns true if `now` reached the deadline, also if [millis] wrapped around in between. A deadline is calculated
+/// with `millis().wrapping_add(timeout)` and may lie up to `usize::MAX / 2` milliseconds in the future.
+pub fn is_expired(now: usize, deadline: usize) -> bool {
+  return (now.wrapping_sub(deadline) as isize) >= 0;
+}
+
+#[doc(hidden)]
+pub fn is_time_started() -> bool {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+  return peripheral_ptr.RCC.apb1enr.read().tim7en().is_enabled();
+}
+
+#[doc(hidden)]
+pub fn add_millis(ms: usize) {
+  let now = free(|cs| {
+    TIME_COUNTER.borrow(cs).replace_with(|&mut i| i.wrapping_add(ms));
+    *TIME_COUNTER.borrow(cs).borrow()
+  });
+  wake_timers(now);
+}
+
+
 // Interrupts =====================================================================================
 #[allow(non_snake_case)]
 #[interrupt]
 fn TIM7() {
-  free(|cs| TIME_COUNTER.borrow(cs).replace_with(|&mut i| i + 1));
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  peripheral_ptr.TIM7.sr.modify(|_, w| w.uif().clear_bit());
+
+  let now = free(|cs| {
+    TIME_COUNTER.borrow(cs).replace_with(|&mut i| i.wrapping_add(1));
+    *TIME_COUNTER.borrow(cs).borrow()
+  });
+  wake_timers(now);
 }
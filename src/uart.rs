@@ -22,20 +22,212 @@
 //!   }
 //! }
 //! ```
+//! 
+//! Values can be printed with the [`uprint!`](crate::uprint) and [`uprintln!`](crate::uprintln) macros, that work like
+//! `print!` and `println!`:
+//! 
+//! ```no_run
+//! let value = analog_read(&pin);
+//! uprintln!(uart, "Sensor: {} ({}%)", value, value * 100 / 1023).unwrap();
+//! ```
 
-use crate::include::{SerialError, ProgError, UART_MAP, PIN_CONF};
-use crate::gpio::{pinmode_alternate_function, Pin, AlternateFunction};
-use stm32f4::stm32f446::{NVIC, Interrupt};
+#![allow(clippy::question_mark, clippy::needless_late_init)]
+
+use crate::include::{SerialError, ProgError, UART_MAP, UART_FLOW_MAP, PIN_CONF};
+use crate::gpio::{pinmode_alternate_function, digital_write, open_drain, set_bias, get_gpio, GpioBias, Pin, AlternateFunction, Output};
+use crate::executor::WakerSlot;
+use crate::time::{millis, is_expired, is_time_started};
+use crate::dma::{DmaStream, DmaDirection, stream_remaining};
+use crate::stream::Stream;
+use stm32f4::stm32f446::{NVIC, Interrupt, interrupt, usart1::RegisterBlock, gpioh::RegisterBlock as GpioRegisterBlock};
+use heapless::String;
+use cortex_m::interrupt::{Mutex, free};
+use cortex_m::peripheral::DWT;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::fmt;
+use core::future::Future;
+use core::task::{Context, Poll};
 use rtt_target::rprintln;
 
+// All U(S)ART peripherals run from the HSI
+const PCLK_FREQ: u32 = 16000000;
+const CYCLES_PER_US: u32 = PCLK_FREQ / 1000000;
+const DEFAULT_TIMEOUT: usize = 1000;
+
+// The autobaud polls the RX pin, at 115200 a bit still lasts 139 cycles
+const COMMON_BAUDRATES: [u32; 11] = [1200, 2400, 4800, 9600, 14400, 19200, 28800, 38400, 57600, 76800, 115200];
+// Maximal deviation of a detected baudrate in 1/1000
+const BAUD_TOLERANCE: u64 = 40;
+
+type CtsCallback = Option<fn(bool)>;
+
+static RX_WAKERS: [WakerSlot; 6] = [const {WakerSlot::new()}; 6];
+static CTS_CALLBACKS: Mutex<RefCell<[CtsCallback; 6]>> = Mutex::new(RefCell::new([None; 6]));
+
+// NDTR of the RX stream at the last idle line, bit 31 marks a new frame
+const NEW_FRAME: u32 = 1 << 31;
+static IDLE_POSITIONS: [AtomicU32; 6] = [const {AtomicU32::new(0)}; 6];
+
+/// Represents the number of data bits in a frame, without the parity bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+  /// Only possible with parity, e.g. 7E1
+  Seven,
+  Eight,
+  /// Only possible without parity, use [write_word](crate::uart::UART::write_word) and
+  /// [read_word](crate::uart::UART::read_word) to access the ninth bit
+  Nine
+}
+
+/// Represents the parity bit that is added to every frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+  None, Even, Odd
+}
+
+/// Represents the number of stop bits. Half and one and a half stop bits are not available on UART4 and UART5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+  Half, One, OneAndHalf, Two
+}
+
+/// Represents the oversampling of the receiver. 8x allows twice the baudrate, 16x is more tolerant to clock deviation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oversampling {
+  Times8, Times16
+}
+
+/// Describes the baudrate and frame format of a serial connection. Is used with
+/// [UART::with_config](crate::uart::UART::with_config).
+///
+/// Starts with 8N1 and 16x oversampling, the other settings can be changed with the builder methods.
+///
+/// # Examples
+///
+/// ```no_run
+/// // 8 data bits, no parity, 2 stop bits
+/// let config = SerialConfig::new(19200).stop_bits(StopBits::Two);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialConfig {
+  pub baud: u32,
+  pub data_bits: DataBits,
+  pub parity: Parity,
+  pub stop_bits: StopBits,
+  pub oversampling: Oversampling
+}
+
+impl SerialConfig {
+  /// Creates a configuration with the specified baudrate and 8N1.
+  pub fn new(baud: u32) -> Self {
+    return Self {
+      baud,
+      data_bits: DataBits::Eight,
+      parity: Parity::None,
+      stop_bits: StopBits::One,
+      oversampling: Oversampling::Times16
+    };
+  }
+
+  /// Sets the number of data bits.
+  pub fn data_bits(mut self, data_bits: DataBits) -> Self {
+    self.data_bits = data_bits;
+    return self;
+  }
+
+  /// Sets the parity.
+  pub fn parity(mut self, parity: Parity) -> Self {
+    self.parity = parity;
+    return self;
+  }
+
+  /// Sets the number of stop bits.
+  pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+    self.stop_bits = stop_bits;
+    return self;
+  }
+
+  /// Sets the oversampling.
+  pub fn oversampling(mut self, oversampling: Oversampling) -> Self {
+    self.oversampling = oversampling;
+    return self;
+  }
+
+  /// Returns the bits of CR1 (M, PCE, PS, OVER8) and CR2 (STOP) for this configuration.
+  #[doc(hidden)]
+  pub fn registers(&self) -> Result<(u32, u32), ProgError> {
+    // The word length includes the parity bit
+    let m = match (self.data_bits, self.parity) {
+      (DataBits::Seven, Parity::None) | (DataBits::Nine, Parity::Even | Parity::Odd) => {
+        return Err(ProgError::InvalidConfiguration);
+      },
+      (DataBits::Seven, _) | (DataBits::Eight, Parity::None) => 0,
+      _ => 1 << 12
+    };
+
+    let parity = match self.parity {
+      Parity::None => 0,
+      Parity::Even => 1 << 10,
+      Parity::Odd => (1 << 10) | (1 << 9)
+    };
+
+    let over8 = match self.oversampling {
+      Oversampling::Times8 => 1 << 15,
+      Oversampling::Times16 => 0
+    };
+
+    let stop = match self.stop_bits {
+      StopBits::One => 0,
+      StopBits::Half => 1 << 12,
+      StopBits::Two => 2 << 12,
+      StopBits::OneAndHalf => 3 << 12
+    };
+
+    return Ok((m | parity | over8, stop));
+  }
+
+  /// Returns the mask for the data bits in the data register, the parity bit is removed.
+  #[doc(hidden)]
+  pub fn data_mask(&self) -> u16 {
+    return match self.data_bits {
+      DataBits::Seven => 0x7F,
+      DataBits::Eight => 0xFF,
+      DataBits::Nine => 0x1FF
+    };
+  }
+}
+
 /// This struct represents a configured UART peripheral.
 pub struct UART {
   #[doc(hidden)]
   core: u8,
   #[doc(hidden)]
+  config: SerialConfig,
+  #[doc(hidden)]
   _tx_pin: Pin<AlternateFunction>,
   #[doc(hidden)]
-  _rx_pin: Pin<AlternateFunction>
+  _rx_pin: Option<Pin<AlternateFunction>>,
+  #[doc(hidden)]
+  half_duplex: bool,
+  #[doc(hidden)]
+  _rts_pin: Option<Pin<AlternateFunction>>,
+  #[doc(hidden)]
+  _cts_pin: Option<Pin<AlternateFunction>>,
+  #[doc(hidden)]
+  rs485: Option<Rs485>,
+  #[doc(hidden)]
+  dma_tx: Option<DmaStream>,
+  #[doc(hidden)]
+  timeout: usize,
+  #[doc(hidden)]
+  peeked: Option<u8>
+}
+
+struct Rs485 {
+  de_pin: Pin<Output>,
+  pre_delay_us: u32,
+  post_delay_us: u32
 }
 
 impl UART {
@@ -44,10 +236,30 @@ impl UART {
   /// This Method expects the used UART core, two [pin identifiers](crate::include::pins) for the tx and rx-pins
   /// and a baudrate as parameters and returns the [UART Struct](crate::uart::UART). Panics if the core or pins
   /// are already used or invalid.
+  ///
+  /// The connection uses 8 data bits, no parity and 1 stop bit (8N1). Use
+  /// [with_config](crate::uart::UART::with_config) for other frame formats.
   pub fn new(core: u8, tx_pin: (char, u8), rx_pin: (char, u8), baud: u32) -> Result<Self, ProgError> {
-    let peripheral_ptr;
-    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
-    let rcc = &peripheral_ptr.RCC;
+    return Self::with_config(core, tx_pin, rx_pin, SerialConfig::new(baud));
+  }
+
+  /// Configure a serial connection with a custom frame format.
+  ///
+  /// Works like [new](crate::uart::UART::new), but takes a [SerialConfig](crate::uart::SerialConfig) with the baudrate,
+  /// word length, parity, stop bits and oversampling. Returns an error-enum if the configuration is not possible.
+  ///
+  /// # Examples
+  ///
+  /// ```no_run
+  /// // 7 data bits, even parity, 1 stop bit
+  /// let config = SerialConfig::new(9600).data_bits(DataBits::Seven).parity(Parity::Even);
+  /// let uart = UART::with_config(2, PA2, PA3, config).unwrap();
+  /// ```
+  pub fn with_config(core: u8, tx_pin: (char, u8), rx_pin: (char, u8), config: SerialConfig) -> Result<Self, ProgError> {
+    let (cr1, cr2) = match check_config(core, &config) {
+      Ok(value) => value,
+      Err(error) => return Err(error)
+    };
 
     let af = if core == 1 || core == 2 || core == 3 {7}
     else {8};
@@ -59,13 +271,13 @@ impl UART {
     }
 
     unsafe {
-      if PIN_CONF.contains(&tx_pin) || PIN_CONF.contains(&rx_pin) {
+      if (*core::ptr::addr_of_mut!(PIN_CONF)).contains(&tx_pin) || (*core::ptr::addr_of_mut!(PIN_CONF)).contains(&rx_pin) {
         rprintln!("These pins are already configured for another function! | UART::new()");
         return Err(ProgError::InvalidConfiguration);
       }
       else {
-        PIN_CONF.push(tx_pin).expect("Could not store pin number! | UART::new()");
-        PIN_CONF.push(rx_pin).expect("Could not store pin number! | UART::new()");
+        (*core::ptr::addr_of_mut!(PIN_CONF)).push(tx_pin).expect("Could not store pin number! | UART::new()");
+        (*core::ptr::addr_of_mut!(PIN_CONF)).push(rx_pin).expect("Could not store pin number! | UART::new()");
       }
     }
 
@@ -79,116 +291,186 @@ impl UART {
       Err(_) => return Err(ProgError::Internal)
     };
     
-    match core {
-      1 => {
-        let uart1 = &peripheral_ptr.USART1;
-        if rcc.apb2enr.read().usart1en().is_enabled() {
-          rprintln!("U(S)ART{} is already configured! | UART::new()", core);
-          return Err(ProgError::InvalidConfiguration);
-        }
-        rcc.apb2enr.modify(|_, w| w.usart1en().enabled());
-        set_baud(core, baud);
-        uart1.cr1.modify(|_, w| {
-          w.te().enabled();
-          w.re().enabled();
-          w.ue().enabled()
-        });
-      },
-      2 => {
-        let uart2 = &peripheral_ptr.USART2;
-        if rcc.apb1enr.read().usart2en().is_enabled() {
-          rprintln!("U(S)ART{} is already configured! | UART::new()", core);
-          return Err(ProgError::InvalidConfiguration);
-        }
-        rcc.apb1enr.modify(|_, w| w.usart2en().enabled());
-        set_baud(core, baud);
-        uart2.cr1.modify(|_, w| {
-          w.te().enabled();
-          w.re().enabled();
-          w.ue().enabled()
-        });
-      },
-      3 => {
-        let uart3 = &peripheral_ptr.USART3;
-        if rcc.apb1enr.read().usart3en().is_enabled() {
-          rprintln!("U(S)ART{} is already configured! | UART::new()", core);
-          return Err(ProgError::InvalidConfiguration);
-        }
-        rcc.apb1enr.modify(|_, w| w.usart3en().enabled());
-        set_baud(core, baud);
-        uart3.cr1.modify(|_, w| {
-          w.te().enabled();
-          w.re().enabled();
-          w.ue().enabled()
-        });
-      },
-      4 => {
-        let uart4 = &peripheral_ptr.UART4;
-        if rcc.apb1enr.read().uart4en().is_enabled() {
-          rprintln!("U(S)ART{} is already configured! | UART::new()", core);
-          return Err(ProgError::InvalidConfiguration);
-        }
-        rcc.apb1enr.modify(|_, w| w.uart4en().enabled());
-        set_baud(core, baud);
-        uart4.cr1.modify(|_, w| {
-          w.te().enabled();
-          w.re().enabled();
-          w.ue().enabled()
-        });
-      },
-      5 => {
-        let uart5 = &peripheral_ptr.UART5;
-        if rcc.apb1enr.read().uart5en().is_enabled() {
-          rprintln!("U(S)ART{} is already configured! | UART::new()", core);
-          return Err(ProgError::InvalidConfiguration);
-        }
-        rcc.apb1enr.modify(|_, w| w.uart5en().enabled());
-        set_baud(core, baud);
-        uart5.cr1.modify(|_, w| {
-          w.te().enabled();
-          w.re().enabled();
-          w.ue().enabled()
-        });
-      },
-      6 => {
-        let uart6 = &peripheral_ptr.USART6;
-        if rcc.apb2enr.read().usart6en().is_enabled() {
-          rprintln!("U(S)ART{} is already configured! | UART::new()", core);
-          return Err(ProgError::InvalidConfiguration);
-        }
-        rcc.apb2enr.modify(|_, w| w.usart6en().enabled());
-        set_baud(core, baud);
-        uart6.cr1.modify(|_, w| {
-          w.te().enabled();
-          w.re().enabled();
-          w.ue().enabled()
-        });
-      },
-      _ => {
-        rprintln!("U(S)ART{} is not a valid U(S)ART peripheral! | UART::new()", core);
-        return Err(ProgError::InvalidConfiguration);
-      }
+    if let Err(error) = enable_clock(core) {return Err(error);}
+
+    if let Err(error) = start_uart(core, cr1, cr2, 0, config.baud) {return Err(error);}
+
+    return Ok(Self {
+      core,
+      config,
+      _tx_pin: tx,
+      _rx_pin: Some(rx),
+      half_duplex: false,
+      _rts_pin: None,
+      _cts_pin: None,
+      rs485: None,
+      dma_tx: None,
+      timeout: DEFAULT_TIMEOUT,
+      peeked: None
+    });
+  }
+
+  /// Configure a single-wire half-duplex connection that sends and receives on the TX pin.
+  ///
+  /// Takes the UART core, a TX pin from the [UART pinmap](crate::include::UART_MAP) and a
+  /// [SerialConfig](crate::uart::SerialConfig). The pin is configured as open-drain with pull-up, so several devices
+  /// can share the wire, e.g. on servo buses. The receiver is turned off during every transmission, so the sent bytes
+  /// are not received as echo. Returns an error-enum if the configuration is not possible.
+  pub fn new_half_duplex(core: u8, tx_pin: (char, u8), config: SerialConfig) -> Result<Self, ProgError> {
+    let (cr1, cr2) = match check_config(core, &config) {
+      Ok(value) => value,
+      Err(error) => return Err(error)
+    };
+
+    let af = if core == 1 || core == 2 || core == 3 {7}
+    else {8};
+
+    if !UART_MAP.tx_pins.iter().zip(UART_MAP.cores.iter()).any(|i| i == (&tx_pin, &core)) {
+      rprintln!("This pin is not available for UART communication! | UART::new_half_duplex()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    let tx = match pinmode_alternate_function(tx_pin, af) {
+      Ok(value) => value,
+      Err(error) => return Err(error)
     };
+    open_drain(&tx, true);
+    set_bias(&tx, GpioBias::Pullup);
+
+    if let Err(error) = enable_clock(core) {return Err(error);}
+    // HDSEL
+    if let Err(error) = start_uart(core, cr1, cr2, 1 << 3, config.baud) {return Err(error);}
 
     return Ok(Self {
       core,
+      config,
       _tx_pin: tx,
-      _rx_pin: rx
+      _rx_pin: None,
+      half_duplex: true,
+      _rts_pin: None,
+      _cts_pin: None,
+      rs485: None,
+      dma_tx: None,
+      timeout: DEFAULT_TIMEOUT,
+      peeked: None
     });
   }
 
+  /// Returns the configuration the connection was created with.
+  pub fn config(&self) -> SerialConfig {
+    return self.config;
+  }
+
+  /// Enables hardware flow control with an RTS and/or a CTS pin from the [flow control map](crate::include::UART_FLOW_MAP).
+  ///
+  /// With RTS the UART signals the other device to pause while a received byte is not read yet. With CTS the UART
+  /// only sends while the other device pulls the line low. Returns an error-enum if the core has no flow control
+  /// lines, like UART4 and UART5, flow control is not supported on this board, like for USART6, or the pins are
+  /// invalid or already used.
+  pub fn enable_flow_control(&mut self, rts_pin: Option<(char, u8)>, cts_pin: Option<(char, u8)>) -> Result<(), ProgError> {
+    if self.core == 6 {
+      // The lines of USART6 are only available on port G, which this package does not have
+      rprintln!("Flow control of USART6 is not supported on this board! | .enable_flow_control()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+    if !UART_FLOW_MAP.cores.contains(&self.core) {
+      rprintln!("U(S)ART{} has no flow control lines! | .enable_flow_control()", self.core);
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    if self._rts_pin.is_some() || self._cts_pin.is_some() {
+      rprintln!("Flow control is already enabled! | .enable_flow_control()");
+      return Err(ProgError::AlreadyConfigured);
+    }
+
+    let valid = |pins: &[(char, u8); 3], pin: Option<(char, u8)>| match pin {
+      Some(pin) => pins.iter().zip(UART_FLOW_MAP.cores.iter()).any(|i| i == (&pin, &self.core)),
+      None => true
+    };
+
+    if !valid(&UART_FLOW_MAP.rts_pins, rts_pin) || !valid(&UART_FLOW_MAP.cts_pins, cts_pin) {
+      rprintln!("These pins are not available for flow control of U(S)ART{}! | .enable_flow_control()", self.core);
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    let rts = match rts_pin {
+      Some(pin) => match pinmode_alternate_function(pin, 7) {
+        Ok(value) => Some(value),
+        Err(error) => return Err(error)
+      },
+      None => None
+    };
+
+    let cts = match cts_pin {
+      Some(pin) => match pinmode_alternate_function(pin, 7) {
+        Ok(value) => Some(value),
+        Err(error) => return Err(error)
+      },
+      None => None
+    };
+
+    // RTSE and CTSE
+    let mut bits = 0;
+    if rts.is_some() {bits |= 1 << 8;}
+    if cts.is_some() {bits |= 1 << 9;}
+    get_uart(self.core).cr3.modify(|r, w| unsafe {w.bits(r.bits() | bits)});
+
+    self._rts_pin = rts;
+    self._cts_pin = cts;
+
+    return Ok(());
+  }
+
+  /// Disables hardware flow control and frees the RTS and CTS pins.
+  pub fn disable_flow_control(&mut self) {
+    // RTSE, CTSE and CTSIE
+    get_uart(self.core).cr3.modify(|r, w| unsafe {w.bits(r.bits() & !((1 << 8) | (1 << 9) | (1 << 10)))});
+    free(|cs| CTS_CALLBACKS.borrow(cs).borrow_mut()[self.core as usize - 1] = None);
+
+    self._rts_pin = None;
+    self._cts_pin = None;
+  }
+
+  /// Calls a function from the interrupt every time the CTS line changes. The argument is true if the other device
+  /// is ready to receive. `None` disables the interrupt.
+  ///
+  /// Returns an error-enum if no CTS pin is configured with
+  /// [enable_flow_control](crate::uart::UART::enable_flow_control).
+  pub fn on_cts_change(&self, callback: Option<fn(bool)>) -> Result<(), ProgError> {
+    if self._cts_pin.is_none() {
+      rprintln!("No CTS pin is configured! | .on_cts_change()");
+      return Err(ProgError::NotConfigured);
+    }
+
+    let uart = get_uart(self.core);
+    free(|cs| CTS_CALLBACKS.borrow(cs).borrow_mut()[self.core as usize - 1] = callback);
+
+    if callback.is_some() {
+      // Clear CTS, then set CTSIE
+      uart.sr.write(|w| unsafe {w.bits(!(1 << 9))});
+      uart.cr3.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 10))});
+      unsafe {NVIC::unmask(get_interrupt(self.core));}
+    }
+    else {uart.cr3.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 10))});}
+
+    return Ok(());
+  }
+
   /// Deacitivates the UART connection and destroys the struct, freeing the core and pins.
   pub fn end(self) {
     let peripheral_ptr;
     unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
     let rcc = &peripheral_ptr.RCC;
 
+    free(|cs| CTS_CALLBACKS.borrow(cs).borrow_mut()[self.core as usize - 1] = None);
+
     match self.core {
       1 => {
         let uart1 = &peripheral_ptr.USART1;
         rcc.apb2enr.modify(|_, w| w.usart1en().disabled());
         uart1.cr1.reset();
         uart1.cr2.reset();
+        uart1.cr3.reset();
         NVIC::mask(Interrupt::USART1);
       },
       2 => {
@@ -196,6 +478,7 @@ impl UART {
         rcc.apb1enr.modify(|_, w| w.usart2en().disabled());
         uart2.cr1.reset();
         uart2.cr2.reset();
+        uart2.cr3.reset();
         NVIC::mask(Interrupt::USART2);
       },
       3 => {
@@ -203,6 +486,7 @@ impl UART {
         rcc.apb1enr.modify(|_, w| w.usart3en().disabled());
         uart3.cr1.reset();
         uart3.cr2.reset();
+        uart3.cr3.reset();
         NVIC::mask(Interrupt::USART3);
       },
       4 => {
@@ -210,6 +494,7 @@ impl UART {
         rcc.apb1enr.modify(|_, w| w.uart4en().disabled());
         uart4.cr1.reset();
         uart4.cr2.reset();
+        uart4.cr3.reset();
         NVIC::mask(Interrupt::UART4);
       },
       5 => {
@@ -217,6 +502,7 @@ impl UART {
         rcc.apb1enr.modify(|_, w| w.uart5en().disabled());
         uart5.cr1.reset();
         uart5.cr2.reset();
+        uart5.cr3.reset();
         NVIC::mask(Interrupt::UART5);
       },
       6 => {
@@ -224,6 +510,7 @@ impl UART {
         rcc.apb2enr.modify(|_, w| w.usart6en().disabled());
         uart6.cr1.reset();
         uart6.cr2.reset();
+        uart6.cr3.reset();
         NVIC::mask(Interrupt::USART6);
       },
       _ => unreachable!()
@@ -232,126 +519,181 @@ impl UART {
     drop(self);
   }
 
-  /// Sends an ASCII char over the serial connection. Returns an error-enum if problems with the connection are detected.
-  pub fn print_char(&self, data: char) -> Result<(), SerialError> {
-    let peripheral_ptr;
-    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
-    
-    match self.core {
-      1 => {
-        let uart1 = &peripheral_ptr.USART1;
-        while uart1.sr.read().txe().bit_is_clear() {
-          if let Err(error) = check_uart_errors(uart1.sr.read().bits()) {return Err(error);}
-        }
-        uart1.dr.write(|w| w.dr().bits(data as u16));
-      },
-      2 => {
-        let uart2 = &peripheral_ptr.USART2;
-        while uart2.sr.read().txe().bit_is_clear() {
-          if let Err(error) = check_uart_errors(uart2.sr.read().bits()) {return Err(error);}
-        }
-        uart2.dr.write(|w| w.dr().bits(data as u16));
-      },
-      3 => {
-        let uart3 = &peripheral_ptr.USART3;
-        while uart3.sr.read().txe().bit_is_clear() {
-          if let Err(error) = check_uart_errors(uart3.sr.read().bits()) {return Err(error);}
-        }
-        uart3.dr.write(|w| w.dr().bits(data as u16));
-      },
-      4 => {
-        let uart4 = &peripheral_ptr.UART4;
-        while uart4.sr.read().txe().bit_is_clear() {
-          if let Err(error) = check_uart_errors(uart4.sr.read().bits()) {return Err(error);}
-        }
-        uart4.dr.write(|w| w.dr().bits(data as u16));
-      },
-      5 => {
-        let uart5 = &peripheral_ptr.UART5;
-        while uart5.sr.read().txe().bit_is_clear() {
-          if let Err(error) = check_uart_errors(uart5.sr.read().bits()) {return Err(error);}
-        }
-        uart5.dr.write(|w| w.dr().bits(data as u16));
-      },
-      6 => {
-        let uart6 = &peripheral_ptr.USART6;
-        while uart6.sr.read().txe().bit_is_clear() {
-          if let Err(error) = check_uart_errors(uart6.sr.read().bits()) {return Err(error);}
-        }
-        uart6.dr.write(|w| w.dr().bits(data as u16));
-      },
-      _ => unreachable!()
-    };
+  /// Enables the RS-485 mode with a pin that drives the DE and /RE inputs of the transceiver.
+  ///
+  /// Every transmission sets the pin high, waits `pre_delay_us` microseconds, sends the data, waits until the last
+  /// stop bit left the UART (TC flag), waits `post_delay_us` microseconds and sets the pin low again to receive. Since
+  /// the pin is low in between, the connection can be used for half-duplex buses like Modbus RTU.
+  pub fn enable_rs485(&mut self, de_pin: Pin<Output>, pre_delay_us: u32, post_delay_us: u32) {
+    digital_write(&de_pin, false);
+    self.rs485 = Some(Rs485 {de_pin, pre_delay_us, post_delay_us});
+  }
 
-    return Ok(());
+  /// Disables the RS-485 mode and returns the DE pin.
+  pub fn disable_rs485(&mut self) -> Option<Pin<Output>> {
+    return self.rs485.take().map(|rs485| rs485.de_pin);
   }
 
-  /// Sends an ASCII string over the serial connection. Returns an error-enum if problems with the connection are detected.
-  pub fn print_str(&self, data: &str) -> Result<(), SerialError> {
-    let peripheral_ptr;
-    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
-    
-    let bytes = data.as_bytes();
-    
-    match self.core {
-      1 => {
-        let uart1 = &peripheral_ptr.USART1;
-        for byte in bytes {
+  /// Sends an ASCII char over the serial connection. Returns an error-enum if problems with the connection are detected.
+  pub fn print_char(&self, data: char) -> Result<(), SerialError> {
+    return self.transmit(|| {
+      let peripheral_ptr;
+      unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+      match self.core {
+        1 => {
+          let uart1 = &peripheral_ptr.USART1;
           while uart1.sr.read().txe().bit_is_clear() {
-            if let Err(error) = check_uart_errors(uart1.sr.read().bits()) {return Err(error);}
+            if let Err(error) = check_uart_errors(uart1.sr.read().bits()) {
+              let _ = uart1.dr.read().bits();
+              return Err(error);
+            }
           }
-          uart1.dr.write(|w| w.dr().bits((*byte).into()));
-        }
-      },
-      2 => {
-        let uart2 = &peripheral_ptr.USART2;
-        for byte in bytes {
+          uart1.dr.write(|w| w.dr().bits(data as u16));
+        },
+        2 => {
+          let uart2 = &peripheral_ptr.USART2;
           while uart2.sr.read().txe().bit_is_clear() {
-            if let Err(error) = check_uart_errors(uart2.sr.read().bits()) {return Err(error);}
+            if let Err(error) = check_uart_errors(uart2.sr.read().bits()) {
+              let _ = uart2.dr.read().bits();
+              return Err(error);
+            }
           }
-          uart2.dr.write(|w| w.dr().bits((*byte).into()));
-        }
-      },
-      3 => {
-        let uart3 = &peripheral_ptr.USART3;
-        for byte in bytes {
+          uart2.dr.write(|w| w.dr().bits(data as u16));
+        },
+        3 => {
+          let uart3 = &peripheral_ptr.USART3;
           while uart3.sr.read().txe().bit_is_clear() {
-            if let Err(error) = check_uart_errors(uart3.sr.read().bits()) {return Err(error);}
+            if let Err(error) = check_uart_errors(uart3.sr.read().bits()) {
+              let _ = uart3.dr.read().bits();
+              return Err(error);
+            }
           }
-          uart3.dr.write(|w| w.dr().bits((*byte).into()));
-        }
-      },
-      4 => {
-        let uart4 = &peripheral_ptr.UART4;
-        for byte in bytes {
+          uart3.dr.write(|w| w.dr().bits(data as u16));
+        },
+        4 => {
+          let uart4 = &peripheral_ptr.UART4;
           while uart4.sr.read().txe().bit_is_clear() {
-            if let Err(error) = check_uart_errors(uart4.sr.read().bits()) {return Err(error);}
+            if let Err(error) = check_uart_errors(uart4.sr.read().bits()) {
+              let _ = uart4.dr.read().bits();
+              return Err(error);
+            }
           }
-          uart4.dr.write(|w| w.dr().bits((*byte).into()));
-        }
-      },
-      5 => {
-        let uart5 = &peripheral_ptr.UART5;
-        for byte in bytes {
+          uart4.dr.write(|w| w.dr().bits(data as u16));
+        },
+        5 => {
+          let uart5 = &peripheral_ptr.UART5;
           while uart5.sr.read().txe().bit_is_clear() {
-            if let Err(error) = check_uart_errors(uart5.sr.read().bits()) {return Err(error);}
+            if let Err(error) = check_uart_errors(uart5.sr.read().bits()) {
+              let _ = uart5.dr.read().bits();
+              return Err(error);
+            }
           }
-          uart5.dr.write(|w| w.dr().bits((*byte).into()));
-        }
-      },
-      6 => {
-        let uart6 = &peripheral_ptr.USART6;
-        for byte in bytes {
+          uart5.dr.write(|w| w.dr().bits(data as u16));
+        },
+        6 => {
+          let uart6 = &peripheral_ptr.USART6;
           while uart6.sr.read().txe().bit_is_clear() {
-            if let Err(error) = check_uart_errors(uart6.sr.read().bits()) {return Err(error);}
+            if let Err(error) = check_uart_errors(uart6.sr.read().bits()) {
+              let _ = uart6.dr.read().bits();
+              return Err(error);
+            }
           }
-          uart6.dr.write(|w| w.dr().bits((*byte).into()));
-        }
-      },
-      _ => unreachable!()
-    };
+          uart6.dr.write(|w| w.dr().bits(data as u16));
+        },
+        _ => unreachable!()
+      };
 
-    return Ok(());
+      return Ok(());
+    });
+  }
+
+  /// Sends an ASCII string over the serial connection. Returns an error-enum if problems with the connection are detected.
+  pub fn print_str(&self, data: &str) -> Result<(), SerialError> {
+    return self.transmit(|| {
+      let peripheral_ptr;
+      unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+      let bytes = data.as_bytes();
+
+      match self.core {
+        1 => {
+          let uart1 = &peripheral_ptr.USART1;
+          for byte in bytes {
+            while uart1.sr.read().txe().bit_is_clear() {
+              if let Err(error) = check_uart_errors(uart1.sr.read().bits()) {
+                let _ = uart1.dr.read().bits();
+                return Err(error);
+              }
+            }
+            uart1.dr.write(|w| w.dr().bits((*byte).into()));
+          }
+        },
+        2 => {
+          let uart2 = &peripheral_ptr.USART2;
+          for byte in bytes {
+            while uart2.sr.read().txe().bit_is_clear() {
+              if let Err(error) = check_uart_errors(uart2.sr.read().bits()) {
+                let _ = uart2.dr.read().bits();
+                return Err(error);
+              }
+            }
+            uart2.dr.write(|w| w.dr().bits((*byte).into()));
+          }
+        },
+        3 => {
+          let uart3 = &peripheral_ptr.USART3;
+          for byte in bytes {
+            while uart3.sr.read().txe().bit_is_clear() {
+              if let Err(error) = check_uart_errors(uart3.sr.read().bits()) {
+                let _ = uart3.dr.read().bits();
+                return Err(error);
+              }
+            }
+            uart3.dr.write(|w| w.dr().bits((*byte).into()));
+          }
+        },
+        4 => {
+          let uart4 = &peripheral_ptr.UART4;
+          for byte in bytes {
+            while uart4.sr.read().txe().bit_is_clear() {
+              if let Err(error) = check_uart_errors(uart4.sr.read().bits()) {
+                let _ = uart4.dr.read().bits();
+                return Err(error);
+              }
+            }
+            uart4.dr.write(|w| w.dr().bits((*byte).into()));
+          }
+        },
+        5 => {
+          let uart5 = &peripheral_ptr.UART5;
+          for byte in bytes {
+            while uart5.sr.read().txe().bit_is_clear() {
+              if let Err(error) = check_uart_errors(uart5.sr.read().bits()) {
+                let _ = uart5.dr.read().bits();
+                return Err(error);
+              }
+            }
+            uart5.dr.write(|w| w.dr().bits((*byte).into()));
+          }
+        },
+        6 => {
+          let uart6 = &peripheral_ptr.USART6;
+          for byte in bytes {
+            while uart6.sr.read().txe().bit_is_clear() {
+              if let Err(error) = check_uart_errors(uart6.sr.read().bits()) {
+                let _ = uart6.dr.read().bits();
+                return Err(error);
+              }
+            }
+            uart6.dr.write(|w| w.dr().bits((*byte).into()));
+          }
+        },
+        _ => unreachable!()
+      };
+
+      return Ok(());
+    });
   }
 
   /// Acts like [print_char](crate::uart::UART::print_char) except it prints a newline at the end of the string.
@@ -370,61 +712,120 @@ impl UART {
     return Ok(());
   }
 
+  /// Sends formatted text over the serial connection. Is used by the [`uprint!`](crate::uprint) and
+  /// [`uprintln!`](crate::uprintln) macros. Returns an error-enum if problems with the connection are detected.
+  pub fn print_fmt(&self, args: fmt::Arguments) -> Result<(), SerialError> {
+    return self.transmit(|| {
+      let mut writer = UartWriter {uart: self, error: None};
+
+      if fmt::write(&mut writer, args).is_err() {
+        return Err(writer.error.unwrap_or(SerialError::Prog(ProgError::Internal)));
+      }
+
+      return Ok(());
+    });
+  }
+
+  /// Sends an integer as text in the specified base, like `Serial.print(value, base)` in Arduino.
+  ///
+  /// The base can be between 2 and 36, e.g. 2 for binary, 10 for decimal and 16 for hexadecimal. Like in Arduino only
+  /// decimal numbers have a sign, other bases print the two's complement of negative numbers.
+  /// Returns an error-enum if the base is invalid or problems with the connection are detected.
+  pub fn print_int(&self, value: i32, base: u8) -> Result<(), SerialError> {
+    let text = match format_int(value, base) {
+      Some(text) => text,
+      None => {
+        rprintln!("{} is not a valid base! | .print_int()", base);
+        return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+      }
+    };
+
+    return self.print_str(&text);
+  }
+
+  /// Sends a floating point number as text with the specified number of decimal places (at most 8), like
+  /// `Serial.print(value, digits)` in Arduino. Does not use the float formatting of `core::fmt`.
+  /// Returns an error-enum if problems with the connection are detected.
+  pub fn print_float(&self, value: f32, digits: u8) -> Result<(), SerialError> {
+    return self.print_str(&format_float(value, digits));
+  }
+
   /// Sends a raw byte over the serial connection. Returns an error-enum if problems with the connection are detected.
   pub fn write(&self, data: u8) -> Result<(), SerialError> {
-    let peripheral_ptr;
-    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+    return self.transmit(|| {
+      let peripheral_ptr;
+      unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
 
-    match self.core {
-      1 => {
-        let uart1 = &peripheral_ptr.USART1;
-        while uart1.sr.read().txe().bit_is_clear() {
-          if let Err(error) = check_uart_errors(uart1.sr.read().bits()) {return Err(error);}
-        }
-        uart1.dr.write(|w| w.dr().bits(data.into()));
-      },
-      2 => {
-        let uart2 = &peripheral_ptr.USART2;
-        while uart2.sr.read().txe().bit_is_clear() {
-          if let Err(error) = check_uart_errors(uart2.sr.read().bits()) {return Err(error);}
-        }
-        uart2.dr.write(|w| w.dr().bits(data.into()));
-      },
-      3 => {
-        let uart3 = &peripheral_ptr.USART3;
-        while uart3.sr.read().txe().bit_is_clear() {
-          if let Err(error) = check_uart_errors(uart3.sr.read().bits()) {return Err(error);}
-        }
-        uart3.dr.write(|w| w.dr().bits(data.into()));
-      },
-      4 => {
-        let uart4 = &peripheral_ptr.UART4;
-        while uart4.sr.read().txe().bit_is_clear() {
-          if let Err(error) = check_uart_errors(uart4.sr.read().bits()) {return Err(error);}
-        }
-        uart4.dr.write(|w| w.dr().bits(data.into()));
-      },
-      5 => {
-        let uart5 = &peripheral_ptr.UART5;
-        while uart5.sr.read().txe().bit_is_clear() {
-          if let Err(error) = check_uart_errors(uart5.sr.read().bits()) {return Err(error);}
-        }
-        uart5.dr.write(|w| w.dr().bits(data.into()));
-      },
-      6 => {
-        let uart6 = &peripheral_ptr.USART6;
-        while uart6.sr.read().txe().bit_is_clear() {
-          if let Err(error) = check_uart_errors(uart6.sr.read().bits()) {return Err(error);}
-        }
-        uart6.dr.write(|w| w.dr().bits(data.into()));
-      },
-      _ => unreachable!()
-    };
+      match self.core {
+        1 => {
+          let uart1 = &peripheral_ptr.USART1;
+          while uart1.sr.read().txe().bit_is_clear() {
+            if let Err(error) = check_uart_errors(uart1.sr.read().bits()) {
+              let _ = uart1.dr.read().bits();
+              return Err(error);
+            }
+          }
+          uart1.dr.write(|w| w.dr().bits(data.into()));
+        },
+        2 => {
+          let uart2 = &peripheral_ptr.USART2;
+          while uart2.sr.read().txe().bit_is_clear() {
+            if let Err(error) = check_uart_errors(uart2.sr.read().bits()) {
+              let _ = uart2.dr.read().bits();
+              return Err(error);
+            }
+          }
+          uart2.dr.write(|w| w.dr().bits(data.into()));
+        },
+        3 => {
+          let uart3 = &peripheral_ptr.USART3;
+          while uart3.sr.read().txe().bit_is_clear() {
+            if let Err(error) = check_uart_errors(uart3.sr.read().bits()) {
+              let _ = uart3.dr.read().bits();
+              return Err(error);
+            }
+          }
+          uart3.dr.write(|w| w.dr().bits(data.into()));
+        },
+        4 => {
+          let uart4 = &peripheral_ptr.UART4;
+          while uart4.sr.read().txe().bit_is_clear() {
+            if let Err(error) = check_uart_errors(uart4.sr.read().bits()) {
+              let _ = uart4.dr.read().bits();
+              return Err(error);
+            }
+          }
+          uart4.dr.write(|w| w.dr().bits(data.into()));
+        },
+        5 => {
+          let uart5 = &peripheral_ptr.UART5;
+          while uart5.sr.read().txe().bit_is_clear() {
+            if let Err(error) = check_uart_errors(uart5.sr.read().bits()) {
+              let _ = uart5.dr.read().bits();
+              return Err(error);
+            }
+          }
+          uart5.dr.write(|w| w.dr().bits(data.into()));
+        },
+        6 => {
+          let uart6 = &peripheral_ptr.USART6;
+          while uart6.sr.read().txe().bit_is_clear() {
+            if let Err(error) = check_uart_errors(uart6.sr.read().bits()) {
+              let _ = uart6.dr.read().bits();
+              return Err(error);
+            }
+          }
+          uart6.dr.write(|w| w.dr().bits(data.into()));
+        },
+        _ => unreachable!()
+      };
 
-    return Ok(());
+      return Ok(());
+    });
   }
 
-  /// Waits until it recieves an ASCII char. Returns an error-enum if problems with the connection are detected.
+  /// Waits until it recieves an ASCII char. Returns `None` if problems with the connection are detected, use
+  /// [read_byte_timeout](crate::uart::UART::read_byte_timeout) to get the error-enum.
   pub fn read_char(&self) -> Option<char> {
     let peripheral_ptr;
     unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
@@ -435,52 +836,71 @@ impl UART {
       1 => {
         let uart1 = &peripheral_ptr.USART1;
         while uart1.sr.read().rxne().bit_is_clear() {
-          if check_uart_errors(uart1.sr.read().bits()).is_err() {return None;}
+          if check_uart_errors(uart1.sr.read().bits()).is_err() {
+            let _ = uart1.dr.read().bits();
+            return None;
+          }
         }
         buffer = uart1.dr.read().dr().bits() as u8;
       },
       2 => {
         let uart2 = &peripheral_ptr.USART2;
         while uart2.sr.read().rxne().bit_is_clear() {
-          if check_uart_errors(uart2.sr.read().bits()).is_err() {return None;}
+          if check_uart_errors(uart2.sr.read().bits()).is_err() {
+            let _ = uart2.dr.read().bits();
+            return None;
+          }
         }
         buffer = uart2.dr.read().dr().bits() as u8;
       },
       3 => {
         let uart3 = &peripheral_ptr.USART3;
         while uart3.sr.read().rxne().bit_is_clear() {
-          if check_uart_errors(uart3.sr.read().bits()).is_err() {return None;}
+          if check_uart_errors(uart3.sr.read().bits()).is_err() {
+            let _ = uart3.dr.read().bits();
+            return None;
+          }
         }
         buffer = uart3.dr.read().dr().bits() as u8;
       },
       4 => {
         let uart4 = &peripheral_ptr.UART4;
         while uart4.sr.read().rxne().bit_is_clear() {
-          if check_uart_errors(uart4.sr.read().bits()).is_err() {return None;}
+          if check_uart_errors(uart4.sr.read().bits()).is_err() {
+            let _ = uart4.dr.read().bits();
+            return None;
+          }
         }
         buffer = uart4.dr.read().dr().bits() as u8;
       },
       5 => {
         let uart5 = &peripheral_ptr.UART5;
         while uart5.sr.read().rxne().bit_is_clear() {
-          if check_uart_errors(uart5.sr.read().bits()).is_err() {return None;}
+          if check_uart_errors(uart5.sr.read().bits()).is_err() {
+            let _ = uart5.dr.read().bits();
+            return None;
+          }
         }
         buffer = uart5.dr.read().dr().bits() as u8;
       },
       6 => {
         let uart6 = &peripheral_ptr.USART6;
         while uart6.sr.read().rxne().bit_is_clear() {
-          if check_uart_errors(uart6.sr.read().bits()).is_err() {return None;}
+          if check_uart_errors(uart6.sr.read().bits()).is_err() {
+            let _ = uart6.dr.read().bits();
+            return None;
+          }
         }
         buffer = uart6.dr.read().dr().bits() as u8;
       },
       _ => unreachable!()
     };
 
-    return Some(buffer as char);
+    return Some((buffer & self.config.data_mask() as u8) as char);
   }
 
-  /// Waits until it recieves a byte. Returns an error-enum if problems with the connection are detected.
+  /// Waits until it recieves a byte. Returns `None` if problems with the connection are detected, use
+  /// [read_byte_timeout](crate::uart::UART::read_byte_timeout) to get the error-enum.
   pub fn read_byte(&self) -> Option<u8> {
     let peripheral_ptr;
     unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
@@ -491,54 +911,788 @@ impl UART {
       1 => {
         let uart1 = &peripheral_ptr.USART1;
         while uart1.sr.read().rxne().bit_is_clear() {
-          if check_uart_errors(uart1.sr.read().bits()).is_err() {return None;}
+          if check_uart_errors(uart1.sr.read().bits()).is_err() {
+            let _ = uart1.dr.read().bits();
+            return None;
+          }
         }
         buffer = uart1.dr.read().dr().bits() as u8;
       },
       2 => {
         let uart2 = &peripheral_ptr.USART2;
         while uart2.sr.read().rxne().bit_is_clear() {
-          if check_uart_errors(uart2.sr.read().bits()).is_err() {return None;}
+          if check_uart_errors(uart2.sr.read().bits()).is_err() {
+            let _ = uart2.dr.read().bits();
+            return None;
+          }
         }
         buffer = uart2.dr.read().dr().bits() as u8;
       },
       3 => {
         let uart3 = &peripheral_ptr.USART3;
         while uart3.sr.read().rxne().bit_is_clear() {
-          if check_uart_errors(uart3.sr.read().bits()).is_err() {return None;}
+          if check_uart_errors(uart3.sr.read().bits()).is_err() {
+            let _ = uart3.dr.read().bits();
+            return None;
+          }
         }
         buffer = uart3.dr.read().dr().bits() as u8;
       },
       4 => {
         let uart4 = &peripheral_ptr.UART4;
         while uart4.sr.read().rxne().bit_is_clear() {
-          if check_uart_errors(uart4.sr.read().bits()).is_err() {return None;}
+          if check_uart_errors(uart4.sr.read().bits()).is_err() {
+            let _ = uart4.dr.read().bits();
+            return None;
+          }
         }
         buffer = uart4.dr.read().dr().bits() as u8;
       },
       5 => {
         let uart5 = &peripheral_ptr.UART5;
         while uart5.sr.read().rxne().bit_is_clear() {
-          if check_uart_errors(uart5.sr.read().bits()).is_err() {return None;}
+          if check_uart_errors(uart5.sr.read().bits()).is_err() {
+            let _ = uart5.dr.read().bits();
+            return None;
+          }
         }
         buffer = uart5.dr.read().dr().bits() as u8;
       },
       6 => {
         let uart6 = &peripheral_ptr.USART6;
         while uart6.sr.read().rxne().bit_is_clear() {
-          if check_uart_errors(uart6.sr.read().bits()).is_err() {return None;}
+          if check_uart_errors(uart6.sr.read().bits()).is_err() {
+            let _ = uart6.dr.read().bits();
+            return None;
+          }
         }
         buffer = uart6.dr.read().dr().bits() as u8;
       },
       _ => unreachable!()
     };
 
-    return Some(buffer);
+    return Some(buffer & self.config.data_mask() as u8);
+  }
+
+  /// Sends the bytes without gaps, in RS-485 mode the driver stays enabled for the whole buffer.
+  /// Returns an error-enum if problems with the connection are detected.
+  pub fn write_bytes(&self, data: &[u8]) -> Result<(), SerialError> {
+    return self.transmit(|| self.send_bytes(data));
+  }
+
+  /// Sends a data word with up to 9 bits, for connections configured with [DataBits::Nine](crate::uart::DataBits).
+  /// Returns an error-enum if problems with the connection are detected.
+  pub fn write_word(&self, data: u16) -> Result<(), SerialError> {
+    return self.transmit(|| {
+      let uart = get_uart(self.core);
+
+      while uart.sr.read().txe().bit_is_clear() {
+        if let Err(error) = check_uart_errors(uart.sr.read().bits()) {
+          let _ = uart.dr.read().bits();
+          return Err(error);
+        }
+      }
+      uart.dr.write(|w| w.dr().bits(data & self.config.data_mask()));
+
+      return Ok(());
+    });
+  }
+
+  /// Waits until it recieves a data word with up to 9 bits. The parity bit is removed.
+  /// Returns an error-enum if problems with the connection are detected.
+  pub fn read_word(&self) -> Result<u16, SerialError> {
+    let uart = get_uart(self.core);
+
+    while uart.sr.read().rxne().bit_is_clear() {
+      if let Err(error) = check_uart_errors(uart.sr.read().bits()) {
+        // Reading DR after SR clears the error flags
+        let _ = uart.dr.read().bits();
+        return Err(error);
+      }
+    }
+
+    return Ok(uart.dr.read().dr().bits() & self.config.data_mask());
+  }
+
+  /// Sets the timeout in milliseconds for [read_until](crate::uart::UART::read_until) and
+  /// [read_line](crate::uart::UART::read_line). The default is 1000ms.
+  pub fn set_timeout(&mut self, timeout_ms: usize) {
+    self.timeout = timeout_ms;
+  }
+
+  /// Waits until it recieves a byte or the timeout in milliseconds expires.
+  ///
+  /// Needs the time base started with [`start_time()`](crate::time::start_time). Returns an error-enum if problems
+  /// with the connection are detected or nothing was received in time. The error flags are cleared, so the next read
+  /// works again.
+  pub fn read_byte_timeout(&self, timeout_ms: usize) -> Result<u8, SerialError> {
+    if !is_time_started() {
+      rprintln!("The time base is not started! | .read_byte_timeout()");
+      return Err(SerialError::Prog(ProgError::NotConfigured));
+    }
+
+    return self.read_before(millis().wrapping_add(timeout_ms));
+  }
+
+  /// Receives bytes until the buffer is full or no byte arrived for the timeout in milliseconds. Returns the number
+  /// of received bytes, which is smaller than the buffer if the timeout expired.
+  ///
+  /// Needs the time base started with [`start_time()`](crate::time::start_time). Returns an error-enum if problems
+  /// with the connection are detected.
+  pub fn read_bytes(&self, buffer: &mut [u8], timeout_ms: usize) -> Result<usize, SerialError> {
+    return self.read_into(buffer, None, timeout_ms);
+  }
+
+  /// Receives bytes until the delimiter is received or the buffer is full. The delimiter is not stored. Returns the
+  /// number of bytes in the buffer, which equals the length of the buffer if it was filled before the delimiter.
+  ///
+  /// Needs the time base started with [`start_time()`](crate::time::start_time). Returns an error-enum if problems
+  /// with the connection are detected or no byte arrived for the [timeout](crate::uart::UART::set_timeout) before the
+  /// delimiter.
+  pub fn read_until(&self, delimiter: u8, buffer: &mut [u8]) -> Result<usize, SerialError> {
+    return self.read_into(buffer, Some(delimiter), self.timeout);
+  }
+
+  /// Receives a line that ends with `\n` or `\r\n`, like [read_until](crate::uart::UART::read_until). The line ending
+  /// is not stored. Returns the number of bytes in the buffer.
+  pub fn read_line(&self, buffer: &mut [u8]) -> Result<usize, SerialError> {
+    let mut len = match self.read_into(buffer, Some(b'\n'), self.timeout) {
+      Ok(value) => value,
+      Err(error) => return Err(error)
+    };
+
+    if len > 0 && buffer[len - 1] == b'\r' {len -= 1;}
+
+    return Ok(len);
+  }
+
+  /// Enables the LIN mode with break detection. Is used by the [lin](crate::lin) module.
+  ///
+  /// Returns an error-enum if the connection is not configured with 8 data bits, no parity and 1 stop bit, or is in
+  /// half-duplex mode.
+  pub fn enable_lin(&mut self) -> Result<(), ProgError> {
+    if self.config.data_bits != DataBits::Eight || self.config.parity != Parity::None || self.config.stop_bits != StopBits::One || self.half_duplex {
+      rprintln!("LIN needs a full-duplex connection with 8N1! | .enable_lin()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    let uart = get_uart(self.core);
+
+    // LINEN and LBDL (11 bit break detection) can only be changed while UE is cleared
+    uart.cr1.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 13))});
+    uart.cr2.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 14) | (1 << 5))});
+    uart.sr.write(|w| unsafe {w.bits(!(1 << 8))});
+    uart.cr1.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 13))});
+
+    return Ok(());
+  }
+
+  /// Disables the LIN mode and the break detection again. Is used by the [lin](crate::lin) module.
+  pub fn disable_lin(&mut self) {
+    let uart = get_uart(self.core);
+
+    // LINEN, LBDIE and LBDL
+    uart.cr1.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 13))});
+    uart.cr2.modify(|r, w| unsafe {w.bits(r.bits() & !((1 << 14) | (1 << 6) | (1 << 5)))});
+    uart.sr.write(|w| unsafe {w.bits(!(1 << 8))});
+    uart.cr1.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 13))});
+  }
+
+  /// Sends a break, i.e. at least 13 bits low in LIN mode. Returns an error-enum if problems with the connection are
+  /// detected.
+  pub fn send_break(&self) -> Result<(), SerialError> {
+    let uart = get_uart(self.core);
+
+    return self.transmit(|| {
+      while uart.sr.read().txe().bit_is_clear() {
+        if let Err(error) = check_uart_errors(uart.sr.read().bits()) {
+          let _ = uart.dr.read().bits();
+          return Err(error);
+        }
+      }
+
+      // SBK is cleared by the hardware after the stop bit of the break
+      uart.cr1.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 0))});
+      while uart.cr1.read().bits() & (1 << 0) != 0 {}
+
+      return Ok(());
+    });
+  }
+
+  /// Checks if a break was received since the last call and clears the flag. Needs the LIN mode.
+  pub fn break_detected(&self) -> bool {
+    let uart = get_uart(self.core);
+
+    // LBD
+    if uart.sr.read().bits() & (1 << 8) == 0 {return false;}
+    uart.sr.write(|w| unsafe {w.bits(!(1 << 8))});
+
+    return true;
+  }
+
+  /// Waits for the sync character `U` (0x55) and switches to the baudrate it was sent with. Returns the detected
+  /// baudrate, which is also stored in the [config](crate::uart::UART::config).
+  ///
+  /// The falling edges of the character are timed on the RX pin with the cycle counter. Interrupts are only disabled
+  /// from the start bit to the last edge, which takes at most one character at 1200 baud. The result is rounded to the
+  /// nearest common baudrate up to 115200. Returns an error-enum if the connection does not use 8 data bits, nothing was
+  /// received in time or the character was no sync character.
+  pub fn autobaud(&mut self, timeout_ms: usize) -> Result<u32, SerialError> {
+    if self.config.data_bits != DataBits::Eight {
+      rprintln!("Autobaud needs 8 data bits! | .autobaud()");
+      return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    let mut core_ptr;
+    unsafe {core_ptr = cortex_m::Peripherals::steal();}
+    core_ptr.DCB.enable_trace();
+    core_ptr.DWT.enable_cycle_counter();
+
+    let uart = get_uart(self.core);
+    let (block, number) = self.rx_pin();
+
+    // The receiver is off while the character is measured, so it does not see it with the old baudrate
+    uart.cr1.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 2))});
+    let edges = free(|_| capture_sync_edges(block, number, timeout_ms as u64 * (PCLK_FREQ / 1000) as u64));
+    uart.cr1.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 2))});
+
+    let edges = match edges {
+      Some(value) => value,
+      None => {
+        rprintln!("No sync character received! | .autobaud()");
+        return Err(SerialError::Prog(ProgError::TimedOut));
+      }
+    };
+
+    let baud = match detect_baudrate(&edges, PCLK_FREQ) {
+      Some(value) => value,
+      None => {
+        rprintln!("The received character is no sync character! | .autobaud()");
+        return Err(SerialError::FrameFormat);
+      }
+    };
+
+    if let Err(error) = set_baud(self.core, baud) {return Err(SerialError::Prog(error));}
+    self.config.baud = baud;
+
+    return Ok(baud);
+  }
+
+  /// Sends a buffer in the background with DMA. Waits until a previous DMA transfer is finished. An empty buffer is
+  /// ignored.
+  ///
+  /// Check with [is_dma_busy](crate::uart::UART::is_dma_busy) if the transfer is done. Returns an error-enum if the DMA
+  /// stream is used by another peripheral or the connection is in RS-485 or half-duplex mode, where the direction
+  /// has to be switched by the CPU.
+  pub fn write_dma(&mut self, data: &'static [u8]) -> Result<(), SerialError> {
+    if self.rs485.is_some() || self.half_duplex {
+      rprintln!("DMA transfers are not available in RS-485 and half-duplex mode! | .write_dma()");
+      return Err(SerialError::Prog(ProgError::PermissionDenied));
+    }
+
+    if data.len() > u16::MAX as usize {
+      rprintln!("A DMA transfer can be at most {} bytes long! | .write_dma()", u16::MAX);
+      return Err(SerialError::Prog(ProgError::InvalidConfiguration));
+    }
+
+    // A stream without data never sets TC, so is_dma_busy would stay true
+    if data.is_empty() {return Ok(());}
+
+    if self.dma_tx.is_none() {
+      let (dma, stream, channel) = get_dma_streams(self.core).0;
+      self.dma_tx = match DmaStream::claim(dma, stream, channel) {
+        Some(value) => Some(value),
+        None => {
+          rprintln!("The DMA stream of U(S)ART{} is already used! | .write_dma()", self.core);
+          return Err(SerialError::Prog(ProgError::AlreadyConfigured));
+        }
+      };
+    }
+
+    self.flush_dma();
+
+    let uart = get_uart(self.core);
+    let stream = self.dma_tx.as_ref().unwrap();
+
+    // Clear TC, then set DMAT
+    uart.sr.write(|w| unsafe {w.bits(!(1 << 6))});
+    uart.cr3.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 7))});
+    unsafe {stream.start(DmaDirection::MemoryToPeripheral, &uart.dr as *const _ as usize, data.as_ptr(), data.len() as u16, false);}
+
+    return Ok(());
+  }
+
+  /// Checks if a DMA transfer is still sending.
+  pub fn is_dma_busy(&self) -> bool {
+    return match &self.dma_tx {
+      Some(stream) => stream.is_running() || get_uart(self.core).sr.read().bits() & (1 << 6) == 0,
+      None => false
+    };
+  }
+
+  /// Waits until a DMA transfer is completely sent.
+  pub fn flush_dma(&self) {
+    while self.is_dma_busy() {}
+  }
+
+  /// Starts receiving into a buffer with circular DMA and returns a [DmaRxReader](crate::uart::DmaRxReader) to access
+  /// the received data.
+  ///
+  /// The DMA writes every received byte into the buffer and starts over at the beginning when it reaches the end. An
+  /// idle line after a byte marks the end of a frame. The buffer has to be larger than the data that arrives between
+  /// two reads, otherwise old data is overwritten. Returns an error-enum if the DMA stream is used by another
+  /// peripheral or the buffer is empty or larger than 65535 bytes.
+  pub fn start_dma_rx(&self, buffer: &'static mut [u8]) -> Result<DmaRxReader, ProgError> {
+    if buffer.is_empty() || buffer.len() > u16::MAX as usize {
+      rprintln!("The DMA buffer has to be between 1 and {} bytes long! | .start_dma_rx()", u16::MAX);
+      return Err(ProgError::InvalidConfiguration);
+    }
+
+    let (dma, stream, channel) = get_dma_streams(self.core).1;
+    let stream = match DmaStream::claim(dma, stream, channel) {
+      Some(value) => value,
+      None => {
+        rprintln!("The DMA stream of U(S)ART{} is already used! | .start_dma_rx()", self.core);
+        return Err(ProgError::AlreadyConfigured);
+      }
+    };
+
+    let uart = get_uart(self.core);
+    IDLE_POSITIONS[self.core as usize - 1].store(0, Ordering::SeqCst);
+
+    // Reading SR and DR clears old flags
+    let _ = uart.sr.read().bits();
+    let _ = uart.dr.read().bits();
+
+    unsafe {stream.start(DmaDirection::PeripheralToMemory, &uart.dr as *const _ as usize, buffer.as_ptr(), buffer.len() as u16, true);}
+    // DMAR and IDLEIE
+    uart.cr3.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 6))});
+    uart.cr1.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 4))});
+    unsafe {NVIC::unmask(get_interrupt(self.core));}
+
+    return Ok(DmaRxReader {
+      core: self.core,
+      stream,
+      buffer: buffer.as_mut_ptr(),
+      len: buffer.len(),
+      position: 0
+    });
+  }
+
+  #[doc(hidden)]
+  pub fn rx_pin(&self) -> (char, u8) {
+    return match &self._rx_pin {
+      Some(pin) => (pin.block, pin.number),
+      None => (self._tx_pin.block, self._tx_pin.number)
+    };
+  }
+
+  /// Asynchronously receives bytes until the buffer is full. Returns an error-enum if problems with the connection are
+  /// detected.
+  ///
+  /// The future sleeps until the receive interrupt of the peripheral wakes it, so other tasks of the
+  /// [executor](crate::executor) can run in the meantime.
+  pub fn read_async<'a>(&'a self, buffer: &'a mut [u8]) -> UartRead<'a> {
+    unsafe {NVIC::unmask(get_interrupt(self.core));}
+
+    return UartRead {
+      uart: self,
+      buffer,
+      position: 0
+    };
+  }
+}
+
+impl UART {
+  fn read_before(&self, deadline: usize) -> Result<u8, SerialError> {
+    let uart = get_uart(self.core);
+
+    loop {
+      let sr = uart.sr.read().bits();
+
+      if let Err(error) = check_uart_errors(sr) {
+        // Reading DR after SR clears the error flags
+        let _ = uart.dr.read().bits();
+        return Err(error);
+      }
+
+      // RXNE
+      if sr & (1 << 5) != 0 {return Ok((uart.dr.read().bits() & self.config.data_mask() as u32) as u8);}
+      if is_expired(millis(), deadline) {return Err(SerialError::Prog(ProgError::TimedOut));}
+    }
+  }
+
+  fn read_into(&self, buffer: &mut [u8], delimiter: Option<u8>, timeout_ms: usize) -> Result<usize, SerialError> {
+    if !is_time_started() {
+      rprintln!("The time base is not started! | .read_bytes()");
+      return Err(SerialError::Prog(ProgError::NotConfigured));
+    }
+
+    let mut len = 0;
+
+    while len < buffer.len() {
+      let byte = match self.read_before(millis().wrapping_add(timeout_ms)) {
+        Ok(value) => value,
+        // Only a record without delimiter is incomplete
+        Err(SerialError::Prog(ProgError::TimedOut)) if delimiter.is_none() => break,
+        Err(error) => return Err(error)
+      };
+
+      if Some(byte) == delimiter {break;}
+      buffer[len] = byte;
+      len += 1;
+    }
+
+    return Ok(len);
+  }
+
+  // Turns the receiver off in half-duplex mode and drives the DE pin in RS-485 mode around a transmission
+  fn transmit<F: FnOnce() -> Result<(), SerialError>>(&self, send: F) -> Result<(), SerialError> {
+    if self.half_duplex {
+      let uart = get_uart(self.core);
+
+      // RE
+      uart.cr1.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 2))});
+      let result = send();
+      while uart.sr.read().bits() & (1 << 6) == 0 {}
+      uart.cr1.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 2))});
+
+      return result;
+    }
+
+    let rs485 = match &self.rs485 {
+      Some(rs485) => rs485,
+      None => return send()
+    };
+
+    digital_write(&rs485.de_pin, true);
+    cortex_m::asm::delay(rs485.pre_delay_us * CYCLES_PER_US);
+
+    let result = send();
+
+    // TC is set after the stop bit of the last byte
+    let uart = get_uart(self.core);
+    while uart.sr.read().bits() & (1 << 6) == 0 {}
+
+    cortex_m::asm::delay(rs485.post_delay_us * CYCLES_PER_US);
+    digital_write(&rs485.de_pin, false);
+
+    return result;
+  }
+
+  fn send_bytes(&self, bytes: &[u8]) -> Result<(), SerialError> {
+    let uart = get_uart(self.core);
+
+    for byte in bytes {
+      while uart.sr.read().txe().bit_is_clear() {
+        if let Err(error) = check_uart_errors(uart.sr.read().bits()) {
+          let _ = uart.dr.read().bits();
+          return Err(error);
+        }
+      }
+      uart.dr.write(|w| w.dr().bits((*byte).into()));
+    }
+
+    return Ok(());
+  }
+}
+
+/// The parsers of the [Stream](crate::stream::Stream) trait with the [timeout](crate::uart::UART::set_timeout) of the
+/// UART. A byte that was peeked by a parser is only returned by the next read of the trait, not by the other read
+/// functions.
+impl Stream for UART {
+  fn timed_read(&mut self) -> Option<u8> {
+    if let Some(byte) = self.peeked.take() {return Some(byte);}
+    return self.read_byte_timeout(self.timeout).ok();
+  }
+
+  fn timed_peek(&mut self) -> Option<u8> {
+    if self.peeked.is_none() {self.peeked = self.read_byte_timeout(self.timeout).ok();}
+    return self.peeked;
+  }
+
+  fn timeout(&self) -> usize {
+    return self.timeout;
+  }
+
+  fn set_timeout(&mut self, timeout_ms: usize) {
+    self.timeout = timeout_ms;
+  }
+}
+
+impl fmt::Write for UART {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    return self.print_str(s).map_err(|_| fmt::Error);
+  }
+}
+
+/// Sends formatted text over a UART, like `print!`.
+///
+/// Takes the [UART](crate::uart::UART), or another writer with a `print_fmt` method like
+/// [UsbSerial](crate::usb::UsbSerial), and a format string with arguments. Returns an error-enum if problems with the
+/// connection are detected.
+///
+/// # Examples
+///
+/// ```no_run
+/// uprint!(uart, "x = {}, y = {:#06x}", x, y).unwrap();
+/// ```
+#[macro_export]
+macro_rules! uprint {
+  ($uart:expr, $($arg:tt)*) => {
+    $uart.print_fmt(::core::format_args!($($arg)*))
+  };
+}
+
+/// Sends formatted text over a UART followed by a newline, like `println!`.
+///
+/// Takes the [UART](crate::uart::UART), or another writer with a `print_fmt` method like
+/// [UsbSerial](crate::usb::UsbSerial), and a format string with arguments. Returns an error-enum if problems with the
+/// connection are detected.
+///
+/// # Examples
+///
+/// ```no_run
+/// uprintln!(uart, "Temperature: {}°C", temp).unwrap();
+/// ```
+#[macro_export]
+macro_rules! uprintln {
+  ($uart:expr) => {
+    $uart.print_fmt(::core::format_args!("\r\n"))
+  };
+  // One call, so the line is sent as one transfer
+  ($uart:expr, $($arg:tt)*) => {
+    $uart.print_fmt(::core::format_args!("{}\r\n", ::core::format_args!($($arg)*)))
+  };
+}
+
+/// Gives access to the data that a circular DMA transfer received. Is returned from
+/// [start_dma_rx](crate::uart::UART::start_dma_rx).
+///
+/// The data is not copied, the returned slices point directly into the DMA buffer. Since the buffer is circular,
+/// a frame can be split into two slices at the end of the buffer.
+///
+/// # Examples
+///
+/// ```no_run
+/// static mut BUFFER: [u8; 512] = [0; 512];
+///
+/// let mut reader = uart.start_dma_rx(unsafe {&mut BUFFER}).unwrap();
+///
+/// loop {
+///   if let Some((first, second)) = reader.next_frame() {
+///     rprintln!("Received {} bytes", first.len() + second.len());
+///   }
+/// }
+/// ```
+pub struct DmaRxReader {
+  #[doc(hidden)]
+  core: u8,
+  #[doc(hidden)]
+  stream: DmaStream,
+  #[doc(hidden)]
+  buffer: *mut u8,
+  #[doc(hidden)]
+  len: usize,
+  #[doc(hidden)]
+  position: usize
+}
+
+impl DmaRxReader {
+  /// Returns the number of received bytes that were not read yet.
+  pub fn available(&self) -> usize {
+    return (self.write_position() + self.len - self.position) % self.len;
+  }
+
+  /// Returns the received bytes up to the last idle line, i.e. the last complete frame, and marks them as read. If
+  /// several frames arrived since the last call, they are returned together.
+  pub fn next_frame(&mut self) -> Option<(&[u8], &[u8])> {
+    let idle = IDLE_POSITIONS[self.core as usize - 1].swap(0, Ordering::SeqCst);
+    if idle & NEW_FRAME == 0 {return None;}
+
+    let end = (self.len - (idle & 0xFFFF) as usize) % self.len;
+    return self.take(end);
+  }
+
+  /// Returns all received bytes that were not read yet, regardless of idle lines, and marks them as read.
+  pub fn read(&mut self) -> Option<(&[u8], &[u8])> {
+    let end = self.write_position();
+    return self.take(end);
+  }
+
+  /// Stops the DMA transfer and returns the buffer.
+  pub fn stop(self) -> &'static mut [u8] {
+    let buffer = unsafe {core::slice::from_raw_parts_mut(self.buffer, self.len)};
+    drop(self);
+    return buffer;
+  }
+
+  fn write_position(&self) -> usize {
+    return (self.len - self.stream.remaining() as usize) % self.len;
+  }
+
+  fn take(&mut self, end: usize) -> Option<(&[u8], &[u8])> {
+    let start = self.position;
+    if start == end {return None;}
+    self.position = end;
+
+    unsafe {
+      if end > start {
+        return Some((core::slice::from_raw_parts(self.buffer.add(start), end - start), &[]));
+      }
+      else {
+        return Some((core::slice::from_raw_parts(self.buffer.add(start), self.len - start), core::slice::from_raw_parts(self.buffer, end)));
+      }
+    }
+  }
+}
+
+impl Drop for DmaRxReader {
+  fn drop(&mut self) {
+    let uart = get_uart(self.core);
+
+    // DMAR and IDLEIE
+    uart.cr1.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 4))});
+    uart.cr3.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 6))});
+    self.stream.stop();
+  }
+}
+
+/// A future that fills a buffer with received bytes. Is returned from [read_async](crate::uart::UART::read_async).
+pub struct UartRead<'a> {
+  #[doc(hidden)]
+  uart: &'a UART,
+  #[doc(hidden)]
+  buffer: &'a mut [u8],
+  #[doc(hidden)]
+  position: usize
+}
+
+impl<'a> Future for UartRead<'a> {
+  type Output = Result<(), SerialError>;
+
+  fn poll(mut self: core::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = &mut *self;
+    let core = this.uart.core;
+    let uart = get_uart(core);
+
+    while this.position < this.buffer.len() {
+      let sr = uart.sr.read().bits();
+
+      if let Err(error) = check_uart_errors(sr) {
+        let _ = uart.dr.read().bits();
+        return Poll::Ready(Err(error));
+      }
+
+      if sr & (1 << 5) == 0 {
+        RX_WAKERS[core as usize - 1].register(cx.waker());
+        // RXNEIE, fires immediately if a byte arrived in the meantime
+        uart.cr1.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 5))});
+        return Poll::Pending;
+      }
+
+      this.buffer[this.position] = (uart.dr.read().bits() & this.uart.config.data_mask() as u32) as u8;
+      this.position += 1;
+    }
+
+    return Poll::Ready(Ok(()));
   }
 }
   
   
 // Private Functions ==============================================================================
+struct UartWriter<'a> {
+  uart: &'a UART,
+  error: Option<SerialError>
+}
+
+impl<'a> fmt::Write for UartWriter<'a> {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    if let Err(error) = self.uart.send_bytes(s.as_bytes()) {
+      self.error = Some(error);
+      return Err(fmt::Error);
+    }
+
+    return Ok(());
+  }
+}
+
+#[doc(hidden)]
+pub fn format_int(value: i32, base: u8) -> Option<String<33>> {
+  if !(2..=36).contains(&base) {return None;}
+
+  let mut text: String<33> = String::new();
+
+  if base == 10 && value < 0 {
+    text.push('-').unwrap();
+    push_digits(&mut text, value.unsigned_abs(), 10);
+  }
+  else {push_digits(&mut text, value as u32, base as u32);}
+
+  return Some(text);
+}
+
+#[doc(hidden)]
+pub fn format_float(value: f32, digits: u8) -> String<24> {
+  let mut text: String<24> = String::new();
+  let digits = digits.min(8);
+
+  if value.is_nan() {
+    text.push_str("nan").unwrap();
+    return text;
+  }
+  if value.is_infinite() {
+    text.push_str(if value < 0.0 {"-inf"} else {"inf"}).unwrap();
+    return text;
+  }
+  if !(-4294967040.0..=4294967040.0).contains(&value) {
+    text.push_str("ovf").unwrap();
+    return text;
+  }
+
+  let mut number = value;
+  if number < 0.0 {
+    text.push('-').unwrap();
+    number = -number;
+  }
+
+  // Round to the last printed digit
+  let mut rounding = 0.5;
+  for _ in 0..digits {rounding /= 10.0;}
+  number += rounding;
+
+  let integer = number as u32;
+  push_digits(&mut text, integer, 10);
+
+  if digits > 0 {
+    text.push('.').unwrap();
+    let mut remainder = number - integer as f32;
+    for _ in 0..digits {
+      remainder *= 10.0;
+      let digit = (remainder as u8).min(9);
+      text.push((b'0' + digit) as char).unwrap();
+      remainder -= digit as f32;
+    }
+  }
+
+  return text;
+}
+
+fn push_digits<const N: usize>(text: &mut String<N>, mut number: u32, base: u32) {
+  let mut digits = [0u8; 32];
+  let mut count = 0;
+
+  loop {
+    let digit = (number % base) as u8;
+    digits[count] = if digit < 10 {b'0' + digit} else {b'A' + digit - 10};
+    count += 1;
+    number /= base;
+    if number == 0 {break;}
+  }
+
+  for digit in digits[..count].iter().rev() {text.push(*digit as char).ok();}
+}
+
 fn check_uart_errors(sr: u32) -> Result<(), SerialError> {
   let bits = sr & 0xF;
 
@@ -550,60 +1704,284 @@ fn check_uart_errors(sr: u32) -> Result<(), SerialError> {
   return Ok(());
 }
 
-fn set_baud(core: u8, baud: u32) {
+#[doc(hidden)]
+pub fn calc_brr(pclk: u32, baud: u32, over8: bool) -> Result<u16, ProgError> {
+  if baud == 0 {return Err(ProgError::InvalidConfiguration);}
+
+  // USARTDIV = pclk / (8 * (2 - OVER8) * baud), rounded to the 4 (or 3) fraction bits
+  let div = (pclk as u64 + baud as u64 / 2) / baud as u64;
+
+  if over8 {
+    let mantissa = div >> 3;
+    if !(1..=0xFFF).contains(&mantissa) {return Err(ProgError::InvalidConfiguration);}
+    return Ok(((mantissa << 4) | (div & 0x7)) as u16);
+  }
+  else {
+    let mantissa = div >> 4;
+    if !(1..=0xFFF).contains(&mantissa) {return Err(ProgError::InvalidConfiguration);}
+    return Ok(div as u16);
+  }
+}
+
+#[doc(hidden)]
+pub fn detect_baudrate(edges: &[u32; 5], clock: u32) -> Option<u32> {
+  let total = edges[4].wrapping_sub(edges[0]);
+  if total == 0 {return None;}
+
+  // The falling edges of 0x55 are two bits apart
+  for pair in edges.windows(2) {
+    if pair[1].wrapping_sub(pair[0]).abs_diff(total / 4) > total / 16 {return None;}
+  }
+
+  let measured = ((clock as u64 * 8 + total as u64 / 2) / total as u64) as u32;
+  return nearest_baudrate(measured);
+}
+
+#[doc(hidden)]
+pub fn nearest_baudrate(measured: u32) -> Option<u32> {
+  // Relative deviation in 1/1000
+  let deviation = |rate: u32| measured.abs_diff(rate) as u64 * 1000 / rate as u64;
+  let rate = COMMON_BAUDRATES.iter().copied().min_by_key(|&rate| deviation(rate))?;
+
+  if deviation(rate) > BAUD_TOLERANCE {return None;}
+  return Some(rate);
+}
+
+// Cycle counts of the five falling edges of 0x55: the start bit and the data bits 1, 3, 5 and 7
+fn capture_sync_edges(block: char, number: u8, timeout_cycles: u64) -> Option<[u32; 5]> {
+  let gpio = get_gpio(block);
+  let mask = 1 << number;
+  // One character at the slowest baudrate
+  let frame_cycles = 10 * (PCLK_FREQ / COMMON_BAUDRATES[0]);
+
+  let mut elapsed: u64 = 0;
+  let mut last = DWT::cycle_count();
+  // A start bit only counts after an idle line
+  let mut idle = false;
+
+  while elapsed < timeout_cycles {
+    // Interrupts stay enabled until the start bit, an incomplete character is dropped
+    let edges = free(|_| {
+      if gpio.idr.read().bits() & mask != 0 {
+        idle = true;
+        return None;
+      }
+      if !idle {return None;}
+      idle = false;
+
+      let start = DWT::cycle_count();
+      let mut edges = [start; 5];
+      for edge in edges.iter_mut().skip(1) {
+        wait_for_level(gpio, mask, true, start, frame_cycles)?;
+        *edge = wait_for_level(gpio, mask, false, start, frame_cycles)?;
+      }
+
+      return Some(edges);
+    });
+    if edges.is_some() {return edges;}
+
+    let now = DWT::cycle_count();
+    elapsed += now.wrapping_sub(last) as u64;
+    last = now;
+  }
+
+  return None;
+}
+
+// Returns the cycle count when the pin has the level, or None if it takes longer than max_cycles since start
+fn wait_for_level(gpio: &GpioRegisterBlock, mask: u32, high: bool, start: u32, max_cycles: u32) -> Option<u32> {
+  loop {
+    let level = gpio.idr.read().bits() & mask != 0;
+    let now = DWT::cycle_count();
+    if level == high {return Some(now);}
+    if now.wrapping_sub(start) > max_cycles {return None;}
+  }
+}
+
+fn get_uart(core: u8) -> &'static RegisterBlock {
+  // All U(S)ART peripherals share the same register layout
+  unsafe {
+    match core {
+      1 => &*stm32f4::stm32f446::USART1::ptr(),
+      2 => &*stm32f4::stm32f446::USART2::ptr(),
+      3 => &*stm32f4::stm32f446::USART3::ptr(),
+      4 => &*(stm32f4::stm32f446::UART4::ptr() as *const RegisterBlock),
+      5 => &*(stm32f4::stm32f446::UART5::ptr() as *const RegisterBlock),
+      6 => &*stm32f4::stm32f446::USART6::ptr(),
+      _ => unreachable!()
+    }
+  }
+}
+
+fn get_interrupt(core: u8) -> Interrupt {
+  return match core {
+    1 => Interrupt::USART1,
+    2 => Interrupt::USART2,
+    3 => Interrupt::USART3,
+    4 => Interrupt::UART4,
+    5 => Interrupt::UART5,
+    6 => Interrupt::USART6,
+    _ => unreachable!()
+  };
+}
+
+// ((DMA, stream, channel) for TX, (DMA, stream, channel) for RX)
+fn get_dma_streams(core: u8) -> ((u8, u8, u8), (u8, u8, u8)) {
+  return match core {
+    1 => ((2, 7, 4), (2, 2, 4)),
+    2 => ((1, 6, 4), (1, 5, 4)),
+    3 => ((1, 3, 4), (1, 1, 4)),
+    4 => ((1, 4, 4), (1, 2, 4)),
+    5 => ((1, 7, 4), (1, 0, 4)),
+    6 => ((2, 6, 5), (2, 1, 5)),
+    _ => unreachable!()
+  };
+}
+
+fn uart_interrupt(core: u8) {
+  let uart = get_uart(core);
+  let cr1 = uart.cr1.read().bits();
+  let sr = uart.sr.read().bits();
+
+  // RXNEIE: data or an overrun is waiting for a reader
+  if cr1 & (1 << 5) != 0 && sr & ((1 << 5) | (1 << 3)) != 0 {
+    uart.cr1.modify(|r, w| unsafe {w.bits(r.bits() & !(1 << 5))});
+    RX_WAKERS[core as usize - 1].wake();
+  }
+
+  // IDLEIE: a DMA frame ended, reading DR after SR clears the flag
+  if cr1 & (1 << 4) != 0 && sr & (1 << 4) != 0 {
+    let _ = uart.dr.read().bits();
+    let (dma, stream, _) = get_dma_streams(core).1;
+    IDLE_POSITIONS[core as usize - 1].store(NEW_FRAME | stream_remaining(dma, stream) as u32, Ordering::SeqCst);
+  }
+
+  // CTSIE: the CTS line changed
+  if uart.cr3.read().bits() & (1 << 10) != 0 && sr & (1 << 9) != 0 {
+    uart.sr.write(|w| unsafe {w.bits(!(1 << 9))});
+    if let Some(callback) = free(|cs| CTS_CALLBACKS.borrow(cs).borrow()[core as usize - 1]) {callback(cts_active(core));}
+  }
+}
+
+fn cts_active(core: u8) -> bool {
   let peripheral_ptr;
   unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
-  
-  // (Mantisse, Fractal)
-  let uartdiv: (f64, f64) = modf(16000000.0 / (16.0 * baud as f64));
+
+  let index = UART_FLOW_MAP.cores.iter().position(|&i| i == core).unwrap();
+  let (block, number) = UART_FLOW_MAP.cts_pins[index];
+
+  let bits = match block {
+    'a' => peripheral_ptr.GPIOA.idr.read().bits(),
+    'b' => peripheral_ptr.GPIOB.idr.read().bits(),
+    _ => unreachable!()
+  };
+
+  // CTS is active low
+  return bits & (1 << number) == 0;
+}
+
+fn check_config(core: u8, config: &SerialConfig) -> Result<(u32, u32), ProgError> {
+  let registers = match config.registers() {
+    Ok(value) => value,
+    Err(error) => {
+      rprintln!("This combination of data bits and parity is not possible! | UART::with_config()");
+      return Err(error);
+    }
+  };
+
+  if let Err(error) = calc_brr(PCLK_FREQ, config.baud, config.oversampling == Oversampling::Times8) {
+    rprintln!("A baudrate of {} is not possible! | UART::with_config()", config.baud);
+    return Err(error);
+  }
+
+  // UART4 and UART5 have no clock line, so they only support 1 and 2 stop bits
+  if (core == 4 || core == 5) && matches!(config.stop_bits, StopBits::Half | StopBits::OneAndHalf) {
+    rprintln!("UART{} only supports 1 and 2 stop bits! | UART::with_config()", core);
+    return Err(ProgError::InvalidConfiguration);
+  }
+
+  return Ok(registers);
+}
+
+fn enable_clock(core: u8) -> Result<(), ProgError> {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rcc = &peripheral_ptr.RCC;
 
   match core {
     1 => {
-      let uart1 = &peripheral_ptr.USART1;
-      uart1.brr.modify(|_, w| {
-        w.div_mantissa().bits(uartdiv.1 as u16);
-        w.div_fraction().bits((uartdiv.0 * 16.0) as u8)
-      });
+      if rcc.apb2enr.read().usart1en().is_enabled() {
+        rprintln!("U(S)ART{} is already configured! | UART::new()", core);
+        return Err(ProgError::InvalidConfiguration);
+      }
+      rcc.apb2enr.modify(|_, w| w.usart1en().enabled());
     },
     2 => {
-      let uart2 = &peripheral_ptr.USART2;
-      uart2.brr.modify(|_, w| {
-        w.div_mantissa().bits(uartdiv.1 as u16);
-        w.div_fraction().bits((uartdiv.0 * 16.0) as u8)
-      });
+      if rcc.apb1enr.read().usart2en().is_enabled() {
+        rprintln!("U(S)ART{} is already configured! | UART::new()", core);
+        return Err(ProgError::InvalidConfiguration);
+      }
+      rcc.apb1enr.modify(|_, w| w.usart2en().enabled());
     },
     3 => {
-      let uart3 = &peripheral_ptr.USART3;
-      uart3.brr.modify(|_, w| {
-        w.div_mantissa().bits(uartdiv.1 as u16);
-        w.div_fraction().bits((uartdiv.0 * 16.0) as u8)
-      });
+      if rcc.apb1enr.read().usart3en().is_enabled() {
+        rprintln!("U(S)ART{} is already configured! | UART::new()", core);
+        return Err(ProgError::InvalidConfiguration);
+      }
+      rcc.apb1enr.modify(|_, w| w.usart3en().enabled());
     },
     4 => {
-      let uart4 = &peripheral_ptr.UART4;
-      uart4.brr.modify(|_, w| {
-        w.div_mantissa().bits(uartdiv.1 as u16);
-        w.div_fraction().bits((uartdiv.0 * 16.0) as u8)
-      });
+      if rcc.apb1enr.read().uart4en().is_enabled() {
+        rprintln!("U(S)ART{} is already configured! | UART::new()", core);
+        return Err(ProgError::InvalidConfiguration);
+      }
+      rcc.apb1enr.modify(|_, w| w.uart4en().enabled());
     },
     5 => {
-      let uart5 = &peripheral_ptr.UART5;
-      uart5.brr.modify(|_, w| {
-        w.div_mantissa().bits(uartdiv.1 as u16);
-        w.div_fraction().bits((uartdiv.0 * 16.0) as u8)
-      });
+      if rcc.apb1enr.read().uart5en().is_enabled() {
+        rprintln!("U(S)ART{} is already configured! | UART::new()", core);
+        return Err(ProgError::InvalidConfiguration);
+      }
+      rcc.apb1enr.modify(|_, w| w.uart5en().enabled());
     },
     6 => {
-      let uart6 = &peripheral_ptr.USART6;
-      uart6.brr.modify(|_, w| {
-        w.div_mantissa().bits(uartdiv.1 as u16);
-        w.div_fraction().bits((uartdiv.0 * 16.0) as u8)
-      });
+      if rcc.apb2enr.read().usart6en().is_enabled() {
+        rprintln!("U(S)ART{} is already configured! | UART::new()", core);
+        return Err(ProgError::InvalidConfiguration);
+      }
+      rcc.apb2enr.modify(|_, w| w.usart6en().enabled());
     },
-    _ => unreachable!()
-  }
+    _ => {
+      rprintln!("U(S)ART{} is not a valid U(S)ART peripheral! | UART::new()", core);
+      return Err(ProgError::InvalidConfiguration);
+    }
+  };
+
+  return Ok(());
+}
+
+fn start_uart(core: u8, cr1: u32, cr2: u32, cr3: u32, baud: u32) -> Result<(), ProgError> {
+  let uart = get_uart(core);
+
+  uart.cr2.modify(|r, w| unsafe {w.bits(r.bits() & !(0x3 << 12) | cr2)});
+  uart.cr3.write(|w| unsafe {w.bits(cr3)});
+  // TE, RE and UE
+  uart.cr1.write(|w| unsafe {w.bits(cr1 | (1 << 3) | (1 << 2) | (1 << 13))});
+
+  return set_baud(core, baud);
 }
 
+fn set_baud(core: u8, baud: u32) -> Result<(), ProgError> {
+  let uart = get_uart(core);
+  let over8 = uart.cr1.read().bits() & (1 << 15) != 0;
+
+  match calc_brr(PCLK_FREQ, baud, over8) {
+    Ok(brr) => uart.brr.write(|w| unsafe {w.bits(brr as u32)}),
+    Err(error) => return Err(error)
+  };
+
+  return Ok(());
+}
 
 #[doc(hidden)]
 pub fn modf(x: f64) -> (f64, f64) {
@@ -640,3 +2018,194 @@ pub fn modf(x: f64) -> (f64, f64) {
   rv2 = f64::from_bits(u);
   return (x - rv2, rv2);
 }
+
+
+// Interrupts =====================================================================================
+#[allow(non_snake_case)]
+#[interrupt]
+fn USART1() {
+  uart_interrupt(1);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn USART2() {
+  uart_interrupt(2);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn USART3() {
+  uart_interrupt(3);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn UART4() {
+  uart_interrupt(4);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn UART5() {
+  uart_interrupt(5);
+}
+
+#[allow(non_snake_case)]
+#[interrupt]
+fn USART6() {
+  uart_interrupt(6);
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn brr_oversampling_16() {
+    // USARTDIV 104.1875 and 8.6875
+    assert_eq!(calc_brr(16000000, 9600, false), Ok(0x683));
+    assert_eq!(calc_brr(16000000, 115200, false), Ok(0x08B));
+  }
+
+  #[test]
+  fn brr_oversampling_8() {
+    // The mantissa moves up by one bit, the three fraction bits stay in BRR[2:0]
+    assert_eq!(calc_brr(16000000, 9600, true), Ok(0xD03));
+    assert_eq!(calc_brr(16000000, 115200, true), Ok(0x113));
+  }
+
+  #[test]
+  fn brr_out_of_range() {
+    assert_eq!(calc_brr(16000000, 0, false), Err(ProgError::InvalidConfiguration));
+    // Mantissa 0 with 16 times oversampling, 1 with 8 times
+    assert_eq!(calc_brr(16000000, 2000000, false), Err(ProgError::InvalidConfiguration));
+    assert_eq!(calc_brr(16000000, 2000000, true), Ok(0x010));
+    // Mantissa above 0xFFF
+    assert_eq!(calc_brr(16000000, 200, false), Err(ProgError::InvalidConfiguration));
+    assert_eq!(calc_brr(16000000, 200, true), Err(ProgError::InvalidConfiguration));
+  }
+
+  // Cycle counts of falling edges at the given bit positions, starting at `start`
+  fn edges_at(start: u32, baud: u32, bits: [u32; 5]) -> [u32; 5] {
+    return bits.map(|bit| start.wrapping_add((bit as u64 * PCLK_FREQ as u64 / baud as u64) as u32));
+  }
+
+  #[test]
+  fn autobaud_exact() {
+    for baud in COMMON_BAUDRATES {
+      assert_eq!(detect_baudrate(&edges_at(1000, baud, [0, 2, 4, 6, 8]), PCLK_FREQ), Some(baud));
+    }
+    // The cycle counter wraps around during the character
+    assert_eq!(detect_baudrate(&edges_at(u32::MAX - 5000, 9600, [0, 2, 4, 6, 8]), PCLK_FREQ), Some(9600));
+  }
+
+  #[test]
+  fn autobaud_jitter() {
+    // 9600 has 3333 cycles between two edges, the polling loop adds a few cycles to every edge
+    let mut edges = edges_at(1000, 9600, [0, 2, 4, 6, 8]);
+    edges[1] += 40;
+    edges[2] -= 25;
+    edges[4] += 12;
+    assert_eq!(detect_baudrate(&edges, PCLK_FREQ), Some(9600));
+
+    // 115200 has 278 cycles between two edges
+    let mut edges = edges_at(1000, 115200, [0, 2, 4, 6, 8]);
+    edges[1] += 10;
+    edges[3] -= 8;
+    assert_eq!(detect_baudrate(&edges, PCLK_FREQ), Some(115200));
+
+    // A sender 2 % too fast still rounds to the common baudrate
+    assert_eq!(detect_baudrate(&edges_at(1000, 19584, [0, 2, 4, 6, 8]), PCLK_FREQ), Some(19200));
+  }
+
+  #[test]
+  fn autobaud_no_sync_character() {
+    // 0x33 has falling edges at the start bit and the bits 3 and 7, the next character follows
+    assert_eq!(detect_baudrate(&edges_at(1000, 9600, [0, 3, 7, 10, 13]), PCLK_FREQ), None);
+    // 0x00 followed by 0x55: one long low phase, then evenly spaced edges
+    assert_eq!(detect_baudrate(&edges_at(1000, 9600, [0, 10, 12, 14, 16]), PCLK_FREQ), None);
+    // No time between the edges
+    assert_eq!(detect_baudrate(&[1000; 5], PCLK_FREQ), None);
+    // Evenly spaced, but faster than every common baudrate
+    assert_eq!(detect_baudrate(&edges_at(1000, 230400, [0, 2, 4, 6, 8]), PCLK_FREQ), None);
+  }
+
+  #[test]
+  fn autobaud_nearest() {
+    assert_eq!(nearest_baudrate(9600), Some(9600));
+    assert_eq!(nearest_baudrate(115200), Some(115200));
+    // 4 % is the limit
+    assert_eq!(nearest_baudrate(9984), Some(9600));
+    assert_eq!(nearest_baudrate(10000), None);
+    assert_eq!(nearest_baudrate(56000), Some(57600));
+    assert_eq!(nearest_baudrate(0), None);
+    assert_eq!(nearest_baudrate(460800), None);
+  }
+
+  // A writer like UsbSerial, which needs &mut self
+  struct Recorder {
+    calls: std::vec::Vec<std::string::String>
+  }
+
+  impl Recorder {
+    fn print_fmt(&mut self, args: fmt::Arguments) -> Result<(), SerialError> {
+      self.calls.push(std::format!("{}", args));
+      return Ok(());
+    }
+  }
+
+  #[test]
+  fn print_macros() {
+    let mut recorder = Recorder {calls: std::vec::Vec::new()};
+
+    uprint!(recorder, "a {}", 1).unwrap();
+    uprintln!(recorder, "b {:03}", 2).unwrap();
+    uprintln!(recorder).unwrap();
+
+    // Every line is sent with one call
+    assert_eq!(recorder.calls, ["a 1", "b 002\r\n", "\r\n"]);
+  }
+
+  #[test]
+  fn integers() {
+    assert_eq!(format_int(0, 10).unwrap(), "0");
+    assert_eq!(format_int(-42, 10).unwrap(), "-42");
+    assert_eq!(format_int(i32::MIN, 10).unwrap(), "-2147483648");
+    assert_eq!(format_int(i32::MAX, 10).unwrap(), "2147483647");
+    assert_eq!(format_int(5, 2).unwrap(), "101");
+    assert_eq!(format_int(0xBEEF, 16).unwrap(), "BEEF");
+    assert_eq!(format_int(35, 36).unwrap(), "Z");
+
+    // Other bases print the two's complement
+    assert_eq!(format_int(-1, 16).unwrap(), "FFFFFFFF");
+    assert_eq!(format_int(i32::MIN, 2).unwrap(), "10000000000000000000000000000000");
+    assert_eq!(format_int(-1, 2).unwrap().len(), 32);
+
+    assert!(format_int(1, 1).is_none());
+    assert!(format_int(1, 37).is_none());
+  }
+
+  #[test]
+  fn floats() {
+    assert_eq!(format_float(12.3456, 2), "12.35");
+    assert_eq!(format_float(-2.5, 0), "-3");
+    assert_eq!(format_float(1.0, 0), "1");
+    assert_eq!(format_float(0.125, 3), "0.125");
+    // At most 8 digits
+    assert_eq!(format_float(0.5, 12), "0.50000000");
+
+    // The rounding carries into the integer part
+    assert_eq!(format_float(0.999, 2), "1.00");
+    assert_eq!(format_float(99.96, 1), "100.0");
+    assert_eq!(format_float(-9.9999, 3), "-10.000");
+
+    assert_eq!(format_float(-0.0, 2), "0.00");
+    assert_eq!(format_float(f32::NAN, 2), "nan");
+    assert_eq!(format_float(f32::INFINITY, 2), "inf");
+    assert_eq!(format_float(f32::NEG_INFINITY, 2), "-inf");
+    assert_eq!(format_float(5e9, 2), "ovf");
+    assert_eq!(format_float(-5e9, 2), "ovf");
+  }
+}
@@ -0,0 +1,353 @@
+//! This module contains the USB device driver for the OTG_FS peripheral and a virtual serial port (CDC-ACM).
+//!
+//! The driver implements the `UsbBus` trait of the [usb-device](https://docs.rs/usb-device) crate, so every class of
+//! the usb-device ecosystem can be used with the [bus](usb_bus). The bus uses the pins PA11 (D-) and PA12 (D+) and the
+//! 48 MHz clock of the PLL, which is started from the HSI (16 MHz / 8 * 96 / 4). The system clock is not changed.
+//!
+//! Only one USB device can be created on the bus. [UsbSerial] is a ready-made device with a virtual serial port, which
+//! offers the same functions as a [UART](crate::uart::UART). The host has to be served regularly, so the device has to
+//! be [polled](UsbSerial::poll) at least every 10ms while no other function of it is called.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::usb::UsbSerial;
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   let mut serial = UsbSerial::new().unwrap();
+//!
+//!   loop {
+//!     serial.poll();
+//!
+//!     // Echo everything that the terminal sends
+//!     if let Some(byte) = serial.try_read_byte() {
+//!       serial.write(byte).ok();
+//!     }
+//!   }
+//! }
+//! ```
+
+#![allow(clippy::question_mark)]
+
+use crate::include::{ProgError, SerialError, pins::{A11, A12}};
+use crate::gpio::{pinmode_alternate_function, set_speed, GpioSpeed};
+use crate::time::{millis, is_expired, is_time_started};
+use core::fmt;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::interrupt::free;
+use synopsys_usb_otg::UsbPeripheral;
+use usb_device::bus::UsbBusAllocator;
+use usb_device::prelude::*;
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+use rtt_target::rprintln;
+
+pub use synopsys_usb_otg::UsbBus;
+
+/// The vendor id of the ready-made devices, from [pid.codes](https://pid.codes).
+pub const DEFAULT_VID: u16 = 0x1209;
+/// The product id of the ready-made devices, the test id of pid.codes. Use your own ids for products.
+pub const DEFAULT_PID: u16 = 0x0001;
+
+// The peripheral has 1.25 KB of FIFO memory
+const FIFO_WORDS: usize = 320;
+// HSI, the AHB is not divided
+const HCLK_FREQ: u32 = 16000000;
+// PLLM = 8, PLLN = 96, PLLP = 2, PLLQ = 4, PLLR = 2, HSI as source
+const PLLCFGR: u32 = 8 | (96 << 6) | (4 << 24) | (2 << 28);
+// PLLM, PLLN, PLLSRC and PLLQ, which set the 48 MHz clock
+const PLL48_MASK: u32 = 0x3F | (0x1FF << 6) | (1 << 22) | (0xF << 24);
+
+static mut EP_MEMORY: [u32; FIFO_WORDS] = [0; FIFO_WORDS];
+static mut USB_BUS: Option<UsbBusAllocator<UsbBus<UsbOtgFs>>> = None;
+static DEVICE_CREATED: AtomicBool = AtomicBool::new(false);
+
+
+/// The OTG_FS peripheral for the [UsbBus] driver.
+pub struct UsbOtgFs {
+  #[doc(hidden)]
+  _private: ()
+}
+
+unsafe impl Sync for UsbOtgFs {}
+
+unsafe impl UsbPeripheral for UsbOtgFs {
+  const REGISTERS: *const () = stm32f4::stm32f446::OTG_FS_GLOBAL::ptr() as *const ();
+  const HIGH_SPEED: bool = false;
+  const FIFO_DEPTH_WORDS: usize = FIFO_WORDS;
+  const ENDPOINT_COUNT: usize = 6;
+
+  fn enable() {
+    let peripheral_ptr;
+    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+    let rcc = &peripheral_ptr.RCC;
+
+    free(|_| {
+      rcc.ahb2enr.modify(|_, w| w.otgfsen().enabled());
+      rcc.ahb2rstr.modify(|_, w| w.otgfsrst().set_bit());
+      rcc.ahb2rstr.modify(|_, w| w.otgfsrst().clear_bit());
+    });
+  }
+
+  fn ahb_frequency_hz(&self) -> u32 {
+    return HCLK_FREQ;
+  }
+}
+
+/// Returns the USB bus, which can be used to create classes and a device of the usb-device crate. The first call
+/// starts the 48 MHz clock and configures the pins PA11 and PA12, later calls return the same bus.
+///
+/// Returns an error-enum if the pins are used for another function or if the PLL was started with a configuration
+/// that does not result in 48 MHz.
+pub fn usb_bus() -> Result<&'static UsbBusAllocator<UsbBus<UsbOtgFs>>, ProgError> {
+  return free(|_| unsafe {
+    if let Some(bus) = (*addr_of!(USB_BUS)).as_ref() {return Ok(bus);}
+
+    if let Err(error) = start_pll() {return Err(error);}
+
+    for pin in [A11, A12] {
+      let pin = match pinmode_alternate_function(pin, 10) {
+        Ok(value) => value,
+        Err(error) => {
+          rprintln!("P{}{} is used for another function! | usb_bus()", pin.0.to_uppercase(), pin.1);
+          return Err(error);
+        }
+      };
+      set_speed(&pin, GpioSpeed::High);
+
+      // The pins stay configured as long as the bus exists, which is forever
+      core::mem::forget(pin);
+    }
+
+    *addr_of_mut!(USB_BUS) = Some(UsbBus::new(UsbOtgFs {_private: ()}, &mut *addr_of_mut!(EP_MEMORY)));
+    return Ok((*addr_of!(USB_BUS)).as_ref().unwrap());
+  });
+}
+
+#[doc(hidden)]
+pub fn claim_device() -> Result<(), ProgError> {
+  if DEVICE_CREATED.swap(true, Ordering::SeqCst) {
+    rprintln!("There is already a USB device! | claim_device()");
+    return Err(ProgError::AlreadyConfigured);
+  }
+
+  return Ok(());
+}
+
+
+// Serial Port ====================================================================================
+/// This struct represents a USB device with a virtual serial port.
+pub struct UsbSerial {
+  #[doc(hidden)]
+  device: UsbDevice<'static, UsbBus<UsbOtgFs>>,
+  #[doc(hidden)]
+  serial: SerialPort<'static, UsbBus<UsbOtgFs>>
+}
+
+impl UsbSerial {
+  /// Creates the device with the [default ids](DEFAULT_VID). Returns an error-enum if there is already a USB device
+  /// or the pins are used for another function.
+  pub fn new() -> Result<Self, ProgError> {
+    return Self::with_ids(DEFAULT_VID, DEFAULT_PID, "Rustuino", "Serial port", "0001");
+  }
+
+  /// Creates the device with the vendor id, product id and strings that are shown by the host. Returns an error-enum
+  /// if there is already a USB device or the pins are used for another function.
+  pub fn with_ids(vid: u16, pid: u16, manufacturer: &'static str, product: &'static str, serial_number: &'static str)
+    -> Result<Self, ProgError> {
+    let bus = match usb_bus() {
+      Ok(value) => value,
+      Err(error) => return Err(error)
+    };
+    if let Err(error) = claim_device() {return Err(error);}
+
+    let serial = SerialPort::new(bus);
+
+    let strings = StringDescriptors::default().manufacturer(manufacturer).product(product).serial_number(serial_number);
+    let device = match UsbDeviceBuilder::new(bus, UsbVidPid(vid, pid)).strings(&[strings]) {
+      Ok(builder) => builder.device_class(USB_CLASS_CDC).build(),
+      Err(_) => {
+        rprintln!("Invalid device strings! | UsbSerial::with_ids()");
+        return Err(ProgError::InvalidConfiguration);
+      }
+    };
+
+    return Ok(Self {device, serial});
+  }
+
+  /// Serves the requests of the host. Has to be called at least every 10ms while no other function is called. Returns
+  /// true if data might have been received.
+  pub fn poll(&mut self) -> bool {
+    return self.device.poll(&mut [&mut self.serial]);
+  }
+
+  /// Returns true if the device is configured by the host and a terminal has opened the port (DTR is set).
+  pub fn is_connected(&self) -> bool {
+    return self.device.state() == UsbDeviceState::Configured && self.serial.dtr();
+  }
+
+  /// Sends an ASCII char. Returns an error-enum if no terminal is [connected](UsbSerial::is_connected).
+  pub fn print_char(&mut self, data: char) -> Result<(), SerialError> {
+    return self.write_bytes(data.encode_utf8(&mut [0; 4]).as_bytes());
+  }
+
+  /// Sends an ASCII string. Returns an error-enum if no terminal is [connected](UsbSerial::is_connected).
+  pub fn print_str(&mut self, data: &str) -> Result<(), SerialError> {
+    return self.write_bytes(data.as_bytes());
+  }
+
+  /// Acts like [print_char](UsbSerial::print_char) except it prints a newline at the end of the string.
+  pub fn println_char(&mut self, data: char) -> Result<(), SerialError> {
+    if let Err(error) = self.print_char(data) {return Err(error);}
+    if let Err(error) = self.print_str("\r\n") {return Err(error);}
+
+    return Ok(());
+  }
+
+  /// Acts like [print_str](UsbSerial::print_str) except it prints a newline at the end of the string.
+  pub fn println_str(&mut self, data: &str) -> Result<(), SerialError> {
+    if let Err(error) = self.print_str(data) {return Err(error);}
+    if let Err(error) = self.print_str("\r\n") {return Err(error);}
+
+    return Ok(());
+  }
+
+  /// Sends formatted text, works with the [`uprint!`](crate::uprint) and [`uprintln!`](crate::uprintln) macros.
+  /// Returns an error-enum if no terminal is [connected](UsbSerial::is_connected).
+  pub fn print_fmt(&mut self, args: fmt::Arguments) -> Result<(), SerialError> {
+    let mut writer = UsbWriter {serial: self, error: None};
+
+    if fmt::write(&mut writer, args).is_err() {
+      return Err(writer.error.unwrap_or(SerialError::Prog(ProgError::Internal)));
+    }
+
+    return Ok(());
+  }
+
+  /// Sends a raw byte. Returns an error-enum if no terminal is [connected](UsbSerial::is_connected).
+  pub fn write(&mut self, data: u8) -> Result<(), SerialError> {
+    return self.write_bytes(&[data]);
+  }
+
+  /// Sends the bytes and waits until the host took them. Returns an error-enum if no terminal is
+  /// [connected](UsbSerial::is_connected) or it is closed while sending.
+  pub fn write_bytes(&mut self, data: &[u8]) -> Result<(), SerialError> {
+    let mut sent = 0;
+
+    while sent < data.len() {
+      self.poll();
+
+      if !self.is_connected() {
+        rprintln!("No terminal is connected! | UsbSerial::write_bytes()");
+        return Err(SerialError::Prog(ProgError::NotConfigured));
+      }
+
+      match self.serial.write(&data[sent..]) {
+        Ok(count) => sent += count,
+        Err(UsbError::WouldBlock) => {},
+        Err(_) => return Err(SerialError::Prog(ProgError::Internal))
+      };
+    }
+
+    return Ok(());
+  }
+
+  /// Waits until it recieves an ASCII char. Returns `None` if the device is not configured by the host.
+  pub fn read_char(&mut self) -> Option<char> {
+    return self.read_byte().map(|byte| byte as char);
+  }
+
+  /// Waits until it recieves a byte. Returns `None` if the device is not configured by the host.
+  pub fn read_byte(&mut self) -> Option<u8> {
+    loop {
+      if let Some(byte) = self.try_read_byte() {return Some(byte);}
+      if self.device.state() != UsbDeviceState::Configured {return None;}
+    }
+  }
+
+  /// Returns a received byte, or `None` if nothing was received. Does not wait.
+  pub fn try_read_byte(&mut self) -> Option<u8> {
+    self.poll();
+
+    let mut buffer = [0];
+    return match self.serial.read(&mut buffer) {
+      Ok(1) => Some(buffer[0]),
+      _ => None
+    };
+  }
+
+  /// Waits until it recieves a byte or the timeout in milliseconds expires.
+  ///
+  /// Needs the time base started with [`start_time()`](crate::time::start_time). Returns an error-enum if nothing was
+  /// received in time.
+  pub fn read_byte_timeout(&mut self, timeout_ms: usize) -> Result<u8, SerialError> {
+    if !is_time_started() {
+      rprintln!("The time base is not started! | UsbSerial::read_byte_timeout()");
+      return Err(SerialError::Prog(ProgError::NotConfigured));
+    }
+
+    let deadline = millis().wrapping_add(timeout_ms);
+
+    loop {
+      if let Some(byte) = self.try_read_byte() {return Ok(byte);}
+      if is_expired(millis(), deadline) {return Err(SerialError::Prog(ProgError::TimedOut));}
+    }
+  }
+
+  /// Returns the device to use other functions of the usb-device crate.
+  pub fn device(&mut self) -> &mut UsbDevice<'static, UsbBus<UsbOtgFs>> {
+    return &mut self.device;
+  }
+}
+
+impl fmt::Write for UsbSerial {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    return self.print_str(s).map_err(|_| fmt::Error);
+  }
+}
+
+
+// Private Functions ==============================================================================
+struct UsbWriter<'a> {
+  serial: &'a mut UsbSerial,
+  error: Option<SerialError>
+}
+
+impl fmt::Write for UsbWriter<'_> {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    if let Err(error) = self.serial.print_str(s) {
+      self.error = Some(error);
+      return Err(fmt::Error);
+    }
+
+    return Ok(());
+  }
+}
+
+// The PLL is only used for the 48 MHz clock, which is selected by default (CK48MSEL = 0)
+fn start_pll() -> Result<(), ProgError> {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rcc = &peripheral_ptr.RCC;
+
+  // PLLON, the configuration can not be changed while the PLL runs
+  if rcc.cr.read().bits() & (1 << 24) != 0 {
+    if rcc.pllcfgr.read().bits() & PLL48_MASK != PLLCFGR & PLL48_MASK {
+      rprintln!("The PLL runs with another configuration! | usb_bus()");
+      return Err(ProgError::InvalidConfiguration);
+    }
+    return Ok(());
+  }
+
+  rcc.pllcfgr.write(|w| unsafe {w.bits(PLLCFGR)});
+  rcc.cr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 24))});
+  // PLLRDY
+  while rcc.cr.read().bits() & (1 << 25) == 0 {}
+
+  return Ok(());
+}
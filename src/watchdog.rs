@@ -0,0 +1,323 @@
+//! This module contains everything that is used for the watchdogs and the reset cause.
+//!
+//! A watchdog resets the microcontroller if it is not fed in time, e.g. because the program is stuck in a loop
+//! waiting for a peripheral. There are two watchdogs:
+//!
+//! | Watchdog                          | Clock       | Timeout         | Can be stopped |
+//! | --------------------------------- | ----------- | --------------- | -------------- |
+//! | [Independent](Watchdog)           | LSI (32kHz) | 1ms to 32s      | No             |
+//! | [Window](WindowWatchdog)          | APB1        | 0.3ms to 131ms  | No             |
+//!
+//! The window watchdog also resets if it is fed too early and can call a function shortly before the reset.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! #![no_std]
+//! #![no_main]
+//!
+//! use rustuino::*;
+//! use rustuino::watchdog::{Watchdog, reset_cause, ResetCause};
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!   if reset_cause() == ResetCause::IndependentWatchdog {
+//!     rprintln!("Recovered from a watchdog reset!");
+//!   }
+//!
+//!   // Reset if the loop takes longer than 500ms
+//!   let watchdog = Watchdog::start(500).unwrap();
+//!
+//!   loop {
+//!     // Do something
+//!     watchdog.feed();
+//!   }
+//! }
+//! ```
+
+use crate::include::ProgError;
+use stm32f4::stm32f446::{NVIC, Interrupt, interrupt};
+use cortex_m::interrupt::{Mutex, free};
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use rtt_target::rprintln;
+
+const LSI_FREQ: u32 = 32000;
+const PCLK1_FREQ: u32 = 16000000;
+
+type Callback = Option<fn()>;
+
+static IWDG_STARTED: AtomicBool = AtomicBool::new(false);
+static WWDG_STARTED: AtomicBool = AtomicBool::new(false);
+static EARLY_WAKEUP_CALLBACK: Mutex<RefCell<Callback>> = Mutex::new(RefCell::new(None));
+// Bit 0 marks that the flags have been read
+static RESET_FLAGS: AtomicU32 = AtomicU32::new(0);
+
+
+/// Represents the possible causes of the last reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetCause {
+  /// Illegal entry into standby or stop mode
+  LowPower,
+  /// The window watchdog was not fed in its window
+  WindowWatchdog,
+  /// The independent watchdog was not fed in time
+  IndependentWatchdog,
+  /// Reset requested by the software, e.g. with `cortex_m::peripheral::SCB::sys_reset()`
+  Software,
+  /// The supply voltage was turned on
+  PowerOn,
+  /// The supply voltage dropped below the brown-out threshold
+  BrownOut,
+  /// The NRST pin was pulled low, e.g. by the reset button
+  Pin,
+  /// No reset flag was set
+  Unknown
+}
+
+/// This struct represents the started independent watchdog (IWDG).
+pub struct Watchdog {
+  #[doc(hidden)]
+  _private: ()
+}
+
+/// This struct represents the started window watchdog (WWDG).
+pub struct WindowWatchdog {
+  #[doc(hidden)]
+  counter: u8
+}
+
+impl Watchdog {
+  /// Starts the independent watchdog with a timeout in milliseconds.
+  ///
+  /// The watchdog runs from the internal LSI oscillator, so the real timeout can vary by a few percent. Once started
+  /// it can only be stopped by a reset. Returns an error-enum if the timeout is out of range or the watchdog is
+  /// already running.
+  pub fn start(timeout_ms: u32) -> Result<Self, ProgError> {
+    let peripheral_ptr;
+    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+    let iwdg = &peripheral_ptr.IWDG;
+
+    let (pr, rlr) = match calc_iwdg(timeout_ms) {
+      Ok(values) => values,
+      Err(error) => {
+        rprintln!("A watchdog timeout of {}ms is not possible! | Watchdog::start()", timeout_ms);
+        return Err(error);
+      }
+    };
+
+    if IWDG_STARTED.swap(true, Ordering::SeqCst) {
+      rprintln!("The watchdog is already running! | Watchdog::start()");
+      return Err(ProgError::AlreadyConfigured);
+    }
+
+    // Starting the watchdog also starts the LSI
+    iwdg.kr.write(|w| unsafe {w.bits(0xCCCC)});
+    iwdg.kr.write(|w| unsafe {w.bits(0x5555)});
+    while iwdg.sr.read().bits() & 0x3 != 0 {}
+    iwdg.pr.write(|w| unsafe {w.bits(pr as u32)});
+    iwdg.rlr.write(|w| unsafe {w.bits(rlr as u32)});
+    while iwdg.sr.read().bits() & 0x3 != 0 {}
+    iwdg.kr.write(|w| unsafe {w.bits(0xAAAA)});
+
+    return Ok(Self {_private: ()});
+  }
+
+  /// Reloads the watchdog counter. Has to be called before the timeout expires.
+  pub fn feed(&self) {
+    let peripheral_ptr;
+    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+    peripheral_ptr.IWDG.kr.write(|w| unsafe {w.bits(0xAAAA)});
+  }
+}
+
+impl WindowWatchdog {
+  /// Starts the window watchdog.
+  ///
+  /// Takes the timeout in milliseconds after which the watchdog resets and the window in milliseconds after feeding
+  /// in which feeding again also causes a reset. A window of 0 allows feeding at any time. The optional callback is
+  /// called from the early wakeup interrupt shortly before the reset, e.g. to save data.
+  /// Returns an error-enum if the timing is out of range or the watchdog is already running.
+  pub fn start(timeout_ms: u32, window_ms: u32, early_wakeup: Option<fn()>) -> Result<Self, ProgError> {
+    let peripheral_ptr;
+    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+    let rcc = &peripheral_ptr.RCC;
+    let wwdg = &peripheral_ptr.WWDG;
+
+    let (wdgtb, counter, window) = match calc_wwdg(timeout_ms, window_ms) {
+      Ok(values) => values,
+      Err(error) => {
+        rprintln!("A timeout of {}ms with a window of {}ms is not possible! | WindowWatchdog::start()", timeout_ms, window_ms);
+        return Err(error);
+      }
+    };
+
+    if WWDG_STARTED.swap(true, Ordering::SeqCst) {
+      rprintln!("The window watchdog is already running! | WindowWatchdog::start()");
+      return Err(ProgError::AlreadyConfigured);
+    }
+
+    free(|cs| EARLY_WAKEUP_CALLBACK.borrow(cs).replace(early_wakeup));
+
+    rcc.apb1enr.modify(|_, w| w.wwdgen().enabled());
+    wwdg.cfr.write(|w| unsafe {w.bits(((wdgtb as u32) << 7) | window as u32)});
+
+    if early_wakeup.is_some() {
+      wwdg.sr.write(|w| unsafe {w.bits(0)});
+      wwdg.cfr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 9))});
+      unsafe {NVIC::unmask(Interrupt::WWDG);}
+    }
+
+    // WDGA with the start value of the counter
+    wwdg.cr.write(|w| unsafe {w.bits((1 << 7) | counter as u32)});
+
+    return Ok(Self {counter});
+  }
+
+  /// Reloads the watchdog counter. Has to be called after the window and before the timeout.
+  pub fn feed(&self) {
+    let peripheral_ptr;
+    unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+    peripheral_ptr.WWDG.cr.write(|w| unsafe {w.bits((1 << 7) | self.counter as u32)});
+  }
+}
+
+
+// Reset Cause ====================================================================================
+/// Returns the cause of the last reset.
+///
+/// The flags are read and cleared on the first call, so later calls return the same value.
+pub fn reset_cause() -> ResetCause {
+  let flags = reset_flags();
+
+  if flags & (1 << 31) != 0 {return ResetCause::LowPower;}
+  else if flags & (1 << 30) != 0 {return ResetCause::WindowWatchdog;}
+  else if flags & (1 << 29) != 0 {return ResetCause::IndependentWatchdog;}
+  else if flags & (1 << 28) != 0 {return ResetCause::Software;}
+  else if flags & (1 << 27) != 0 {return ResetCause::PowerOn;}
+  else if flags & (1 << 25) != 0 {return ResetCause::BrownOut;}
+  else if flags & (1 << 26) != 0 {return ResetCause::Pin;}
+  else {return ResetCause::Unknown;}
+}
+
+/// Checks if the last reset was caused by one of the watchdogs.
+pub fn was_watchdog_reset() -> bool {
+  return matches!(reset_cause(), ResetCause::WindowWatchdog | ResetCause::IndependentWatchdog);
+}
+
+
+// Private Functions ==============================================================================
+#[doc(hidden)]
+pub fn calc_iwdg(timeout_ms: u32) -> Result<(u8, u16), ProgError> {
+  if timeout_ms == 0 {return Err(ProgError::InvalidConfiguration);}
+
+  // Prescaler 4 << PR, 12 bit reload value
+  for pr in 0..=6 {
+    let ticks = (timeout_ms as u64 * LSI_FREQ as u64) / (1000 * (4 << pr) as u64);
+    if ticks <= 0x1000 {return Ok((pr, ticks.max(1) as u16 - 1));}
+  }
+
+  return Err(ProgError::InvalidConfiguration);
+}
+
+#[doc(hidden)]
+pub fn calc_wwdg(timeout_ms: u32, window_ms: u32) -> Result<(u8, u8, u8), ProgError> {
+  if timeout_ms == 0 || window_ms >= timeout_ms {return Err(ProgError::InvalidConfiguration);}
+
+  // The counter counts from T down to 0x40 with PCLK1 / 4096 / 2^WDGTB and resets at 0x3F
+  for wdgtb in 0..=3 {
+    let tick_us = (4096u64 << wdgtb) * 1_000_000 / PCLK1_FREQ as u64;
+    let ticks = (timeout_ms as u64 * 1000 + tick_us / 2) / tick_us;
+    if ticks <= 64 {
+      let ticks = ticks.max(1);
+      let window_ticks = (window_ms as u64 * 1000 + tick_us / 2) / tick_us;
+      if window_ticks >= ticks {return Err(ProgError::InvalidConfiguration);}
+      let counter = 0x3F + ticks;
+      return Ok((wdgtb, counter as u8, (counter - window_ticks) as u8));
+    }
+  }
+
+  return Err(ProgError::InvalidConfiguration);
+}
+
+fn reset_flags() -> u32 {
+  let flags = RESET_FLAGS.load(Ordering::SeqCst);
+  if flags & 1 != 0 {return flags;}
+
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+  let rcc = &peripheral_ptr.RCC;
+
+  let flags = (rcc.csr.read().bits() & 0xFE00_0000) | 1;
+  // RMVF
+  rcc.csr.modify(|r, w| unsafe {w.bits(r.bits() | (1 << 24))});
+  RESET_FLAGS.store(flags, Ordering::SeqCst);
+
+  return flags;
+}
+
+
+// Interrupts =====================================================================================
+#[allow(non_snake_case)]
+#[interrupt]
+fn WWDG() {
+  let peripheral_ptr;
+  unsafe {peripheral_ptr = stm32f4::stm32f446::Peripherals::steal();}
+
+  peripheral_ptr.WWDG.sr.write(|w| unsafe {w.bits(0)});
+
+  if let Some(callback) = free(|cs| *EARLY_WAKEUP_CALLBACK.borrow(cs).borrow()) {callback();}
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn iwdg_prescaler_and_reload() {
+    // LSI / 4 gives 8 ticks per millisecond
+    assert_eq!(calc_iwdg(1), Ok((0, 7)));
+    assert_eq!(calc_iwdg(512), Ok((0, 0xFFF)));
+    // The next prescaler halves the resolution
+    assert_eq!(calc_iwdg(513), Ok((1, 2051)));
+    assert_eq!(calc_iwdg(1000), Ok((1, 3999)));
+    assert_eq!(calc_iwdg(5000), Ok((4, 2499)));
+  }
+
+  #[test]
+  fn iwdg_limits() {
+    assert_eq!(calc_iwdg(0), Err(ProgError::InvalidConfiguration));
+    // LSI / 256 with the full 12 bit reload value
+    assert_eq!(calc_iwdg(32768), Ok((6, 0xFFF)));
+    assert_eq!(calc_iwdg(32800), Err(ProgError::InvalidConfiguration));
+  }
+
+  #[test]
+  fn wwdg_prescaler_and_counter() {
+    // One tick lasts 256us with WDGTB 0, the counter starts above 0x3F
+    assert_eq!(calc_wwdg(1, 0), Ok((0, 0x43, 0x43)));
+    assert_eq!(calc_wwdg(16, 0), Ok((0, 0x7E, 0x7E)));
+    // 66 ticks do not fit, so the tick doubles
+    assert_eq!(calc_wwdg(17, 0), Ok((1, 0x60, 0x60)));
+    // 49 ticks of 2048us, refreshes are allowed after 24 ticks
+    assert_eq!(calc_wwdg(100, 50), Ok((3, 0x70, 0x58)));
+  }
+
+  #[test]
+  fn wwdg_limits() {
+    assert_eq!(calc_wwdg(0, 0), Err(ProgError::InvalidConfiguration));
+    assert_eq!(calc_wwdg(132, 0), Ok((3, 0x7F, 0x7F)));
+    assert_eq!(calc_wwdg(133, 0), Err(ProgError::InvalidConfiguration));
+  }
+
+  #[test]
+  fn wwdg_window_after_timeout() {
+    assert_eq!(calc_wwdg(10, 10), Err(ProgError::InvalidConfiguration));
+    assert_eq!(calc_wwdg(10, 20), Err(ProgError::InvalidConfiguration));
+    // Both round to 49 ticks
+    assert_eq!(calc_wwdg(101, 100), Err(ProgError::InvalidConfiguration));
+  }
+}